@@ -0,0 +1,3 @@
+-- Delayed deletion: `/trash` marks the row instead of deleting it when
+-- TRASH_GRACE_PERIOD_HOURS is set. NULL means the row is not trashed.
+ALTER TABLE secret ADD COLUMN trash_after TEXT;
@@ -0,0 +1,2 @@
+DROP INDEX secret_expires_at;
+ALTER TABLE secret DROP COLUMN expires_at;
@@ -0,0 +1,4 @@
+-- Optional retention: a record stored with `ttl_days` expires at this time.
+-- NULL (every legacy record) never expires.
+ALTER TABLE secret ADD COLUMN expires_at TEXT;
+CREATE INDEX secret_expires_at ON secret (expires_at) WHERE expires_at IS NOT NULL;
@@ -0,0 +1 @@
+DROP TABLE opaque_record;
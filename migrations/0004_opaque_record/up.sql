@@ -0,0 +1,9 @@
+-- OPAQUE registrations (`/opaque/*`): the registration record (client
+-- public key, masking key, envelope) and the secret it unlocks, one row per
+-- credential. Legacy `secret` rows are untouched.
+CREATE TABLE opaque_record (
+    credential_id TEXT PRIMARY KEY NOT NULL,
+    created_at TEXT NOT NULL,
+    registration_record TEXT NOT NULL,
+    encrypted_secret TEXT NOT NULL
+);
@@ -0,0 +1,2 @@
+ALTER TABLE secret DROP COLUMN federation_id;
+ALTER TABLE secret DROP COLUMN share_index;
@@ -0,0 +1,4 @@
+-- Federation mode: the Shamir share a record holds and the split it belongs
+-- to. NULL (every legacy record) is a whole secret.
+ALTER TABLE secret ADD COLUMN share_index INTEGER;
+ALTER TABLE secret ADD COLUMN federation_id TEXT;
@@ -0,0 +1,2 @@
+DROP TABLE replication_tombstone;
+DROP TABLE replication_log;
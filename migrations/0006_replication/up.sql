@@ -0,0 +1,16 @@
+-- Peer replication (see replication.rs). `replication_log` holds the local
+-- changes the peer has not acknowledged yet, oldest first: a write carries
+-- the record as JSON, a deletion only its id and time. `replication_tombstone`
+-- remembers recent deletions, local or replicated, so that a write still in
+-- flight cannot bring a deleted record back.
+CREATE TABLE replication_log (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    secret_id TEXT NOT NULL,
+    record TEXT,
+    deleted_at TEXT
+);
+CREATE TABLE replication_tombstone (
+    secret_id TEXT PRIMARY KEY NOT NULL,
+    deleted_at TEXT NOT NULL
+);
+CREATE INDEX replication_tombstone_deleted_at ON replication_tombstone (deleted_at);
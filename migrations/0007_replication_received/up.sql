@@ -0,0 +1,6 @@
+-- The `sent_at` of the last batch accepted from the peer (see
+-- replication.rs). A batch sent at or before it is a replay, refused.
+CREATE TABLE replication_received (
+    peer INTEGER PRIMARY KEY NOT NULL CHECK (peer = 0),
+    sent_at TEXT NOT NULL
+);
@@ -0,0 +1,7 @@
+-- Transparency log (see transparency.rs): the hash of each leaf, in hex,
+-- appended in the transaction of the change it records. Never updated or
+-- deleted.
+CREATE TABLE log_leaf (
+    leaf_index INTEGER PRIMARY KEY NOT NULL,
+    leaf_hash TEXT NOT NULL
+);
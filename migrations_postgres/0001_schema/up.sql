@@ -0,0 +1,13 @@
+-- The PostgreSQL schema matches the SQLite one after all its migrations, so
+-- both backends share `schema.rs` and `Secret`. Timestamps stay fixed-width
+-- UTC text (see `database_timestamp`); the "C" collation makes their text
+-- order their time order whatever the cluster locale.
+CREATE TABLE secret (
+    id TEXT PRIMARY KEY NOT NULL,
+    created_at TEXT NOT NULL,
+    encrypted_secret TEXT NOT NULL,
+    trash_after TEXT COLLATE "C",
+    expires_at TEXT COLLATE "C"
+);
+CREATE INDEX secret_trash_after ON secret (trash_after) WHERE trash_after IS NOT NULL;
+CREATE INDEX secret_expires_at ON secret (expires_at) WHERE expires_at IS NOT NULL;
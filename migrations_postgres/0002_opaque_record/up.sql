@@ -0,0 +1,7 @@
+-- Same table as the SQLite migration 0004_opaque_record.
+CREATE TABLE opaque_record (
+    credential_id TEXT PRIMARY KEY NOT NULL,
+    created_at TEXT NOT NULL,
+    registration_record TEXT NOT NULL,
+    encrypted_secret TEXT NOT NULL
+);
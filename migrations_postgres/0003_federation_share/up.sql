@@ -0,0 +1,3 @@
+-- Same columns as the SQLite migration 0005_federation_share.
+ALTER TABLE secret ADD COLUMN share_index INTEGER;
+ALTER TABLE secret ADD COLUMN federation_id TEXT;
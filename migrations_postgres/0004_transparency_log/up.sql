@@ -0,0 +1,5 @@
+-- Same table as the SQLite migration 0007_transparency_log.
+CREATE TABLE log_leaf (
+    leaf_index BIGINT PRIMARY KEY,
+    leaf_hash TEXT NOT NULL
+);
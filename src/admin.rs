@@ -0,0 +1,311 @@
+//! Offline maintenance behind the `keychain-admin` binary. Every report
+//! follows the server's log discipline: counts and sizes only, never a
+//! secret_id, a timestamp of a single record, or a secret.
+
+use std::fmt::Write;
+use std::io::{BufReader, BufWriter, Write as _};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use diesel::{sql_query, ExpressionMethods, QueryDsl, QueryableByName, RunQueryDsl};
+use diesel::{
+    sql_types::{BigInt, Text},
+    SqliteConnection,
+};
+
+use crate::database::{
+    enable_wal, purge_opaque_records_created_before, purge_secrets_created_before, run_migrations,
+};
+use crate::retention::purge_all;
+use crate::schema::secret::dsl::*;
+
+pub const USAGE: &str = "usage: keychain-admin <command>
+
+commands:
+  migrate                    run pending migrations and enable WAL mode
+  stats                      record counts, encrypted_secret sizes, records per month
+  integrity-check            run SQLite's integrity check
+  vacuum                     rebuild the database file to reclaim free pages
+  purge --older-than <days>  delete every record created more than <days> ago
+  export <file> [--recipient <age1...>]
+                             dump every record to a new file, optionally age-encrypted
+  import <file> [--identity <identity file>]
+                             insert the records of an export, keeping existing ones";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Migrate,
+    Stats,
+    IntegrityCheck,
+    Vacuum,
+    Purge {
+        older_than_days: u32,
+    },
+    Export {
+        path: PathBuf,
+        recipient: Option<String>,
+    },
+    Import {
+        path: PathBuf,
+        identity: Option<PathBuf>,
+    },
+}
+
+impl Command {
+    /// Commands allowed to create a missing database file.
+    pub fn creates_database(&self) -> bool {
+        matches!(self, Command::Migrate | Command::Import { .. })
+    }
+}
+
+pub fn parse_args(args: &[String]) -> Result<Command, String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["migrate"] => Ok(Command::Migrate),
+        ["stats"] => Ok(Command::Stats),
+        ["integrity-check"] => Ok(Command::IntegrityCheck),
+        ["vacuum"] => Ok(Command::Vacuum),
+        ["purge", "--older-than", days] => match days.parse::<u32>() {
+            // zero would delete every record stored before this second
+            Ok(days) if days > 0 => Ok(Command::Purge {
+                older_than_days: days,
+            }),
+            _ => Err("--older-than must be a positive number of days".to_string()),
+        },
+        ["export", path] => Ok(Command::Export {
+            path: PathBuf::from(path),
+            recipient: None,
+        }),
+        ["export", path, "--recipient", recipient] => Ok(Command::Export {
+            path: PathBuf::from(path),
+            recipient: Some(recipient.to_string()),
+        }),
+        ["import", path] => Ok(Command::Import {
+            path: PathBuf::from(path),
+            identity: None,
+        }),
+        ["import", path, "--identity", identity] => Ok(Command::Import {
+            path: PathBuf::from(path),
+            identity: Some(PathBuf::from(identity)),
+        }),
+        [] => Err("missing command".to_string()),
+        _ => Err(format!("invalid arguments: {}", args.join(" "))),
+    }
+}
+
+/// Aggregates reported by `stats`.
+#[derive(Debug, PartialEq)]
+pub struct Stats {
+    pub secrets: i64,
+    /// Records marked by `/trash` during a grace period.
+    pub pending_deletion: i64,
+    /// Records stored with `ttl_days`.
+    pub expiring: i64,
+    /// `encrypted_secret` lengths rounded up to a power of two, with counts.
+    pub size_histogram: Vec<(usize, i64)>,
+    /// `YYYY-MM` of `created_at`, with counts.
+    pub created_by_month: Vec<(String, i64)>,
+}
+
+#[derive(QueryableByName)]
+struct LengthCount {
+    #[diesel(sql_type = BigInt)]
+    length: i64,
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+}
+
+#[derive(QueryableByName)]
+struct MonthCount {
+    #[diesel(sql_type = Text)]
+    month: String,
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+}
+
+pub fn stats(connection: &mut SqliteConnection) -> Result<Stats, diesel::result::Error> {
+    let secrets = secret.count().get_result(connection)?;
+    let pending_deletion = secret
+        .filter(trash_after.is_not_null())
+        .count()
+        .get_result(connection)?;
+    let expiring = secret
+        .filter(expires_at.is_not_null())
+        .count()
+        .get_result(connection)?;
+
+    let mut size_histogram = Vec::<(usize, i64)>::new();
+    for row in sql_query(
+        "SELECT length(encrypted_secret) AS length, COUNT(*) AS count \
+         FROM secret GROUP BY length ORDER BY length",
+    )
+    .load::<LengthCount>(connection)?
+    {
+        let bucket = usize::try_from(row.length)
+            .unwrap_or_default()
+            .next_power_of_two();
+        match size_histogram.last_mut() {
+            Some((last, count)) if *last == bucket => *count += row.count,
+            _ => size_histogram.push((bucket, row.count)),
+        }
+    }
+
+    let created_by_month = sql_query(
+        "SELECT substr(created_at, 1, 7) AS month, COUNT(*) AS count \
+         FROM secret GROUP BY month ORDER BY month",
+    )
+    .load::<MonthCount>(connection)?
+    .into_iter()
+    .map(|row| (row.month, row.count))
+    .collect();
+
+    Ok(Stats {
+        secrets,
+        pending_deletion,
+        expiring,
+        size_histogram,
+        created_by_month,
+    })
+}
+
+/// Runs `PRAGMA integrity_check`: a healthy database returns `["ok"]`. The
+/// messages name pages, rowids and indexes, never column values.
+pub fn integrity_check(
+    connection: &mut SqliteConnection,
+) -> Result<Vec<String>, diesel::result::Error> {
+    #[derive(QueryableByName)]
+    struct IntegrityCheck {
+        #[diesel(sql_type = Text)]
+        integrity_check: String,
+    }
+
+    Ok(sql_query("PRAGMA integrity_check")
+        .load::<IntegrityCheck>(connection)?
+        .into_iter()
+        .map(|row| row.integrity_check)
+        .collect())
+}
+
+pub fn database_size(connection: &mut SqliteConnection) -> Result<i64, diesel::result::Error> {
+    #[derive(QueryableByName)]
+    struct DatabaseSize {
+        #[diesel(sql_type = BigInt)]
+        bytes: i64,
+    }
+
+    Ok(sql_query(
+        "SELECT page_count * page_size AS bytes FROM pragma_page_count(), pragma_page_size()",
+    )
+    .get_result::<DatabaseSize>(connection)?
+    .bytes)
+}
+
+/// Writes an export to a new `0600` file; a failed export removes it.
+fn export_to_file(
+    connection: &mut SqliteConnection,
+    path: &Path,
+    recipient: Option<&str>,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    // Never overwrite: the path may be an earlier export or the database.
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    let mut output = BufWriter::new(file);
+    let exported = match recipient {
+        Some(recipient) => {
+            crate::export::encrypt_to(recipient, &mut output).and_then(|mut encrypted| {
+                let exported = crate::export::export(connection, &mut encrypted)?;
+                encrypted.finish()?;
+                Ok(exported)
+            })
+        }
+        None => crate::export::export(connection, &mut output),
+    }
+    .and_then(|exported| {
+        output.flush()?;
+        Ok(exported)
+    });
+    if exported.is_err() {
+        let _ = std::fs::remove_file(path);
+    }
+    exported
+}
+
+/// Runs the command and returns its report. A failed integrity check is an
+/// `Err` carrying the SQLite messages. `replicate` tells that the server has
+/// a replication peer: purged records then reach it as tombstones.
+pub fn execute(
+    connection: &mut SqliteConnection,
+    command: &Command,
+    replicate: bool,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let mut report = String::new();
+    match command {
+        Command::Migrate => {
+            let applied = run_migrations(connection)?;
+            enable_wal(connection)?;
+            writeln!(report, "migrations applied: {applied}")?;
+        }
+        Command::Stats => {
+            let stats = stats(connection)?;
+            writeln!(report, "secrets: {}", stats.secrets)?;
+            writeln!(report, "pending deletion: {}", stats.pending_deletion)?;
+            writeln!(report, "with expiry: {}", stats.expiring)?;
+            writeln!(report, "encrypted_secret length:")?;
+            for (bucket, count) in &stats.size_histogram {
+                writeln!(report, "  <= {bucket}: {count}")?;
+            }
+            writeln!(report, "created_at by month:")?;
+            for (month, count) in &stats.created_by_month {
+                writeln!(report, "  {month}: {count}")?;
+            }
+        }
+        Command::IntegrityCheck => {
+            let messages = integrity_check(connection)?;
+            if messages != ["ok"] {
+                return Err(messages.join("\n").into());
+            }
+            writeln!(report, "integrity check: ok")?;
+        }
+        Command::Vacuum => {
+            let before = database_size(connection)?;
+            sql_query("VACUUM").execute(connection)?;
+            let after = database_size(connection)?;
+            writeln!(report, "database size: {before} -> {after} bytes")?;
+        }
+        Command::Purge { older_than_days } => {
+            let cutoff = chrono::Utc::now() - chrono::Duration::days(i64::from(*older_than_days));
+            // OPAQUE registrations are records too: the cutoff applies alike.
+            let purged = purge_all(|batch_size| {
+                purge_secrets_created_before(connection, cutoff, batch_size, replicate)
+            })? + purge_all(|batch_size| {
+                purge_opaque_records_created_before(connection, cutoff, batch_size)
+            })?;
+            writeln!(report, "secrets purged: {purged}")?;
+        }
+        Command::Export { path, recipient } => {
+            let exported = export_to_file(connection, path, recipient.as_deref())?;
+            writeln!(report, "secrets exported: {exported}")?;
+        }
+        Command::Import { path, identity } => {
+            run_migrations(connection)?;
+            enable_wal(connection)?;
+            let mut input = BufReader::new(std::fs::File::open(path)?);
+            let summary = match identity {
+                Some(identity) => crate::export::import(
+                    connection,
+                    &mut crate::export::decrypt_with(identity, input)?,
+                )?,
+                None => crate::export::import(connection, &mut input)?,
+            };
+            writeln!(
+                report,
+                "secrets imported: {} of {} (the others were already present)",
+                summary.inserted, summary.records
+            )?;
+        }
+    }
+    Ok(report)
+}
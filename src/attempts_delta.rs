@@ -0,0 +1,153 @@
+//! History behind `/attempts/delta`: what changed between consecutive
+//! `/attempts` snapshots, so a client holding an older snapshot downloads
+//! only the entries that moved instead of the whole map.
+//!
+//! Only the changes are kept, never past snapshots. Their total size is
+//! bounded by a budget (the identifier capacity): under churn the oldest
+//! transitions are dropped, and clients based on them get the full snapshot.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::models::{AttemptEntry, AttemptsDelta};
+
+/// The transition from the snapshot `base_etag` to the next one built.
+pub struct SnapshotChange {
+    base_etag: String,
+    /// Entries of the newer snapshot that were absent (`true`) or different
+    /// (`false`) in the older one.
+    upserted: Vec<(AttemptEntry, bool)>,
+    removed: Vec<String>,
+}
+
+impl SnapshotChange {
+    fn len(&self) -> usize {
+        self.upserted.len() + self.removed.len()
+    }
+}
+
+/// Compares two snapshots, both sorted by `id_hash` (see `build_snapshot`),
+/// in a single merge pass.
+pub fn diff(base_etag: &str, old: &[AttemptEntry], new: &[AttemptEntry]) -> SnapshotChange {
+    let mut change = SnapshotChange {
+        base_etag: base_etag.to_string(),
+        upserted: Vec::new(),
+        removed: Vec::new(),
+    };
+    let (mut old, mut new) = (old.iter().peekable(), new.iter().peekable());
+    loop {
+        match (old.peek(), new.peek()) {
+            (None, None) => break,
+            (Some(before), Some(after)) if before.id_hash == after.id_hash => {
+                if before != after {
+                    change.upserted.push(((*after).clone(), false));
+                }
+                old.next();
+                new.next();
+            }
+            (Some(before), Some(after)) if before.id_hash < after.id_hash => {
+                change.removed.push(before.id_hash.clone());
+                old.next();
+            }
+            (Some(before), None) => {
+                change.removed.push(before.id_hash.clone());
+                old.next();
+            }
+            (_, Some(after)) => {
+                change.upserted.push(((*after).clone(), true));
+                new.next();
+            }
+        }
+    }
+    change
+}
+
+/// Recent transitions, oldest first, ending at the current snapshot.
+#[derive(Default)]
+pub struct History {
+    changes: VecDeque<SnapshotChange>,
+    total: usize,
+}
+
+impl History {
+    /// Appends the latest transition, then drops the oldest ones until the
+    /// retained changes fit `budget` entries.
+    pub fn push(&mut self, change: SnapshotChange, budget: usize) {
+        self.total += change.len();
+        self.changes.push_back(change);
+        while self.total > budget {
+            let Some(oldest) = self.changes.pop_front() else {
+                break;
+            };
+            self.total -= oldest.len();
+        }
+    }
+
+    /// Composes every transition from the snapshot `base_etag` to the
+    /// current one, `None` when that base is no longer (or never was) in
+    /// the history. Entries are sorted by `id_hash`, like the snapshot.
+    pub fn since(&self, base_etag: &str) -> Option<Composed> {
+        // The latest occurrence: a snapshot whose content came back has the
+        // same ETag, and the later transitions are the shorter path.
+        let start = self
+            .changes
+            .iter()
+            .rposition(|change| change.base_etag == base_etag)?;
+
+        // id_hash -> (present in the base snapshot, latest entry if any)
+        let mut composed: BTreeMap<&str, (bool, Option<&AttemptEntry>)> = BTreeMap::new();
+        for change in self.changes.range(start..) {
+            for (entry, added) in &change.upserted {
+                composed.entry(&entry.id_hash).or_insert((!added, None)).1 = Some(entry);
+            }
+            for id_hash in &change.removed {
+                composed.entry(id_hash).or_insert((true, None)).1 = None;
+            }
+        }
+
+        let mut result = Composed::default();
+        for (id_hash, (in_base, latest)) in composed {
+            match (in_base, latest) {
+                (false, Some(entry)) => result.added.push(entry.clone()),
+                (true, Some(entry)) => result.changed.push(entry.clone()),
+                (true, None) => result.removed.push(id_hash.to_string()),
+                // added, then removed again: the base never saw it
+                (false, None) => {}
+            }
+        }
+        Some(result)
+    }
+}
+
+#[derive(Default)]
+pub struct Composed {
+    pub added: Vec<AttemptEntry>,
+    pub changed: Vec<AttemptEntry>,
+    pub removed: Vec<String>,
+}
+
+impl Composed {
+    pub fn len(&self) -> usize {
+        self.added.len() + self.changed.len() + self.removed.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn into_delta(
+        self,
+        base_etag: String,
+        etag: String,
+        collection_started_at: chrono::DateTime<chrono::Utc>,
+    ) -> AttemptsDelta {
+        AttemptsDelta {
+            version: 1,
+            base_etag,
+            etag,
+            collection_started_at,
+            added: self.added,
+            changed: self.changed,
+            removed: self.removed,
+        }
+    }
+}
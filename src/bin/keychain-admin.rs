@@ -0,0 +1,46 @@
+//! Offline maintenance for the secret database. Reads DATABASE_URL and
+//! REPLICATION_PEER_URL like the server (process environment first, then
+//! `.env`).
+
+use std::process::ExitCode;
+
+use keychain::{admin, database};
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = match admin::parse_args(&args) {
+        Ok(command) => command,
+        Err(error) => {
+            eprintln!("{error}\n\n{}", admin::USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    dotenvy::dotenv().ok();
+    let Ok(database_url) = std::env::var("DATABASE_URL") else {
+        eprintln!("DATABASE_URL must be set");
+        return ExitCode::FAILURE;
+    };
+    // Opening a missing file would create an empty database: only `migrate`
+    // and `import` may do that.
+    if !command.creates_database() && !std::path::Path::new(&database_url).exists() {
+        eprintln!("database {database_url} does not exist, run `keychain-admin migrate` first");
+        return ExitCode::FAILURE;
+    }
+
+    // With a peer, the server logs deletions for replication: so must a
+    // purge, or the peer would keep the purged records.
+    let replicate = std::env::var("REPLICATION_PEER_URL").is_ok_and(|url| !url.is_empty());
+
+    let mut connection = database::establish_connection(database_url);
+    match admin::execute(&mut connection, &command, replicate) {
+        Ok(report) => {
+            print!("{report}");
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("{error}");
+            ExitCode::FAILURE
+        }
+    }
+}
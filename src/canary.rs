@@ -0,0 +1,191 @@
+//! Signed warrant canary (optional, disabled by default). The operator signs
+//! a small JSON document offline with an Ed25519 key whose public half is
+//! pinned in the configuration:
+//!
+//! ```json
+//! {
+//!   "statement": "No warrants, gag orders or seizures as of this date.",
+//!   "issued_at": "2026-10-01T00:00:00Z",
+//!   "expires_at": "2026-11-01T00:00:00Z",
+//!   "bitcoin_block_hash": "00000000000000000001…"
+//! }
+//! ```
+//!
+//! The block hash proves the document was not signed before that block was
+//! mined; the expiry makes a canary that is simply no longer renewed a
+//! signal in itself. The server stores it as `{"document": base64,
+//! "signature": base64}` at CANARY_SIGNED_PATH and serves it verbatim in
+//! `/info`: clients verify the signature over the decoded document bytes,
+//! so no JSON canonicalization is involved.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::Deserialize;
+
+use crate::models::SignedCanary;
+use crate::utils::{is_256bits_hex_hash, sha256_hex};
+
+/// How often the watcher re-reads the document and checks its expiry, so
+/// the warning is logged even when nobody requests `/info`.
+const EXPIRY_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(600);
+
+/// The signed statement. Unknown fields are accepted: clients read them
+/// from the signed bytes, the server only needs the dates.
+#[derive(Deserialize)]
+struct CanaryDocument {
+    statement: String,
+    issued_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    bitcoin_block_hash: String,
+}
+
+/// Parses CANARY_PUBLIC_KEY: a raw Ed25519 public key, 64 hex characters.
+pub fn parse_public_key(hex_key: &str) -> Result<VerifyingKey, String> {
+    let bytes: [u8; 32] = hex::decode(hex_key)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or("CANARY_PUBLIC_KEY must be a 32-byte Ed25519 public key in hex")?;
+    VerifyingKey::from_bytes(&bytes)
+        .map_err(|_| "CANARY_PUBLIC_KEY is not a valid Ed25519 public key".to_string())
+}
+
+/// A verified document, with the expiry the server watches.
+#[derive(Clone)]
+pub struct VerifiedCanary {
+    pub signed: SignedCanary,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Reads and verifies the signed document at `path`. The error names the
+/// problem without echoing the file contents.
+pub fn load_signed_canary(path: &Path, key: &VerifyingKey) -> Result<VerifiedCanary, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|error| format!("cannot read {}: {error}", path.display()))?;
+    let signed: SignedCanary = serde_json::from_str(&contents)
+        .map_err(|error| format!("{} is not a signed canary: {error}", path.display()))?;
+    let document = STANDARD
+        .decode(&signed.document)
+        .map_err(|_| "the canary document is not base64".to_string())?;
+    let signature = STANDARD
+        .decode(&signed.signature)
+        .ok()
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        .ok_or("the canary signature is not a base64 Ed25519 signature")?;
+    // Strict verification: no malleable signatures, no small-order keys.
+    key.verify_strict(&document, &signature)
+        .map_err(|_| "the canary signature does not match CANARY_PUBLIC_KEY".to_string())?;
+
+    let parsed: CanaryDocument = serde_json::from_slice(&document)
+        .map_err(|error| format!("the canary document is invalid: {error}"))?;
+    if parsed.statement.is_empty() {
+        return Err("the canary statement is empty".to_string());
+    }
+    if parsed.expires_at <= parsed.issued_at {
+        return Err("the canary expires_at must be after issued_at".to_string());
+    }
+    if !is_256bits_hex_hash(&parsed.bitcoin_block_hash) {
+        return Err("the canary bitcoin_block_hash must be 64 hex characters".to_string());
+    }
+    Ok(VerifiedCanary {
+        signed,
+        expires_at: parsed.expires_at,
+    })
+}
+
+/// The signed canary currently served, re-read when the file changes so the
+/// operator renews it without a restart.
+pub struct SignedCanarySource {
+    path: PathBuf,
+    key: VerifyingKey,
+    state: Mutex<SourceState>,
+}
+
+struct SourceState {
+    /// Metadata (modification time, length) the current document was read
+    /// under, or the last failed read: an unchanged file is not re-read.
+    file_version: Option<(std::time::SystemTime, u64)>,
+    current: VerifiedCanary,
+    expiry_logged: bool,
+}
+
+impl SignedCanarySource {
+    /// Loads the document at startup. Unlike a later re-read, a missing or
+    /// invalid document is an error: the server refuses to start rather
+    /// than serve no canary at all.
+    pub fn open(path: PathBuf, key: VerifyingKey) -> Result<Self, String> {
+        let file_version = file_version(&path);
+        let current = load_signed_canary(&path, &key)?;
+        Ok(Self {
+            path,
+            key,
+            state: Mutex::new(SourceState {
+                file_version,
+                current,
+                expiry_logged: false,
+            }),
+        })
+    }
+
+    pub fn public_key_hex(&self) -> String {
+        hex::encode(self.key.as_bytes())
+    }
+
+    /// SHA-256 of the raw public key, the value clients pin.
+    pub fn fingerprint(&self) -> String {
+        sha256_hex(self.key.as_bytes())
+    }
+
+    /// The document to serve and whether it has expired at `now`. A file
+    /// that became unreadable or fails verification is an ops error: the
+    /// last verified document stays served (its expiry keeps running, so
+    /// this never masks a canary that is not renewed).
+    pub fn current(&self, now: DateTime<Utc>) -> (SignedCanary, bool) {
+        let mut state = self.state.lock().expect("the canary lock is not poisoned");
+        let version = file_version(&self.path);
+        if version != state.file_version {
+            state.file_version = version;
+            match load_signed_canary(&self.path, &self.key) {
+                Ok(loaded) => {
+                    tracing::info!(expires_at = %loaded.expires_at, "signed canary loaded");
+                    state.current = loaded;
+                    state.expiry_logged = false;
+                }
+                Err(error) => {
+                    tracing::warn!(%error, "signed canary not updated, serving the previous one");
+                }
+            }
+        }
+
+        let expired = now >= state.current.expires_at;
+        if expired && !state.expiry_logged {
+            state.expiry_logged = true;
+            tracing::warn!(expires_at = %state.current.expires_at, "signed canary has expired");
+        }
+        (state.current.signed.clone(), expired)
+    }
+}
+
+fn file_version(path: &Path) -> Option<(std::time::SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((
+        metadata
+            .modified()
+            .unwrap_or(std::time::SystemTime::UNIX_EPOCH),
+        metadata.len(),
+    ))
+}
+
+/// Spawns the task that notices expiry without `/info` traffic.
+pub fn spawn_expiry_watch(source: Arc<SignedCanarySource>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(EXPIRY_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            source.current(Utc::now());
+        }
+    });
+}
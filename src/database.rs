@@ -1,12 +1,18 @@
 use crate::schema::secret::dsl::*;
 
 use crate::AppState;
-use crate::{models::Secret, schema::secret::*};
+use crate::{
+    models::{
+        LogLeaf, OpaqueRecord, ReplicationApplied, ReplicationEntry, ReplicationLogRow, Secret,
+    },
+    schema::secret::*,
+};
 
+use diesel::r2d2::{ConnectionManager, CustomizeConnection, Pool, PooledConnection};
 use diesel::sql_query;
 use diesel::{
-    Connection, ExpressionMethods, OptionalExtension, QueryDsl, QueryableByName, RunQueryDsl,
-    SqliteConnection,
+    BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension, QueryDsl,
+    QueryableByName, RunQueryDsl, SelectableHelper, SqliteConnection,
 };
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 
@@ -27,22 +33,27 @@ struct SecretColumn {
     default_value: Option<String>,
 }
 
-pub fn init_db(state: AppState) {
-    let mut connection = establish_connection(state.database_url);
-    run_migrations(&mut connection).expect("Failed to initialize database migrations");
+pub(crate) fn init_db(state: AppState) {
+    state
+        .secret_store
+        .migrate()
+        .expect("Failed to initialize database migrations");
+}
 
-    // enable WAL mode to allow replication with litestream
-    sql_query("PRAGMA journal_mode = WAL;")
-        .execute(&mut connection)
-        .expect("Failed to enable WAL mode");
+/// Enables WAL mode to allow replication with litestream. The mode is stored
+/// in the database file, so it persists for every later connection.
+pub fn enable_wal(connection: &mut SqliteConnection) -> Result<(), diesel::result::Error> {
+    sql_query("PRAGMA journal_mode = WAL;").execute(connection)?;
+    Ok(())
 }
 
 /// Runs embedded migrations, adopting an exact pre-Diesel `secret` table when
 /// necessary. Adoption creates only Diesel's ledger and never creates or
 /// changes `secret`; it can be removed once every database has been adopted.
+/// Returns the number of migrations applied (an adoption is not counted).
 pub fn run_migrations(
     connection: &mut SqliteConnection,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
     let secret_exists = table_exists(connection, "secret")?;
     let ledger_exists = table_exists(connection, "__diesel_schema_migrations")?;
 
@@ -87,7 +98,66 @@ pub fn run_migrations(
         })?;
     }
 
-    connection.run_pending_migrations(MIGRATIONS)?;
+    Ok(connection.run_pending_migrations(MIGRATIONS)?.len())
+}
+
+/// Latest applied migration version, or `None` for a database the server
+/// has never migrated (reading the ledger through Diesel would create it and
+/// break the legacy adoption above).
+pub fn schema_version(
+    connection: &mut SqliteConnection,
+) -> Result<Option<String>, diesel::result::Error> {
+    #[derive(QueryableByName)]
+    struct Version {
+        #[diesel(sql_type = diesel::sql_types::Nullable<diesel::sql_types::Text>)]
+        version: Option<String>,
+    }
+
+    if !table_exists(connection, "__diesel_schema_migrations")? {
+        return Ok(None);
+    }
+    Ok(
+        sql_query("SELECT MAX(version) AS version FROM __diesel_schema_migrations")
+            .get_result::<Version>(connection)?
+            .version,
+    )
+}
+
+/// Version of the newest embedded migration.
+pub fn latest_migration_version() -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(
+        diesel::migration::MigrationSource::<diesel::sqlite::Sqlite>::migrations(&MIGRATIONS)?
+            .iter()
+            .map(|migration| migration.name().version().to_string())
+            .max()
+            .unwrap_or_default(),
+    )
+}
+
+/// Readiness checks on a fresh connection: it answers a query, the database
+/// is in WAL mode, and the ledger is at the newest embedded migration.
+/// Returns the name of the first failed check.
+pub fn check_ready(connection: &mut SqliteConnection) -> Result<(), &'static str> {
+    #[derive(QueryableByName)]
+    struct JournalMode {
+        #[diesel(sql_type = diesel::sql_types::Text)]
+        journal_mode: String,
+    }
+
+    sql_query("SELECT 1")
+        .execute(connection)
+        .map_err(|_| "query")?;
+    let journal_mode = sql_query("PRAGMA journal_mode")
+        .get_result::<JournalMode>(connection)
+        .map_err(|_| "query")?;
+    if !journal_mode.journal_mode.eq_ignore_ascii_case("wal") {
+        return Err("wal");
+    }
+    let current = schema_version(connection).map_err(|_| "query")?;
+    let latest = latest_migration_version().map_err(|_| "migrations")?;
+    if current.as_deref() != Some(latest.as_str()) {
+        return Err("migrations");
+    }
     Ok(())
 }
 
@@ -115,24 +185,240 @@ fn table_exists(
 pub fn establish_connection(database_url: String) -> SqliteConnection {
     let mut connection =
         SqliteConnection::establish(&database_url).expect("Error connecting to database");
+    apply_connection_pragmas(&mut connection).expect("Failed to set busy_timeout");
+    connection
+}
+
+pub(crate) fn apply_connection_pragmas(
+    connection: &mut SqliteConnection,
+) -> Result<(), diesel::result::Error> {
     // busy_timeout is per-connection: without it, concurrent writers in WAL
     // mode fail immediately with SQLITE_BUSY instead of waiting.
-    sql_query("PRAGMA busy_timeout = 5000;")
-        .execute(&mut connection)
-        .expect("Failed to set busy_timeout");
-    connection
+    sql_query("PRAGMA busy_timeout = 5000;").execute(connection)?;
+    Ok(())
 }
 
+pub type DatabasePool = Pool<ConnectionManager<SqliteConnection>>;
+pub type PooledSqliteConnection = PooledConnection<ConnectionManager<SqliteConnection>>;
+
+/// Applies the per-connection pragmas once, when the pool opens a connection.
+#[derive(Debug)]
+struct ConnectionPragmas;
+
+impl CustomizeConnection<SqliteConnection, diesel::r2d2::Error> for ConnectionPragmas {
+    fn on_acquire(&self, connection: &mut SqliteConnection) -> Result<(), diesel::r2d2::Error> {
+        apply_connection_pragmas(connection).map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+/// A permit holder never waits for a free connection, only for a new one to
+/// open: past this delay the database is unreachable, not busy.
+const POOL_CONNECTION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Pool of long-lived connections, `max_size` being DATABASE_MAX_CONCURRENCY.
+/// Connections are opened on first use and then kept; each checkout runs a
+/// `SELECT 1` health check, and a broken connection is replaced. The pool
+/// never admits work on its own: `database_semaphore` has as many permits as
+/// the pool has connections, so a permit holder never waits for one.
+pub fn new_pool(database_url: &str, max_size: u32) -> DatabasePool {
+    Pool::builder()
+        .max_size(max_size)
+        .min_idle(Some(0))
+        .idle_timeout(None)
+        .connection_timeout(POOL_CONNECTION_TIMEOUT)
+        .test_on_check_out(true)
+        .connection_customizer(Box::new(ConnectionPragmas))
+        .build_unchecked(ConnectionManager::new(database_url))
+}
+
+/// Checks a connection out of `pool`. A pool failure is reported as a
+/// database error: callers treat both alike (`500`, attempt refunded).
+pub fn pooled_connection(
+    pool: &DatabasePool,
+) -> Result<PooledSqliteConnection, diesel::result::Error> {
+    pool.get().map_err(|error| {
+        diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::ClosedConnection,
+            Box::new(error.to_string()),
+        )
+    })
+}
+
+/// Inserts a record as is: the import of `keychain-admin`. An inserted
+/// record is a store in the transparency log, with its original
+/// `created_at`, so that the log of the new server covers what it holds.
 pub fn write(connection: &mut SqliteConnection, new_secret: &Secret) -> bool {
+    insert_logged(connection, new_secret, false)
+        .and_then(|inserted| match inserted {
+            1 => append_log_leaf(connection, &store_leaf(new_secret)),
+            _ => Ok(()),
+        })
+        .is_ok()
+}
+
+/// `/store`: inserts `new_secret` unless its id already exists, and logs
+/// the store in the transparency log either way, so that the receipt does
+/// not tell a duplicate apart. With `replicate`, an inserted record is also
+/// appended to the replication log.
+pub fn insert_secret(
+    connection: &mut SqliteConnection,
+    new_secret: &Secret,
+    replicate: bool,
+) -> Result<(), diesel::result::Error> {
+    connection.immediate_transaction(|connection| {
+        insert_logged(connection, new_secret, replicate)?;
+        append_log_leaf(connection, &store_leaf(new_secret))
+    })
+}
+
+fn store_leaf(record: &Secret) -> crate::transparency::Hash {
+    crate::transparency::store_leaf(&record.id, &record.encrypted_secret, &record.created_at)
+}
+
+fn trash_leaf(record: &Secret) -> crate::transparency::Hash {
+    crate::transparency::trash_leaf(&record.id, &record.encrypted_secret, &record.created_at)
+}
+
+/// Appends a leaf to the transparency log, at the next index. Runs in the
+/// transaction of the change it records: SQLite's write lock keeps the
+/// indexes gap-free and in commit order.
+fn append_log_leaf(
+    connection: &mut SqliteConnection,
+    leaf: &crate::transparency::Hash,
+) -> Result<(), diesel::result::Error> {
+    sql_query(
+        "INSERT INTO log_leaf (leaf_index, leaf_hash) \
+         SELECT COALESCE(MAX(leaf_index) + 1, 0), ? FROM log_leaf",
+    )
+    .bind::<diesel::sql_types::Text, _>(hex::encode(leaf))
+    .execute(connection)?;
+    Ok(())
+}
+
+/// Transparency log leaves from `from_index` on, up to `limit`.
+pub fn read_log_leaves(
+    connection: &mut SqliteConnection,
+    from_index: i64,
+    limit: i64,
+) -> Result<Vec<LogLeaf>, diesel::result::Error> {
+    use crate::schema::log_leaf;
+    log_leaf::table
+        .filter(log_leaf::leaf_index.ge(from_index))
+        .order(log_leaf::leaf_index.asc())
+        .limit(limit)
+        .select(LogLeaf::as_select())
+        .load(connection)
+}
+
+/// Returns 1 if the record was inserted, 0 if its id already existed.
+fn insert_logged(
+    connection: &mut SqliteConnection,
+    new_secret: &Secret,
+    replicate: bool,
+) -> Result<usize, diesel::result::Error> {
     // ON CONFLICT DO NOTHING: storing is idempotent. The response must not
     // reveal whether the secret_id already exists, otherwise /store becomes
     // an unthrottled authentication_key oracle (a 403 would confirm a
     // correct guess without ever touching the fetch rate-limit).
-    diesel::insert_into(crate::schema::secret::table)
+    let inserted = diesel::insert_into(crate::schema::secret::table)
         .values(new_secret)
         .on_conflict_do_nothing()
-        .execute(connection)
-        .is_ok()
+        .execute(connection)?;
+    if inserted == 1 && replicate {
+        use crate::schema::replication_log;
+        let record = serde_json::to_string(new_secret)
+            .map_err(|error| diesel::result::Error::SerializationError(Box::new(error)))?;
+        diesel::insert_into(replication_log::table)
+            .values((
+                replication_log::secret_id.eq(&new_secret.id),
+                replication_log::record.eq(record),
+            ))
+            .execute(connection)?;
+    }
+    Ok(inserted)
+}
+
+/// Records the deletion of `secret_id` for the peer: a tombstone in the
+/// replication log, and a local one so that a write of the same record
+/// still on its way from the peer does not bring it back.
+fn log_deletion(
+    connection: &mut SqliteConnection,
+    secret_id: &str,
+    replicate: bool,
+) -> Result<(), diesel::result::Error> {
+    if !replicate {
+        return Ok(());
+    }
+    use crate::schema::replication_log;
+    let deleted_at = replication_timestamp(chrono::Utc::now());
+    diesel::insert_into(replication_log::table)
+        .values((
+            replication_log::secret_id.eq(secret_id),
+            replication_log::deleted_at.eq(&deleted_at),
+        ))
+        .execute(connection)?;
+    keep_tombstone(connection, secret_id, &deleted_at)
+}
+
+/// Keeps the later deletion time when `secret_id` already has a tombstone.
+fn keep_tombstone(
+    connection: &mut SqliteConnection,
+    secret_id: &str,
+    deleted_at: &str,
+) -> Result<(), diesel::result::Error> {
+    sql_query(
+        "INSERT INTO replication_tombstone (secret_id, deleted_at) VALUES (?, ?) \
+         ON CONFLICT (secret_id) DO UPDATE SET deleted_at = max(deleted_at, excluded.deleted_at)",
+    )
+    .bind::<diesel::sql_types::Text, _>(secret_id)
+    .bind::<diesel::sql_types::Text, _>(deleted_at)
+    .execute(connection)?;
+    Ok(())
+}
+
+/// Inserts an OPAQUE registration unless its credential is already
+/// registered, like `insert_secret`: a repeated registration neither
+/// overwrites nor signals the existing one.
+pub fn insert_opaque_record(
+    connection: &mut SqliteConnection,
+    record: &OpaqueRecord,
+) -> Result<bool, diesel::result::Error> {
+    let inserted = diesel::insert_into(crate::schema::opaque_record::table)
+        .values(record)
+        .on_conflict_do_nothing()
+        .execute(connection)?;
+    Ok(inserted == 1)
+}
+
+pub fn read_opaque_record(
+    connection: &mut SqliteConnection,
+    credential: &str,
+) -> Result<Option<OpaqueRecord>, diesel::result::Error> {
+    use crate::schema::opaque_record::dsl::{credential_id, opaque_record};
+    opaque_record
+        .filter(credential_id.eq(credential))
+        .first::<OpaqueRecord>(connection)
+        .optional()
+}
+
+/// Fixed-width UTC timestamp (`2026-08-05T12:00:00Z`) for `trash_after`: its
+/// text order is its time order, so SQLite compares it as a plain string.
+pub fn database_timestamp(value: chrono::DateTime<chrono::Utc>) -> String {
+    value.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+/// Fixed-width UTC timestamp with nanoseconds, for the tombstones: a record
+/// re-stored in the second of its deletion must still be told apart.
+pub fn replication_timestamp(value: chrono::DateTime<chrono::Utc>) -> String {
+    value.to_rfc3339_opts(chrono::SecondsFormat::Nanos, true)
+}
+
+/// Parses a `created_at` or `deleted_at`. A value that does not parse (a
+/// legacy record's placeholder) counts as older than any deletion.
+fn replication_time(value: &str) -> chrono::DateTime<chrono::Utc> {
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|value| value.with_timezone(&chrono::Utc))
+        .unwrap_or(chrono::DateTime::<chrono::Utc>::MIN_UTC)
 }
 
 pub fn read_secret_by_id(
@@ -142,15 +428,157 @@ pub fn read_secret_by_id(
     // Err (SQLITE_BUSY, I/O error, ...) must stay distinguishable from
     // Ok(None): a database failure is not a wrong credential and must never
     // consume a rate-limit attempt.
+    // A record past its `trash_after` or `expires_at` is deleted, whether or
+    // not the purge task has run yet.
+    let now = database_timestamp(chrono::Utc::now());
     secret
         .filter(id.eq(secret_id))
+        .filter(trash_after.is_null().or(trash_after.gt(&now)))
+        .filter(expires_at.is_null().or(expires_at.gt(&now)))
         .first::<Secret>(connection)
         .optional()
 }
 
+/// Delayed `/trash`: marks the record for deletion at `deletion_at` instead
+/// of deleting it. A record already marked keeps its earlier deadline, so a
+/// repeated `/trash` cannot postpone the purge.
+pub fn read_and_mark_secret_by_id(
+    connection: &mut SqliteConnection,
+    secret_id: &str,
+    deletion_at: &str,
+) -> Result<Option<Secret>, diesel::result::Error> {
+    connection.immediate_transaction(|connection| {
+        let Some(mut stored_secret) = read_secret_by_id(connection, secret_id)? else {
+            return Ok(None);
+        };
+        if stored_secret.trash_after.is_some() {
+            return Ok(Some(stored_secret));
+        }
+
+        let updated = diesel::update(secret.filter(id.eq(secret_id)))
+            .set(trash_after.eq(deletion_at))
+            .execute(connection)?;
+        if updated != 1 {
+            return Err(diesel::result::Error::NotFound);
+        }
+
+        stored_secret.trash_after = Some(deletion_at.to_owned());
+        Ok(Some(stored_secret))
+    })
+}
+
+/// Cancels a pending deletion. A live, unmarked record is left as is.
+pub fn restore_secret_by_id(
+    connection: &mut SqliteConnection,
+    secret_id: &str,
+) -> Result<Option<Secret>, diesel::result::Error> {
+    connection.immediate_transaction(|connection| {
+        let Some(mut stored_secret) = read_secret_by_id(connection, secret_id)? else {
+            return Ok(None);
+        };
+        if stored_secret.trash_after.is_some() {
+            diesel::update(secret.filter(id.eq(secret_id)))
+                .set(trash_after.eq(None::<String>))
+                .execute(connection)?;
+            stored_secret.trash_after = None;
+        }
+        Ok(Some(stored_secret))
+    })
+}
+
+/// Deletes up to `batch_size` records whose grace period has elapsed.
+/// Returns the count; batches keep each write lock short. Each deletion is
+/// a trash in the transparency log and, with `replicate`, a tombstone: this
+/// is where a delayed `/trash` reaches the peer.
+pub fn purge_trashed_secrets(
+    connection: &mut SqliteConnection,
+    now: chrono::DateTime<chrono::Utc>,
+    batch_size: i64,
+    replicate: bool,
+) -> Result<usize, diesel::result::Error> {
+    connection.immediate_transaction(|connection| {
+        let due: Vec<Secret> = secret
+            .filter(trash_after.le(database_timestamp(now)))
+            .limit(batch_size)
+            .load(connection)?;
+        delete_logged(connection, &due, replicate)
+    })
+}
+
+/// Deletes up to `batch_size` records whose retention has ended. Records
+/// stored without `ttl_days` (every legacy record) are never selected.
+/// Expiry is logged like a trash, but not replicated: each peer expires its
+/// own copy.
+pub fn purge_expired_secrets(
+    connection: &mut SqliteConnection,
+    now: chrono::DateTime<chrono::Utc>,
+    batch_size: i64,
+) -> Result<usize, diesel::result::Error> {
+    connection.immediate_transaction(|connection| {
+        let due: Vec<Secret> = secret
+            .filter(expires_at.le(database_timestamp(now)))
+            .limit(batch_size)
+            .load(connection)?;
+        delete_logged(connection, &due, false)
+    })
+}
+
+fn delete_logged(
+    connection: &mut SqliteConnection,
+    records: &[Secret],
+    replicate: bool,
+) -> Result<usize, diesel::result::Error> {
+    for record in records {
+        diesel::delete(secret.filter(id.eq(&record.id))).execute(connection)?;
+        log_deletion(connection, &record.id, replicate)?;
+        append_log_leaf(connection, &trash_leaf(record))?;
+    }
+    Ok(records.len())
+}
+
+/// Deletes up to `batch_size` records created before `cutoff`, whatever
+/// their `trash_after` or `expires_at`: the operator-run retention of
+/// `keychain-admin purge`. Only whole seconds are compared, so a record
+/// created during the cutoff second is kept. Logged like a trash, and
+/// replicated: the peer purges the same records.
+pub fn purge_secrets_created_before(
+    connection: &mut SqliteConnection,
+    cutoff: chrono::DateTime<chrono::Utc>,
+    batch_size: i64,
+    replicate: bool,
+) -> Result<usize, diesel::result::Error> {
+    connection.immediate_transaction(|connection| {
+        let due: Vec<Secret> = secret
+            .filter(
+                diesel::dsl::sql::<diesel::sql_types::Bool>("substr(created_at, 1, 19) < substr(")
+                    .bind::<diesel::sql_types::Text, _>(database_timestamp(cutoff))
+                    .sql(", 1, 19)"),
+            )
+            .limit(batch_size)
+            .load(connection)?;
+        delete_logged(connection, &due, replicate)
+    })
+}
+
+/// `purge_secrets_created_before` for the OPAQUE registrations.
+pub fn purge_opaque_records_created_before(
+    connection: &mut SqliteConnection,
+    cutoff: chrono::DateTime<chrono::Utc>,
+    batch_size: i64,
+) -> Result<usize, diesel::result::Error> {
+    sql_query(
+        "DELETE FROM opaque_record WHERE credential_id IN (SELECT credential_id \
+         FROM opaque_record WHERE substr(created_at, 1, 19) < substr(?, 1, 19) LIMIT ?)",
+    )
+    .bind::<diesel::sql_types::Text, _>(database_timestamp(cutoff))
+    .bind::<diesel::sql_types::BigInt, _>(batch_size)
+    .execute(connection)
+}
+
 pub fn read_and_trash_secret_by_id(
     connection: &mut SqliteConnection,
     secret_id: &str,
+    replicate: bool,
 ) -> Result<Option<Secret>, diesel::result::Error> {
     connection.immediate_transaction(|connection| {
         let stored_secret = read_secret_by_id(connection, secret_id)?;
@@ -162,7 +590,165 @@ pub fn read_and_trash_secret_by_id(
         if deleted != 1 {
             return Err(diesel::result::Error::NotFound);
         }
+        log_deletion(connection, secret_id, replicate)?;
+        append_log_leaf(connection, &trash_leaf(&stored_secret))?;
+
+        Ok(Some(stored_secret))
+    })
+}
+
+/// Replaces the record `old_secret_id` with `new_secret` in one transaction:
+/// a crash leaves either the old record or the new one, never both or
+/// neither. The insert keeps the `/store` semantics (`ON CONFLICT DO
+/// NOTHING`): an existing `new_secret.id` is neither overwritten nor
+/// signalled, and the old record is removed either way — or, with a
+/// `deletion_at`, marked for delayed deletion like a `/trash`.
+pub fn rotate_secret_by_id(
+    connection: &mut SqliteConnection,
+    old_secret_id: &str,
+    new_secret: &Secret,
+    deletion_at: Option<&str>,
+    replicate: bool,
+) -> Result<Option<Secret>, diesel::result::Error> {
+    connection.immediate_transaction(|connection| {
+        let Some(stored_secret) = read_secret_by_id(connection, old_secret_id)? else {
+            return Ok(None);
+        };
+
+        insert_logged(connection, new_secret, replicate)?;
+        append_log_leaf(connection, &store_leaf(new_secret))?;
+        let changed = match deletion_at {
+            // An earlier deadline is kept, as for a repeated `/trash`.
+            Some(deletion_at) if stored_secret.trash_after.is_none() => {
+                diesel::update(secret.filter(id.eq(old_secret_id)))
+                    .set(trash_after.eq(deletion_at))
+                    .execute(connection)?
+            }
+            Some(_) => 1,
+            None => {
+                let deleted =
+                    diesel::delete(secret.filter(id.eq(old_secret_id))).execute(connection)?;
+                log_deletion(connection, old_secret_id, replicate)?;
+                append_log_leaf(connection, &trash_leaf(&stored_secret))?;
+                deleted
+            }
+        };
+        if changed != 1 {
+            return Err(diesel::result::Error::NotFound);
+        }
 
         Ok(Some(stored_secret))
     })
 }
+
+/// Oldest entries of the replication log, up to `limit`.
+pub fn read_replication_log(
+    connection: &mut SqliteConnection,
+    limit: i64,
+) -> Result<Vec<ReplicationLogRow>, diesel::result::Error> {
+    use crate::schema::replication_log;
+    replication_log::table
+        .order(replication_log::seq.asc())
+        .limit(limit)
+        .select(ReplicationLogRow::as_select())
+        .load(connection)
+}
+
+/// Drops the entries the peer has applied, up to `last_seq` included.
+pub fn acknowledge_replication_log(
+    connection: &mut SqliteConnection,
+    last_seq: i32,
+) -> Result<usize, diesel::result::Error> {
+    use crate::schema::replication_log;
+    diesel::delete(replication_log::table.filter(replication_log::seq.le(last_seq)))
+        .execute(connection)
+}
+
+/// Applies a batch received from the peer, in one transaction and without
+/// logging it again. A write keeps the `/store` semantics and is skipped if
+/// the record was deleted at or after its `created_at`; a tombstone deletes
+/// the record if it was created at or before `deleted_at`, so that a record
+/// stored again after its deletion survives. Both orders converge.
+///
+/// `None`, applying nothing, when the batch was not sent after the last
+/// one applied: a replay, which could otherwise bring back a record whose
+/// tombstone was pruned since.
+pub fn apply_replication_entries(
+    connection: &mut SqliteConnection,
+    sent_at: &str,
+    entries: &[ReplicationEntry],
+) -> Result<Option<ReplicationApplied>, diesel::result::Error> {
+    use crate::schema::{replication_received, replication_tombstone};
+    connection.immediate_transaction(|connection| {
+        let sent_at = replication_time(sent_at);
+        let received = replication_received::table
+            .select(replication_received::sent_at)
+            .first::<String>(connection)
+            .optional()?;
+        if received.is_some_and(|received| replication_time(&received) >= sent_at) {
+            return Ok(None);
+        }
+        sql_query(
+            "INSERT INTO replication_received (peer, sent_at) VALUES (0, ?) \
+             ON CONFLICT (peer) DO UPDATE SET sent_at = excluded.sent_at",
+        )
+        .bind::<diesel::sql_types::Text, _>(replication_timestamp(sent_at))
+        .execute(connection)?;
+
+        let mut applied = ReplicationApplied {
+            written: 0,
+            deleted: 0,
+        };
+        for entry in entries {
+            match entry {
+                ReplicationEntry::Write(record) => {
+                    let tombstone = replication_tombstone::table
+                        .find(&record.id)
+                        .select(replication_tombstone::deleted_at)
+                        .first::<String>(connection)
+                        .optional()?;
+                    let deleted_since = tombstone.is_some_and(|deleted_at| {
+                        replication_time(&deleted_at) >= replication_time(&record.created_at)
+                    });
+                    if !deleted_since && insert_logged(connection, record, false)? == 1 {
+                        append_log_leaf(connection, &store_leaf(record))?;
+                        applied.written += 1;
+                    }
+                }
+                ReplicationEntry::Tombstone {
+                    id: secret_id,
+                    deleted_at,
+                } => {
+                    let deleted_at = replication_time(deleted_at);
+                    keep_tombstone(connection, secret_id, &replication_timestamp(deleted_at))?;
+                    let stored = secret
+                        .find(secret_id)
+                        .first::<Secret>(connection)
+                        .optional()?;
+                    if let Some(stored) =
+                        stored.filter(|stored| replication_time(&stored.created_at) <= deleted_at)
+                    {
+                        diesel::delete(secret.filter(id.eq(secret_id))).execute(connection)?;
+                        append_log_leaf(connection, &trash_leaf(&stored))?;
+                        applied.deleted += 1;
+                    }
+                }
+            }
+        }
+        Ok(Some(applied))
+    })
+}
+
+/// Forgets the tombstones of deletions before `cutoff`. A write older than
+/// that can no longer be in flight.
+pub fn prune_replication_tombstones(
+    connection: &mut SqliteConnection,
+    cutoff: chrono::DateTime<chrono::Utc>,
+) -> Result<usize, diesel::result::Error> {
+    use crate::schema::replication_tombstone;
+    diesel::delete(
+        replication_tombstone::table
+            .filter(replication_tombstone::deleted_at.lt(replication_timestamp(cutoff))),
+    )
+    .execute(connection)
+}
@@ -1,67 +1,106 @@
 use chrono::Duration;
-use dotenvy::dotenv;
-use std::{collections::HashMap, env, fmt::Display, str::FromStr, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize},
+        Arc,
+    },
+};
 use tokio::sync::{Mutex, Semaphore};
 
-use crate::AppState;
-
-fn optional_env<T>(name: &str, default: T) -> T
-where
-    T: FromStr,
-    T::Err: Display,
-{
-    match env::var(name) {
-        Ok(value) => value.parse().unwrap_or_else(|error| {
-            eprintln!("Error: {name} has an invalid value: {error}");
-            std::process::exit(1);
-        }),
-        Err(env::VarError::NotPresent) => default,
-        Err(error) => {
-            eprintln!("Error: cannot read {name}: {error}");
-            std::process::exit(1);
-        }
-    }
-}
+use crate::{config::ConfigSources, AppState};
 
 /// Builds a database path unique to this call, under the OS temp directory.
 /// Every test calls `env::init()`, so each test gets its own SQLite file:
 /// without this, all tests shared a single file and ran into each other's
 /// data under `cargo test`'s parallel execution.
 #[cfg(test)]
-pub(crate) struct TestDatabaseGuard {
-    path: std::path::PathBuf,
+pub(crate) enum TestDatabaseGuard {
+    Sqlite(std::path::PathBuf),
+    #[cfg(feature = "postgres")]
+    Postgres {
+        server_url: String,
+        database: String,
+    },
 }
 
 #[cfg(test)]
 impl Drop for TestDatabaseGuard {
     fn drop(&mut self) {
-        for suffix in ["", "-wal", "-shm"] {
-            let path = if suffix.is_empty() {
-                self.path.clone()
-            } else {
-                std::path::PathBuf::from(format!("{}{}", self.path.display(), suffix))
-            };
-            let _ = std::fs::remove_file(path);
+        match self {
+            TestDatabaseGuard::Sqlite(path) => {
+                for suffix in ["", "-wal", "-shm"] {
+                    let path = if suffix.is_empty() {
+                        path.clone()
+                    } else {
+                        std::path::PathBuf::from(format!("{}{}", path.display(), suffix))
+                    };
+                    let _ = std::fs::remove_file(path);
+                }
+            }
+            #[cfg(feature = "postgres")]
+            TestDatabaseGuard::Postgres {
+                server_url,
+                database,
+            } => {
+                use diesel::{Connection, RunQueryDsl};
+                // FORCE: the pool of the dropped state may still hold
+                // connections to it.
+                if let Ok(mut connection) = diesel::PgConnection::establish(server_url) {
+                    let _ = diesel::sql_query(format!(
+                        "DROP DATABASE IF EXISTS {database} WITH (FORCE)"
+                    ))
+                    .execute(&mut connection);
+                }
+            }
         }
     }
 }
 
 #[cfg(test)]
-pub(crate) fn unique_test_database() -> (String, Arc<TestDatabaseGuard>) {
+pub(crate) fn unique_test_suffix() -> String {
     static COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
     let count = COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
     let nanos = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap_or_default()
         .as_nanos();
+    format!("{}_{}_{}", std::process::id(), count, nanos)
+}
+
+#[cfg(test)]
+pub(crate) fn unique_test_database() -> (String, Arc<TestDatabaseGuard>) {
     let path = std::env::temp_dir().join(format!(
-        "recoverbull-test-{}-{}-{}.sqlite3",
-        std::process::id(),
-        count,
-        nanos
+        "recoverbull-test-{}.sqlite3",
+        unique_test_suffix().replace('_', "-")
     ));
     let url = path.to_string_lossy().into_owned();
-    (url, Arc::new(TestDatabaseGuard { path }))
+    (url, Arc::new(TestDatabaseGuard::Sqlite(path)))
+}
+
+/// The PostgreSQL counterpart of `unique_test_database`: creates a fresh
+/// database on the server of `server_url` (TEST_POSTGRES_URL) and returns
+/// its URL. The guard drops it.
+#[cfg(all(test, feature = "postgres"))]
+pub(crate) fn unique_test_postgres_database(server_url: &str) -> (String, Arc<TestDatabaseGuard>) {
+    use diesel::{Connection, RunQueryDsl};
+
+    let database = format!("keychain_test_{}", unique_test_suffix());
+    let mut connection =
+        diesel::PgConnection::establish(server_url).expect("TEST_POSTGRES_URL is unreachable");
+    diesel::sql_query(format!("CREATE DATABASE {database}"))
+        .execute(&mut connection)
+        .expect("failed to create the test database");
+    let (server, _) = server_url
+        .rsplit_once('/')
+        .expect("TEST_POSTGRES_URL must name a database");
+    (
+        format!("{server}/{database}"),
+        Arc::new(TestDatabaseGuard::Postgres {
+            server_url: server_url.to_string(),
+            database,
+        }),
+    )
 }
 
 /// Validates the security-critical configuration values.
@@ -152,6 +191,361 @@ pub fn validate_token_bucket(name: &str, burst: f64, refill: f64) -> Result<(),
     Ok(())
 }
 
+/// Validates the escalating-backoff base delay against the cooldown. Zero
+/// disables backoff (hard cap until the window resets). A base at or beyond
+/// the cooldown could never unlock a candidate before the reset: it would
+/// look enabled while behaving exactly like the hard cap, so it is refused.
+pub fn validate_backoff(base_seconds: u64, rate_limit_cooldown: i64) -> Result<(), String> {
+    if base_seconds > 0 && base_seconds as i128 >= i128::from(rate_limit_cooldown) * 60 {
+        return Err(format!(
+            "RATE_LIMIT_BACKOFF_BASE_SECONDS must be 0 (disabled) or below the cooldown ({} seconds), got {}",
+            rate_limit_cooldown.saturating_mul(60),
+            base_seconds
+        ));
+    }
+    Ok(())
+}
+
+/// Upper bound of the base proof-of-work difficulty: 32 bits is already
+/// billions of hashes per guess, beyond what a phone can solve.
+pub const MAX_POW_BASE_DIFFICULTY_BITS: u32 = 32;
+
+/// Validates the proof-of-work knobs. A base above the bound would lock
+/// every client out; an escalation step above 8 bits makes the second guess
+/// 256 times the first, indistinguishable from a hard cap; a zero or
+/// multi-hour challenge lifetime is either unusable or lets solutions be
+/// stockpiled long in advance.
+pub fn validate_pow(
+    difficulty_bits: u32,
+    difficulty_step_bits: u32,
+    challenge_lifetime_seconds: u64,
+) -> Result<(), String> {
+    if difficulty_bits > MAX_POW_BASE_DIFFICULTY_BITS {
+        return Err(format!(
+            "POW_DIFFICULTY_BITS must be at most {MAX_POW_BASE_DIFFICULTY_BITS}, got {difficulty_bits}"
+        ));
+    }
+    if difficulty_step_bits > 8 {
+        return Err(format!(
+            "POW_DIFFICULTY_STEP_BITS must be at most 8, got {difficulty_step_bits}"
+        ));
+    }
+    if challenge_lifetime_seconds == 0 || challenge_lifetime_seconds > 3600 {
+        return Err(format!(
+            "POW_CHALLENGE_LIFETIME_SECONDS must be between 1 and 3600, got {challenge_lifetime_seconds}"
+        ));
+    }
+    Ok(())
+}
+
+/// Validates the rate-limit state file location. It must be a separate file:
+/// the secret database is what a backup or an export copies around, and the
+/// rate-limit state has no business travelling with it.
+pub fn validate_rate_limit_state_url(state_url: &str, database_url: &str) -> Result<(), String> {
+    if state_url.trim().is_empty() {
+        return Err("RATE_LIMIT_STATE_URL must not be empty when set".to_string());
+    }
+    if state_url == database_url {
+        return Err("RATE_LIMIT_STATE_URL must differ from DATABASE_URL".to_string());
+    }
+    Ok(())
+}
+
+/// Upper bound of the trash grace period: 30 days. Longer, and a deleted
+/// backup lingers well past the point where its owner meant it gone.
+pub const MAX_TRASH_GRACE_PERIOD_HOURS: u64 = 720;
+
+/// Validates the delay between `/trash` and the actual deletion. Zero keeps
+/// the immediate deletion.
+pub fn validate_trash_grace_period(hours: u64) -> Result<(), String> {
+    if hours > MAX_TRASH_GRACE_PERIOD_HOURS {
+        return Err(format!(
+            "TRASH_GRACE_PERIOD_HOURS must be at most {MAX_TRASH_GRACE_PERIOD_HOURS}, got {hours}"
+        ));
+    }
+    Ok(())
+}
+
+/// Upper bound of `SECRET_MAX_TTL_DAYS`: a century, far beyond any useful
+/// retention and well inside chrono's range.
+pub const MAX_SECRET_TTL_DAYS: u32 = 36_500;
+
+/// Validates the largest retention a client may request. Zero disables
+/// expiry: records are then kept until trashed, as before.
+pub fn validate_secret_max_ttl(days: u32) -> Result<(), String> {
+    if days > MAX_SECRET_TTL_DAYS {
+        return Err(format!(
+            "SECRET_MAX_TTL_DAYS must be at most {MAX_SECRET_TTL_DAYS}, got {days}"
+        ));
+    }
+    Ok(())
+}
+
+/// Validates the federation: distinct v3 onion hostnames of the peers
+/// (this server excluded) and a threshold of 2 to the federation size, or
+/// neither. A share index is a non-zero byte, hence at most 254 peers.
+pub fn validate_federation(peers: &[String], threshold: Option<u8>) -> Result<(), String> {
+    let Some(threshold) = threshold else {
+        if peers.is_empty() {
+            return Ok(());
+        }
+        return Err("FEDERATION_PEERS requires FEDERATION_THRESHOLD".to_string());
+    };
+    if peers.is_empty() {
+        return Err("FEDERATION_THRESHOLD requires FEDERATION_PEERS".to_string());
+    }
+    if peers.len() > 254 {
+        return Err(format!(
+            "FEDERATION_PEERS must list at most 254 peers, got {}",
+            peers.len()
+        ));
+    }
+    for (position, peer) in peers.iter().enumerate() {
+        let valid = peer.strip_suffix(".onion").is_some_and(|host| {
+            host.len() == 56 && host.bytes().all(|c| matches!(c, b'a'..=b'z' | b'2'..=b'7'))
+        });
+        if !valid {
+            return Err(format!(
+                "FEDERATION_PEERS must list v3 onion hostnames, got {peer:?}"
+            ));
+        }
+        if peers[..position].contains(peer) {
+            return Err(format!("FEDERATION_PEERS lists {peer} twice"));
+        }
+    }
+    let members = peers.len() + 1;
+    if threshold < 2 || usize::from(threshold) > members {
+        return Err(format!(
+            "FEDERATION_THRESHOLD must be between 2 and {members}, got {threshold}"
+        ));
+    }
+    Ok(())
+}
+
+/// Largest `SECRET_MAX_LENGTH` with replication: a record must fit in one
+/// batch (see `replication::MAX_BATCH_BODY`).
+pub const MAX_REPLICATED_SECRET_LENGTH: usize = 262_144;
+
+/// Validates the replication settings: a peer URL, a key path and a listen
+/// address go together. PostgreSQL is refused, having a replication of its
+/// own, and records must fit in a batch.
+pub fn validate_replication(
+    peer_url: Option<&str>,
+    key_path: Option<&str>,
+    listen_address: Option<&str>,
+    database_url: &str,
+    secret_max_length: usize,
+) -> Result<(), String> {
+    let peer_url = match (peer_url, key_path) {
+        (None, None) if listen_address.is_none() => return Ok(()),
+        (None, None) => {
+            return Err("REPLICATION_LISTEN_ADDRESS requires REPLICATION_PEER_URL".to_string())
+        }
+        (Some(_), Some("")) => return Err("REPLICATION_KEY_PATH must not be empty".to_string()),
+        (Some(peer_url), Some(_)) => peer_url,
+        (Some(_), None) => {
+            return Err("REPLICATION_PEER_URL requires REPLICATION_KEY_PATH".to_string())
+        }
+        (None, Some(_)) => {
+            return Err("REPLICATION_KEY_PATH requires REPLICATION_PEER_URL".to_string())
+        }
+    };
+    crate::replication::parse_peer_url(peer_url)?;
+    if listen_address.is_none() {
+        return Err("REPLICATION_PEER_URL requires REPLICATION_LISTEN_ADDRESS".to_string());
+    }
+    if database_url.starts_with("postgres://") || database_url.starts_with("postgresql://") {
+        return Err(
+            "REPLICATION_PEER_URL needs a SQLite DATABASE_URL; replicate PostgreSQL with its own tools"
+                .to_string(),
+        );
+    }
+    if secret_max_length > MAX_REPLICATED_SECRET_LENGTH {
+        return Err(format!(
+            "SECRET_MAX_LENGTH must be at most {MAX_REPLICATED_SECRET_LENGTH} with replication, got {secret_max_length}"
+        ));
+    }
+    Ok(())
+}
+
+/// Loads the key signing the `/attempts` snapshots: an Ed25519 seed, 64 hex
+/// characters (`openssl rand -hex 32`), in a file only the service account
+/// can read. Without a path, a fresh key is drawn for this boot: the
+/// signatures are then only as trustworthy as the `/info` that serves it.
+pub fn attempts_signing_key(path: Option<&str>) -> Result<ed25519_dalek::SigningKey, String> {
+    let seed = match path {
+        Some(path) => read_seed_file("ATTEMPTS_SIGNING_KEY_PATH", path)?,
+        None => {
+            let mut seed = [0u8; 32];
+            getrandom::getrandom(&mut seed).expect("the OS random number generator is available");
+            seed
+        }
+    };
+    Ok(ed25519_dalek::SigningKey::from_bytes(&seed))
+}
+
+/// Loads the `/oprf` evaluation key from its seed file, same format as the
+/// attempts signing key. There is no per-boot fallback: every record stored
+/// with OPRF-derived keys depends on this exact seed.
+pub fn oprf_key(path: &str) -> Result<crate::oprf::OprfKey, String> {
+    read_seed_file("OPRF_KEY_PATH", path).map(|seed| crate::oprf::OprfKey::derive(&seed))
+}
+
+/// Loads the OPAQUE server keys (OPRF seed and long-term key pair) from
+/// their seed file. No per-boot fallback either: every OPAQUE registration
+/// depends on this exact seed.
+pub fn opaque_server(path: &str) -> Result<crate::opaque::OpaqueServer, String> {
+    read_seed_file("OPAQUE_KEY_PATH", path).map(|seed| crate::opaque::OpaqueServer::derive(&seed))
+}
+
+/// Loads the replication peer and the key shared with it, same format as
+/// the other seeds. Both peers must hold the same file.
+pub fn replication(
+    peer_url: &str,
+    key_path: &str,
+) -> Result<crate::replication::Replication, String> {
+    crate::replication::Replication::new(
+        peer_url,
+        read_seed_file("REPLICATION_KEY_PATH", key_path)?,
+    )
+}
+
+/// A 32-byte seed in hex, from a file only the service account can read.
+fn read_seed_file(variable: &str, path: &str) -> Result<[u8; 32], String> {
+    let metadata = std::fs::metadata(path)
+        .map_err(|error| format!("{variable}: cannot read {path}: {error}"))?;
+    if std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()) & 0o077 != 0 {
+        return Err(format!(
+            "{variable}: {path} must not be accessible by group or others (chmod 600)"
+        ));
+    }
+    let contents = std::fs::read_to_string(path)
+        .map_err(|error| format!("{variable}: cannot read {path}: {error}"))?;
+    let mut seed = [0u8; 32];
+    hex::decode_to_slice(contents.trim(), &mut seed)
+        .map_err(|_| format!("{variable}: {path} must hold a 32-byte seed in hex"))?;
+    Ok(seed)
+}
+
+/// Validates the signed canary settings: the key and the document path go
+/// together, and the key must be a usable Ed25519 public key. The document
+/// itself is verified when the server starts.
+pub fn validate_signed_canary(
+    public_key: Option<&str>,
+    signed_path: Option<&str>,
+) -> Result<(), String> {
+    match (public_key, signed_path) {
+        (None, None) => Ok(()),
+        (Some(public_key), Some(signed_path)) => {
+            crate::canary::parse_public_key(public_key)?;
+            if signed_path.is_empty() {
+                return Err("CANARY_SIGNED_PATH must not be empty".to_string());
+            }
+            Ok(())
+        }
+        _ => Err("CANARY_PUBLIC_KEY and CANARY_SIGNED_PATH must be set together".to_string()),
+    }
+}
+
+/// Validates the operator listener address (`/metrics`, `/healthz`,
+/// `/readyz`). It must be a loopback socket address distinct from
+/// SERVER_ADDRESS: the onion service forwards to SERVER_ADDRESS, and anything
+/// else it could reach would be public.
+pub fn validate_operator_address(
+    operator_address: &str,
+    server_address: &str,
+) -> Result<(), String> {
+    let loopback = match operator_address.parse::<std::net::SocketAddr>() {
+        Ok(address) => address.ip().is_loopback(),
+        Err(_) => operator_address
+            .strip_prefix("localhost:")
+            .is_some_and(|port| port.parse::<u16>().is_ok()),
+    };
+    if !loopback {
+        return Err(format!(
+            "OPERATOR_ADDRESS must be a loopback address with a port (e.g. 127.0.0.1:9100), got {operator_address}"
+        ));
+    }
+    if operator_address == server_address {
+        return Err("OPERATOR_ADDRESS must differ from SERVER_ADDRESS".to_string());
+    }
+    Ok(())
+}
+
+/// Validates the address `/replication` is served on: a socket address
+/// (`ip:port`, where the peer can reach it) distinct from SERVER_ADDRESS and
+/// OPERATOR_ADDRESS, so that neither the onion service nor the operator
+/// listener ever serves it.
+pub fn validate_replication_listen_address(
+    listen_address: &str,
+    server_address: &str,
+    operator_address: Option<&str>,
+) -> Result<(), String> {
+    if listen_address.parse::<std::net::SocketAddr>().is_err() {
+        return Err(format!(
+            "REPLICATION_LISTEN_ADDRESS must be an ip:port socket address (e.g. 10.0.0.1:3001), got {listen_address}"
+        ));
+    }
+    if listen_address == server_address {
+        return Err("REPLICATION_LISTEN_ADDRESS must differ from SERVER_ADDRESS".to_string());
+    }
+    if operator_address == Some(listen_address) {
+        return Err("REPLICATION_LISTEN_ADDRESS must differ from OPERATOR_ADDRESS".to_string());
+    }
+    Ok(())
+}
+
+/// Validates a `unix:/path` SERVER_ADDRESS: the path must be absolute, so
+/// the socket does not depend on the working directory the server is started
+/// from. TCP addresses are left to the bind.
+pub fn validate_server_address(server_address: &str) -> Result<(), String> {
+    let Some(path) = server_address.strip_prefix("unix:") else {
+        return Ok(());
+    };
+    if !path.starts_with('/') {
+        return Err(format!(
+            "SERVER_ADDRESS unix socket path must be absolute (e.g. unix:/run/keychain/keychain.sock), got {server_address}"
+        ));
+    }
+    Ok(())
+}
+
+/// Default permissions of the Unix socket: the server's user and group (the
+/// proxy's or Tor's group) may connect, nobody else.
+pub const DEFAULT_SERVER_SOCKET_MODE: u32 = 0o660;
+
+/// Parses and validates SERVER_SOCKET_MODE, an octal permission mode.
+/// Connecting to a socket takes write permission: a world-writable socket
+/// would let any local user bypass the proxy, so it is refused.
+pub fn parse_server_socket_mode(mode: &str) -> Result<u32, String> {
+    let parsed = u32::from_str_radix(mode, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| {
+            format!("SERVER_SOCKET_MODE must be an octal mode such as 660, got {mode}")
+        })?;
+    if parsed & 0o002 != 0 {
+        return Err(format!(
+            "SERVER_SOCKET_MODE must not grant write permission to others, got {mode}"
+        ));
+    }
+    Ok(parsed)
+}
+
+/// Upper bound of the shutdown drain: a supervisor polling `/readyz` every
+/// few seconds needs far less, and systemd stops waiting after 90 seconds.
+pub const MAX_SHUTDOWN_DRAIN_SECONDS: u64 = 60;
+
+/// Validates the delay between the readiness flip and the graceful shutdown.
+pub fn validate_shutdown_drain(seconds: u64) -> Result<(), String> {
+    if seconds > MAX_SHUTDOWN_DRAIN_SECONDS {
+        return Err(format!(
+            "SHUTDOWN_DRAIN_SECONDS must be at most {MAX_SHUTDOWN_DRAIN_SECONDS}, got {seconds}"
+        ));
+    }
+    Ok(())
+}
+
 /// Validates the `/attempts` snapshot TTL: zero would force a fresh snapshot
 /// computation on every request, defeating the point of caching.
 pub fn validate_snapshot_ttl(seconds: u64) -> Result<(), String> {
@@ -252,37 +646,19 @@ pub fn canary_file_state_cached(
 }
 
 pub fn init() -> AppState {
-    // Whether CANARY comes from the process environment must be known before
+    // Whether CANARY comes from the process environment is recorded before
     // dotenv loads the file: dotenvy never overrides an existing variable.
     // An environment-provided canary is authoritative (file edits are
     // ignored); a file-provided canary follows the file at request time.
-    let canary_from_env = env::var("CANARY").is_ok();
-    // dotenv() returns the path of the file it loaded, which may be in a
-    // parent directory: keep it as the live canary source.
-    let dotenv_path = dotenv().ok();
-
-    let server_addr: String = env::var("SERVER_ADDRESS").expect("SERVER_ADDRESS must be set");
-    let rate_limit_cooldown =
-        env::var("RATE_LIMIT_COOLDOWN").expect("RATE_LIMIT_COOLDOWN must be set");
-    let secret_max_length = env::var("SECRET_MAX_LENGTH").expect("SECRET_MAX_LENGTH must be set");
-    let canary = env::var("CANARY").expect("CANARY must be set");
-    let (rate_limit_max_attempts_name, rate_limit_max_attempts) = match env::var(
-        "RATE_LIMIT_MAX_ATTEMPTS",
-    ) {
-        Ok(value) => ("RATE_LIMIT_MAX_ATTEMPTS", value),
-        Err(env::VarError::NotPresent) => match env::var("RATE_LIMIT_MAX_FAILED_ATTEMPTS") {
-            Ok(value) => {
-                #[cfg(not(test))]
-                eprintln!(
-                        "Warning: RATE_LIMIT_MAX_FAILED_ATTEMPTS is deprecated; use RATE_LIMIT_MAX_ATTEMPTS"
-                    );
-                ("RATE_LIMIT_MAX_FAILED_ATTEMPTS", value)
+    let (config, config_sources) =
+        match ConfigSources::from_startup().and_then(|sources| Ok((sources.load()?, sources))) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                println!("Error: {e}");
+                std::process::exit(1);
             }
-            Err(env::VarError::NotPresent) => panic!("RATE_LIMIT_MAX_ATTEMPTS must be set"),
-            Err(error) => panic!("cannot read RATE_LIMIT_MAX_FAILED_ATTEMPTS: {error}"),
-        },
-        Err(error) => panic!("cannot read RATE_LIMIT_MAX_ATTEMPTS: {error}"),
-    };
+        };
+    let canary_from_env = config_sources.process_variables.contains("CANARY");
 
     #[cfg(test)]
     let (database_url, test_database_guard) = {
@@ -293,119 +669,190 @@ pub fn init() -> AppState {
         unique_test_database()
     };
     #[cfg(not(test))]
-    let database_url = { env::var("DATABASE_URL").expect("DATABASE_URL must be set") };
+    let database_url = config.database.url.clone();
 
-    let rate_limit_cooldown = match rate_limit_cooldown.parse::<i64>() {
-        Ok(number) => number,
-        Err(e) => {
-            println!("Error: RATE_LIMIT_COOLDOWN must be a integer: {}", e);
-            std::process::exit(1);
+    let mut pow_key = [0u8; 32];
+    getrandom::getrandom(&mut pow_key).expect("the OS random number generator is available");
+
+    // Rate-limit state persistence (optional, disabled by default): windows
+    // survive restarts instead of being refunded. Tests opt in explicitly.
+    #[cfg(test)]
+    let rate_limit_state_url: Option<String> = None;
+    #[cfg(not(test))]
+    let rate_limit_state_url = config.rate_limit.state_url.clone();
+    let mut identifier_rate_limit = HashMap::new();
+    let mut attempts_collection_started_at = chrono::Utc::now();
+    if let Some(state_url) = &rate_limit_state_url {
+        match crate::rate_limit_state::restore(
+            state_url,
+            attempts_collection_started_at,
+            Duration::minutes(config.rate_limit.cooldown_minutes),
+            config.rate_limit.max_identifiers,
+        ) {
+            Ok(loaded) => {
+                tracing::info!(windows = loaded.windows.len(), "rate-limit state restored");
+                identifier_rate_limit = loaded.windows;
+                attempts_collection_started_at = loaded.collection_started_at;
+            }
+            // Starting with an empty map would silently refund every
+            // persisted guess: refuse to start instead.
+            Err(e) => {
+                println!("Error: cannot load RATE_LIMIT_STATE_URL: {e}");
+                std::process::exit(1);
+            }
         }
+    }
+
+    let replication = match (&config.replication.peer_url, &config.replication.key_path) {
+        (Some(peer_url), Some(key_path)) => match replication(peer_url, key_path) {
+            Ok(replication) => Some(Arc::new(replication)),
+            Err(e) => {
+                println!("Error: {e}");
+                std::process::exit(1);
+            }
+        },
+        _ => None,
     };
 
-    let secret_max_length = match secret_max_length.parse::<usize>() {
-        Ok(number) => number,
+    let secret_store = match crate::secret_store::new_secret_store(
+        &database_url,
+        config.database.max_concurrency as u32,
+        replication.is_some(),
+    ) {
+        Ok(secret_store) => secret_store,
         Err(e) => {
-            println!("Error: SECRET_MAX_LENGTH must be a usize: {}", e);
+            println!("Error: {e}");
             std::process::exit(1);
         }
     };
 
-    let rate_limit_max_attempts = match rate_limit_max_attempts.parse::<u8>() {
-        Ok(number) => number,
-        Err(e) => {
-            println!("Error: {rate_limit_max_attempts_name} must be a u8: {e}");
-            std::process::exit(1);
+    // The signed canary is verified before serving anything: a configured
+    // but broken document would otherwise go unnoticed until a client
+    // complains.
+    let signed_canary = match (&config.canary.public_key, &config.canary.signed_path) {
+        (Some(public_key), Some(signed_path)) => {
+            match crate::canary::parse_public_key(public_key)
+                .and_then(|key| crate::canary::SignedCanarySource::open(signed_path.into(), key))
+            {
+                Ok(source) => Some(Arc::new(source)),
+                Err(e) => {
+                    println!("Error: CANARY_SIGNED_PATH: {e}");
+                    std::process::exit(1);
+                }
+            }
         }
+        _ => None,
     };
 
-    if let Err(e) = validate_config(
-        rate_limit_cooldown,
-        secret_max_length,
-        rate_limit_max_attempts,
-    ) {
-        println!("Error: {}", e);
-        std::process::exit(1);
-    }
-
-    // Global write damper (optional, with defaults). Behind an onion
-    // service per-IP limiting is useless, so the bucket is global.
-    let store_rate_limit_burst: f64 = optional_env("STORE_RATE_LIMIT_BURST", 10.0);
-    let store_rate_limit_refill: f64 = optional_env("STORE_RATE_LIMIT_REFILL_PER_SECOND", 2.0);
-    if let Err(e) = validate_token_bucket("STORE", store_rate_limit_burst, store_rate_limit_refill)
-    {
-        println!("Error: {e}");
-        std::process::exit(1);
-    }
-
-    // A second global bucket bounds identifier spraying and database reads.
-    // It is deliberately independent from the per-identifier security budget.
-    let lookup_rate_limit_burst: f64 = optional_env("LOOKUP_RATE_LIMIT_BURST", 100.0);
-    let lookup_rate_limit_refill: f64 = optional_env("LOOKUP_RATE_LIMIT_REFILL_PER_SECOND", 5.0);
-    if let Err(e) =
-        validate_token_bucket("LOOKUP", lookup_rate_limit_burst, lookup_rate_limit_refill)
-    {
-        println!("Error: {e}");
-        std::process::exit(1);
-    }
-
-    let rate_limit_max_identifiers = optional_env("RATE_LIMIT_MAX_IDENTIFIERS", 100_000usize);
-    let database_max_concurrency = optional_env("DATABASE_MAX_CONCURRENCY", 16usize);
-    if let Err(e) = validate_capacity(rate_limit_max_identifiers, database_max_concurrency) {
-        println!("Error: {}", e);
-        std::process::exit(1);
-    }
-
-    // `/attempts` serves a cached snapshot; this third bucket bounds direct
-    // cache-bypass traffic without consuming lookup tokens needed for
-    // recovery. The reverse-proxy cache absorbs the normal read volume.
-    let attempts_rate_limit_burst: f64 = optional_env("ATTEMPTS_RATE_LIMIT_BURST", 20.0);
-    let attempts_rate_limit_refill: f64 =
-        optional_env("ATTEMPTS_RATE_LIMIT_REFILL_PER_SECOND", 2.0);
-    if let Err(e) = validate_token_bucket(
-        "ATTEMPTS",
-        attempts_rate_limit_burst,
-        attempts_rate_limit_refill,
-    ) {
-        println!("Error: {e}");
-        std::process::exit(1);
-    }
+    let attempts_signing_key =
+        match attempts_signing_key(config.telemetry.signing_key_path.as_deref()) {
+            Ok(key) => Arc::new(key),
+            Err(e) => {
+                println!("Error: {e}");
+                std::process::exit(1);
+            }
+        };
 
-    let attempts_snapshot_ttl_seconds = optional_env("ATTEMPTS_SNAPSHOT_TTL_SECONDS", 60u64);
-    if let Err(e) = validate_snapshot_ttl(attempts_snapshot_ttl_seconds) {
-        println!("Error: {e}");
-        std::process::exit(1);
-    }
+    let oprf_key = config
+        .oprf
+        .key_path
+        .as_deref()
+        .map(|path| match oprf_key(path) {
+            Ok(key) => Arc::new(key),
+            Err(e) => {
+                println!("Error: {e}");
+                std::process::exit(1);
+            }
+        });
 
+    let opaque_server = config
+        .opaque
+        .key_path
+        .as_deref()
+        .map(|path| match opaque_server(path) {
+            Ok(server) => Arc::new(server),
+            Err(e) => {
+                println!("Error: {e}");
+                std::process::exit(1);
+            }
+        });
+
+    let running_config = Arc::new(std::sync::Mutex::new(config.clone()));
     AppState {
-        server_address: server_addr,
+        server_address: config.server.address,
+        server_socket_mode: config.server.socket_mode,
+        secret_store,
+        #[cfg(test)]
         database_url,
         #[cfg(test)]
         _test_database_guard: test_database_guard,
-        canary,
-        canary_from_env,
-        canary_path: dotenv_path.unwrap_or_else(|| std::path::PathBuf::from(".env")),
+        canary: config.canary.value,
+        canary_from_env: canary_from_env || config.canary.from_file,
+        // The dotenv file may be in a parent directory: keep the path it
+        // was loaded from as the live canary source.
+        canary_path: config_sources
+            .dotenv_path
+            .clone()
+            .unwrap_or_else(|| std::path::PathBuf::from(".env")),
         canary_cache: Arc::new(Mutex::new(None)),
-        rate_limit_cooldown: Duration::minutes(rate_limit_cooldown as i64),
-        identifier_rate_limit: Arc::new(Mutex::new(HashMap::new())),
-        secret_max_length,
-        rate_limit_max_attempts,
+        signed_canary,
+        rate_limit_cooldown: Duration::minutes(config.rate_limit.cooldown_minutes),
+        identifier_rate_limit: Arc::new(Mutex::new(identifier_rate_limit)),
+        secret_max_length: Arc::new(AtomicUsize::new(config.server.secret_max_length)),
+        rate_limit_max_attempts: config.rate_limit.max_attempts,
+        rate_limit_backoff_base: Duration::seconds(config.rate_limit.backoff_base_seconds as i64),
+        pow_difficulty_bits: config.rate_limit.pow_difficulty_bits,
+        pow_difficulty_step_bits: config.rate_limit.pow_difficulty_step_bits,
+        pow_challenge_lifetime: Duration::seconds(
+            config.rate_limit.pow_challenge_lifetime_seconds as i64,
+        ),
+        pow_key: Arc::new(pow_key),
         store_token_bucket: Arc::new(Mutex::new(crate::rate_limit::TokenBucket::new(
-            store_rate_limit_burst,
-            store_rate_limit_refill,
+            config.buckets.store.burst,
+            config.buckets.store.refill_per_second,
         ))),
         lookup_token_bucket: Arc::new(Mutex::new(crate::rate_limit::TokenBucket::new(
-            lookup_rate_limit_burst,
-            lookup_rate_limit_refill,
+            config.buckets.lookup.burst,
+            config.buckets.lookup.refill_per_second,
         ))),
         attempts_token_bucket: Arc::new(Mutex::new(crate::rate_limit::TokenBucket::new(
-            attempts_rate_limit_burst,
-            attempts_rate_limit_refill,
+            config.buckets.attempts.burst,
+            config.buckets.attempts.refill_per_second,
         ))),
-        rate_limit_max_identifiers,
-        database_semaphore: Arc::new(Semaphore::new(database_max_concurrency)),
-        attempts_collection_started_at: chrono::Utc::now(),
+        rate_limit_max_identifiers: config.rate_limit.max_identifiers,
+        database_semaphore: Arc::new(Semaphore::new(config.database.max_concurrency)),
+        attempts_collection_started_at,
+        trash_grace_period: Duration::hours(config.database.trash_grace_period_hours as i64),
+        secret_max_ttl_days: config.database.secret_max_ttl_days,
+        rate_limit_state: rate_limit_state_url
+            .map(|url| Arc::new(crate::rate_limit_state::StateFile::new(url))),
         attempts_snapshot: Arc::new(Mutex::new(None)),
-        attempts_snapshot_ttl: std::time::Duration::from_secs(attempts_snapshot_ttl_seconds),
+        attempts_signing_key,
+        oprf_key,
+        opaque_server,
+        opaque_logins: Arc::new(Mutex::new(HashMap::new())),
+        opaque_registrations: Arc::new(Mutex::new(HashMap::new())),
+        federation: config.federation.threshold.map(|threshold| {
+            Arc::new(crate::models::Federation {
+                peers: config.federation.peers.clone(),
+                threshold,
+            })
+        }),
+        replication,
+        replication_address: config.replication.listen_address.clone(),
+        replication_token_bucket: Arc::new(Mutex::new(crate::rate_limit::TokenBucket::new(
+            crate::replication::RECEIVE_BURST,
+            crate::replication::RECEIVE_REFILL_PER_SECOND,
+        ))),
+        transparency_log: Arc::new(crate::transparency::TransparencyLog::default()),
+        attempts_snapshot_ttl_seconds: Arc::new(AtomicU64::new(
+            config.telemetry.snapshot_ttl_seconds,
+        )),
+        metrics: Arc::new(crate::metrics::Metrics::default()),
+        config: running_config,
+        config_sources: Arc::new(config_sources),
+        operator_address: config.server.operator_address,
+        shutting_down: Arc::new(std::sync::atomic::AtomicBool::new(false)),
+        shutdown_drain: std::time::Duration::from_secs(config.server.shutdown_drain_seconds),
     }
 }
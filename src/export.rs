@@ -0,0 +1,228 @@
+//! Portable dump of the `secret` and `opaque_record` tables, for moving a
+//! server to new hardware without copying the SQLite file and its WAL by
+//! hand.
+//!
+//! Layout (integers big-endian):
+//!
+//! ```text
+//! magic           16 bytes  "keychain export\n"
+//! format_version  u16       FORMAT_VERSION
+//! schema_version  u16 length + UTF-8, latest migration of the source database
+//! record_count    u64
+//! checksum        32 bytes  SHA-256 of the record section
+//! records         record_count × (u32 length + JSON `Secret` or `OpaqueRecord`)
+//! ```
+//!
+//! Every `Secret` comes first, then every `OpaqueRecord`; their fields tell
+//! them apart. The header is written before the records, so the export reads
+//! the tables twice inside one read transaction: both passes see the same
+//! snapshot. The
+//! whole file can be wrapped in age encryption to an operator X25519 key.
+
+use std::io::{Read, Write};
+
+use diesel::{connection::DefaultLoadingMode, Connection, QueryDsl, RunQueryDsl, SqliteConnection};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::database::{insert_opaque_record, latest_migration_version, schema_version, write};
+use crate::models::{OpaqueRecord, Secret};
+use crate::schema::opaque_record::dsl::{credential_id, opaque_record};
+use crate::schema::secret::dsl::*;
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+const MAGIC: &[u8; 16] = b"keychain export\n";
+const FORMAT_VERSION: u16 = 1;
+/// First bytes of every age-encrypted file.
+const AGE_MAGIC: &[u8] = b"age-encryption.o";
+/// A stored record is a few hundred bytes: anything larger is a corrupted
+/// or hostile file, refused before allocating.
+const MAX_RECORD_LENGTH: u32 = 64 * 1024;
+
+#[derive(Debug, PartialEq)]
+pub struct ImportSummary {
+    /// Records read from the export.
+    pub records: usize,
+    /// Records that were not in the database yet.
+    pub inserted: usize,
+}
+
+/// One exported row. Untagged: a `Secret` serializes exactly as in exports
+/// made before OPAQUE registrations existed.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Record {
+    Secret(Secret),
+    Opaque(OpaqueRecord),
+}
+
+fn encode_record(record: &Record) -> Result<Vec<u8>, Error> {
+    let json = serde_json::to_vec(record)?;
+    let mut encoded = Vec::with_capacity(4 + json.len());
+    encoded.extend_from_slice(&u32::try_from(json.len())?.to_be_bytes());
+    encoded.extend_from_slice(&json);
+    Ok(encoded)
+}
+
+/// Visits every record in export order: secrets, then OPAQUE registrations,
+/// each by primary key.
+fn for_each_record(
+    connection: &mut SqliteConnection,
+    mut visit: impl FnMut(Record) -> Result<(), Error>,
+) -> Result<(), Error> {
+    for record in secret
+        .order(id)
+        .load_iter::<Secret, DefaultLoadingMode>(connection)?
+    {
+        visit(Record::Secret(record?))?;
+    }
+    for record in opaque_record
+        .order(credential_id)
+        .load_iter::<OpaqueRecord, DefaultLoadingMode>(connection)?
+    {
+        visit(Record::Opaque(record?))?;
+    }
+    Ok(())
+}
+
+/// Streams every record of `connection` to `output`. Returns the count.
+pub fn export(connection: &mut SqliteConnection, output: &mut impl Write) -> Result<usize, Error> {
+    connection.transaction(|connection| {
+        let Some(version) = schema_version(connection)? else {
+            return Err(
+                "the database has no migration ledger: run `keychain-admin migrate` first".into(),
+            );
+        };
+
+        let mut hasher = Sha256::new();
+        let mut record_count: u64 = 0;
+        for_each_record(connection, |record| {
+            hasher.update(encode_record(&record)?);
+            record_count += 1;
+            Ok(())
+        })?;
+
+        output.write_all(MAGIC)?;
+        output.write_all(&FORMAT_VERSION.to_be_bytes())?;
+        output.write_all(&u16::try_from(version.len())?.to_be_bytes())?;
+        output.write_all(version.as_bytes())?;
+        output.write_all(&record_count.to_be_bytes())?;
+        output.write_all(&hasher.finalize())?;
+
+        let mut written: u64 = 0;
+        for_each_record(connection, |record| {
+            output.write_all(&encode_record(&record)?)?;
+            written += 1;
+            Ok(())
+        })?;
+        if written != record_count {
+            return Err("the tables changed during the export".into());
+        }
+        output.flush()?;
+        Ok(usize::try_from(record_count)?)
+    })
+}
+
+fn read_array<const N: usize>(input: &mut impl Read) -> Result<[u8; N], Error> {
+    let mut bytes = [0; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Inserts every record of an export with `/store` semantics: a record whose
+/// id already exists is left untouched, so importing the same file twice is
+/// a no-op. All or nothing: a checksum mismatch, a truncated file or
+/// trailing data rolls the whole import back.
+pub fn import(
+    connection: &mut SqliteConnection,
+    input: &mut impl Read,
+) -> Result<ImportSummary, Error> {
+    let magic = read_array::<16>(input)?;
+    if &magic != MAGIC {
+        if magic.starts_with(AGE_MAGIC) {
+            return Err("the export is encrypted: pass --identity".into());
+        }
+        return Err("not a keychain export".into());
+    }
+    let format_version = u16::from_be_bytes(read_array(input)?);
+    if format_version != FORMAT_VERSION {
+        return Err(format!("unsupported export format version {format_version}").into());
+    }
+    let version_length = u16::from_be_bytes(read_array(input)?);
+    let mut version = vec![0; usize::from(version_length)];
+    input.read_exact(&mut version)?;
+    let version = String::from_utf8(version)?;
+    // A newer schema may carry columns this build would silently drop.
+    let known_version = latest_migration_version()?;
+    if version > known_version {
+        return Err(format!(
+            "the export has schema version {version}, newer than this build ({known_version})"
+        )
+        .into());
+    }
+    let record_count = u64::from_be_bytes(read_array(input)?);
+    let checksum = read_array::<32>(input)?;
+
+    connection.immediate_transaction(|connection| {
+        let count_records = |connection: &mut SqliteConnection| {
+            Ok::<i64, diesel::result::Error>(
+                secret.count().get_result::<i64>(connection)?
+                    + opaque_record.count().get_result::<i64>(connection)?,
+            )
+        };
+        let before = count_records(connection)?;
+        let mut hasher = Sha256::new();
+        for _ in 0..record_count {
+            let length = u32::from_be_bytes(read_array(input)?);
+            if length > MAX_RECORD_LENGTH {
+                return Err("record too large".into());
+            }
+            let mut json = vec![0; usize::try_from(length)?];
+            input.read_exact(&mut json)?;
+            hasher.update(length.to_be_bytes());
+            hasher.update(&json);
+            let written = match serde_json::from_slice(&json)? {
+                Record::Secret(record) => write(connection, &record),
+                Record::Opaque(record) => insert_opaque_record(connection, &record).is_ok(),
+            };
+            if !written {
+                return Err("failed to write a record".into());
+            }
+        }
+        if input.read(&mut [0; 1])? != 0 {
+            return Err("trailing data after the last record".into());
+        }
+        if hasher.finalize().as_slice() != checksum {
+            return Err("checksum mismatch: the export is corrupted".into());
+        }
+        let after = count_records(connection)?;
+        Ok(ImportSummary {
+            records: usize::try_from(record_count)?,
+            inserted: usize::try_from(after - before)?,
+        })
+    })
+}
+
+/// Wraps `output` in age encryption to an X25519 recipient (`age1...`).
+/// `finish` must be called on the returned writer.
+pub fn encrypt_to<W: Write>(
+    recipient: &str,
+    output: W,
+) -> Result<age::stream::StreamWriter<W>, Error> {
+    let recipient = recipient.parse::<age::x25519::Recipient>()?;
+    let encryptor =
+        age::Encryptor::with_recipients(std::iter::once(&recipient as &dyn age::Recipient))?;
+    Ok(encryptor.wrap_output(output)?)
+}
+
+/// Decrypts `input` with the identities of an age identity file.
+pub fn decrypt_with<R: Read>(
+    identity_file: &std::path::Path,
+    input: R,
+) -> Result<age::stream::StreamReader<R>, Error> {
+    let identities = age::IdentityFile::from_file(identity_file.to_string_lossy().into_owned())?
+        .into_identities()?;
+    let decryptor = age::Decryptor::new(input)?;
+    Ok(decryptor.decrypt(identities.iter().map(|identity| identity.as_ref()))?)
+}
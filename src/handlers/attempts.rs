@@ -1,24 +1,59 @@
-use std::{io::Write, sync::Arc};
+use std::{collections::HashMap, io::Write, sync::Arc};
 
 use axum::{
     body::{Body, Bytes},
-    extract::State,
+    extract::{Path, Query, State},
     http::{header, HeaderMap, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ed25519_dalek::{Signer, SigningKey};
 use flate2::{write::GzEncoder, Compression};
+use serde::Deserialize;
+use tokio::sync::MutexGuard;
 
 use crate::{
-    models::{error_body, retry_after_response, AttemptEntry, AttemptsSnapshot},
-    utils::{sha256_hex, truncate_to_hour},
-    AppState, AttemptsSnapshotCache,
+    attempts_delta::{diff, Composed},
+    models::{error_body, retry_after_response, AttemptEntry, AttemptsBucket, AttemptsSnapshot},
+    utils::{is_hex_of_length, sha256_hex, truncate_to_hour},
+    xor_filter::XorFilter,
+    AppState, AttemptsBody, AttemptsSnapshotCache,
 };
 
 /// Small fixed advisory backoff for the global attempts-telemetry bucket:
 /// there is no cooldown deadline to derive here, only "try again shortly".
 const GLOBAL_OVERLOAD_RETRY_AFTER_SECS: u64 = 1;
 
+/// Header carrying the snapshot signature, cached by proxies with the body.
+pub const SIGNATURE_HEADER: &str = "attempts-signature";
+
+/// Prefix of the signed message, before the uncompressed JSON body: a
+/// signature over the snapshot can never be replayed as one over anything
+/// else, should the key ever sign other data.
+pub const SIGNATURE_CONTEXT: &[u8] = b"keychain attempts snapshot v1\n";
+
+/// Same as `SIGNATURE_CONTEXT`, for `/attempts/delta` bodies.
+pub const DELTA_SIGNATURE_CONTEXT: &[u8] = b"keychain attempts delta v1\n";
+
+/// Same as `SIGNATURE_CONTEXT`, for `/attempts/bucket` bodies.
+pub const BUCKET_SIGNATURE_CONTEXT: &[u8] = b"keychain attempts bucket v1\n";
+
+/// Same as `SIGNATURE_CONTEXT`, for `/attempts/filter` bodies, signed as
+/// served (they are not compressed).
+pub const FILTER_SIGNATURE_CONTEXT: &[u8] = b"keychain attempts filter v1\n";
+
+/// Hex characters of `id_hash` a bucket query reveals: 12 bits, 4096
+/// buckets, about 25 identifiers each when the map is full.
+pub const BUCKET_PREFIX_LENGTH: usize = 3;
+
+/// Bucket responses hold exactly this many entries, padded with zero-counter
+/// entries, so their size does not tell an observer how many identifiers
+/// share the prefix. A full map at the default RATE_LIMIT_MAX_IDENTIFIERS
+/// puts about 25 in a bucket; a fuller bucket is refused, not truncated,
+/// since dropping entries would hide attempts from their owner.
+pub const BUCKET_ENTRIES: usize = 64;
+
 /// Public lookup telemetry.
 ///
 /// Publishes the identifiers currently rate-limited for fetch/trash lookups,
@@ -35,36 +70,281 @@ const GLOBAL_OVERLOAD_RETRY_AFTER_SECS: u64 = 1;
 /// multiplies full-map serialization work. The body is always gzip — there
 /// is no uncompressed variant, which keeps one representation, one ETag and
 /// one cache entry.
+///
+/// Each build is signed with `attempts_signing_key` (public key in `/info`)
+/// over the uncompressed JSON, so the signature still verifies when a proxy
+/// or cache re-encodes the body. Ed25519 is deterministic: an unchanged
+/// snapshot keeps the same signature, like its ETag.
 pub async fn get_attempts(State(state): State<AppState>, headers: HeaderMap) -> Response {
-    {
-        let mut bucket = state.attempts_token_bucket.lock().await;
-        if !bucket.try_consume() {
-            tracing::warn!("attempts telemetry rate-limit exceeded");
-            return retry_after_response(
-                StatusCode::SERVICE_UNAVAILABLE,
-                GLOBAL_OVERLOAD_RETRY_AFTER_SECS,
-                "Too many attempts telemetry requests, retry later",
-            );
+    if let Err(response) = consume_attempts_token(&state).await {
+        return response;
+    }
+    let (cached, ttl) = match fresh_snapshot(&state).await {
+        Ok(fresh) => fresh,
+        Err(response) => return response,
+    };
+    let snapshot = cached.as_ref().expect("snapshot was initialized");
+    let max_age = remaining_max_age(snapshot.created_at, ttl);
+    let response = snapshot_response(snapshot, &headers, max_age);
+    drop(cached);
+    response
+}
+
+/// Query of `/attempts/delta`: the ETag of the client's last snapshot, with
+/// or without its quotes.
+#[derive(Deserialize)]
+pub struct DeltaQuery {
+    since: String,
+}
+
+/// Incremental lookup telemetry: the entries added, changed and removed
+/// since the snapshot `since`, in the same hour-truncated form as
+/// `/attempts`. When that snapshot is no longer in the history, or the delta
+/// would outgrow the snapshot, the full `/attempts` representation is served
+/// instead (its body has `entries`, a delta has `base_etag`). Each delta is
+/// built at most once per snapshot, under the same lock as the snapshot.
+pub async fn get_attempts_delta(
+    State(state): State<AppState>,
+    Query(query): Query<DeltaQuery>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(response) = consume_attempts_token(&state).await {
+        return response;
+    }
+    let (mut cached, ttl) = match fresh_snapshot(&state).await {
+        Ok(fresh) => fresh,
+        Err(response) => return response,
+    };
+    let snapshot = cached.as_mut().expect("snapshot was initialized");
+    let max_age = remaining_max_age(snapshot.created_at, ttl);
+    let since = format!("\"{}\"", query.since.trim_matches('"'));
+
+    let delta = match snapshot.delta_bodies.get(&since) {
+        Some(delta) => Some(delta.clone()),
+        None => match build_delta(&state, snapshot, &since).await {
+            Ok(delta) => delta,
+            Err(response) => return response,
+        },
+    };
+    let response = match delta {
+        Some(delta) => signed_gzip_response(
+            &headers,
+            delta.body.as_ref().clone(),
+            &delta.etag,
+            &delta.signature,
+            max_age,
+        ),
+        None => snapshot_response(snapshot, &headers, max_age),
+    };
+    drop(cached);
+    response
+}
+
+/// Private membership lookup, like a password-breach range query: the
+/// snapshot entries whose `id_hash` starts with `prefix`. A client checking
+/// its own identifier reveals only the prefix and downloads a few kilobytes
+/// instead of the whole snapshot. Cut from the cached snapshot, so it costs
+/// no rebuild and shows exactly what `/attempts` shows.
+pub async fn get_attempts_bucket(
+    State(state): State<AppState>,
+    Path(prefix): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let prefix = prefix.to_lowercase();
+    if !is_hex_of_length(BUCKET_PREFIX_LENGTH, &prefix) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(error_body(format!(
+                "prefix must be {BUCKET_PREFIX_LENGTH} hex characters"
+            ))),
+        )
+            .into_response();
+    }
+    if let Err(response) = consume_attempts_token(&state).await {
+        return response;
+    }
+    let (cached, ttl) = match fresh_snapshot(&state).await {
+        Ok(fresh) => fresh,
+        Err(response) => return response,
+    };
+    let snapshot = cached.as_ref().expect("snapshot was initialized");
+    let max_age = remaining_max_age(snapshot.created_at, ttl);
+    // Entries are sorted by id_hash: the bucket is one contiguous range.
+    let start = snapshot
+        .entries
+        .partition_point(|entry| entry.id_hash.as_str() < prefix.as_str());
+    let end = start
+        + snapshot.entries[start..].partition_point(|entry| entry.id_hash.starts_with(&prefix));
+    if end - start > BUCKET_ENTRIES {
+        return (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(error_body(format!(
+                "bucket exceeds {BUCKET_ENTRIES} entries, use /attempts"
+            ))),
+        )
+            .into_response();
+    }
+    let mut entries = snapshot.entries[start..end].to_vec();
+    let (etag, collection_started_at) = (snapshot.etag.clone(), snapshot.collection_started_at);
+    drop(cached);
+
+    // Padding is derived from the snapshot ETag: identical for every request
+    // against the same snapshot, so the response stays cacheable.
+    for index in entries.len()..BUCKET_ENTRIES {
+        let filler = sha256_hex(format!("{etag}/{prefix}/{index}").as_bytes());
+        entries.push(AttemptEntry {
+            id_hash: format!("{prefix}{}", &filler[BUCKET_PREFIX_LENGTH..]),
+            total_attempts: 0,
+            failed_attempts: 0,
+            total_requests: 0,
+            window_started_at: collection_started_at,
+            last_attempt_at: collection_started_at,
+        });
+    }
+    entries.sort_by(|a, b| a.id_hash.cmp(&b.id_hash));
+
+    let bucket = AttemptsBucket {
+        version: 1,
+        prefix,
+        etag,
+        collection_started_at,
+        entries,
+    };
+    let raw = serde_json::to_vec(&bucket).expect("attempts bucket is serializable");
+    match sign_and_compress(&state.attempts_signing_key, BUCKET_SIGNATURE_CONTEXT, &raw) {
+        Ok((gzip, signature)) => {
+            let etag = format!("\"{}\"", sha256_hex(&gzip));
+            signed_gzip_response(&headers, Bytes::from(gzip), &etag, &signature, max_age)
+        }
+        Err(error) => {
+            tracing::error!(error = %error, "failed to compress attempts bucket");
+            internal_error()
         }
     }
+}
+
+/// Membership pre-check for slow links: an xor filter (format in
+/// `xor_filter`) over the `id_hash` of every identifier with failed
+/// attempts, about 1.2 bytes per such identifier. A miss is definitive; a hit
+/// (or a 1/256 false positive) is confirmed with `/attempts/bucket`. Built
+/// with each snapshot, so it always matches what `/attempts` shows.
+pub async fn get_attempts_filter(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Err(response) = consume_attempts_token(&state).await {
+        return response;
+    }
+    let (cached, ttl) = match fresh_snapshot(&state).await {
+        Ok(fresh) => fresh,
+        Err(response) => return response,
+    };
+    let snapshot = cached.as_ref().expect("snapshot was initialized");
+    let max_age = remaining_max_age(snapshot.created_at, ttl);
+    let filter = &snapshot.filter;
+    let response = signed_response(
+        &headers,
+        filter.body.as_ref().clone(),
+        &[(header::CONTENT_TYPE, "application/octet-stream")],
+        &filter.etag,
+        &filter.signature,
+        max_age,
+    );
+    drop(cached);
+    response
+}
+
+/// Global bucket of every `/attempts` route, and of the `/log` routes:
+/// both serve public, signed views to anyone.
+pub async fn consume_attempts_token(state: &AppState) -> Result<(), Response> {
+    let mut bucket = state.attempts_token_bucket.lock().await;
+    if !bucket.try_consume() {
+        tracing::warn!("attempts telemetry rate-limit exceeded");
+        state
+            .metrics
+            .attempts_bucket_rejections
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        return Err(retry_after_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            GLOBAL_OVERLOAD_RETRY_AFTER_SECS,
+            "Too many attempts telemetry requests, retry later",
+        ));
+    }
+    Ok(())
+}
 
+/// Locks the snapshot cache, rebuilding the snapshot first when its TTL has
+/// elapsed: concurrent requests wait for one build instead of each starting
+/// their own.
+async fn fresh_snapshot(
+    state: &AppState,
+) -> Result<
+    (
+        MutexGuard<'_, Option<AttemptsSnapshotCache>>,
+        std::time::Duration,
+    ),
+    Response,
+> {
+    let ttl = std::time::Duration::from_secs(
+        state
+            .attempts_snapshot_ttl_seconds
+            .load(std::sync::atomic::Ordering::Relaxed),
+    );
     let mut cached = state.attempts_snapshot.lock().await;
     if cached
         .as_ref()
-        .is_none_or(|snapshot| snapshot.created_at.elapsed() >= state.attempts_snapshot_ttl)
+        .is_none_or(|snapshot| snapshot.created_at.elapsed() >= ttl)
     {
-        match build_snapshot(&state).await {
-            Ok(snapshot) => *cached = Some(snapshot),
-            Err(response) => return response,
-        }
+        let started = std::time::Instant::now();
+        let built = build_snapshot(state, cached.take()).await;
+        state.metrics.snapshot_rebuild.observe(started.elapsed());
+        *cached = Some(built?);
     }
+    Ok((cached, ttl))
+}
 
-    let snapshot = cached.as_ref().expect("snapshot was initialized");
-    let etag = snapshot.etag.clone();
-    let body = snapshot.gzip_body.as_ref().clone();
-    let max_age = remaining_max_age(snapshot.created_at, state.attempts_snapshot_ttl);
-    drop(cached);
+fn snapshot_response(
+    snapshot: &AttemptsSnapshotCache,
+    headers: &HeaderMap,
+    max_age: u64,
+) -> Response {
+    signed_gzip_response(
+        headers,
+        snapshot.gzip_body.as_ref().clone(),
+        &snapshot.etag,
+        &snapshot.signature,
+        max_age,
+    )
+}
 
+/// A cached gzip JSON body with its strong ETag and signature, or a bodyless
+/// `304` when `If-None-Match` matches the ETag.
+fn signed_gzip_response(
+    headers: &HeaderMap,
+    body: Bytes,
+    etag: &str,
+    signature: &str,
+    max_age: u64,
+) -> Response {
+    signed_response(
+        headers,
+        body,
+        &[
+            (header::CONTENT_TYPE, "application/json"),
+            (header::CONTENT_ENCODING, "gzip"),
+        ],
+        etag,
+        signature,
+        max_age,
+    )
+}
+
+/// Same as `signed_gzip_response`, with the given representation headers.
+fn signed_response(
+    headers: &HeaderMap,
+    body: Bytes,
+    representation: &[(header::HeaderName, &'static str)],
+    etag: &str,
+    signature: &str,
+    max_age: u64,
+) -> Response {
     let not_modified = headers
         .get(header::IF_NONE_MATCH)
         .and_then(|value| value.to_str().ok())
@@ -83,10 +363,12 @@ pub async fn get_attempts(State(state): State<AppState>, headers: HeaderMap) ->
             .status(StatusCode::NOT_MODIFIED)
             .body(Body::empty())
     } else {
-        Response::builder()
-            .status(StatusCode::OK)
-            .header(header::CONTENT_TYPE, "application/json")
-            .header(header::CONTENT_ENCODING, "gzip")
+        representation
+            .iter()
+            .fold(
+                Response::builder().status(StatusCode::OK),
+                |builder, (name, value)| builder.header(name, *value),
+            )
             .body(Body::from(body))
     }
     .expect("static attempts response headers are valid");
@@ -97,14 +379,107 @@ pub async fn get_attempts(State(state): State<AppState>, headers: HeaderMap) ->
         format!("public, max-age={max_age}").parse().unwrap(),
     );
     response_headers.insert(header::ETAG, etag.parse().unwrap());
+    // Also on 304: caches refresh their stored headers from it.
+    response_headers.insert(SIGNATURE_HEADER, signature.parse().unwrap());
     response
 }
 
+/// Composes, serializes, compresses and signs the delta from `since` to the
+/// current snapshot, and caches it. `Ok(None)` when the full snapshot must
+/// be served instead. The history is moved to a blocking thread for the
+/// composition (up to the identifier capacity of changes) and put back.
+async fn build_delta(
+    state: &AppState,
+    snapshot: &mut AttemptsSnapshotCache,
+    since: &str,
+) -> Result<Option<AttemptsBody>, Response> {
+    let history = std::mem::take(&mut snapshot.history);
+    let (since_owned, etag) = (since.to_string(), snapshot.etag.clone());
+    let (entry_count, collection_started_at) =
+        (snapshot.entries.len(), snapshot.collection_started_at);
+    let signing_key = state.attempts_signing_key.clone();
+    let built = tokio::task::spawn_blocking(move || {
+        let composed = if since_owned == etag {
+            Some(Composed::default())
+        } else {
+            history.since(&since_owned)
+        };
+        let delta = match composed {
+            // A delta larger than the snapshot saves nothing.
+            Some(composed) if composed.is_empty() || composed.len() <= entry_count => {
+                let delta = composed.into_delta(since_owned, etag, collection_started_at);
+                let raw = serde_json::to_vec(&delta).expect("attempts delta is serializable");
+                Some(sign_and_compress(
+                    &signing_key,
+                    DELTA_SIGNATURE_CONTEXT,
+                    &raw,
+                ))
+            }
+            _ => None,
+        };
+        (history, delta)
+    })
+    .await;
+
+    match built {
+        Ok((history, delta)) => {
+            snapshot.history = history;
+            let delta = match delta {
+                Some(Ok((gzip, signature))) => Some(AttemptsBody {
+                    etag: format!("\"{}\"", sha256_hex(&gzip)),
+                    body: Arc::new(Bytes::from(gzip)),
+                    signature,
+                }),
+                Some(Err(error)) => {
+                    tracing::error!(error = %error, "failed to compress attempts delta");
+                    return Err(internal_error());
+                }
+                None => None,
+            };
+            if let Some(delta) = &delta {
+                snapshot
+                    .delta_bodies
+                    .insert(since.to_string(), delta.clone());
+            }
+            Ok(delta)
+        }
+        Err(error) => {
+            tracing::error!(error = %error, "attempts delta task panicked");
+            Err(internal_error())
+        }
+    }
+}
+
+/// Signs `context || raw`, then gzip-compresses `raw`. flate2 writes a zero
+/// mtime, so identical content gives identical bytes and signature.
+fn sign_and_compress(
+    signing_key: &SigningKey,
+    context: &[u8],
+    raw: &[u8],
+) -> std::io::Result<(Vec<u8>, String)> {
+    let signature = signing_key.sign(&[context, raw].concat());
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::new(6));
+    encoder.write_all(raw)?;
+    Ok((encoder.finish()?, STANDARD.encode(signature.to_bytes())))
+}
+
+fn internal_error() -> Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(error_body("Internal server error")),
+    )
+        .into_response()
+}
+
 /// Collects the current entries under the rate-limit lock, then serializes
 /// and compresses on a blocking thread after releasing it. flate2 writes a
 /// zero mtime in the gzip header, so identical content produces identical
-/// bytes and a stable ETag across rebuilds.
-async fn build_snapshot(state: &AppState) -> Result<AttemptsSnapshotCache, Response> {
+/// bytes and a stable ETag across rebuilds. When the content changed, the
+/// transition from `previous` is appended to the delta history.
+async fn build_snapshot(
+    state: &AppState,
+    previous: Option<AttemptsSnapshotCache>,
+) -> Result<AttemptsSnapshotCache, Response> {
     let now = chrono::Utc::now();
     let mut entries: Vec<AttemptEntry> = {
         let mut identifier_rate_limit = state.identifier_rate_limit.lock().await;
@@ -141,15 +516,34 @@ async fn build_snapshot(state: &AppState) -> Result<AttemptsSnapshotCache, Respo
         entries,
     };
 
+    let signing_key = state.attempts_signing_key.clone();
+    // At most the identifier capacity of retained changes: one full
+    // snapshot's worth.
+    let history_budget = state.rate_limit_max_identifiers;
     let built = tokio::task::spawn_blocking(move || -> std::io::Result<AttemptsSnapshotCache> {
         let raw = serde_json::to_vec(&payload).expect("attempts snapshot is serializable");
-        let mut encoder = GzEncoder::new(Vec::new(), Compression::new(6));
-        encoder.write_all(&raw)?;
-        let gzip = encoder.finish()?;
+        let (gzip, signature) = sign_and_compress(&signing_key, SIGNATURE_CONTEXT, &raw)?;
+        let etag = format!("\"{}\"", sha256_hex(&gzip));
+        let filter = build_filter(&signing_key, &payload.entries);
+        let (history, delta_bodies) = match previous {
+            Some(previous) if previous.etag == etag => (previous.history, previous.delta_bodies),
+            Some(mut previous) => {
+                let change = diff(&previous.etag, &previous.entries, &payload.entries);
+                previous.history.push(change, history_budget);
+                (previous.history, HashMap::new())
+            }
+            None => Default::default(),
+        };
         Ok(AttemptsSnapshotCache {
-            etag: format!("\"{}\"", sha256_hex(&gzip)),
+            etag,
             gzip_body: Arc::new(Bytes::from(gzip)),
+            signature,
             created_at: std::time::Instant::now(),
+            entries: payload.entries,
+            collection_started_at: payload.collection_started_at,
+            history,
+            delta_bodies,
+            filter,
         })
     })
     .await;
@@ -158,23 +552,37 @@ async fn build_snapshot(state: &AppState) -> Result<AttemptsSnapshotCache, Respo
         Ok(Ok(snapshot)) => Ok(snapshot),
         Ok(Err(error)) => {
             tracing::error!(error = %error, "failed to compress attempts snapshot");
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(error_body("Internal server error")),
-            )
-                .into_response())
+            Err(internal_error())
         }
         Err(error) => {
             tracing::error!(error = %error, "attempts snapshot task panicked");
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(error_body("Internal server error")),
-            )
-                .into_response())
+            Err(internal_error())
         }
     }
 }
 
+/// The xor filter of the identifiers with failed attempts in `entries`. Its
+/// ETag and signature are over the served bytes; it is deterministic, so an
+/// unchanged set keeps both.
+fn build_filter(signing_key: &SigningKey, entries: &[AttemptEntry]) -> AttemptsBody {
+    let keys: Vec<[u8; 32]> = entries
+        .iter()
+        .filter(|entry| entry.failed_attempts > 0)
+        .filter_map(|entry| {
+            let mut key = [0u8; 32];
+            hex::decode_to_slice(&entry.id_hash, &mut key).ok()?;
+            Some(key)
+        })
+        .collect();
+    let bytes = XorFilter::build(&keys).to_bytes();
+    let signature = signing_key.sign(&[FILTER_SIGNATURE_CONTEXT, &bytes].concat());
+    AttemptsBody {
+        etag: format!("\"{}\"", sha256_hex(&bytes)),
+        body: Arc::new(Bytes::from(bytes)),
+        signature: STANDARD.encode(signature.to_bytes()),
+    }
+}
+
 /// Remaining snapshot freshness, rounded up to the next second so clients
 /// never cache past the rebuild. Never zero: a zero max-age would let some
 /// caches treat the body as immediately stale and hammer the origin.
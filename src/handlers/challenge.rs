@@ -0,0 +1,27 @@
+use axum::extract::State;
+use axum::{http::StatusCode, Json};
+
+use crate::models::Challenge;
+use crate::pow::issue_challenge;
+use crate::AppState;
+
+/// Issues a proof-of-work challenge for `/fetch` and `/trash`.
+///
+/// Challenges are stateless (an HMAC over the issue time), so this route
+/// needs neither storage nor a token bucket: issuing one costs less than
+/// serving `/info`. The challenge says nothing about any identifier; the
+/// puzzle is bound to the identifier and candidate only when it is solved.
+pub async fn get_challenge(State(state): State<AppState>) -> (StatusCode, Json<Challenge>) {
+    let now = chrono::Utc::now();
+    let challenge = issue_challenge(&state.pow_key, now);
+    let issued_at = chrono::DateTime::from_timestamp(now.timestamp(), 0).unwrap_or(now);
+    (
+        StatusCode::OK,
+        Json(Challenge {
+            challenge,
+            expires_at: issued_at + state.pow_challenge_lifetime,
+            difficulty_bits: state.pow_difficulty_bits,
+            difficulty_step_bits: state.pow_difficulty_step_bits,
+        }),
+    )
+}
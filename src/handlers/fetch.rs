@@ -4,11 +4,12 @@ use axum::{http::StatusCode, Json};
 use serde_json::json;
 use std::collections::HashMap;
 
-use crate::database::{establish_connection, read_and_trash_secret_by_id, read_secret_by_id};
+use crate::database::database_timestamp;
 use crate::models::{
-    error_body, retry_after_response, AttemptStatus, CandidateState, FetchSecret, RateLimitInfo,
-    ResponseFailedAttempt,
+    error_body, retry_after_response, AttemptStatus, CandidateState, FetchSecret, ProofOfWork,
+    RateLimitInfo, ResponseFailedAttempt, Secret,
 };
+use crate::pow::{difficulty, meets_difficulty, verify_challenge};
 use crate::utils::{generate_secret_id, identifier_hash, is_256bits_hex_hash};
 use crate::AppState;
 
@@ -34,7 +35,7 @@ fn remove_pending(
     }
 }
 
-async fn remove_pending_async(
+pub async fn remove_pending_async(
     state: &AppState,
     id_hash: &str,
     candidate: &str,
@@ -44,9 +45,48 @@ async fn remove_pending_async(
     remove_pending(&mut map, id_hash, candidate, generation);
 }
 
+/// Commits a `Pending` candidate of the window `generation`, counting a
+/// failure when `failed`: for admissions with no database lookup behind
+/// them (see `Reservation`).
+async fn commit_pending(
+    state: &AppState,
+    id_hash: &str,
+    candidate: &str,
+    generation: chrono::DateTime<chrono::Utc>,
+    failed: bool,
+) {
+    let mut map = state.identifier_rate_limit.lock().await;
+    if let Some(info) = map.get_mut(id_hash) {
+        if info.window_started_at == generation
+            && info.candidates.get(candidate) == Some(&CandidateState::Pending)
+        {
+            info.candidates
+                .insert(candidate.to_owned(), CandidateState::Committed);
+            if failed {
+                info.failed_candidates = info.failed_candidates.saturating_add(1);
+            }
+        }
+    }
+}
+
+/// Takes back one failure of the window `generation`, counted by a commit
+/// that later proved to be a success (an OPAQUE login confirmed by KE3).
+pub async fn clear_failure(
+    state: &AppState,
+    id_hash: &str,
+    generation: chrono::DateTime<chrono::Utc>,
+) {
+    let mut map = state.identifier_rate_limit.lock().await;
+    if let Some(info) = map.get_mut(id_hash) {
+        if info.window_started_at == generation {
+            info.failed_candidates = info.failed_candidates.saturating_sub(1);
+        }
+    }
+}
+
 /// The generation check makes a delayed cancellation safe even if it outlives
 /// the cooldown and a replacement window has already been created.
-struct PendingGuard {
+pub struct PendingGuard {
     state: AppState,
     id_hash: String,
     candidate: String,
@@ -55,7 +95,7 @@ struct PendingGuard {
 }
 
 impl PendingGuard {
-    fn new(
+    pub fn new(
         state: AppState,
         id_hash: String,
         candidate: String,
@@ -70,7 +110,7 @@ impl PendingGuard {
         }
     }
 
-    fn disarm(&mut self) {
+    pub fn disarm(&mut self) {
         self.armed = false;
     }
 }
@@ -101,38 +141,46 @@ impl Drop for PendingGuard {
 
 fn attempt_status(
     info: &RateLimitInfo,
-    max: u8,
     previous: Option<chrono::DateTime<chrono::Utc>>,
-    cooldown: chrono::TimeDelta,
+    state: &AppState,
 ) -> AttemptStatus {
     let count = info.candidate_count();
     AttemptStatus {
         version: 1,
         total_attempts: count,
         failed_attempts: info.failed_candidates,
-        remaining_attempts: max.saturating_sub(count),
+        remaining_attempts: state.rate_limit_max_attempts.saturating_sub(count),
         total_requests: info.total_requests,
         window_started_at: info.window_started_at,
         previous_attempt_at: previous,
-        resets_at: info.last_candidate_at + cooldown,
+        resets_at: info.last_candidate_at + state.rate_limit_cooldown,
+        next_attempt_at: info.next_candidate_at(
+            state.rate_limit_max_attempts,
+            state.rate_limit_backoff_base,
+            state.rate_limit_cooldown,
+        ),
     }
 }
 
 fn rate_limited(
     count: u8,
     last_candidate_at: chrono::DateTime<chrono::Utc>,
+    next_attempt_at: chrono::DateTime<chrono::Utc>,
     requested_at: chrono::DateTime<chrono::Utc>,
     state: &AppState,
 ) -> Response {
     tracing::warn!("rate-limit lockout");
-    let retry_after_secs = (last_candidate_at + state.rate_limit_cooldown - requested_at)
-        .num_seconds()
-        .max(1) as u64;
+    // Rounded up: retrying after a truncated delay would land just before the
+    // unlock and be rejected again.
+    let remaining = next_attempt_at - requested_at;
+    let retry_after_secs =
+        (remaining.num_seconds() + i64::from(remaining.subsec_nanos() > 0)).max(1) as u64;
     let response = json!({
         "error": "Too many attempts",
         "requested_at": last_candidate_at,
         "rate_limit_cooldown": state.rate_limit_cooldown.num_minutes(),
         "attempts": count,
+        "next_attempt_at": next_attempt_at,
     });
     let mut http_response = (StatusCode::TOO_MANY_REQUESTS, Json(response)).into_response();
     http_response.headers_mut().insert(
@@ -145,12 +193,245 @@ fn rate_limited(
     http_response
 }
 
-enum Admission {
-    New(AttemptStatus, chrono::DateTime<chrono::Utc>),
+pub enum Admission {
+    /// Carries a copy of the window to write through when rate-limit state
+    /// persistence is enabled.
+    New(
+        AttemptStatus,
+        chrono::DateTime<chrono::Utc>,
+        Option<RateLimitInfo>,
+    ),
     Replay(AttemptStatus, chrono::DateTime<chrono::Utc>),
     Pending,
 }
 
+/// The global lookup bucket, shared by every route that spends the
+/// per-identifier budget.
+pub async fn consume_lookup_token(state: &AppState) -> Result<(), Response> {
+    let mut bucket = state.lookup_token_bucket.lock().await;
+    if !bucket.try_consume() {
+        tracing::warn!("global lookup rate-limit exceeded");
+        state
+            .metrics
+            .lookup_bucket_rejections
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        return Err(retry_after_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            GLOBAL_OVERLOAD_RETRY_AFTER_SECS,
+            "Too many lookup requests, retry later",
+        ));
+    }
+    Ok(())
+}
+
+/// A candidate admitted by `reserve` for a request with no database lookup
+/// to finalize it (`/oprf`, `/opaque/*`). A new candidate stays `Pending`
+/// until `commit`; dropped before, its slot is given back.
+pub struct Reservation {
+    state: AppState,
+    id_hash: String,
+    candidate: String,
+    pub attempt_status: AttemptStatus,
+    /// Window of a new candidate; `None` for a replay.
+    generation: Option<chrono::DateTime<chrono::Utc>>,
+    pending_guard: Option<PendingGuard>,
+}
+
+impl Reservation {
+    /// Commits a new candidate, as a failure when `failed`, and returns its
+    /// window generation; a replay commits nothing and returns `None`.
+    pub async fn commit(mut self, failed: bool) -> Option<chrono::DateTime<chrono::Utc>> {
+        let generation = self.generation?;
+        commit_pending(
+            &self.state,
+            &self.id_hash,
+            &self.candidate,
+            generation,
+            failed,
+        )
+        .await;
+        if let Some(guard) = self.pending_guard.as_mut() {
+            guard.disarm();
+        }
+        Some(generation)
+    }
+}
+
+/// `admit`, then, for a new candidate, the write-through of the window: the
+/// reservation reaches the state file before anything is released, like a
+/// lookup's. `Err` is the response to return as is.
+pub async fn reserve(
+    state: &AppState,
+    id_hash: &str,
+    candidate: &str,
+    pow: Option<&ProofOfWork>,
+) -> Result<Reservation, Response> {
+    let (attempt_status, generation, persisted_window) =
+        match admit(state, id_hash, candidate, pow, chrono::Utc::now()).await? {
+            Admission::Pending => {
+                return Err(retry_after_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    GLOBAL_OVERLOAD_RETRY_AFTER_SECS,
+                    "Candidate lookup pending, retry later",
+                ));
+            }
+            Admission::Replay(status, _) => (status, None, None),
+            Admission::New(status, generation, persisted_window) => {
+                (status, Some(generation), persisted_window)
+            }
+        };
+    let mut reservation = Reservation {
+        state: state.clone(),
+        id_hash: id_hash.to_owned(),
+        candidate: candidate.to_owned(),
+        attempt_status,
+        generation,
+        pending_guard: generation.map(|generation| {
+            PendingGuard::new(
+                state.clone(),
+                id_hash.to_owned(),
+                candidate.to_owned(),
+                generation,
+            )
+        }),
+    };
+    if let (Some(rate_limit_state), Some(window), Some(generation)) =
+        (state.rate_limit_state.clone(), persisted_window, generation)
+    {
+        let window_id_hash = id_hash.to_owned();
+        let persisted = tokio::task::spawn_blocking(move || {
+            rate_limit_state.persist_entry(&window_id_hash, &window)
+        })
+        .await;
+        if !matches!(persisted, Ok(Ok(()))) {
+            remove_pending_async(state, id_hash, candidate, generation).await;
+            if let Some(guard) = reservation.pending_guard.as_mut() {
+                guard.disarm();
+            }
+            tracing::error!("failed to persist a reservation");
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(error_body("Internal server error")),
+            )
+                .into_response());
+        }
+    }
+    Ok(reservation)
+}
+
+/// Charges `candidate` to the budget of `id_hash`: the cooldown, capacity,
+/// saturation (with the escalating backoff) and proof-of-work checks, then
+/// the reservation of a new candidate as `Pending`. `Err` is the response
+/// to return as is.
+pub async fn admit(
+    state: &AppState,
+    id_hash: &str,
+    candidate: &str,
+    pow: Option<&ProofOfWork>,
+    requested_at: chrono::DateTime<chrono::Utc>,
+) -> Result<Admission, Response> {
+    let pow_required = state.pow_difficulty_bits > 0;
+    // Authenticity and expiry are stateless checks: a forged or stale
+    // challenge is rejected before the rate-limit lock is taken.
+    if pow_required
+        && pow.is_some_and(|proof| {
+            !verify_challenge(
+                &state.pow_key,
+                proof,
+                state.pow_challenge_lifetime,
+                requested_at,
+            )
+        })
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(error_body("Invalid or expired proof-of-work challenge")),
+        )
+            .into_response());
+    }
+    let mut map = state.identifier_rate_limit.lock().await;
+    if map.get(id_hash).is_some_and(|info| {
+        requested_at.signed_duration_since(info.last_candidate_at) > state.rate_limit_cooldown
+    }) {
+        map.remove(id_hash);
+    }
+    if !map.contains_key(id_hash) && map.len() >= state.rate_limit_max_identifiers {
+        map.retain(|_, info| {
+            requested_at.signed_duration_since(info.last_candidate_at) <= state.rate_limit_cooldown
+        });
+        if map.len() >= state.rate_limit_max_identifiers {
+            return Err(retry_after_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                GLOBAL_OVERLOAD_RETRY_AFTER_SECS,
+                "Rate-limit capacity exhausted, retry later",
+            ));
+        }
+    }
+    let info = map
+        .entry(id_hash.to_owned())
+        .or_insert_with(|| RateLimitInfo::new(requested_at));
+    info.total_requests = info.total_requests.saturating_add(1);
+    info.last_request_at = requested_at;
+    // This check intentionally precedes membership, including for known
+    // candidates, so saturation cannot become an authentication oracle.
+    // Beyond the budget, the escalating backoff (when enabled) admits one
+    // more candidate per unlock: an attacker can delay recovery but never
+    // deny it for good.
+    if info.candidate_count() >= state.rate_limit_max_attempts {
+        let unlocks_at = info.backoff_unlocks_at(
+            state.rate_limit_max_attempts,
+            state.rate_limit_backoff_base,
+            state.rate_limit_cooldown,
+        );
+        if !unlocks_at.is_some_and(|unlocks_at| unlocks_at <= requested_at) {
+            return Err(rate_limited(
+                info.candidate_count(),
+                info.last_candidate_at,
+                unlocks_at.unwrap_or(info.last_candidate_at + state.rate_limit_cooldown),
+                requested_at,
+                state,
+            ));
+        }
+    }
+    // Like saturation, the proof-of-work check precedes membership and
+    // applies to replays too: exempting known candidates would tell a
+    // caller, for free, whether a candidate was tried in this window.
+    if pow_required {
+        let required = difficulty(
+            state.pow_difficulty_bits,
+            state.pow_difficulty_step_bits,
+            info.candidate_count(),
+        );
+        if !pow.is_some_and(|proof| meets_difficulty(proof, id_hash, candidate, required)) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": "Proof-of-work required",
+                    "pow_difficulty_bits": required,
+                })),
+            )
+                .into_response());
+        }
+    }
+    Ok(match info.candidates.get(candidate).copied() {
+        Some(CandidateState::Pending) => Admission::Pending,
+        Some(CandidateState::Committed) => {
+            Admission::Replay(attempt_status(info, None, state), info.window_started_at)
+        }
+        None => {
+            let previous = (info.candidate_count() > 0).then_some(info.last_candidate_at);
+            info.candidates
+                .insert(candidate.to_owned(), CandidateState::Pending);
+            info.last_candidate_at = requested_at;
+            Admission::New(
+                attempt_status(info, previous, state),
+                info.window_started_at,
+                state.rate_limit_state.is_some().then(|| info.clone()),
+            )
+        }
+    })
+}
+
 enum FinalizerError {
     Database(diesel::result::Error),
     Join(tokio::task::JoinError),
@@ -199,11 +480,32 @@ async fn finalize(
     }
 }
 
+/// What an admitted candidate does with the record it authenticates.
+pub enum LookupAction {
+    Fetch,
+    Trash,
+    /// Replaces the record with this one, atomically.
+    Rotate(Secret),
+    /// Cancels a pending delayed deletion.
+    Restore,
+}
+
 pub async fn fetch_secret(
     State(state): State<AppState>,
     Json(request): Json<FetchSecret>,
     is_trashing_secret: bool,
 ) -> Response {
+    let action = if is_trashing_secret {
+        LookupAction::Trash
+    } else {
+        LookupAction::Fetch
+    };
+    lookup(state, request, action).await
+}
+
+/// The shared `/fetch`, `/trash`, `/rotate` and `/restore` path: every action consumes
+/// the candidate budget of the authenticated identifier the same way.
+pub async fn lookup(state: AppState, request: FetchSecret, action: LookupAction) -> Response {
     let identifier = request.identifier.to_lowercase();
     let authentication_key = request.authentication_key.to_lowercase();
     if !is_256bits_hex_hash(&identifier) || !is_256bits_hex_hash(&authentication_key) {
@@ -216,81 +518,40 @@ pub async fn fetch_secret(
             .into_response();
     }
     let id_hash = identifier_hash(&identifier).expect("validated hex identifier");
-    {
-        let mut bucket = state.lookup_token_bucket.lock().await;
-        if !bucket.try_consume() {
-            tracing::warn!("global lookup rate-limit exceeded");
-            return retry_after_response(
-                StatusCode::SERVICE_UNAVAILABLE,
-                GLOBAL_OVERLOAD_RETRY_AFTER_SECS,
-                "Too many lookup requests, retry later",
-            );
-        }
+    if let Err(response) = consume_lookup_token(&state).await {
+        return response;
     }
     let candidate = generate_secret_id(&identifier, &authentication_key);
+    // Rotating onto the same credentials would insert nothing and delete the
+    // record: the client can see this coming, so it is a request error.
+    if matches!(&action, LookupAction::Rotate(new_secret) if new_secret.id == candidate) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(error_body(
+                "new_secret must use a different identifier or authentication_key",
+            )),
+        )
+            .into_response();
+    }
+    let is_trashing_secret = matches!(action, LookupAction::Trash);
+    let is_rotating_secret = matches!(action, LookupAction::Rotate(_));
+    let is_restoring_secret = matches!(action, LookupAction::Restore);
+    // With a grace period, deletions (`/trash`, the replaced record of
+    // `/rotate`) only mark the record, and `/restore` can cancel them.
     let requested_at = chrono::Utc::now();
-    let admission = {
-        let mut map = state.identifier_rate_limit.lock().await;
-        if map.get(&id_hash).is_some_and(|info| {
-            requested_at.signed_duration_since(info.last_candidate_at) > state.rate_limit_cooldown
-        }) {
-            map.remove(&id_hash);
-        }
-        if !map.contains_key(&id_hash) && map.len() >= state.rate_limit_max_identifiers {
-            map.retain(|_, info| {
-                requested_at.signed_duration_since(info.last_candidate_at)
-                    <= state.rate_limit_cooldown
-            });
-            if map.len() >= state.rate_limit_max_identifiers {
-                return retry_after_response(
-                    StatusCode::SERVICE_UNAVAILABLE,
-                    GLOBAL_OVERLOAD_RETRY_AFTER_SECS,
-                    "Rate-limit capacity exhausted, retry later",
-                );
-            }
-        }
-        let info = map
-            .entry(id_hash.clone())
-            .or_insert_with(|| RateLimitInfo::new(requested_at));
-        info.total_requests = info.total_requests.saturating_add(1);
-        info.last_request_at = requested_at;
-        // This check intentionally precedes membership, including for known
-        // candidates, so saturation cannot become an authentication oracle.
-        if info.candidate_count() >= state.rate_limit_max_attempts {
-            return rate_limited(
-                info.candidate_count(),
-                info.last_candidate_at,
-                requested_at,
-                &state,
-            );
-        }
-        match info.candidates.get(&candidate).copied() {
-            Some(CandidateState::Pending) => Admission::Pending,
-            Some(CandidateState::Committed) => Admission::Replay(
-                attempt_status(
-                    info,
-                    state.rate_limit_max_attempts,
-                    None,
-                    state.rate_limit_cooldown,
-                ),
-                info.window_started_at,
-            ),
-            None => {
-                let previous = (info.candidate_count() > 0).then_some(info.last_candidate_at);
-                info.candidates
-                    .insert(candidate.clone(), CandidateState::Pending);
-                info.last_candidate_at = requested_at;
-                Admission::New(
-                    attempt_status(
-                        info,
-                        state.rate_limit_max_attempts,
-                        previous,
-                        state.rate_limit_cooldown,
-                    ),
-                    info.window_started_at,
-                )
-            }
-        }
+    let deletion_at = (state.trash_grace_period > chrono::TimeDelta::zero())
+        .then(|| database_timestamp(requested_at + state.trash_grace_period));
+    let admission = match admit(
+        &state,
+        &id_hash,
+        &candidate,
+        request.pow.as_ref(),
+        requested_at,
+    )
+    .await
+    {
+        Ok(admission) => admission,
+        Err(response) => return response,
     };
     if matches!(admission, Admission::Pending) {
         return retry_after_response(
@@ -299,9 +560,9 @@ pub async fn fetch_secret(
             "Candidate lookup pending, retry later",
         );
     }
-    let (attempt_status, generation, is_new) = match admission {
-        Admission::New(status, generation) => (status, generation, true),
-        Admission::Replay(status, generation) => (status, generation, false),
+    let (attempt_status, generation, is_new, persisted_window) = match admission {
+        Admission::New(status, generation, window) => (status, generation, true, window),
+        Admission::Replay(status, generation) => (status, generation, false, None),
         Admission::Pending => unreachable!("pending admission returned above"),
     };
     let mut pending_guard = is_new.then(|| {
@@ -313,12 +574,17 @@ pub async fn fetch_secret(
         )
     });
 
-    let permit = match tokio::time::timeout(
+    let waiting_since = std::time::Instant::now();
+    let permit = tokio::time::timeout(
         DATABASE_PERMIT_TIMEOUT,
         state.database_semaphore.clone().acquire_owned(),
     )
-    .await
-    {
+    .await;
+    state
+        .metrics
+        .database_permit_wait
+        .observe(waiting_since.elapsed());
+    let permit = match permit {
         Ok(Ok(permit)) => permit,
         Ok(Err(_)) | Err(_) => {
             if let Some(guard) = pending_guard.as_mut() {
@@ -337,23 +603,36 @@ pub async fn fetch_secret(
         }
     };
 
-    let database_url = state.database_url.clone();
+    let secret_store = state.secret_store.clone();
     #[cfg(test)]
     let test_database_guard = state._test_database_guard.clone();
     let key_id = candidate.clone();
     let task_id_hash = id_hash.clone();
     let task_candidate = candidate.clone();
     let task_state = state.clone();
+    let rate_limit_state = state.rate_limit_state.clone();
+    let window_id_hash = id_hash.clone();
     let task = tokio::spawn(async move {
         let database_result = tokio::task::spawn_blocking(move || {
             #[cfg(test)]
             let _test_database_guard = test_database_guard;
             let _database_permit = permit;
-            let mut connection = establish_connection(database_url);
-            if is_trashing_secret {
-                read_and_trash_secret_by_id(&mut connection, &key_id)
-            } else {
-                read_secret_by_id(&mut connection, &key_id)
+            // The reservation reaches the state file before the lookup runs:
+            // if it cannot, the lookup does not happen either (and the slot
+            // is refunded like any database error).
+            if let (Some(rate_limit_state), Some(window)) = (rate_limit_state, persisted_window) {
+                rate_limit_state.persist_entry(&window_id_hash, &window)?;
+            }
+            match action {
+                LookupAction::Fetch => secret_store.read(&key_id),
+                LookupAction::Trash => match &deletion_at {
+                    Some(deletion_at) => secret_store.read_and_mark(&key_id, deletion_at),
+                    None => secret_store.read_and_trash(&key_id),
+                },
+                LookupAction::Rotate(new_secret) => {
+                    secret_store.rotate(&key_id, &new_secret, deletion_at.as_deref())
+                }
+                LookupAction::Restore => secret_store.restore(&key_id),
             }
         })
         .await;
@@ -414,6 +693,32 @@ pub async fn fetch_secret(
     };
 
     match result {
+        Some(_) if is_rotating_secret => {
+            tracing::info!(
+                attempts = attempt_status.total_attempts,
+                failed_attempts = attempt_status.failed_attempts,
+                "secret rotated"
+            );
+            // The replaced record is not returned: the caller already holds
+            // it, and the new one is whatever it just sent.
+            (
+                StatusCode::CREATED,
+                Json(json!({ "attempt_status": attempt_status })),
+            )
+                .into_response()
+        }
+        Some(_) if is_restoring_secret => {
+            tracing::info!(
+                attempts = attempt_status.total_attempts,
+                failed_attempts = attempt_status.failed_attempts,
+                "secret restored"
+            );
+            (
+                StatusCode::OK,
+                Json(json!({ "attempt_status": attempt_status })),
+            )
+                .into_response()
+        }
         Some(key) => {
             let code = if is_trashing_secret {
                 StatusCode::ACCEPTED
@@ -444,6 +749,7 @@ pub async fn fetch_secret(
                     rate_limit_cooldown: state.rate_limit_cooldown.num_minutes(),
                     attempts: attempt_status.total_attempts,
                     total_requests: attempt_status.total_requests,
+                    next_attempt_at: attempt_status.next_attempt_at,
                 }),
             )
                 .into_response()
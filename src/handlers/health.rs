@@ -0,0 +1,63 @@
+use std::sync::atomic::Ordering;
+
+use axum::{extract::State, http::StatusCode, Json};
+
+use crate::models::Readiness;
+use crate::AppState;
+
+/// The probe opens a fresh connection, which waits up to `busy_timeout`
+/// behind a writer: past this delay the database counts as unavailable.
+const READINESS_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Liveness: the process answers. Never touches the database, so a
+/// supervisor does not restart a server that is only waiting on it.
+pub async fn get_healthz() -> StatusCode {
+    StatusCode::OK
+}
+
+/// Readiness: not shutting down, and the database accepts a fresh
+/// connection, answers a query and has the current schema (see
+/// `SecretStore::readiness`). Also reports how full the rate-limit map is;
+/// a full map refuses new identifiers (`503`) but does not fail readiness.
+///
+/// The probe does not take a `database_semaphore` permit: it must report a
+/// saturated server as busy (`/metrics`), not as broken.
+pub async fn get_readyz(State(state): State<AppState>) -> (StatusCode, Json<Readiness>) {
+    let failed_check = if state.shutting_down.load(Ordering::SeqCst) {
+        Some("shutting down")
+    } else {
+        let secret_store = state.secret_store.clone();
+        #[cfg(test)]
+        let test_database_guard = state._test_database_guard.clone();
+        let probe = tokio::task::spawn_blocking(move || {
+            #[cfg(test)]
+            let _test_database_guard = test_database_guard;
+            secret_store.readiness()
+        });
+        match tokio::time::timeout(READINESS_TIMEOUT, probe).await {
+            Ok(Ok(Ok(()))) => None,
+            Ok(Ok(Err(check))) => Some(check),
+            Ok(Err(_)) => Some("query"),
+            Err(_) => Some("timeout"),
+        }
+    };
+    if let Some(check) = failed_check {
+        tracing::warn!(check, "readiness check failed");
+    }
+
+    let identifier_rate_limit_entries = state.identifier_rate_limit.lock().await.len();
+    let status = if failed_check.is_none() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(Readiness {
+            ready: failed_check.is_none(),
+            failed_check: failed_check.map(str::to_string),
+            identifier_rate_limit_entries,
+            identifier_rate_limit_capacity: state.rate_limit_max_identifiers,
+        }),
+    )
+}
@@ -29,14 +29,45 @@ pub async fn get_info(State(state): State<AppState>) -> (StatusCode, Json<Value>
         }
     };
 
+    // The signed canary is verified once per file change, never per
+    // request; expiry is evaluated at request time.
+    let signed_canary = state.signed_canary.as_ref().map(|source| {
+        let (signed, expired) = source.current(chrono::Utc::now());
+        (source, signed, expired)
+    });
+
     let info = &Info {
         canary,
-        secret_max_length: state.secret_max_length,
+        secret_max_length: state
+            .secret_max_length
+            .load(std::sync::atomic::Ordering::Relaxed),
         rate_limit_cooldown: state.rate_limit_cooldown.num_minutes() as u64,
         rate_limit_max_attempts: state.rate_limit_max_attempts,
         rate_limit_max_failed_attempts: state.rate_limit_max_attempts,
         attempts_collection_started_at: truncate_to_hour(state.attempts_collection_started_at),
         max_attempt_identifiers: state.rate_limit_max_identifiers,
+        rate_limit_backoff_base_seconds: state.rate_limit_backoff_base.num_seconds() as u64,
+        pow_difficulty_bits: state.pow_difficulty_bits,
+        pow_difficulty_step_bits: state.pow_difficulty_step_bits,
+        pow_challenge_lifetime_seconds: state.pow_challenge_lifetime.num_seconds() as u64,
+        trash_grace_period_hours: state.trash_grace_period.num_hours() as u64,
+        secret_max_ttl_days: state.secret_max_ttl_days,
+        attempts_public_key: hex::encode(state.attempts_signing_key.verifying_key().as_bytes()),
+        attempts_bucket_prefix_length: crate::handlers::attempts::BUCKET_PREFIX_LENGTH,
+        oprf_public_key: state.oprf_key.as_ref().map(|key| key.public_key_hex()),
+        opaque_public_key: state
+            .opaque_server
+            .as_ref()
+            .map(|server| server.public_key_hex()),
+        federation: state.federation.as_deref().cloned(),
+        canary_public_key: signed_canary
+            .as_ref()
+            .map(|(source, _, _)| source.public_key_hex()),
+        canary_key_fingerprint: signed_canary
+            .as_ref()
+            .map(|(source, _, _)| source.fingerprint()),
+        canary_expired: signed_canary.as_ref().map(|(_, _, expired)| *expired),
+        signed_canary: signed_canary.map(|(_, signed, _)| signed),
     };
 
     (StatusCode::OK, Json(json!(info)))
@@ -0,0 +1,162 @@
+use axum::extract::{Query, State};
+use axum::response::{IntoResponse, Response};
+use axum::{http::StatusCode, Json};
+
+use crate::handlers::attempts::consume_attempts_token;
+use crate::models::{
+    error_body, retry_after_response, ConsistencyProof, ConsistencyQuery, InclusionQuery,
+};
+use crate::transparency::{self, MerkleTree};
+use crate::AppState;
+
+const DATABASE_PERMIT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+const GLOBAL_OVERLOAD_RETRY_AFTER_SECS: u64 = 1;
+
+/// Why a query on the tree got no answer.
+enum Refusal {
+    BadRequest(&'static str),
+    NotFound,
+    Internal,
+}
+
+impl IntoResponse for Refusal {
+    fn into_response(self) -> Response {
+        match self {
+            Refusal::BadRequest(message) => {
+                (StatusCode::BAD_REQUEST, Json(error_body(message))).into_response()
+            }
+            Refusal::NotFound => (
+                StatusCode::NOT_FOUND,
+                Json(error_body("leaf not found in the tree")),
+            )
+                .into_response(),
+            Refusal::Internal => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(error_body("Internal server error")),
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// Runs `task` on the tree, synced with the leaves committed so far, on a
+/// blocking thread under a database permit.
+async fn with_tree<T: Send + 'static>(
+    state: &AppState,
+    task: impl FnOnce(&MerkleTree, &ed25519_dalek::SigningKey) -> Result<T, Refusal> + Send + 'static,
+) -> Result<T, Response> {
+    consume_attempts_token(state).await?;
+
+    let database_permit = match tokio::time::timeout(
+        DATABASE_PERMIT_TIMEOUT,
+        state.database_semaphore.clone().acquire_owned(),
+    )
+    .await
+    {
+        Ok(Ok(permit)) => permit,
+        Ok(Err(_)) | Err(_) => {
+            tracing::warn!("database concurrency limit exceeded");
+            return Err(retry_after_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                GLOBAL_OVERLOAD_RETRY_AFTER_SECS,
+                "Database busy, retry later",
+            ));
+        }
+    };
+
+    let secret_store = state.secret_store.clone();
+    let transparency_log = state.transparency_log.clone();
+    let signing_key = state.attempts_signing_key.clone();
+    #[cfg(test)]
+    let test_database_guard = state._test_database_guard.clone();
+    let task = tokio::task::spawn_blocking(move || {
+        #[cfg(test)]
+        let _test_database_guard = test_database_guard;
+        let _database_permit = database_permit;
+        match transparency_log.synced(&*secret_store) {
+            Ok(tree) => task(&tree, &signing_key),
+            Err(error) => {
+                tracing::error!(error = %error, "database error on the transparency log");
+                Err(Refusal::Internal)
+            }
+        }
+    })
+    .await;
+
+    match task {
+        Ok(result) => result.map_err(IntoResponse::into_response),
+        Err(error) => {
+            tracing::error!(error = %error, "database task panicked");
+            Err(Refusal::Internal.into_response())
+        }
+    }
+}
+
+/// `GET /log/head`: the signed head of the whole log.
+pub async fn get_head(State(state): State<AppState>) -> Response {
+    match with_tree(&state, |tree, signing_key| {
+        Ok(transparency::tree_head(tree, tree.size(), signing_key))
+    })
+    .await
+    {
+        Ok(head) => (StatusCode::OK, Json(head)).into_response(),
+        Err(response) => response,
+    }
+}
+
+/// `GET /log/inclusion`: the inclusion proof of a leaf, by hash. Only a
+/// holder of the record can compute its leaf hashes; for anyone else, a
+/// `404` says nothing about which records exist.
+pub async fn get_inclusion(
+    State(state): State<AppState>,
+    Query(query): Query<InclusionQuery>,
+) -> Response {
+    let mut leaf = [0u8; 32];
+    if hex::decode_to_slice(query.leaf_hash.to_lowercase(), &mut leaf).is_err() {
+        return Refusal::BadRequest("leaf_hash is not a 256 bits HEX hash").into_response();
+    }
+
+    match with_tree(&state, move |tree, signing_key| {
+        let tree_size = query.tree_size.unwrap_or(tree.size());
+        if tree_size > tree.size() {
+            return Err(Refusal::BadRequest("tree_size exceeds the size of the log"));
+        }
+        transparency::inclusion(tree, &leaf, tree_size, signing_key).ok_or(Refusal::NotFound)
+    })
+    .await
+    {
+        Ok(proof) => (StatusCode::OK, Json(proof)).into_response(),
+        Err(response) => response,
+    }
+}
+
+/// `GET /log/consistency`: proves the tree of `second` leaves extends the
+/// one of `first` leaves, with the head of `second`.
+pub async fn get_consistency(
+    State(state): State<AppState>,
+    Query(query): Query<ConsistencyQuery>,
+) -> Response {
+    match with_tree(&state, move |tree, signing_key| {
+        let second = query.second.unwrap_or(tree.size());
+        if query.first == 0 || query.first > second || second > tree.size() {
+            return Err(Refusal::BadRequest(
+                "expected 0 < first <= second <= the size of the log",
+            ));
+        }
+        Ok(ConsistencyProof {
+            first: query.first,
+            second,
+            consistency_path: tree
+                .consistency_proof(query.first, second)
+                .iter()
+                .map(hex::encode)
+                .collect(),
+            tree_head: transparency::tree_head(tree, second, signing_key),
+        })
+    })
+    .await
+    {
+        Ok(proof) => (StatusCode::OK, Json(proof)).into_response(),
+        Err(response) => response,
+    }
+}
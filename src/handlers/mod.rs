@@ -1,4 +1,12 @@
 pub mod attempts;
+pub mod challenge;
 pub mod fetch;
+pub mod health;
 pub mod info;
+pub mod log;
+pub mod opaque;
+pub mod oprf;
+pub mod replication;
+pub mod restore;
+pub mod rotate;
 pub mod store;
@@ -0,0 +1,489 @@
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::{http::StatusCode, Json};
+use serde_json::Value;
+
+use crate::handlers::fetch::{clear_failure, consume_lookup_token, reserve};
+use crate::handlers::store::consume_store_token;
+use crate::models::{
+    error_body, retry_after_response, AttemptStatus, OpaqueLoginChallenge, OpaqueLoginFinish,
+    OpaqueLoginResult, OpaqueLoginStart, OpaqueRecord, OpaqueRegistrationRequest,
+    OpaqueRegistrationResponse, OpaqueRegistrationUpload,
+};
+use crate::opaque::{is_valid_record, ServerLogin, KE1_LENGTH, RECORD_LENGTH};
+use crate::oprf::is_valid_element;
+use crate::secret_store::{SecretStore, StoreResult};
+use crate::utils::{identifier_hash, is_256bits_hex_hash, is_base64, sha256_hex};
+use crate::AppState;
+
+const DATABASE_PERMIT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+const GLOBAL_OVERLOAD_RETRY_AFTER_SECS: u64 = 1;
+
+/// Time between KE2 and KE3, or between the two steps of a registration:
+/// room for the client's Argon2 on a slow phone.
+const LOGIN_LIFETIME: chrono::TimeDelta = chrono::TimeDelta::seconds(120);
+
+/// A login between KE2 and KE3.
+pub struct PendingLogin {
+    server_login: ServerLogin,
+    id_hash: String,
+    candidate: String,
+    /// Window of the slot this login took, while its provisional failure is
+    /// still counted there (see `login_finish`).
+    charged: Option<chrono::DateTime<chrono::Utc>>,
+    /// `None` for an unregistered credential, whose login cannot succeed.
+    encrypted_secret: Option<String>,
+    attempt_status: AttemptStatus,
+    expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A registration between its evaluation and its record. Requiring it on
+/// `/opaque/register/finish` binds each record to a slot of the
+/// identifier's budget.
+pub struct PendingRegistration {
+    credential_id: String,
+    expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// The key of an identifier's OPAQUE record and the credential identifier
+/// its OPRF key is derived from: neither the identifier nor its `/attempts`
+/// hash.
+pub fn opaque_credential_id(identifier: &str) -> String {
+    sha256_hex(format!("opaque:{identifier}").as_bytes())
+}
+
+/// The candidate an OPAQUE evaluation is charged as: one per blinded
+/// element, shared by registration and login, since both evaluate the same
+/// per-credential OPRF key. A retried request is a free replay.
+pub fn opaque_candidate(identifier: &str, blinded_element: &str) -> String {
+    sha256_hex(format!("opaque-oprf:{identifier}{blinded_element}").as_bytes())
+}
+
+/// Drops the logins whose KE3 never came, with the secrets they hold, and
+/// the registrations never finished.
+pub async fn sweep_expired_logins(state: &AppState) {
+    let now = chrono::Utc::now();
+    state
+        .opaque_logins
+        .lock()
+        .await
+        .retain(|_, login| login.expires_at > now);
+    state
+        .opaque_registrations
+        .lock()
+        .await
+        .retain(|_, registration| registration.expires_at > now);
+}
+
+fn random_id() -> String {
+    let mut id = [0u8; 16];
+    getrandom::getrandom(&mut id).expect("the OS random number generator is available");
+    hex::encode(id)
+}
+
+fn not_enabled() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(error_body("OPAQUE is not enabled on this server")),
+    )
+        .into_response()
+}
+
+fn bad_request(message: &str) -> Response {
+    (StatusCode::BAD_REQUEST, Json(error_body(message))).into_response()
+}
+
+fn internal_error() -> Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(error_body("Internal server error")),
+    )
+        .into_response()
+}
+
+fn decode_hex<const N: usize>(value: &str) -> Option<[u8; N]> {
+    hex::decode(value).ok()?.try_into().ok()
+}
+
+/// Runs `task` on a blocking thread under a database permit, like the
+/// `/store` write. `Err` is the response to return as is.
+async fn with_store<T: Send + 'static>(
+    state: &AppState,
+    task: impl FnOnce(&dyn SecretStore) -> StoreResult<T> + Send + 'static,
+) -> Result<T, Response> {
+    let waiting_since = std::time::Instant::now();
+    let database_permit = tokio::time::timeout(
+        DATABASE_PERMIT_TIMEOUT,
+        state.database_semaphore.clone().acquire_owned(),
+    )
+    .await;
+    state
+        .metrics
+        .database_permit_wait
+        .observe(waiting_since.elapsed());
+    let Ok(Ok(database_permit)) = database_permit else {
+        tracing::warn!("database concurrency limit exceeded");
+        return Err(retry_after_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            GLOBAL_OVERLOAD_RETRY_AFTER_SECS,
+            "Database busy, retry later",
+        ));
+    };
+    let secret_store = state.secret_store.clone();
+    #[cfg(test)]
+    let test_database_guard = state._test_database_guard.clone();
+    let task = tokio::task::spawn_blocking(move || {
+        #[cfg(test)]
+        let _test_database_guard = test_database_guard;
+        let _database_permit = database_permit;
+        task(secret_store.as_ref())
+    })
+    .await;
+    match task {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(error)) => {
+            tracing::error!(error = %error, "database error on opaque record");
+            Err(internal_error())
+        }
+        Err(error) => {
+            tracing::error!(error = %error, "database task panicked");
+            Err(internal_error())
+        }
+    }
+}
+
+/// OPAQUE registration, first step: the OPRF evaluation of the client's
+/// blinded password. Like an `/oprf` evaluation, it is a guess against the
+/// credential, so it takes a slot of the identifier's budget; it is never a
+/// failed attempt.
+pub async fn register_start(
+    State(state): State<AppState>,
+    Json(request): Json<OpaqueRegistrationRequest>,
+) -> Response {
+    let Some(opaque_server) = state.opaque_server.clone() else {
+        return not_enabled();
+    };
+    let identifier = request.identifier.to_lowercase();
+    let blinded_element = request.blinded_element.to_lowercase();
+    let Some(blinded) = decode_hex::<32>(&blinded_element)
+        .filter(|blinded| is_256bits_hex_hash(&identifier) && is_valid_element(blinded))
+    else {
+        return bad_request("identifier or blinded_element are invalid");
+    };
+    let id_hash = identifier_hash(&identifier).expect("validated hex identifier");
+    // Pending registrations are bounded like the attempt map.
+    {
+        let mut registrations = state.opaque_registrations.lock().await;
+        if registrations.len() >= state.rate_limit_max_identifiers {
+            let now = chrono::Utc::now();
+            registrations.retain(|_, registration| registration.expires_at > now);
+            if registrations.len() >= state.rate_limit_max_identifiers {
+                return retry_after_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    GLOBAL_OVERLOAD_RETRY_AFTER_SECS,
+                    "Too many pending registrations, retry later",
+                );
+            }
+        }
+    }
+    if let Err(response) = consume_lookup_token(&state).await {
+        return response;
+    }
+
+    let candidate = opaque_candidate(&identifier, &blinded_element);
+    let reservation = match reserve(&state, &id_hash, &candidate, request.pow.as_ref()).await {
+        Ok(reservation) => reservation,
+        Err(response) => return response,
+    };
+    let attempt_status = reservation.attempt_status.clone();
+    reservation.commit(false).await;
+
+    let credential_id = opaque_credential_id(&identifier);
+    let registration_response = opaque_server
+        .registration_response(credential_id.as_bytes(), &blinded)
+        .expect("blinded element validated above");
+    let registration_id = random_id();
+    state.opaque_registrations.lock().await.insert(
+        registration_id.clone(),
+        PendingRegistration {
+            credential_id,
+            expires_at: chrono::Utc::now() + LOGIN_LIFETIME,
+        },
+    );
+    tracing::info!(
+        attempts = attempt_status.total_attempts,
+        "opaque registration evaluated"
+    );
+    (
+        StatusCode::OK,
+        Json(OpaqueRegistrationResponse {
+            registration_id,
+            registration_response: hex::encode(registration_response),
+            attempt_status,
+        }),
+    )
+        .into_response()
+}
+
+/// Body limit of `/opaque/register/finish`: the fixed-size fields stay
+/// under 600 bytes, with the headroom of the other routes on top of the
+/// secret.
+pub fn register_finish_body_limit(secret_max_length: usize) -> usize {
+    1024 + secret_max_length
+}
+
+/// Buffers the `/opaque/register/finish` body up to the limit derived from
+/// the current `secret_max_length`, before the JSON extractor sees it.
+pub async fn limit_register_finish_body(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let limit = register_finish_body_limit(
+        state
+            .secret_max_length
+            .load(std::sync::atomic::Ordering::Relaxed),
+    );
+    let (parts, body) = request.into_parts();
+    match axum::body::to_bytes(body, limit).await {
+        Ok(body) => next.run(Request::from_parts(parts, Body::from(body))).await,
+        Err(_) => (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(error_body("Request body too large")),
+        )
+            .into_response(),
+    }
+}
+
+/// OPAQUE registration, second step: stores the record and the secret,
+/// token-bucketed like `/store`. It takes the single-use `registration_id`
+/// of a started registration for the same identifier, so a record always
+/// costs a slot of its budget. An identifier already registered is never
+/// overwritten: the registration is refused with `409`.
+pub async fn register_finish(
+    State(state): State<AppState>,
+    Json(request): Json<OpaqueRegistrationUpload>,
+) -> Response {
+    if state.opaque_server.is_none() {
+        return not_enabled();
+    }
+    let identifier = request.identifier.to_lowercase();
+    let registration_record = request.registration_record.to_lowercase();
+    let encrypted_secret = request.encrypted_secret;
+    if !is_256bits_hex_hash(&identifier)
+        || !hex::decode(&registration_record).is_ok_and(|record| is_valid_record(&record))
+    {
+        return bad_request("identifier or registration_record are invalid");
+    }
+    let secret_max_length = state
+        .secret_max_length
+        .load(std::sync::atomic::Ordering::Relaxed);
+    if encrypted_secret.is_empty()
+        || encrypted_secret.len() > secret_max_length
+        || !is_base64(&encrypted_secret)
+    {
+        return bad_request(&format!(
+            "encrypted_secret must be base64, 1 to {secret_max_length} characters"
+        ));
+    }
+    if let Err(response) = consume_store_token(&state).await {
+        return response;
+    }
+    let credential_id = opaque_credential_id(&identifier);
+    let registration = state
+        .opaque_registrations
+        .lock()
+        .await
+        .remove(&request.registration_id.to_lowercase());
+    if !registration.is_some_and(|registration| {
+        registration.credential_id == credential_id && registration.expires_at > chrono::Utc::now()
+    }) {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(error_body("Unknown or expired registration")),
+        )
+            .into_response();
+    }
+
+    let record = OpaqueRecord {
+        credential_id,
+        created_at: chrono::Utc::now().to_rfc3339(),
+        registration_record,
+        encrypted_secret,
+    };
+    match with_store(&state, move |store| store.write_opaque(&record)).await {
+        Ok(true) => {
+            tracing::info!("opaque record stored");
+            (StatusCode::CREATED, Json(Value::Null)).into_response()
+        }
+        Ok(false) => {
+            tracing::info!("opaque registration refused, identifier already registered");
+            (
+                StatusCode::CONFLICT,
+                Json(error_body("Identifier already registered")),
+            )
+                .into_response()
+        }
+        Err(response) => response,
+    }
+}
+
+/// OPAQUE login, KE1 to KE2. The evaluation is a guess, charged like a
+/// `/fetch` candidate; it counts as a failed attempt until its KE3 proves
+/// otherwise, since a client holding a wrong password never sends one. An
+/// unregistered identifier gets a fake KE2 of the same shape.
+pub async fn login_start(
+    State(state): State<AppState>,
+    Json(request): Json<OpaqueLoginStart>,
+) -> Response {
+    let Some(opaque_server) = state.opaque_server.clone() else {
+        return not_enabled();
+    };
+    let identifier = request.identifier.to_lowercase();
+    let ke1_hex = request.ke1.to_lowercase();
+    let Some(ke1) = decode_hex::<KE1_LENGTH>(&ke1_hex).filter(|ke1| {
+        is_256bits_hex_hash(&identifier)
+            && is_valid_element(&ke1[..32].try_into().expect("32 bytes"))
+            && is_valid_element(&ke1[64..].try_into().expect("32 bytes"))
+    }) else {
+        return bad_request("identifier or ke1 are invalid");
+    };
+    let id_hash = identifier_hash(&identifier).expect("validated hex identifier");
+    // Pending logins are bounded like the attempt map.
+    {
+        let mut logins = state.opaque_logins.lock().await;
+        if logins.len() >= state.rate_limit_max_identifiers {
+            let now = chrono::Utc::now();
+            logins.retain(|_, login| login.expires_at > now);
+            if logins.len() >= state.rate_limit_max_identifiers {
+                return retry_after_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    GLOBAL_OVERLOAD_RETRY_AFTER_SECS,
+                    "Too many pending logins, retry later",
+                );
+            }
+        }
+    }
+    if let Err(response) = consume_lookup_token(&state).await {
+        return response;
+    }
+
+    let candidate = opaque_candidate(&identifier, &ke1_hex[..64]);
+    let reservation = match reserve(&state, &id_hash, &candidate, request.pow.as_ref()).await {
+        Ok(reservation) => reservation,
+        Err(response) => return response,
+    };
+    // Dropping the reservation on a database error gives the slot back.
+    let credential_id = opaque_credential_id(&identifier);
+    let read_credential_id = credential_id.clone();
+    let stored = match with_store(&state, move |store| store.read_opaque(&read_credential_id)).await
+    {
+        Ok(stored) => stored,
+        Err(response) => return response,
+    };
+    let attempt_status = reservation.attempt_status.clone();
+    let generation = reservation.commit(true).await;
+
+    let (record, encrypted_secret) = match stored {
+        Some(stored) => match decode_hex::<RECORD_LENGTH>(&stored.registration_record)
+            .filter(|record| is_valid_record(record))
+        {
+            Some(record) => (Some(record), Some(stored.encrypted_secret)),
+            None => {
+                tracing::error!("invalid stored opaque record");
+                (None, None)
+            }
+        },
+        None => (None, None),
+    };
+    let (ke2, server_login) = opaque_server
+        .login(credential_id.as_bytes(), record.as_ref(), &ke1)
+        .expect("ke1 validated above");
+
+    let login_id = random_id();
+    {
+        let mut logins = state.opaque_logins.lock().await;
+        // A retried KE1 is a replay: its login inherits the provisional
+        // failure of the first one, so whichever finishes clears it.
+        let charged = generation.or_else(|| {
+            logins
+                .values_mut()
+                .find(|login| login.id_hash == id_hash && login.candidate == candidate)
+                .and_then(|login| login.charged.take())
+        });
+        logins.insert(
+            login_id.clone(),
+            PendingLogin {
+                server_login,
+                id_hash,
+                candidate,
+                charged,
+                encrypted_secret,
+                attempt_status: attempt_status.clone(),
+                expires_at: chrono::Utc::now() + LOGIN_LIFETIME,
+            },
+        );
+    }
+    tracing::info!(
+        attempts = attempt_status.total_attempts,
+        "opaque login started"
+    );
+    (
+        StatusCode::OK,
+        Json(OpaqueLoginChallenge {
+            login_id,
+            ke2: hex::encode(ke2),
+            attempt_status,
+        }),
+    )
+        .into_response()
+}
+
+/// OPAQUE login, KE3: a valid client MAC releases the secret and clears the
+/// login's provisional failure. Each `login_id` is single use, so KE3 cannot
+/// be guessed online.
+pub async fn login_finish(
+    State(state): State<AppState>,
+    Json(request): Json<OpaqueLoginFinish>,
+) -> Response {
+    if state.opaque_server.is_none() {
+        return not_enabled();
+    }
+    let login_id = request.login_id.to_lowercase();
+    let Some(ke3) = decode_hex::<64>(&request.ke3) else {
+        return bad_request("ke3 is invalid");
+    };
+    let login = state.opaque_logins.lock().await.remove(&login_id);
+    let Some(login) = login.filter(|login| login.expires_at > chrono::Utc::now()) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(error_body("Unknown or expired login")),
+        )
+            .into_response();
+    };
+    let encrypted_secret = match login.encrypted_secret {
+        Some(encrypted_secret) if login.server_login.finish(&ke3) => encrypted_secret,
+        _ => {
+            tracing::info!("opaque login failed");
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(error_body("Authentication failed")),
+            )
+                .into_response();
+        }
+    };
+    if let Some(generation) = login.charged {
+        clear_failure(&state, &login.id_hash, generation).await;
+    }
+    tracing::info!("opaque login finished");
+    (
+        StatusCode::OK,
+        Json(OpaqueLoginResult {
+            encrypted_secret,
+            attempt_status: login.attempt_status,
+        }),
+    )
+        .into_response()
+}
@@ -0,0 +1,89 @@
+use axum::extract::State;
+use axum::response::{IntoResponse, Response};
+use axum::{http::StatusCode, Json};
+
+use crate::handlers::fetch::{consume_lookup_token, reserve};
+use crate::models::{error_body, OprfEvaluation, OprfRequest};
+use crate::oprf::is_valid_element;
+use crate::utils::{identifier_hash, is_256bits_hex_hash, sha256_hex};
+use crate::AppState;
+
+/// The candidate an evaluation is charged as: distinct from every
+/// `secret_id`, and the same for a retried request, which is then a free
+/// replay like a `/fetch` replay.
+pub fn oprf_candidate(identifier: &str, blinded_element: &str) -> String {
+    sha256_hex(format!("oprf:{identifier}{blinded_element}").as_bytes())
+}
+
+/// OPRF hardening of the password-derived keys (see oprf.rs). Every
+/// evaluation is a guess against the identifier's records, so it is
+/// admitted exactly like a `/fetch` candidate and takes one slot of the
+/// same budget: with a leaked database, each offline guess now needs an
+/// online, budgeted evaluation. The blinded element tells the server
+/// nothing about the password, so no evaluation is ever a failed attempt.
+/// The evaluation is bound to the identifier (POPRF `info`, see oprf.rs):
+/// the budget it was charged to is the only one it can help guess against.
+pub async fn evaluate(State(state): State<AppState>, Json(request): Json<OprfRequest>) -> Response {
+    let Some(oprf_key) = state.oprf_key.clone() else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(error_body("OPRF is not enabled on this server")),
+        )
+            .into_response();
+    };
+    let identifier = request.identifier.to_lowercase();
+    let blinded_element = request.blinded_element.to_lowercase();
+    let blinded = match <[u8; 32]>::try_from(hex::decode(&blinded_element).unwrap_or_default()) {
+        Ok(blinded) if is_256bits_hex_hash(&identifier) => blinded,
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(error_body(
+                    "identifier or blinded_element are not 256 bits HEX values",
+                )),
+            )
+                .into_response();
+        }
+    };
+    // Decoding is cheap and stateless: a malformed element costs no slot.
+    if !is_valid_element(&blinded) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(error_body(
+                "blinded_element is not a valid ristretto255 element",
+            )),
+        )
+            .into_response();
+    }
+    let id_hash = identifier_hash(&identifier).expect("validated hex identifier");
+    if let Err(response) = consume_lookup_token(&state).await {
+        return response;
+    }
+
+    let candidate = oprf_candidate(&identifier, &blinded_element);
+    let reservation = match reserve(&state, &id_hash, &candidate, request.pow.as_ref()).await {
+        Ok(reservation) => reservation,
+        Err(response) => return response,
+    };
+    let attempt_status = reservation.attempt_status.clone();
+    reservation.commit(false).await;
+
+    let info = hex::decode(&id_hash).expect("identifier_hash is hex");
+    // A zero tweaked key needs a sha256 preimage of `-skS`'s tweak.
+    let (evaluated, proof) = oprf_key
+        .evaluate(&blinded, &info)
+        .expect("blinded element validated above");
+    tracing::info!(
+        attempts = attempt_status.total_attempts,
+        "oprf evaluation released"
+    );
+    (
+        StatusCode::OK,
+        Json(OprfEvaluation {
+            evaluated_element: hex::encode(evaluated),
+            proof: hex::encode(proof),
+            attempt_status,
+        }),
+    )
+        .into_response()
+}
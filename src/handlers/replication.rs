@@ -0,0 +1,136 @@
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
+use axum::{http::StatusCode, Json};
+
+use crate::models::{error_body, retry_after_response, ReplicationBatch};
+use crate::replication::{is_stale, validate_batch, SIGNATURE_HEADER};
+use crate::AppState;
+
+const DATABASE_PERMIT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+const GLOBAL_OVERLOAD_RETRY_AFTER_SECS: u64 = 1;
+
+/// A batch sent before the last one applied, or too long ago: a replay.
+fn stale() -> Response {
+    tracing::warn!("stale replication batch refused");
+    (
+        StatusCode::CONFLICT,
+        Json(error_body("Stale replication batch")),
+    )
+        .into_response()
+}
+
+/// `POST /replication`: applies a batch pushed by the peer (see
+/// replication.rs). Served only on the replication listener. The signature
+/// is checked before the body is parsed; nothing is applied unless the
+/// whole batch is well-formed and sent after the last one applied.
+pub async fn receive_batch(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let Some(replication) = state.replication.clone() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !state.replication_token_bucket.lock().await.try_consume() {
+        tracing::warn!("replication rate-limit exceeded");
+        state
+            .metrics
+            .replication_bucket_rejections
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        return retry_after_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            GLOBAL_OVERLOAD_RETRY_AFTER_SECS,
+            "Too many replication batches, retry later",
+        );
+    }
+    let signature = headers
+        .get(SIGNATURE_HEADER)
+        .and_then(|signature| signature.to_str().ok());
+    if !signature.is_some_and(|signature| replication.verify(&body, signature)) {
+        tracing::warn!("replication batch with an invalid signature");
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(error_body("Invalid replication signature")),
+        )
+            .into_response();
+    }
+
+    let batch = match serde_json::from_slice::<ReplicationBatch>(&body) {
+        Ok(batch) => batch,
+        Err(_) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(error_body("malformed replication batch")),
+            )
+                .into_response();
+        }
+    };
+    let secret_max_length = state
+        .secret_max_length
+        .load(std::sync::atomic::Ordering::Relaxed);
+    if let Err(message) = validate_batch(&batch, secret_max_length) {
+        return (StatusCode::BAD_REQUEST, Json(error_body(message))).into_response();
+    }
+    if is_stale(&batch.sent_at, chrono::Utc::now()) {
+        return stale();
+    }
+
+    let database_permit = match tokio::time::timeout(
+        DATABASE_PERMIT_TIMEOUT,
+        state.database_semaphore.clone().acquire_owned(),
+    )
+    .await
+    {
+        Ok(Ok(permit)) => permit,
+        Ok(Err(_)) | Err(_) => {
+            tracing::warn!("database concurrency limit exceeded");
+            return retry_after_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                GLOBAL_OVERLOAD_RETRY_AFTER_SECS,
+                "Database busy, retry later",
+            );
+        }
+    };
+
+    let secret_store = state.secret_store.clone();
+    #[cfg(test)]
+    let test_database_guard = state._test_database_guard.clone();
+    let task = tokio::task::spawn_blocking(move || {
+        #[cfg(test)]
+        let _test_database_guard = test_database_guard;
+        let _database_permit = database_permit;
+        secret_store.apply_replication(&batch.sent_at, &batch.entries)
+    })
+    .await;
+
+    match task {
+        Ok(Ok(None)) => stale(),
+        Ok(Ok(Some(applied))) => {
+            // Log discipline: counts only, never ids.
+            tracing::info!(
+                written = applied.written,
+                deleted = applied.deleted,
+                "replication batch applied"
+            );
+            (StatusCode::OK, Json(applied)).into_response()
+        }
+        Ok(Err(error)) => {
+            tracing::error!(error = %error, "database error on replication");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(error_body("Internal server error")),
+            )
+                .into_response()
+        }
+        Err(error) => {
+            tracing::error!(error = %error, "database task panicked");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(error_body("Internal server error")),
+            )
+                .into_response()
+        }
+    }
+}
@@ -0,0 +1,16 @@
+use axum::extract::State;
+use axum::response::Response;
+use axum::Json;
+
+use crate::handlers::fetch::{lookup, LookupAction};
+use crate::models::FetchSecret;
+use crate::AppState;
+
+/// Cancels a delayed deletion. Same credentials, budget and proof-of-work as
+/// `/fetch`: restoring is no cheaper a guess than fetching.
+pub async fn restore_secret(
+    State(state): State<AppState>,
+    Json(request): Json<FetchSecret>,
+) -> Response {
+    lookup(state, request, LookupAction::Restore).await
+}
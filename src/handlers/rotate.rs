@@ -0,0 +1,34 @@
+use axum::extract::State;
+use axum::response::{IntoResponse, Response};
+use axum::{http::StatusCode, Json};
+
+use crate::handlers::fetch::{lookup, LookupAction};
+use crate::handlers::store::{consume_store_token, new_secret};
+use crate::models::{error_body, FetchSecret, RotateSecret};
+use crate::AppState;
+
+/// Replaces a record in one transaction: the old credentials are checked
+/// (and rate-limited) exactly like `/fetch`, and the new record is validated
+/// and token-bucketed exactly like `/store`.
+pub async fn rotate_secret(
+    State(state): State<AppState>,
+    Json(request): Json<RotateSecret>,
+) -> Response {
+    let replacement = match new_secret(&state, &request.new_secret) {
+        Ok(replacement) => replacement,
+        Err(message) => {
+            return (StatusCode::BAD_REQUEST, Json(error_body(message))).into_response();
+        }
+    };
+
+    if let Err(response) = consume_store_token(&state).await {
+        return response;
+    }
+
+    let current = FetchSecret {
+        identifier: request.identifier,
+        authentication_key: request.authentication_key,
+        pow: request.pow,
+    };
+    lookup(state, current, LookupAction::Rotate(replacement)).await
+}
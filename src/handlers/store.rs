@@ -1,10 +1,9 @@
 use axum::extract::State;
 use axum::response::{IntoResponse, Response};
 use axum::{http::StatusCode, Json};
-use serde_json::Value;
 
-use crate::database::establish_connection;
-use crate::models::{error_body, retry_after_response, Secret, StoreSecret};
+use crate::database::database_timestamp;
+use crate::models::{error_body, retry_after_response, Secret, StoreReceipt, StoreSecret};
 use crate::utils::{generate_secret_id, is_256bits_hex_hash, is_base64};
 use crate::AppState;
 
@@ -15,10 +14,9 @@ const DATABASE_PERMIT_TIMEOUT: std::time::Duration = std::time::Duration::from_s
 /// cooldown deadline to derive here, only "try again shortly".
 const GLOBAL_OVERLOAD_RETRY_AFTER_SECS: u64 = 1;
 
-pub async fn store_secret(
-    State(state): State<AppState>,
-    Json(request): Json<StoreSecret>,
-) -> Response {
+/// Canonicalizes and validates a record to store, shared by `/store` and the
+/// replacement record of `/rotate`. The error is the `400` message.
+pub fn new_secret(state: &AppState, request: &StoreSecret) -> Result<Secret, String> {
     // canonicalize hex inputs: "AB…" and "ab…" are the same logical value
     // and must map to the same record and the same rate-limit entry
     let authentication_key = &request.authentication_key.to_lowercase();
@@ -26,70 +24,120 @@ pub async fn store_secret(
     let identifier = &request.identifier.to_lowercase();
 
     if !is_256bits_hex_hash(identifier) || !is_256bits_hex_hash(authentication_key) {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(error_body(
-                "identifier or authentication_key are not 256 bits HEX hashes",
-            )),
-        )
-            .into_response();
+        return Err("identifier or authentication_key are not 256 bits HEX hashes".to_string());
     }
 
     if encrypted_secret.is_empty() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(error_body("encrypted_secret is empty")),
-        )
-            .into_response();
+        return Err("encrypted_secret is empty".to_string());
     }
 
     // Length before base64: the cheap check rejects oversized input without
     // paying for a full decode of a body that will be rejected anyway.
-    if encrypted_secret.len() > state.secret_max_length {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(error_body(format!(
-                "encrypted_secret length exceeds the limit {}",
-                state.secret_max_length
-            ))),
-        )
-            .into_response();
+    let secret_max_length = state
+        .secret_max_length
+        .load(std::sync::atomic::Ordering::Relaxed);
+    if encrypted_secret.len() > secret_max_length {
+        return Err(format!(
+            "encrypted_secret length exceeds the limit {secret_max_length}"
+        ));
     }
 
     if !is_base64(encrypted_secret) {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(error_body("encrypted_secret should be base64 encoded")),
-        )
-            .into_response();
+        return Err("encrypted_secret should be base64 encoded".to_string());
     }
 
-    // Global write damper: unauthenticated writes are token-bucketed so a
-    // flood cannot fill the database at full speed.
-    {
-        let mut bucket = state.store_token_bucket.lock().await;
-        if !bucket.try_consume() {
-            tracing::warn!("store rate-limit exceeded");
-            return retry_after_response(
-                StatusCode::SERVICE_UNAVAILABLE,
-                GLOBAL_OVERLOAD_RETRY_AFTER_SECS,
-                "Too many store requests, retry later",
-            );
+    let created_at = chrono::Utc::now();
+    let expires_at = match request.ttl_days {
+        None => None,
+        Some(ttl_days) if ttl_days == 0 || ttl_days > state.secret_max_ttl_days => {
+            return Err(format!(
+                "ttl_days must be between 1 and {}",
+                state.secret_max_ttl_days
+            ));
         }
-    }
+        Some(ttl_days) => Some(database_timestamp(
+            created_at + chrono::Duration::days(i64::from(ttl_days)),
+        )),
+    };
 
-    let key = Secret {
+    let (share_index, federation_id) = match (request.share_index, &request.federation_id) {
+        (None, None) => (None, None),
+        (Some(share_index), Some(federation_id)) => {
+            let Some(federation) = &state.federation else {
+                return Err("this server is not part of a federation".to_string());
+            };
+            let members = federation.peers.len() + 1;
+            if share_index == 0 || share_index as usize > members {
+                return Err(format!("share_index must be between 1 and {members}"));
+            }
+            let federation_id = federation_id.to_lowercase();
+            if !is_256bits_hex_hash(&federation_id) {
+                return Err("federation_id is not a 256 bits HEX hash".to_string());
+            }
+            (Some(share_index as i32), Some(federation_id))
+        }
+        _ => return Err("share_index and federation_id go together".to_string()),
+    };
+
+    Ok(Secret {
         id: generate_secret_id(identifier, authentication_key),
-        created_at: chrono::Utc::now().to_rfc3339(),
+        created_at: created_at.to_rfc3339(),
         encrypted_secret: encrypted_secret.clone(),
+        trash_after: None,
+        expires_at,
+        share_index,
+        federation_id,
+    })
+}
+
+/// Global write damper: unauthenticated writes are token-bucketed so a flood
+/// cannot fill the database at full speed. Shared by `/store` and `/rotate`.
+pub async fn consume_store_token(state: &AppState) -> Result<(), Response> {
+    let mut bucket = state.store_token_bucket.lock().await;
+    if !bucket.try_consume() {
+        tracing::warn!("store rate-limit exceeded");
+        state
+            .metrics
+            .store_bucket_rejections
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        return Err(retry_after_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            GLOBAL_OVERLOAD_RETRY_AFTER_SECS,
+            "Too many store requests, retry later",
+        ));
+    }
+    Ok(())
+}
+
+/// `POST /store`: `201` with the inclusion proof of the store in the
+/// transparency log, whether the record is new or a duplicate (see
+/// transparency.rs).
+pub async fn store_secret(
+    State(state): State<AppState>,
+    Json(request): Json<StoreSecret>,
+) -> Response {
+    let key = match new_secret(&state, &request) {
+        Ok(key) => key,
+        Err(message) => {
+            return (StatusCode::BAD_REQUEST, Json(error_body(message))).into_response();
+        }
     };
 
-    let database_permit = match tokio::time::timeout(
+    if let Err(response) = consume_store_token(&state).await {
+        return response;
+    }
+
+    let waiting_since = std::time::Instant::now();
+    let database_permit = tokio::time::timeout(
         DATABASE_PERMIT_TIMEOUT,
         state.database_semaphore.clone().acquire_owned(),
     )
-    .await
-    {
+    .await;
+    state
+        .metrics
+        .database_permit_wait
+        .observe(waiting_since.elapsed());
+    let database_permit = match database_permit {
         Ok(Ok(permit)) => permit,
         Ok(Err(_)) | Err(_) => {
             tracing::warn!("database concurrency limit exceeded");
@@ -103,20 +151,25 @@ pub async fn store_secret(
 
     // diesel is synchronous: run the write on a blocking thread so it
     // cannot stall the async workers
-    let database_url = state.database_url.clone();
+    let secret_store = state.secret_store.clone();
+    let transparency_log = state.transparency_log.clone();
+    let signing_key = state.attempts_signing_key.clone();
     #[cfg(test)]
     let test_database_guard = state._test_database_guard.clone();
     let task = tokio::task::spawn_blocking(move || {
         #[cfg(test)]
         let _test_database_guard = test_database_guard;
         let _database_permit = database_permit;
-        let mut connection = establish_connection(database_url);
-        crate::database::write(&mut connection, &key)
+        secret_store.write(&key)?;
+        let tree = transparency_log.synced(&*secret_store)?;
+        let leaf = crate::transparency::store_leaf(&key.id, &key.encrypted_secret, &key.created_at);
+        crate::transparency::inclusion(&tree, &leaf, tree.size(), &signing_key)
+            .ok_or_else(|| diesel::result::Error::NotFound)
     })
     .await;
 
-    let is_stored = match task {
-        Ok(is_stored) => is_stored,
+    let stored = match task {
+        Ok(stored) => stored,
         Err(error) => {
             tracing::error!(error = %error, "database task panicked");
             return (
@@ -127,14 +180,13 @@ pub async fn store_secret(
         }
     };
 
-    match is_stored {
-        true => {
+    match stored {
+        Ok(log) => {
             tracing::info!("secret stored");
-            // No useful body on success: the client only needs the status.
-            (StatusCode::CREATED, Json(Value::Null)).into_response()
+            (StatusCode::CREATED, Json(StoreReceipt { log })).into_response()
         }
-        false => {
-            tracing::error!("database error on store");
+        Err(error) => {
+            tracing::error!(error = %error, "database error on store");
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(error_body("Internal server error")),
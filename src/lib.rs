@@ -0,0 +1,297 @@
+//! Secret server library: the `keychain` server binary runs [`run`], and the
+//! `keychain-admin` binary reuses [`database`] for offline maintenance.
+
+pub mod admin;
+mod attempts_delta;
+mod canary;
+mod config;
+pub mod database;
+mod env;
+mod export;
+mod handlers;
+mod listener;
+mod metrics;
+pub mod models;
+mod opaque;
+mod oprf;
+#[cfg(feature = "postgres")]
+mod postgres;
+mod pow;
+mod rate_limit;
+mod rate_limit_state;
+mod reload;
+mod replication;
+mod retention;
+mod router;
+pub mod schema;
+mod secret_store;
+pub mod shamir;
+
+#[cfg(test)]
+mod tests;
+pub mod transparency;
+mod utils;
+mod xor_filter;
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize},
+        Arc,
+    },
+    time::Instant,
+};
+
+use axum::body::Bytes;
+use chrono::TimeDelta;
+use tokio::sync::{Mutex, Semaphore};
+
+/// Immutable `/attempts` representation: serialized and compressed at most
+/// once per TTL window, then shared by every response without copying.
+struct AttemptsSnapshotCache {
+    gzip_body: Arc<Bytes>,
+    etag: String,
+    /// Base64 Ed25519 signature of the uncompressed body, see
+    /// `handlers::attempts::SIGNATURE_CONTEXT`.
+    signature: String,
+    created_at: Instant,
+    /// This snapshot's entries, sorted: the next build is diffed against
+    /// them for `/attempts/delta`.
+    entries: Vec<models::AttemptEntry>,
+    collection_started_at: chrono::DateTime<chrono::Utc>,
+    /// Transitions of the previous builds, ending at this snapshot.
+    history: attempts_delta::History,
+    /// Delta bodies already built against this snapshot, by base ETag:
+    /// each is built once, like the snapshot itself.
+    delta_bodies: HashMap<String, AttemptsBody>,
+    /// `/attempts/filter` of the same build: an xor filter over the
+    /// identifiers with failed attempts, uncompressed.
+    filter: AttemptsBody,
+}
+
+/// A derived `/attempts` representation with its own ETag and signature.
+#[derive(Clone)]
+struct AttemptsBody {
+    body: Arc<Bytes>,
+    etag: String,
+    signature: String,
+}
+
+#[derive(Clone)]
+struct AppState {
+    /// `host:port`, or `unix:/path` for a Unix domain socket.
+    server_address: String,
+    /// Permissions applied to the Unix socket after binding.
+    server_socket_mode: u32,
+    /// The server only goes through `database_pool`; tests open side
+    /// connections (lock holders, direct inserts) to the same file.
+    #[cfg(test)]
+    database_url: String,
+    #[cfg(test)]
+    _test_database_guard: Arc<env::TestDatabaseGuard>,
+    /// Warrant canary captured at startup. Serves as the fallback when the
+    /// dotenv file is unreadable, and as the authoritative value when it
+    /// came from the process environment.
+    canary: String,
+    /// True when CANARY was provided by the process environment (dotenvy
+    /// never overrides it) or by CONFIG_FILE: the dotenv file is then
+    /// ignored at request time.
+    canary_from_env: bool,
+    /// Dotenv file the canary is re-read from, so an operator can update or
+    /// remove it without restarting the server.
+    canary_path: std::path::PathBuf,
+    /// Cache of the last dotenv-file canary parse, invalidated by file
+    /// metadata (modification time and length) rather than on every
+    /// request, since `/info` is deliberately not rate-limited.
+    canary_cache: Arc<Mutex<Option<env::CachedCanary>>>,
+    /// Operator-signed canary with an expiry; unset when not configured.
+    signed_canary: Option<Arc<canary::SignedCanarySource>>,
+    rate_limit_cooldown: TimeDelta,
+    identifier_rate_limit: Arc<Mutex<HashMap<String, models::RateLimitInfo>>>,
+    /// Reloadable (SIGHUP), like the token buckets and the snapshot TTL.
+    secret_max_length: Arc<AtomicUsize>,
+    rate_limit_max_attempts: u8,
+    /// Base delay of the escalating backoff beyond `rate_limit_max_attempts`;
+    /// zero keeps the hard cap until the window resets.
+    rate_limit_backoff_base: TimeDelta,
+    /// Proof-of-work difficulty for a fresh identifier; zero disables it.
+    pow_difficulty_bits: u32,
+    pow_difficulty_step_bits: u32,
+    pow_challenge_lifetime: TimeDelta,
+    /// Random key authenticating stateless `/challenge` values, drawn at
+    /// startup and never persisted.
+    pow_key: Arc<[u8; 32]>,
+    store_token_bucket: Arc<Mutex<rate_limit::TokenBucket>>,
+    lookup_token_bucket: Arc<Mutex<rate_limit::TokenBucket>>,
+    attempts_token_bucket: Arc<Mutex<rate_limit::TokenBucket>>,
+    rate_limit_max_identifiers: usize,
+    database_semaphore: Arc<Semaphore>,
+    /// Backend of the `secret` records, with one pooled connection per
+    /// `database_semaphore` permit.
+    secret_store: Arc<dyn secret_store::SecretStore>,
+    attempts_collection_started_at: chrono::DateTime<chrono::Utc>,
+    /// Delay between `/trash` and the deletion; zero deletes immediately.
+    trash_grace_period: TimeDelta,
+    /// Largest `ttl_days` accepted by `/store`; zero disables expiry.
+    secret_max_ttl_days: u32,
+    /// Optional persistence of `identifier_rate_limit` across restarts.
+    rate_limit_state: Option<Arc<rate_limit_state::StateFile>>,
+    attempts_snapshot: Arc<Mutex<Option<AttemptsSnapshotCache>>>,
+    attempts_snapshot_ttl_seconds: Arc<AtomicU64>,
+    /// Signs every `/attempts` snapshot build; its public half is in `/info`.
+    attempts_signing_key: Arc<ed25519_dalek::SigningKey>,
+    /// Evaluates `/oprf`; unset when OPRF_KEY_PATH is not configured.
+    oprf_key: Option<Arc<oprf::OprfKey>>,
+    /// Serves `/opaque/*`; unset when OPAQUE_KEY_PATH is not configured.
+    opaque_server: Option<Arc<opaque::OpaqueServer>>,
+    /// OPAQUE logins between KE2 and KE3, by `login_id`. Memory only: a
+    /// restart makes the clients start over.
+    opaque_logins: Arc<Mutex<HashMap<String, handlers::opaque::PendingLogin>>>,
+    /// OPAQUE registrations between their evaluation and their record, by
+    /// `registration_id`. Memory only, like `opaque_logins`.
+    opaque_registrations: Arc<Mutex<HashMap<String, handlers::opaque::PendingRegistration>>>,
+    /// The federation this server belongs to, listed in `/info`; unset
+    /// refuses shares on `/store`.
+    federation: Option<Arc<models::Federation>>,
+    /// The peer `/store`, `/rotate` and `/trash` are replicated to, and
+    /// accepted from on `/replication`; unset when REPLICATION_PEER_URL is
+    /// not configured.
+    replication: Option<Arc<replication::Replication>>,
+    /// Address of the listener serving `/replication` to the peer, never
+    /// SERVER_ADDRESS; set together with `replication`.
+    replication_address: Option<String>,
+    /// Dampens `/replication`, like `store_token_bucket` does `/store`.
+    replication_token_bucket: Arc<Mutex<rate_limit::TokenBucket>>,
+    /// In-memory Merkle tree over the `log_leaf` table, served on `/log/*`.
+    transparency_log: Arc<transparency::TransparencyLog>,
+    /// The effective configuration last applied, at startup or by a reload:
+    /// what a reload is compared against.
+    config: Arc<std::sync::Mutex<config::Config>>,
+    config_sources: Arc<config::ConfigSources>,
+    /// Aggregate counters, served on `operator_address` only.
+    metrics: Arc<metrics::Metrics>,
+    /// Loopback address of the operator listener (`/metrics`, `/healthz`,
+    /// `/readyz`); unset disables it.
+    operator_address: Option<String>,
+    /// Set as soon as a shutdown signal arrives, before the public listener
+    /// stops accepting: `/readyz` fails from then on.
+    shutting_down: Arc<std::sync::atomic::AtomicBool>,
+    /// Delay between the readiness flip and the graceful shutdown.
+    shutdown_drain: std::time::Duration,
+}
+
+/// `--check-config`: prints the effective configuration (CONFIG_FILE merged
+/// under the environment) and fails if any value is missing or invalid.
+pub fn check_config() -> std::process::ExitCode {
+    match config::ConfigSources::from_startup().and_then(|sources| sources.load()) {
+        Ok(config) => {
+            print!("{}", config.render());
+            std::process::ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::ExitCode::FAILURE
+        }
+    }
+}
+
+/// Starts the server and serves until SIGINT or SIGTERM.
+pub async fn run() {
+    let app_state = crate::env::init();
+
+    if listener::unix_socket_path(&app_state.server_address).is_none()
+        && !app_state.server_address.starts_with("127.0.0.1")
+        && !app_state.server_address.starts_with("localhost")
+        && !app_state.server_address.starts_with("[::1]")
+    {
+        eprintln!(
+            "WARNING: SERVER_ADDRESS ({}) is not loopback. This server is designed to run behind a Tor onion service or a TLS-terminating proxy; never expose it directly on a public interface.",
+            app_state.server_address
+        );
+    }
+
+    crate::database::init_db(app_state.clone());
+
+    crate::rate_limit::spawn_sweeper(app_state.clone());
+    crate::rate_limit_state::spawn_flusher(app_state.clone());
+    crate::retention::spawn_purger(app_state.clone());
+    if app_state.replication.is_some() {
+        crate::replication::spawn_shipper(app_state.clone());
+    }
+    crate::reload::spawn_reloader(app_state.clone());
+    if let Some(signed_canary) = &app_state.signed_canary {
+        crate::canary::spawn_expiry_watch(signed_canary.clone());
+    }
+
+    // Not part of the graceful shutdown: readiness keeps answering (failing)
+    // while the public listener drains.
+    if let Some(operator_address) = &app_state.operator_address {
+        let listener = tokio::net::TcpListener::bind(operator_address)
+            .await
+            .unwrap();
+        let operator_app = router::operator(app_state.clone());
+        tokio::spawn(async move {
+            axum::serve(listener, operator_app).await.unwrap();
+        });
+    }
+
+    // Batches from the peer, on their own listener: never reachable through
+    // the onion service.
+    if let Some(replication_address) = &app_state.replication_address {
+        let listener = tokio::net::TcpListener::bind(replication_address)
+            .await
+            .unwrap();
+        let replication_app = router::replication(app_state.clone());
+        tokio::spawn(async move {
+            axum::serve(listener, replication_app).await.unwrap();
+        });
+    }
+
+    let app = router::new(app_state.clone());
+
+    let listener =
+        listener::Listener::bind(&app_state.server_address, app_state.server_socket_mode)
+            .await
+            .unwrap();
+    tracing::info!("listening on {}", listener.local_address().unwrap());
+    listener
+        .serve(app, shutdown_signal(app_state.clone()))
+        .await
+        .unwrap();
+
+    // In-flight requests have finished: the final flush also carries the
+    // telemetry counters written since the last periodic flush.
+    crate::rate_limit_state::flush(&app_state).await;
+}
+
+/// Waits for SIGINT or SIGTERM. Graceful shutdown lets in-flight requests
+/// finish instead of being killed mid-handler: `/trash` commits its database
+/// transaction before sending the response, so an abrupt process kill
+/// between the commit and the response would make the caller retry (or give
+/// up on) a backup that was, in fact, already permanently deleted.
+///
+/// Readiness flips to failing first, then `shutdown_drain` gives a supervisor
+/// polling `/readyz` time to stop routing requests here.
+async fn shutdown_signal(state: AppState) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    tokio::select! {
+        _ = ctrl_c => tracing::info!("received SIGINT, starting graceful shutdown"),
+        _ = terminate => tracing::info!("received SIGTERM, starting graceful shutdown"),
+    }
+    state
+        .shutting_down
+        .store(true, std::sync::atomic::Ordering::SeqCst);
+    tokio::time::sleep(state.shutdown_drain).await;
+}
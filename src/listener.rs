@@ -0,0 +1,136 @@
+//! The public listener: TCP, or a Unix domain socket when SERVER_ADDRESS is
+//! `unix:/path`. A socket keeps the server off the host's TCP ports
+//! entirely; nginx or Tor connect to the path instead of a loopback port.
+//!
+//! axum 0.7's `serve` only accepts a `TcpListener`, so the Unix listener has
+//! its own accept loop: HTTP/1.1 (all the proxies in front speak it), with
+//! the same graceful shutdown.
+
+use std::future::Future;
+use std::io;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use axum::Router;
+use hyper_util::rt::TokioIo;
+use hyper_util::server::graceful::GracefulShutdown;
+use hyper_util::service::TowerToHyperService;
+use tokio::net::{TcpListener, UnixListener, UnixStream};
+
+/// Path of a `unix:/path` SERVER_ADDRESS, `None` for a TCP address.
+pub fn unix_socket_path(server_address: &str) -> Option<&Path> {
+    server_address.strip_prefix("unix:").map(Path::new)
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix {
+        listener: UnixListener,
+        path: PathBuf,
+    },
+}
+
+impl Listener {
+    /// Binds SERVER_ADDRESS. A Unix socket gets `socket_mode` permissions; a
+    /// stale socket left by a crashed server is replaced, but a socket
+    /// another server still accepts on, or any other file, is not.
+    pub async fn bind(server_address: &str, socket_mode: u32) -> io::Result<Self> {
+        let Some(path) = unix_socket_path(server_address) else {
+            return Ok(Listener::Tcp(TcpListener::bind(server_address).await?));
+        };
+
+        match std::fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_socket() => {
+                if UnixStream::connect(path).await.is_ok() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AddrInUse,
+                        format!("{} is in use by a running server", path.display()),
+                    ));
+                }
+                tracing::info!("removing stale socket");
+                std::fs::remove_file(path)?;
+            }
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", path.display()),
+                ));
+            }
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
+
+        let listener = UnixListener::bind(path)?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(socket_mode))?;
+        Ok(Listener::Unix {
+            listener,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// The bound address, in SERVER_ADDRESS form (`127.0.0.1:3000`,
+    /// `unix:/run/keychain/keychain.sock`).
+    pub fn local_address(&self) -> io::Result<String> {
+        match self {
+            Listener::Tcp(listener) => Ok(listener.local_addr()?.to_string()),
+            Listener::Unix { path, .. } => Ok(format!("unix:{}", path.display())),
+        }
+    }
+
+    /// Serves `app` until `signal` completes, then lets in-flight requests
+    /// finish. The socket file is removed once the listener is closed.
+    pub async fn serve(
+        self,
+        app: Router,
+        signal: impl Future<Output = ()> + Send + 'static,
+    ) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => {
+                axum::serve(listener, app)
+                    .with_graceful_shutdown(signal)
+                    .await
+            }
+            Listener::Unix { listener, path } => {
+                let result = serve_unix(&listener, app, signal).await;
+                drop(listener);
+                let _ = std::fs::remove_file(&path);
+                result
+            }
+        }
+    }
+}
+
+async fn serve_unix(
+    listener: &UnixListener,
+    app: Router,
+    signal: impl Future<Output = ()>,
+) -> io::Result<()> {
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(signal);
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                // Per-connection failures (e.g. the peer went away) must not
+                // stop the listener; back off briefly on resource exhaustion.
+                Err(error) => {
+                    tracing::warn!(%error, "failed to accept a connection");
+                    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                    continue;
+                }
+            },
+            _ = &mut signal => break,
+        };
+
+        let connection = hyper::server::conn::http1::Builder::new()
+            .serve_connection(TokioIo::new(stream), TowerToHyperService::new(app.clone()));
+        let connection = graceful.watch(connection);
+        tokio::spawn(async move {
+            if let Err(error) = connection.await {
+                tracing::debug!(%error, "failed to serve a connection");
+            }
+        });
+    }
+    graceful.shutdown().await;
+    Ok(())
+}
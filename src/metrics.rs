@@ -0,0 +1,235 @@
+//! Operator metrics in the Prometheus text format.
+//!
+//! `/metrics` is never part of the public router: it is served on the
+//! operator listener (OPERATOR_ADDRESS, loopback only, see
+//! `router::operator`), so it cannot be reached through the onion service.
+//! Everything here is an aggregate. Label values come from fixed sets (route
+//! templates, status codes, bucket names), never from a request: no
+//! identifier, id_hash, secret_id or raw path can become a series.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::AppState;
+
+/// Routes of the public router. Anything else (404s, whose path is chosen by
+/// the client) is counted as `other`.
+const ROUTES: &[&str] = &[
+    "/store",
+    "/fetch",
+    "/trash",
+    "/rotate",
+    "/restore",
+    "/oprf",
+    "/opaque/register/start",
+    "/opaque/register/finish",
+    "/opaque/login/start",
+    "/opaque/login/finish",
+    "/info",
+    "/challenge",
+    "/attempts",
+    "/attempts/delta",
+    "/attempts/bucket/:prefix",
+    "/attempts/filter",
+    "/log/head",
+    "/log/inclusion",
+    "/log/consistency",
+    "/replication",
+];
+
+/// The database permit times out after one second (`DATABASE_PERMIT_TIMEOUT`).
+const PERMIT_WAIT_BOUNDS: &[f64] = &[0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0];
+const SNAPSHOT_REBUILD_BOUNDS: &[f64] = &[0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+/// Fixed-bucket histogram of durations.
+pub struct Histogram {
+    bounds: &'static [f64],
+    /// Per bucket, not cumulative; the last one is `+Inf`.
+    buckets: Vec<AtomicU64>,
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum_nanos: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let index = self
+            .bounds
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos.fetch_add(
+            u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    fn render(&self, output: &mut String, name: &str, help: &str) {
+        output.push_str(&format!("# HELP {name} {help}\n# TYPE {name} histogram\n"));
+        let mut cumulative = 0;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let bound = self
+                .bounds
+                .get(index)
+                .map_or_else(|| "+Inf".to_string(), f64::to_string);
+            output.push_str(&format!("{name}_bucket{{le=\"{bound}\"}} {cumulative}\n"));
+        }
+        let sum = Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed)).as_secs_f64();
+        output.push_str(&format!("{name}_sum {sum}\n{name}_count {cumulative}\n"));
+    }
+}
+
+pub struct Metrics {
+    responses: std::sync::Mutex<BTreeMap<(&'static str, u16), u64>>,
+    pub store_bucket_rejections: AtomicU64,
+    pub lookup_bucket_rejections: AtomicU64,
+    pub attempts_bucket_rejections: AtomicU64,
+    pub replication_bucket_rejections: AtomicU64,
+    pub sweeper_removals: AtomicU64,
+    pub database_permit_wait: Histogram,
+    pub snapshot_rebuild: Histogram,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            responses: std::sync::Mutex::new(BTreeMap::new()),
+            store_bucket_rejections: AtomicU64::new(0),
+            lookup_bucket_rejections: AtomicU64::new(0),
+            attempts_bucket_rejections: AtomicU64::new(0),
+            replication_bucket_rejections: AtomicU64::new(0),
+            sweeper_removals: AtomicU64::new(0),
+            database_permit_wait: Histogram::new(PERMIT_WAIT_BOUNDS),
+            snapshot_rebuild: Histogram::new(SNAPSHOT_REBUILD_BOUNDS),
+        }
+    }
+}
+
+fn render_counter(output: &mut String, name: &str, help: &str, value: u64) {
+    output.push_str(&format!(
+        "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}\n"
+    ));
+}
+
+fn render_gauge(output: &mut String, name: &str, help: &str, value: usize) {
+    output.push_str(&format!(
+        "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}\n"
+    ));
+}
+
+impl Metrics {
+    fn record_response(&self, route: &'static str, status: StatusCode) {
+        let mut responses = self.responses.lock().unwrap_or_else(|e| e.into_inner());
+        *responses.entry((route, status.as_u16())).or_default() += 1;
+    }
+
+    /// The whole exposition. `identifier_rate_limit_entries` and
+    /// `database_permits_available` are read by the caller at scrape time.
+    pub fn render(
+        &self,
+        identifier_rate_limit_entries: usize,
+        database_permits_available: usize,
+    ) -> String {
+        let mut output = String::new();
+        output.push_str(
+            "# HELP keychain_http_responses_total Responses by route and status code.\n\
+             # TYPE keychain_http_responses_total counter\n",
+        );
+        {
+            let responses = self.responses.lock().unwrap_or_else(|e| e.into_inner());
+            for ((route, status), count) in responses.iter() {
+                output.push_str(&format!(
+                    "keychain_http_responses_total{{route=\"{route}\",status=\"{status}\"}} {count}\n"
+                ));
+            }
+        }
+        output.push_str(
+            "# HELP keychain_token_bucket_rejections_total Requests refused by a global token bucket.\n\
+             # TYPE keychain_token_bucket_rejections_total counter\n",
+        );
+        for (bucket, counter) in [
+            ("store", &self.store_bucket_rejections),
+            ("lookup", &self.lookup_bucket_rejections),
+            ("attempts", &self.attempts_bucket_rejections),
+            ("replication", &self.replication_bucket_rejections),
+        ] {
+            output.push_str(&format!(
+                "keychain_token_bucket_rejections_total{{bucket=\"{bucket}\"}} {}\n",
+                counter.load(Ordering::Relaxed)
+            ));
+        }
+        render_counter(
+            &mut output,
+            "keychain_rate_limit_sweeper_removals_total",
+            "Expired rate-limit windows removed by the periodic sweep.",
+            self.sweeper_removals.load(Ordering::Relaxed),
+        );
+        render_gauge(
+            &mut output,
+            "keychain_identifier_rate_limit_entries",
+            "Identifiers currently tracked by the rate limiter.",
+            identifier_rate_limit_entries,
+        );
+        render_gauge(
+            &mut output,
+            "keychain_database_permits_available",
+            "Free database concurrency slots.",
+            database_permits_available,
+        );
+        self.database_permit_wait.render(
+            &mut output,
+            "keychain_database_permit_wait_seconds",
+            "Time spent waiting for a database concurrency slot, timeouts included.",
+        );
+        self.snapshot_rebuild.render(
+            &mut output,
+            "keychain_attempts_snapshot_rebuild_seconds",
+            "Duration of the /attempts snapshot rebuilds.",
+        );
+        output
+    }
+}
+
+/// Middleware of the public router: counts every response by route template
+/// and status, including the body-limit and timeout rejections.
+pub async fn count_responses(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|matched| ROUTES.iter().find(|route| **route == matched.as_str()))
+        .copied()
+        .unwrap_or("other");
+    let response = next.run(request).await;
+    state.metrics.record_response(route, response.status());
+    response
+}
+
+pub async fn get_metrics(State(state): State<AppState>) -> Response {
+    let identifier_rate_limit_entries = state.identifier_rate_limit.lock().await.len();
+    let body = state.metrics.render(
+        identifier_rate_limit_entries,
+        state.database_semaphore.available_permits(),
+    );
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response()
+}
@@ -44,6 +44,75 @@ pub struct Info {
     /// Configured capacity of the attempt map, so clients can compute the
     /// snapshot fullness ratio. Never a live count.
     pub max_attempt_identifiers: usize,
+    /// Base delay of the escalating backoff beyond `rate_limit_max_attempts`
+    /// (candidate `max + n` unlocks `base * 2^n` after the previous one).
+    /// Zero when disabled: the budget then unlocks only at the window reset.
+    pub rate_limit_backoff_base_seconds: u64,
+    /// Current proof-of-work difficulty for a fresh identifier; zero when
+    /// `/fetch` and `/trash` do not require a solved challenge.
+    pub pow_difficulty_bits: u32,
+    /// Extra bits required per distinct candidate already admitted.
+    pub pow_difficulty_step_bits: u32,
+    pub pow_challenge_lifetime_seconds: u64,
+    /// Delay between `/trash` and the actual deletion, during which
+    /// `/restore` cancels it. Zero when `/trash` deletes immediately.
+    pub trash_grace_period_hours: u64,
+    /// Largest `ttl_days` accepted by `/store`; zero when the server does
+    /// not offer expiry.
+    pub secret_max_ttl_days: u32,
+    /// Ed25519 public key verifying the `Attempts-Signature` header of
+    /// `/attempts`, raw and in hex.
+    pub attempts_public_key: String,
+    /// Hex characters of `id_hash` an `/attempts/bucket/{prefix}` query
+    /// takes: exactly this many.
+    pub attempts_bucket_prefix_length: usize,
+    /// ristretto255 public key of the `/oprf` evaluations, in hex, when
+    /// the server offers OPRF hardening: clients pin it and verify every
+    /// evaluation's proof against it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oprf_public_key: Option<String>,
+    /// ristretto255 public key of the OPAQUE server, in hex, when the
+    /// server offers `/opaque/*`. The envelope authenticates it already;
+    /// its presence tells clients they can register.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opaque_public_key: Option<String>,
+    /// The other Key Servers of this server's federation and the number of
+    /// shares a recovery needs, when the operator configured one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub federation: Option<Federation>,
+    /// Operator-signed warrant canary, when configured (see canary.rs).
+    /// Absent fields keep older clients and servers compatible.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signed_canary: Option<SignedCanary>,
+    /// Raw Ed25519 public key the canary is signed with, in hex.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canary_public_key: Option<String>,
+    /// SHA-256 of `canary_public_key`, in hex: the value clients pin.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canary_key_fingerprint: Option<String>,
+    /// True once the signed canary is past its `expires_at` without a
+    /// renewal: clients must treat it like a removed canary.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canary_expired: Option<bool>,
+}
+
+/// A federation of independent Key Servers, each holding one Shamir share
+/// of a client's secret (see shamir.rs).
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Federation {
+    /// Onion addresses of the peers, this server excluded.
+    pub peers: Vec<String>,
+    /// Shares needed to recover a secret.
+    pub threshold: u8,
+}
+
+/// The signed canary as stored and served: the document bytes and their
+/// detached Ed25519 signature, both base64.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SignedCanary {
+    pub document: String,
+    pub signature: String,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -51,12 +120,148 @@ pub struct StoreSecret {
     pub identifier: String,
     pub authentication_key: String,
     pub encrypted_secret: String,
+    /// Optional retention in days, at most `secret_max_ttl_days` from
+    /// `/info`. Without it the record is kept until trashed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_days: Option<u32>,
+    /// Index of the Shamir share this record holds, 1 to the federation
+    /// size; only accepted by a server configured with a federation, and
+    /// always with `federation_id`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub share_index: Option<u32>,
+    /// Names the split the share belongs to, 64 hex characters chosen by
+    /// the client, so that it can tell shares of different splits apart.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub federation_id: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct FetchSecret {
     pub identifier: String,
     pub authentication_key: String,
+    /// Solved `/challenge` puzzle; required when the server enables
+    /// proof-of-work (`pow_difficulty_bits > 0` in `/info`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pow: Option<ProofOfWork>,
+}
+
+/// `/rotate` request: the current credentials, authenticated and rate-limited
+/// exactly like `/fetch`, and the record that replaces them.
+#[derive(Serialize, Deserialize)]
+pub struct RotateSecret {
+    pub identifier: String,
+    pub authentication_key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pow: Option<ProofOfWork>,
+    pub new_secret: StoreSecret,
+}
+
+/// `/oprf` request: a blinded element to evaluate for `identifier`, charged
+/// to that identifier's budget like a `/fetch` candidate.
+#[derive(Serialize, Deserialize)]
+pub struct OprfRequest {
+    pub identifier: String,
+    /// Compressed ristretto255 element, in hex.
+    pub blinded_element: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pow: Option<ProofOfWork>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OprfEvaluation {
+    /// Compressed ristretto255 element, in hex.
+    pub evaluated_element: String,
+    /// DLEQ proof `c || s` against `/info` `oprf_public_key` tweaked by
+    /// the identifier's POPRF `info`, in hex.
+    pub proof: String,
+    pub attempt_status: AttemptStatus,
+}
+
+/// `/opaque/register/start` request: the OPAQUE registration request (a
+/// blinded element), charged to `identifier`'s budget like an `/oprf`
+/// evaluation.
+#[derive(Serialize, Deserialize)]
+pub struct OpaqueRegistrationRequest {
+    pub identifier: String,
+    /// Compressed ristretto255 element, in hex.
+    pub blinded_element: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pow: Option<ProofOfWork>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OpaqueRegistrationResponse {
+    /// Single use, required by `/opaque/register/finish`.
+    pub registration_id: String,
+    /// `evaluated_message || server_public_key`, in hex.
+    pub registration_response: String,
+    pub attempt_status: AttemptStatus,
+}
+
+/// `/opaque/register/finish` request: the record to store for
+/// `identifier`, with the secret a successful login releases.
+#[derive(Serialize, Deserialize)]
+pub struct OpaqueRegistrationUpload {
+    /// From the `/opaque/register/start` response for `identifier`.
+    pub registration_id: String,
+    pub identifier: String,
+    /// `client_public_key || masking_key || envelope`, in hex.
+    pub registration_record: String,
+    pub encrypted_secret: String,
+}
+
+/// `/opaque/login/start` request.
+#[derive(Serialize, Deserialize)]
+pub struct OpaqueLoginStart {
+    pub identifier: String,
+    /// KE1, in hex.
+    pub ke1: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pow: Option<ProofOfWork>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OpaqueLoginChallenge {
+    /// Names this login in `/opaque/login/finish`.
+    pub login_id: String,
+    /// KE2, in hex.
+    pub ke2: String,
+    pub attempt_status: AttemptStatus,
+}
+
+/// `/opaque/login/finish` request.
+#[derive(Serialize, Deserialize)]
+pub struct OpaqueLoginFinish {
+    pub login_id: String,
+    /// KE3 (the client MAC), in hex.
+    pub ke3: String,
+}
+
+/// `/opaque/login/finish` response, like a `/fetch` one.
+#[derive(Serialize, Deserialize)]
+pub struct OpaqueLoginResult {
+    pub encrypted_secret: String,
+    pub attempt_status: AttemptStatus,
+}
+
+/// A hashcash-style solution: `sha256(challenge ":" id_hash ":" candidate
+/// ":" nonce)` must start with the required number of zero bits, where
+/// `id_hash` is the `/attempts` hash of the identifier and `candidate` is the
+/// `secret_id` (both lowercase hex).
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ProofOfWork {
+    pub challenge: String,
+    pub nonce: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Challenge {
+    pub challenge: String,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    /// Difficulty for an identifier without candidates in its window.
+    pub difficulty_bits: u32,
+    /// Extra bits per distinct candidate already admitted in the window.
+    pub difficulty_step_bits: u32,
 }
 
 #[derive(Insertable, Serialize, Deserialize, Queryable, Selectable)]
@@ -65,6 +270,132 @@ pub struct Secret {
     pub id: String,
     pub created_at: String,
     pub encrypted_secret: String,
+    /// When a trashed record is purged, unless `/restore` cancels it first
+    /// (see `TRASH_GRACE_PERIOD_HOURS`). Absent for a live record.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trash_after: Option<String>,
+    /// End of the retention requested with `ttl_days`. Absent for a record
+    /// stored without one, which never expires.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+    /// Shamir share of a federated record, returned as stored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub share_index: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub federation_id: Option<String>,
+}
+
+/// An OPAQUE registration (see opaque.rs), one per credential.
+#[derive(Insertable, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::opaque_record)]
+pub struct OpaqueRecord {
+    /// `opaque_credential_id` of the identifier: never the identifier
+    /// itself, nor its `/attempts` hash.
+    pub credential_id: String,
+    pub created_at: String,
+    /// `client_public_key || masking_key || envelope`, in hex.
+    pub registration_record: String,
+    pub encrypted_secret: String,
+}
+
+/// One change shipped to the replication peer (see replication.rs).
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplicationEntry {
+    /// A record inserted on the sender, applied with the `/store` semantics.
+    Write(Secret),
+    /// A record deleted on the sender: by `/trash`, `/rotate`, or the purge
+    /// at the end of its grace period.
+    Tombstone { id: String, deleted_at: String },
+}
+
+/// Body of `POST /replication`, signed with the replication key.
+#[derive(Serialize, Deserialize)]
+pub struct ReplicationBatch {
+    /// When the sender pushed the batch. Signed with the entries, so that a
+    /// replay is recognized: the receiver only accepts batches sent after
+    /// the last one it applied.
+    pub sent_at: String,
+    pub entries: Vec<ReplicationEntry>,
+}
+
+/// Changes a batch made on the receiving peer; entries it already had
+/// count for nothing.
+#[derive(Serialize, Deserialize)]
+pub struct ReplicationApplied {
+    pub written: usize,
+    pub deleted: usize,
+}
+
+/// A local change not yet acknowledged by the peer: `record` (the JSON of
+/// a `Secret`) for a write, `deleted_at` for a tombstone.
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::schema::replication_log)]
+pub struct ReplicationLogRow {
+    pub seq: i32,
+    pub secret_id: String,
+    pub record: Option<String>,
+    pub deleted_at: Option<String>,
+}
+
+/// A leaf of the transparency log (see transparency.rs), in hex.
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::schema::log_leaf)]
+pub struct LogLeaf {
+    pub leaf_index: i64,
+    pub leaf_hash: String,
+}
+
+/// Signed head of the first `tree_size` leaves of the transparency log.
+#[derive(Serialize, Deserialize)]
+pub struct TreeHead {
+    pub tree_size: u64,
+    pub root_hash: String,
+    /// Unix time of the signature, in seconds.
+    pub timestamp: i64,
+    /// Ed25519, under `attempts_public_key`, in base64.
+    pub signature: String,
+}
+
+/// `/log/inclusion` response, and the receipt of `/store`: the audit path
+/// of a leaf in the tree of `tree_size` leaves, and that tree's head.
+#[derive(Serialize, Deserialize)]
+pub struct InclusionProof {
+    pub leaf_index: u64,
+    pub tree_size: u64,
+    pub audit_path: Vec<String>,
+    pub tree_head: TreeHead,
+}
+
+/// `/log/consistency` response: proves the tree of `second` leaves, whose
+/// head is included, extends the tree of `first` leaves.
+#[derive(Serialize, Deserialize)]
+pub struct ConsistencyProof {
+    pub first: u64,
+    pub second: u64,
+    pub consistency_path: Vec<String>,
+    pub tree_head: TreeHead,
+}
+
+/// `/store` response: where the store was logged.
+#[derive(Serialize, Deserialize)]
+pub struct StoreReceipt {
+    pub log: InclusionProof,
+}
+
+/// `/log/inclusion` query: a leaf hash, in hex, and the tree size to prove
+/// it in, the current one by default.
+#[derive(Serialize, Deserialize)]
+pub struct InclusionQuery {
+    pub leaf_hash: String,
+    pub tree_size: Option<u64>,
+}
+
+/// `/log/consistency` query; `second` is the current size by default.
+#[derive(Serialize, Deserialize)]
+pub struct ConsistencyQuery {
+    pub first: u64,
+    pub second: Option<u64>,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -105,6 +436,48 @@ impl RateLimitInfo {
             .try_into()
             .expect("candidate map cannot exceed the configured u8 bound")
     }
+
+    /// When the escalating backoff admits the next distinct candidate beyond
+    /// the `max` budget: candidate `max + n` unlocks `backoff_base * 2^n`
+    /// after the previous one. `None` while the budget still has room, when
+    /// backoff is disabled (zero base), or when the delay would reach the
+    /// window reset anyway: the budget then only unlocks when the window
+    /// expires, exactly like the hard cap.
+    pub fn backoff_unlocks_at(
+        &self,
+        max: u8,
+        backoff_base: chrono::TimeDelta,
+        cooldown: chrono::TimeDelta,
+    ) -> Option<chrono::DateTime<chrono::Utc>> {
+        let count = self.candidate_count();
+        // u8::MAX is the hard bound of the candidate set, backoff or not.
+        if count < max || count == u8::MAX || backoff_base <= chrono::TimeDelta::zero() {
+            return None;
+        }
+        let delay = 2i32
+            .checked_pow(u32::from(count - max))
+            .and_then(|factor| backoff_base.checked_mul(factor))
+            .filter(|delay| *delay < cooldown)?;
+        Some(self.last_candidate_at + delay)
+    }
+
+    /// Earliest time a new distinct candidate is admitted: `None` while the
+    /// budget still has room, otherwise the backoff unlock or, without one,
+    /// the window reset.
+    pub fn next_candidate_at(
+        &self,
+        max: u8,
+        backoff_base: chrono::TimeDelta,
+        cooldown: chrono::TimeDelta,
+    ) -> Option<chrono::DateTime<chrono::Utc>> {
+        if self.candidate_count() < max {
+            return None;
+        }
+        Some(
+            self.backoff_unlocks_at(max, backoff_base, cooldown)
+                .unwrap_or(self.last_candidate_at + cooldown),
+        )
+    }
 }
 
 /// Attempt counters reported to the caller of a successful `/fetch` or
@@ -124,6 +497,10 @@ pub struct AttemptStatus {
     /// Distinct candidate immediately preceding this request, if any.
     pub previous_attempt_at: Option<chrono::DateTime<chrono::Utc>>,
     pub resets_at: chrono::DateTime<chrono::Utc>,
+    /// When the next distinct candidate is admitted once the budget is
+    /// exhausted (escalating backoff, or the reset without it); `null` while
+    /// attempts remain.
+    pub next_attempt_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -133,9 +510,11 @@ pub struct ResponseFailedAttempt {
     pub rate_limit_cooldown: i64,
     pub attempts: u8,
     pub total_requests: u64,
+    /// Same meaning as `AttemptStatus::next_attempt_at`.
+    pub next_attempt_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct AttemptEntry {
     /// SHA-256 of the raw identifier bytes, so clients can recognize their
     /// own identifier without exposing it (pre-image resistance).
@@ -157,3 +536,43 @@ pub struct AttemptsSnapshot {
     pub collection_started_at: chrono::DateTime<chrono::Utc>,
     pub entries: Vec<AttemptEntry>,
 }
+
+/// `/attempts/bucket/{prefix}` body: the snapshot entries whose `id_hash`
+/// starts with `prefix`, padded (see `handlers::attempts::BUCKET_ENTRIES`).
+#[derive(Serialize, Deserialize)]
+pub struct AttemptsBucket {
+    pub version: u8,
+    pub prefix: String,
+    /// ETag of the snapshot the bucket was cut from.
+    pub etag: String,
+    pub collection_started_at: chrono::DateTime<chrono::Utc>,
+    pub entries: Vec<AttemptEntry>,
+}
+
+/// `/attempts/delta` body: applied to the snapshot `base_etag`, it yields
+/// the snapshot `etag`. Every list is sorted by `id_hash`.
+#[derive(Serialize, Deserialize)]
+pub struct AttemptsDelta {
+    pub version: u8,
+    pub base_etag: String,
+    /// ETag of the resulting snapshot: the next `since`.
+    pub etag: String,
+    pub collection_started_at: chrono::DateTime<chrono::Utc>,
+    /// Entries absent from the base snapshot.
+    pub added: Vec<AttemptEntry>,
+    /// Entries of the base snapshot whose counters or timestamps moved.
+    pub changed: Vec<AttemptEntry>,
+    /// `id_hash` of the base entries that expired since.
+    pub removed: Vec<String>,
+}
+
+/// `/readyz` body. `failed_check` names the first failed check (`shutting
+/// down`, `connection`, `query`, `wal`, `migrations`, `timeout`) and is absent
+/// when ready.
+#[derive(Serialize, Deserialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub failed_check: Option<String>,
+    pub identifier_rate_limit_entries: usize,
+    pub identifier_rate_limit_capacity: usize,
+}
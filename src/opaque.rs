@@ -0,0 +1,500 @@
+//! Server side of the OPAQUE login (RFC 9807): OPAQUE-3DH over the
+//! `ristretto255-SHA512` suite, HKDF-SHA512 and HMAC-SHA512. The password
+//! and anything derived from it stay on the client: the server stores the
+//! registration record (the client's public key, a masking key and the
+//! envelope), evaluates the OPRF with a key derived per credential, and
+//! checks the client's key-confirmation MAC.
+//!
+//! The key stretching function (Argon2id) runs on the client only. The
+//! client side (with the identity stretching of the RFC vectors) lives here
+//! too, under `cfg(test)`, like in oprf.rs.
+//!
+//! Identities are the defaults of the RFC: the two public keys.
+
+use curve25519_dalek::{
+    ristretto::{CompressedRistretto, RistrettoPoint},
+    scalar::Scalar,
+    traits::Identity,
+};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha512};
+
+use crate::oprf::{derive_key_pair, BASE_CONTEXT_STRING};
+
+/// Application context bound into every handshake transcript.
+pub const CONTEXT: &[u8] = b"keychain opaque v1";
+
+/// `Nn`, `Nh` (= `Nm` = `Nx`) and `Npk`.
+const NONCE_LENGTH: usize = 32;
+const HASH_LENGTH: usize = 64;
+const PUBLIC_KEY_LENGTH: usize = 32;
+const ENVELOPE_LENGTH: usize = NONCE_LENGTH + HASH_LENGTH;
+/// `client_public_key || masking_key || envelope`.
+pub const RECORD_LENGTH: usize = PUBLIC_KEY_LENGTH + HASH_LENGTH + ENVELOPE_LENGTH;
+/// `blinded_message || client_nonce || client_public_keyshare`.
+pub const KE1_LENGTH: usize = 32 + NONCE_LENGTH + PUBLIC_KEY_LENGTH;
+/// `evaluated_message || masking_nonce || masked_response`.
+const CREDENTIAL_RESPONSE_LENGTH: usize = 32 + NONCE_LENGTH + PUBLIC_KEY_LENGTH + ENVELOPE_LENGTH;
+/// `credential_response || server_nonce || server_public_keyshare || server_mac`.
+pub const KE2_LENGTH: usize =
+    CREDENTIAL_RESPONSE_LENGTH + NONCE_LENGTH + PUBLIC_KEY_LENGTH + HASH_LENGTH;
+
+type HmacSha512 = Hmac<Sha512>;
+
+/// Server-wide secrets, derived from the 32-byte seed in OPAQUE_KEY_PATH.
+pub struct OpaqueServer {
+    oprf_seed: [u8; HASH_LENGTH],
+    private_key: Scalar,
+    public_key: [u8; 32],
+    context: Vec<u8>,
+}
+
+/// What `login` keeps until the client's KE3 arrives.
+pub struct ServerLogin {
+    client_mac_key: [u8; HASH_LENGTH],
+    /// `Hash(preamble || server_mac)`, the input of the expected client MAC.
+    transcript_hash: [u8; HASH_LENGTH],
+}
+
+fn extract(ikm: &[u8]) -> [u8; HASH_LENGTH] {
+    Hkdf::<Sha512>::extract(None, ikm).0.into()
+}
+
+fn expand<const N: usize>(key: &[u8], info: &[&[u8]]) -> [u8; N] {
+    let mut output = [0u8; N];
+    Hkdf::<Sha512>::from_prk(key)
+        .expect("a 64-byte pseudorandom key")
+        .expand_multi_info(info, &mut output)
+        .expect("a valid HKDF-SHA512 output length");
+    output
+}
+
+fn mac(key: &[u8], message: &[&[u8]]) -> [u8; HASH_LENGTH] {
+    let mut mac = HmacSha512::new_from_slice(key).expect("HMAC accepts any key length");
+    for part in message {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+/// `Derive-Secret`: `Expand-Label(secret, label, transcript_hash, Nx)`.
+fn derive_secret(secret: &[u8], label: &[u8], transcript_hash: &[u8]) -> [u8; HASH_LENGTH] {
+    let label = [b"OPAQUE-", label].concat();
+    expand(
+        secret,
+        &[
+            &(HASH_LENGTH as u16).to_be_bytes(),
+            &[label.len() as u8],
+            &label,
+            &[transcript_hash.len() as u8],
+            transcript_hash,
+        ],
+    )
+}
+
+fn derive_diffie_hellman_key_pair(seed: &[u8; 32]) -> (Scalar, RistrettoPoint) {
+    derive_key_pair(
+        seed,
+        b"OPAQUE-DeriveDiffieHellmanKeyPair",
+        BASE_CONTEXT_STRING,
+    )
+}
+
+fn decode_element(bytes: &[u8]) -> Option<RistrettoPoint> {
+    CompressedRistretto::from_slice(bytes)
+        .ok()?
+        .decompress()
+        .filter(|point| *point != RistrettoPoint::identity())
+}
+
+/// `CreateCleartextCredentials` with the default identities, which only
+/// the client's envelope covers.
+#[cfg(test)]
+fn cleartext_credentials(server_public_key: &[u8], client_public_key: &[u8]) -> Vec<u8> {
+    [
+        server_public_key,
+        &(PUBLIC_KEY_LENGTH as u16).to_be_bytes(),
+        server_public_key,
+        &(PUBLIC_KEY_LENGTH as u16).to_be_bytes(),
+        client_public_key,
+    ]
+    .concat()
+}
+
+/// `Hash(Preamble(...))` of the 3DH handshake.
+fn preamble_hash(
+    context: &[u8],
+    client_public_key: &[u8],
+    ke1: &[u8],
+    server_public_key: &[u8],
+    credential_response: &[u8],
+    server_nonce: &[u8],
+    server_public_keyshare: &[u8],
+) -> Vec<u8> {
+    let identity_length = (PUBLIC_KEY_LENGTH as u16).to_be_bytes();
+    Sha512::new()
+        .chain_update(b"OPAQUEv1-")
+        .chain_update((context.len() as u16).to_be_bytes())
+        .chain_update(context)
+        .chain_update(identity_length)
+        .chain_update(client_public_key)
+        .chain_update(ke1)
+        .chain_update(identity_length)
+        .chain_update(server_public_key)
+        .chain_update(credential_response)
+        .chain_update(server_nonce)
+        .chain_update(server_public_keyshare)
+        .finalize()
+        .to_vec()
+}
+
+/// `DeriveKeys`: the server and client MAC keys. The session key is not
+/// derived: nothing on the server uses it.
+fn derive_mac_keys(
+    shared_secrets: [RistrettoPoint; 3],
+    preamble_hash: &[u8],
+) -> ([u8; HASH_LENGTH], [u8; HASH_LENGTH]) {
+    let ikm: Vec<u8> = shared_secrets
+        .iter()
+        .flat_map(|point| point.compress().to_bytes())
+        .collect();
+    let handshake_secret = derive_secret(&extract(&ikm), b"HandshakeSecret", preamble_hash);
+    (
+        derive_secret(&handshake_secret, b"ServerMAC", b""),
+        derive_secret(&handshake_secret, b"ClientMAC", b""),
+    )
+}
+
+/// The response pad masking `server_public_key || envelope`.
+fn credential_response_pad(
+    masking_key: &[u8],
+    masking_nonce: &[u8],
+) -> [u8; PUBLIC_KEY_LENGTH + ENVELOPE_LENGTH] {
+    expand(masking_key, &[masking_nonce, b"CredentialResponsePad"])
+}
+
+/// A registration record the server accepts: the right length and a valid
+/// client public key.
+pub fn is_valid_record(record: &[u8]) -> bool {
+    record.len() == RECORD_LENGTH && decode_element(&record[..PUBLIC_KEY_LENGTH]).is_some()
+}
+
+impl OpaqueServer {
+    /// The OPRF seed and the long-term key pair, both derived from `seed`:
+    /// the seed file is the whole backup.
+    pub fn derive(seed: &[u8; 32]) -> Self {
+        let pseudorandom_key = extract(seed);
+        let oprf_seed = expand(&pseudorandom_key, &[b"keychain opaque v1 oprf seed"]);
+        let key_seed = expand(&pseudorandom_key, &[b"keychain opaque v1 server key"]);
+        let (private_key, _) = derive_diffie_hellman_key_pair(&key_seed);
+        Self::from_parts(oprf_seed, private_key, CONTEXT)
+    }
+
+    pub fn from_parts(oprf_seed: [u8; HASH_LENGTH], private_key: Scalar, context: &[u8]) -> Self {
+        Self {
+            oprf_seed,
+            private_key,
+            public_key: (private_key * curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT)
+                .compress()
+                .to_bytes(),
+            context: context.to_vec(),
+        }
+    }
+
+    pub fn public_key_hex(&self) -> String {
+        hex::encode(self.public_key)
+    }
+
+    /// `BlindEvaluate` with the OPRF key of `credential_identifier`, `None`
+    /// for an invalid blinded element.
+    fn evaluate(&self, credential_identifier: &[u8], blinded: &[u8]) -> Option<[u8; 32]> {
+        let point = decode_element(blinded)?;
+        let seed = expand(&self.oprf_seed, &[credential_identifier, b"OprfKey"]);
+        let (oprf_key, _) = derive_key_pair(&seed, b"OPAQUE-DeriveKeyPair", BASE_CONTEXT_STRING);
+        Some((oprf_key * point).compress().to_bytes())
+    }
+
+    /// `CreateRegistrationResponse`: `evaluated_message || server_public_key`.
+    pub fn registration_response(
+        &self,
+        credential_identifier: &[u8],
+        blinded: &[u8; 32],
+    ) -> Option<[u8; 64]> {
+        let evaluated = self.evaluate(credential_identifier, blinded)?;
+        Some(
+            [evaluated, self.public_key]
+                .concat()
+                .try_into()
+                .expect("64 bytes"),
+        )
+    }
+
+    /// `GenerateKE2`. Without a `record` (an unregistered credential), a
+    /// fake one derived from the OPRF seed stands in: the response has the
+    /// same shape and is the same for every retry, and the login then fails
+    /// at KE3 exactly like a wrong password. `None` for an invalid KE1.
+    pub fn login(
+        &self,
+        credential_identifier: &[u8],
+        record: Option<&[u8; RECORD_LENGTH]>,
+        ke1: &[u8; KE1_LENGTH],
+    ) -> Option<([u8; KE2_LENGTH], ServerLogin)> {
+        let mut randomness = [0u8; 96];
+        getrandom::getrandom(&mut randomness).expect("the OS random number generator is available");
+        self.login_with(credential_identifier, record, ke1, &randomness)
+    }
+
+    /// `login` with its randomness given, as in the RFC vectors:
+    /// `masking_nonce || server_nonce || server_keyshare_seed`.
+    pub fn login_with(
+        &self,
+        credential_identifier: &[u8],
+        record: Option<&[u8; RECORD_LENGTH]>,
+        ke1: &[u8; KE1_LENGTH],
+        randomness: &[u8; 96],
+    ) -> Option<([u8; KE2_LENGTH], ServerLogin)> {
+        let client_public_keyshare = decode_element(&ke1[64..])?;
+        let evaluated = self.evaluate(credential_identifier, &ke1[..32])?;
+        let (masking_nonce, rest) = randomness.split_at(NONCE_LENGTH);
+        let (server_nonce, server_keyshare_seed) = rest.split_at(NONCE_LENGTH);
+
+        let record = record.copied().unwrap_or_else(|| {
+            let (_, client_public_key) = derive_diffie_hellman_key_pair(&expand(
+                &self.oprf_seed,
+                &[credential_identifier, b"FakeClientKey"],
+            ));
+            let masking_key: [u8; HASH_LENGTH] =
+                expand(&self.oprf_seed, &[credential_identifier, b"FakeMaskingKey"]);
+            let mut record = [0u8; RECORD_LENGTH];
+            record[..PUBLIC_KEY_LENGTH].copy_from_slice(client_public_key.compress().as_bytes());
+            record[PUBLIC_KEY_LENGTH..PUBLIC_KEY_LENGTH + HASH_LENGTH]
+                .copy_from_slice(&masking_key);
+            record
+        });
+        let (client_public_key, rest) = record.split_at(PUBLIC_KEY_LENGTH);
+        let (masking_key, envelope) = rest.split_at(HASH_LENGTH);
+        let client_public_key_point =
+            decode_element(client_public_key).expect("records are validated when registered");
+
+        let mut credential_response = Vec::with_capacity(CREDENTIAL_RESPONSE_LENGTH);
+        credential_response.extend_from_slice(&evaluated);
+        credential_response.extend_from_slice(masking_nonce);
+        let pad = credential_response_pad(masking_key, masking_nonce);
+        let masked = [&self.public_key[..], envelope].concat();
+        credential_response.extend(pad.iter().zip(masked).map(|(pad, byte)| pad ^ byte));
+
+        let (server_private_keyshare, server_public_keyshare) =
+            derive_diffie_hellman_key_pair(server_keyshare_seed.try_into().expect("32 bytes"));
+        let server_public_keyshare = server_public_keyshare.compress().to_bytes();
+        let preamble_hash = preamble_hash(
+            &self.context,
+            client_public_key,
+            ke1,
+            &self.public_key,
+            &credential_response,
+            server_nonce,
+            &server_public_keyshare,
+        );
+        let (server_mac_key, client_mac_key) = derive_mac_keys(
+            [
+                server_private_keyshare * client_public_keyshare,
+                self.private_key * client_public_keyshare,
+                server_private_keyshare * client_public_key_point,
+            ],
+            &preamble_hash,
+        );
+        let server_mac = mac(&server_mac_key, &[&preamble_hash]);
+
+        let ke2 = [
+            &credential_response[..],
+            server_nonce,
+            &server_public_keyshare,
+            &server_mac,
+        ]
+        .concat();
+        Some((
+            ke2.try_into().expect("KE2 length"),
+            ServerLogin {
+                client_mac_key,
+                transcript_hash: Sha512::new()
+                    .chain_update(&preamble_hash)
+                    .chain_update(server_mac)
+                    .finalize()
+                    .into(),
+            },
+        ))
+    }
+}
+
+impl ServerLogin {
+    /// `ServerFinish`: whether KE3 (the client MAC) is the expected one,
+    /// compared in constant time.
+    pub fn finish(&self, client_mac: &[u8]) -> bool {
+        HmacSha512::new_from_slice(&self.client_mac_key)
+            .expect("HMAC accepts any key length")
+            .chain_update(self.transcript_hash)
+            .verify_slice(client_mac)
+            .is_ok()
+    }
+}
+
+/// Client: the OPRF output through the identity stretching of the RFC
+/// vectors, then `Extract`. Real clients stretch with Argon2id.
+#[cfg(test)]
+fn randomized_password(password: &[u8], blind: &Scalar, evaluated: &[u8]) -> Option<[u8; 64]> {
+    let unblinded = blind.invert() * decode_element(evaluated)?;
+    let oprf_output = crate::oprf::finalize_unblinded(password, &unblinded);
+    Some(extract(&[oprf_output, oprf_output].concat()))
+}
+
+/// Client: the envelope keys, `(auth_key, export_key, client key pair)`.
+#[cfg(test)]
+fn envelope_keys(
+    randomized_password: &[u8],
+    envelope_nonce: &[u8],
+) -> ([u8; 64], [u8; 64], (Scalar, RistrettoPoint)) {
+    (
+        expand(randomized_password, &[envelope_nonce, b"AuthKey"]),
+        expand(randomized_password, &[envelope_nonce, b"ExportKey"]),
+        derive_diffie_hellman_key_pair(&expand(
+            randomized_password,
+            &[envelope_nonce, b"PrivateKey"],
+        )),
+    )
+}
+
+/// Client: `CreateRegistrationRequest` with a given blind.
+#[cfg(test)]
+pub fn blind(password: &[u8], blind: &Scalar) -> [u8; 32] {
+    (blind * crate::oprf::hash_to_group(password, BASE_CONTEXT_STRING))
+        .compress()
+        .to_bytes()
+}
+
+/// Client: `FinalizeRegistrationRequest`, the record and the export key.
+#[cfg(test)]
+pub fn finish_registration(
+    password: &[u8],
+    blind: &Scalar,
+    response: &[u8; 64],
+    envelope_nonce: &[u8; 32],
+) -> Option<([u8; RECORD_LENGTH], [u8; 64])> {
+    let randomized_password = randomized_password(password, blind, &response[..32])?;
+    let masking_key: [u8; 64] = expand(&randomized_password, &[b"MaskingKey"]);
+    let (auth_key, export_key, (_, client_public_key)) =
+        envelope_keys(&randomized_password, envelope_nonce);
+    let client_public_key = client_public_key.compress().to_bytes();
+    let auth_tag = mac(
+        &auth_key,
+        &[
+            envelope_nonce,
+            &cleartext_credentials(&response[32..], &client_public_key),
+        ],
+    );
+    let record = [
+        &client_public_key[..],
+        &masking_key,
+        envelope_nonce,
+        &auth_tag,
+    ]
+    .concat();
+    Some((record.try_into().expect("record length"), export_key))
+}
+
+/// Client state between KE1 and KE3.
+#[cfg(test)]
+pub struct ClientLogin {
+    password: Vec<u8>,
+    blind: Scalar,
+    private_keyshare: Scalar,
+    pub ke1: [u8; KE1_LENGTH],
+}
+
+/// Client: `GenerateKE1` with given randomness.
+#[cfg(test)]
+pub fn start_login(
+    password: &[u8],
+    blind_scalar: &Scalar,
+    client_nonce: &[u8; 32],
+    keyshare_seed: &[u8; 32],
+) -> ClientLogin {
+    let (private_keyshare, public_keyshare) = derive_diffie_hellman_key_pair(keyshare_seed);
+    let ke1 = [
+        &blind(password, blind_scalar)[..],
+        client_nonce,
+        public_keyshare.compress().as_bytes(),
+    ]
+    .concat();
+    ClientLogin {
+        password: password.to_vec(),
+        blind: *blind_scalar,
+        private_keyshare,
+        ke1: ke1.try_into().expect("KE1 length"),
+    }
+}
+
+/// Client: `GenerateKE3`, KE3 (the client MAC) and the export key. `None`
+/// when the envelope does not open (wrong password, or an unregistered
+/// credential) or the server MAC does not verify.
+#[cfg(test)]
+pub fn finish_login(
+    login: &ClientLogin,
+    context: &[u8],
+    ke2: &[u8; KE2_LENGTH],
+) -> Option<([u8; 64], [u8; 64])> {
+    let (credential_response, rest) = ke2.split_at(CREDENTIAL_RESPONSE_LENGTH);
+    let (server_nonce, rest) = rest.split_at(NONCE_LENGTH);
+    let (server_public_keyshare, server_mac) = rest.split_at(PUBLIC_KEY_LENGTH);
+    let randomized_password =
+        randomized_password(&login.password, &login.blind, &credential_response[..32])?;
+    let masking_key: [u8; 64] = expand(&randomized_password, &[b"MaskingKey"]);
+    let pad = credential_response_pad(&masking_key, &credential_response[32..64]);
+    let unmasked: Vec<u8> = pad
+        .iter()
+        .zip(&credential_response[64..])
+        .map(|(pad, byte)| pad ^ byte)
+        .collect();
+    let (server_public_key, envelope) = unmasked.split_at(PUBLIC_KEY_LENGTH);
+    let (envelope_nonce, auth_tag) = envelope.split_at(NONCE_LENGTH);
+    let (auth_key, export_key, (client_private_key, client_public_key)) =
+        envelope_keys(&randomized_password, envelope_nonce);
+    let client_public_key = client_public_key.compress().to_bytes();
+    let expected_tag = mac(
+        &auth_key,
+        &[
+            envelope_nonce,
+            &cleartext_credentials(server_public_key, &client_public_key),
+        ],
+    );
+    if expected_tag[..] != *auth_tag {
+        return None;
+    }
+
+    let server_public_keyshare_point = decode_element(server_public_keyshare)?;
+    let preamble_hash = preamble_hash(
+        context,
+        &client_public_key,
+        &login.ke1,
+        server_public_key,
+        credential_response,
+        server_nonce,
+        server_public_keyshare,
+    );
+    let (server_mac_key, client_mac_key) = derive_mac_keys(
+        [
+            login.private_keyshare * server_public_keyshare_point,
+            login.private_keyshare * decode_element(server_public_key)?,
+            client_private_key * server_public_keyshare_point,
+        ],
+        &preamble_hash,
+    );
+    if mac(&server_mac_key, &[&preamble_hash])[..] != *server_mac {
+        return None;
+    }
+    let transcript_hash = Sha512::new()
+        .chain_update(&preamble_hash)
+        .chain_update(server_mac)
+        .finalize();
+    Some((mac(&client_mac_key, &[&transcript_hash]), export_key))
+}
@@ -0,0 +1,257 @@
+//! Server side of the `/oprf` evaluation: the POPRF of RFC 9497 in the
+//! `ristretto255-SHA512` suite, mode `0x02`. The client blinds its
+//! Argon2-derived value, the server evaluates it under its secret scalar
+//! tweaked by the public `info`, and proves (DLEQ) that it used the key
+//! published in `/info`; the unblinded result never reaches the server.
+//!
+//! `info` is `sha256(identifier)`, the rate-limit bucket: an evaluation is
+//! only worth something for the identifier whose budget paid for it, so
+//! throwaway identifiers buy no evaluations against another one's records.
+//!
+//! The client side (blinding, proof verification, finalization) lives here
+//! too, under `cfg(test)`: it checks the server against the RFC vectors.
+
+use curve25519_dalek::{
+    constants::RISTRETTO_BASEPOINT_POINT,
+    ristretto::{CompressedRistretto, RistrettoPoint},
+    scalar::Scalar,
+    traits::Identity,
+};
+use sha2::{Digest, Sha512};
+
+/// `"OPRFV1-" || I2OSP(mode, 1) || "-" || identifier`, mode 0x02 (POPRF).
+const CONTEXT_STRING: &[u8] = b"OPRFV1-\x02-ristretto255-SHA512";
+
+/// The same suite in mode 0x00 (OPRF), the one OPAQUE builds on (see
+/// opaque.rs): no proof, the server key is per credential.
+pub const BASE_CONTEXT_STRING: &[u8] = b"OPRFV1-\x00-ristretto255-SHA512";
+
+/// `info` of `DeriveKeyPair`: the seed file holds key material for this use
+/// only.
+const KEY_INFO: &[u8] = b"keychain oprf v1";
+
+/// The evaluation key, derived from the 32-byte seed in OPRF_KEY_PATH.
+pub struct OprfKey {
+    secret: Scalar,
+    public: RistrettoPoint,
+}
+
+/// `expand_message_xmd` (RFC 9380) with SHA-512, for the one length the
+/// suite uses: 64 bytes, a single block.
+fn expand_message_xmd(message: &[&[u8]], dst: &[&[u8]]) -> [u8; 64] {
+    let dst_len: usize = dst.iter().map(|part| part.len()).sum();
+    let dst_prime = [dst.concat(), vec![dst_len as u8]].concat();
+    let mut b0 = Sha512::new().chain_update([0u8; 128]);
+    for part in message {
+        b0.update(part);
+    }
+    let b0 = b0
+        .chain_update(64u16.to_be_bytes())
+        .chain_update([0u8])
+        .chain_update(&dst_prime)
+        .finalize();
+    Sha512::new()
+        .chain_update(b0)
+        .chain_update([1u8])
+        .chain_update(&dst_prime)
+        .finalize()
+        .into()
+}
+
+fn hash_to_scalar(message: &[&[u8]], dst: &[u8], context: &[u8]) -> Scalar {
+    Scalar::from_bytes_mod_order_wide(&expand_message_xmd(message, &[dst, context]))
+}
+
+#[cfg(test)]
+pub fn hash_to_group(input: &[u8], context: &[u8]) -> RistrettoPoint {
+    RistrettoPoint::from_uniform_bytes(&expand_message_xmd(&[input], &[b"HashToGroup-", context]))
+}
+
+/// `I2OSP(len(bytes), 2) || bytes`.
+fn length_prefixed(bytes: &[u8]) -> Vec<u8> {
+    [&(bytes.len() as u16).to_be_bytes()[..], bytes].concat()
+}
+
+/// `ComputeComposites` for a single element: the proof then covers one
+/// `(c, d)` pair, which POPRF orders (evaluated, blinded).
+fn composite_weight(key: &RistrettoPoint, c: &[u8; 32], d: &[u8; 32]) -> Scalar {
+    let seed_dst = [b"Seed-", CONTEXT_STRING].concat();
+    let seed = Sha512::new()
+        .chain_update(length_prefixed(key.compress().as_bytes()))
+        .chain_update(length_prefixed(&seed_dst))
+        .finalize();
+    hash_to_scalar(
+        &[
+            &length_prefixed(&seed),
+            &0u16.to_be_bytes(),
+            &length_prefixed(c),
+            &length_prefixed(d),
+            b"Composite",
+        ],
+        b"HashToScalar-",
+        CONTEXT_STRING,
+    )
+}
+
+fn challenge(key: &RistrettoPoint, points: [&RistrettoPoint; 4]) -> Scalar {
+    let mut transcript = length_prefixed(key.compress().as_bytes());
+    for point in points {
+        transcript.extend(length_prefixed(point.compress().as_bytes()));
+    }
+    hash_to_scalar(
+        &[&transcript, b"Challenge"],
+        b"HashToScalar-",
+        CONTEXT_STRING,
+    )
+}
+
+/// The POPRF tweak `m` of `info`: the evaluation key is `skS + m`, the
+/// public one `pkS + m·G`.
+fn info_tweak(info: &[u8]) -> Scalar {
+    hash_to_scalar(
+        &[b"Info", &length_prefixed(info)],
+        b"HashToScalar-",
+        CONTEXT_STRING,
+    )
+}
+
+/// RFC 9497 `DeriveKeyPair` in the suite of `context`.
+pub fn derive_key_pair(seed: &[u8; 32], info: &[u8], context: &[u8]) -> (Scalar, RistrettoPoint) {
+    let info_length = (info.len() as u16).to_be_bytes();
+    let secret = (0u8..=255)
+        .map(|counter| {
+            hash_to_scalar(
+                &[seed, &info_length, info, &[counter]],
+                b"DeriveKeyPair",
+                context,
+            )
+        })
+        .find(|secret| *secret != Scalar::ZERO)
+        .expect("a non-zero scalar within 256 tries");
+    (secret, secret * RISTRETTO_BASEPOINT_POINT)
+}
+
+/// `Finalize` of the base mode, on the unblinded element (OPAQUE's).
+#[cfg(test)]
+pub fn finalize_unblinded(input: &[u8], unblinded: &RistrettoPoint) -> [u8; 64] {
+    Sha512::new()
+        .chain_update(length_prefixed(input))
+        .chain_update(length_prefixed(unblinded.compress().as_bytes()))
+        .chain_update(b"Finalize")
+        .finalize()
+        .into()
+}
+
+/// A compressed ristretto255 element other than the identity.
+pub fn is_valid_element(element: &[u8; 32]) -> bool {
+    CompressedRistretto(*element)
+        .decompress()
+        .is_some_and(|point| point != RistrettoPoint::identity())
+}
+
+impl OprfKey {
+    /// `DeriveKeyPair(seed, KEY_INFO)`: the same seed always gives the same
+    /// key, so the seed file is the whole backup.
+    pub fn derive(seed: &[u8; 32]) -> Self {
+        Self::derive_key_pair(seed, KEY_INFO)
+    }
+
+    /// RFC 9497 `DeriveKeyPair`.
+    pub fn derive_key_pair(seed: &[u8; 32], info: &[u8]) -> Self {
+        let (secret, public) = derive_key_pair(seed, info, CONTEXT_STRING);
+        Self { secret, public }
+    }
+
+    pub fn public_key_hex(&self) -> String {
+        hex::encode(self.public.compress().as_bytes())
+    }
+
+    /// `BlindEvaluate` under `info`, with its proof: the evaluated element
+    /// and `c || s`. `None` when `blinded` is not a valid, non-identity
+    /// element, or when `info` cancels the key (`skS + m = 0`).
+    pub fn evaluate(&self, blinded: &[u8; 32], info: &[u8]) -> Option<([u8; 32], [u8; 64])> {
+        let mut random = [0u8; 64];
+        getrandom::getrandom(&mut random).expect("the OS random number generator is available");
+        self.evaluate_with(blinded, info, Scalar::from_bytes_mod_order_wide(&random))
+    }
+
+    /// `evaluate` with the proof's random scalar given, as in the RFC vectors.
+    pub fn evaluate_with(
+        &self,
+        blinded: &[u8; 32],
+        info: &[u8],
+        proof_random: Scalar,
+    ) -> Option<([u8; 32], [u8; 64])> {
+        let point = CompressedRistretto(*blinded).decompress()?;
+        if point == RistrettoPoint::identity() {
+            return None;
+        }
+        let tweaked_secret = self.secret + info_tweak(info);
+        if tweaked_secret == Scalar::ZERO {
+            return None;
+        }
+        let tweaked_key = tweaked_secret * RISTRETTO_BASEPOINT_POINT;
+        let evaluated_point = tweaked_secret.invert() * point;
+        let evaluated = evaluated_point.compress().to_bytes();
+
+        // GenerateProof(t, G, tweakedKey, evaluated, blinded): with one
+        // element, M = d·evaluated and Z = t·M = d·blinded.
+        let composite = composite_weight(&tweaked_key, &evaluated, blinded) * evaluated_point;
+        let t2 = proof_random * RISTRETTO_BASEPOINT_POINT;
+        let t3 = proof_random * composite;
+        let c = challenge(
+            &tweaked_key,
+            [&composite, &(tweaked_secret * composite), &t2, &t3],
+        );
+        let s = proof_random - c * tweaked_secret;
+        let mut proof = [0u8; 64];
+        proof[..32].copy_from_slice(c.as_bytes());
+        proof[32..].copy_from_slice(s.as_bytes());
+        Some((evaluated, proof))
+    }
+}
+
+/// Client: `Blind` with a given scalar.
+#[cfg(test)]
+pub fn blind(input: &[u8], blind: &Scalar) -> [u8; 32] {
+    (blind * hash_to_group(input, CONTEXT_STRING))
+        .compress()
+        .to_bytes()
+}
+
+/// Client: `VerifyProof` against the pinned public key tweaked by `info`,
+/// then `Finalize`. `None` when the proof does not verify.
+#[cfg(test)]
+pub fn finalize(
+    public_key: &[u8; 32],
+    input: &[u8],
+    info: &[u8],
+    blind: &Scalar,
+    blinded: &[u8; 32],
+    evaluated: &[u8; 32],
+    proof: &[u8; 64],
+) -> Option<[u8; 64]> {
+    let public = CompressedRistretto(*public_key).decompress()?;
+    let tweaked_key = public + info_tweak(info) * RISTRETTO_BASEPOINT_POINT;
+    let evaluated_point = CompressedRistretto(*evaluated).decompress()?;
+    let c = Option::<Scalar>::from(Scalar::from_canonical_bytes(proof[..32].try_into().ok()?))?;
+    let s = Option::<Scalar>::from(Scalar::from_canonical_bytes(proof[32..].try_into().ok()?))?;
+    let weight = composite_weight(&tweaked_key, evaluated, blinded);
+    let composite = weight * evaluated_point;
+    let z = weight * CompressedRistretto(*blinded).decompress()?;
+    let t2 = s * RISTRETTO_BASEPOINT_POINT + c * tweaked_key;
+    let t3 = s * composite + c * z;
+    if challenge(&tweaked_key, [&composite, &z, &t2, &t3]) != c {
+        return None;
+    }
+    let unblinded = blind.invert() * evaluated_point;
+    Some(
+        Sha512::new()
+            .chain_update(length_prefixed(input))
+            .chain_update(length_prefixed(info))
+            .chain_update(length_prefixed(unblinded.compress().as_bytes()))
+            .chain_update(b"Finalize")
+            .finalize()
+            .into(),
+    )
+}
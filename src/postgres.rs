@@ -0,0 +1,280 @@
+//! PostgreSQL backend of [`SecretStore`], built with the `postgres` feature
+//! and selected by a `postgres://` DATABASE_URL. Same tables and
+//! guarantees as SQLite: row locks (`FOR UPDATE`) and single-statement
+//! `DELETE ... RETURNING` replace SQLite's `immediate_transaction`, and a
+//! table lock orders the transparency log appends.
+
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use diesel::{
+    sql_query, BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension,
+    PgConnection, QueryDsl, RunQueryDsl,
+};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+
+use crate::database::database_timestamp;
+use crate::models::{LogLeaf, OpaqueRecord, Secret};
+use crate::schema::secret::dsl::*;
+use crate::secret_store::{SecretStore, StoreResult};
+
+pub const POSTGRES_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations_postgres");
+
+/// See `database::POOL_CONNECTION_TIMEOUT`.
+const POOL_CONNECTION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+pub struct PostgresStore {
+    database_url: String,
+    pool: Pool<ConnectionManager<PgConnection>>,
+}
+
+impl PostgresStore {
+    /// Same pool policy as SQLite: opened on first use, kept, checked with
+    /// `SELECT 1` before every use.
+    pub fn new(database_url: &str, max_connections: u32) -> Self {
+        Self {
+            database_url: database_url.to_string(),
+            pool: Pool::builder()
+                .max_size(max_connections)
+                .min_idle(Some(0))
+                .idle_timeout(None)
+                .connection_timeout(POOL_CONNECTION_TIMEOUT)
+                .test_on_check_out(true)
+                .build_unchecked(ConnectionManager::new(database_url)),
+        }
+    }
+
+    fn connection(&self) -> StoreResult<PooledConnection<ConnectionManager<PgConnection>>> {
+        self.pool.get().map_err(|error| {
+            diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::ClosedConnection,
+                Box::new(error.to_string()),
+            )
+        })
+    }
+}
+
+/// Locks and returns the live record: past `trash_after` or `expires_at` it
+/// is deleted, whether or not the purge task has run yet.
+fn lock_live_secret(connection: &mut PgConnection, secret_id: &str) -> StoreResult<Option<Secret>> {
+    let now = database_timestamp(chrono::Utc::now());
+    secret
+        .filter(id.eq(secret_id))
+        .filter(trash_after.is_null().or(trash_after.gt(&now)))
+        .filter(expires_at.is_null().or(expires_at.gt(&now)))
+        .for_update()
+        .first::<Secret>(connection)
+        .optional()
+}
+
+/// Appends a leaf to the transparency log, as `database::append_log_leaf`.
+/// The lock, held until commit, keeps the indexes gap-free and in commit
+/// order; call it last in the transaction.
+fn append_log_leaf(connection: &mut PgConnection, record: &Secret, trash: bool) -> StoreResult<()> {
+    let leaf = if trash {
+        crate::transparency::trash_leaf
+    } else {
+        crate::transparency::store_leaf
+    }(&record.id, &record.encrypted_secret, &record.created_at);
+    sql_query("LOCK TABLE log_leaf IN EXCLUSIVE MODE").execute(connection)?;
+    sql_query(
+        "INSERT INTO log_leaf (leaf_index, leaf_hash) \
+         SELECT COALESCE(MAX(leaf_index) + 1, 0), $1 FROM log_leaf",
+    )
+    .bind::<diesel::sql_types::Text, _>(hex::encode(leaf))
+    .execute(connection)?;
+    Ok(())
+}
+
+/// Deletes the records selected for a purge and logs their trashes.
+fn delete_logged(connection: &mut PgConnection, records: &[Secret]) -> StoreResult<usize> {
+    let ids: Vec<&str> = records.iter().map(|record| record.id.as_str()).collect();
+    diesel::delete(secret.filter(id.eq_any(ids))).execute(connection)?;
+    for record in records {
+        append_log_leaf(connection, record, true)?;
+    }
+    Ok(records.len())
+}
+
+impl SecretStore for PostgresStore {
+    fn migrate(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.connection()?
+            .run_pending_migrations(POSTGRES_MIGRATIONS)?;
+        Ok(())
+    }
+
+    fn readiness(&self) -> Result<(), &'static str> {
+        let mut connection =
+            PgConnection::establish(&self.database_url).map_err(|_| "connection")?;
+        sql_query("SELECT 1")
+            .execute(&mut connection)
+            .map_err(|_| "query")?;
+        match connection.has_pending_migration(POSTGRES_MIGRATIONS) {
+            Ok(false) => Ok(()),
+            Ok(true) | Err(_) => Err("migrations"),
+        }
+    }
+
+    fn write(&self, new_secret: &Secret) -> StoreResult<()> {
+        // ON CONFLICT DO NOTHING, as for SQLite: see `database::insert_secret`.
+        self.connection()?.transaction(|connection| {
+            diesel::insert_into(crate::schema::secret::table)
+                .values(new_secret)
+                .on_conflict_do_nothing()
+                .execute(connection)?;
+            append_log_leaf(connection, new_secret, false)
+        })
+    }
+
+    fn read(&self, secret_id: &str) -> StoreResult<Option<Secret>> {
+        let now = database_timestamp(chrono::Utc::now());
+        secret
+            .filter(id.eq(secret_id))
+            .filter(trash_after.is_null().or(trash_after.gt(&now)))
+            .filter(expires_at.is_null().or(expires_at.gt(&now)))
+            .first::<Secret>(&mut self.connection()?)
+            .optional()
+    }
+
+    fn read_and_trash(&self, secret_id: &str) -> StoreResult<Option<Secret>> {
+        // One statement: of two concurrent calls, exactly one gets the row.
+        let now = database_timestamp(chrono::Utc::now());
+        self.connection()?.transaction(|connection| {
+            let deleted = diesel::delete(
+                secret
+                    .filter(id.eq(secret_id))
+                    .filter(trash_after.is_null().or(trash_after.gt(&now)))
+                    .filter(expires_at.is_null().or(expires_at.gt(&now))),
+            )
+            .get_result::<Secret>(connection)
+            .optional()?;
+            if let Some(stored_secret) = &deleted {
+                append_log_leaf(connection, stored_secret, true)?;
+            }
+            Ok(deleted)
+        })
+    }
+
+    fn read_and_mark(&self, secret_id: &str, deletion_at: &str) -> StoreResult<Option<Secret>> {
+        self.connection()?.transaction(|connection| {
+            let Some(mut stored_secret) = lock_live_secret(connection, secret_id)? else {
+                return Ok(None);
+            };
+            // An earlier deadline is kept: a repeated `/trash` cannot
+            // postpone the purge.
+            if stored_secret.trash_after.is_none() {
+                diesel::update(secret.filter(id.eq(secret_id)))
+                    .set(trash_after.eq(deletion_at))
+                    .execute(connection)?;
+                stored_secret.trash_after = Some(deletion_at.to_owned());
+            }
+            Ok(Some(stored_secret))
+        })
+    }
+
+    fn restore(&self, secret_id: &str) -> StoreResult<Option<Secret>> {
+        self.connection()?.transaction(|connection| {
+            let Some(mut stored_secret) = lock_live_secret(connection, secret_id)? else {
+                return Ok(None);
+            };
+            if stored_secret.trash_after.is_some() {
+                diesel::update(secret.filter(id.eq(secret_id)))
+                    .set(trash_after.eq(None::<String>))
+                    .execute(connection)?;
+                stored_secret.trash_after = None;
+            }
+            Ok(Some(stored_secret))
+        })
+    }
+
+    fn rotate(
+        &self,
+        old_secret_id: &str,
+        new_secret: &Secret,
+        deletion_at: Option<&str>,
+    ) -> StoreResult<Option<Secret>> {
+        self.connection()?.transaction(|connection| {
+            let Some(stored_secret) = lock_live_secret(connection, old_secret_id)? else {
+                return Ok(None);
+            };
+
+            diesel::insert_into(crate::schema::secret::table)
+                .values(new_secret)
+                .on_conflict_do_nothing()
+                .execute(connection)?;
+            append_log_leaf(connection, new_secret, false)?;
+            match deletion_at {
+                Some(deletion_at) if stored_secret.trash_after.is_none() => {
+                    diesel::update(secret.filter(id.eq(old_secret_id)))
+                        .set(trash_after.eq(deletion_at))
+                        .execute(connection)?;
+                }
+                Some(_) => {}
+                None => {
+                    diesel::delete(secret.filter(id.eq(old_secret_id))).execute(connection)?;
+                    append_log_leaf(connection, &stored_secret, true)?;
+                }
+            }
+            Ok(Some(stored_secret))
+        })
+    }
+
+    fn write_opaque(&self, record: &OpaqueRecord) -> StoreResult<bool> {
+        let inserted = diesel::insert_into(crate::schema::opaque_record::table)
+            .values(record)
+            .on_conflict_do_nothing()
+            .execute(&mut self.connection()?)?;
+        Ok(inserted == 1)
+    }
+
+    fn read_opaque(&self, credential: &str) -> StoreResult<Option<OpaqueRecord>> {
+        use crate::schema::opaque_record::dsl::{credential_id, opaque_record};
+        opaque_record
+            .filter(credential_id.eq(credential))
+            .first::<OpaqueRecord>(&mut self.connection()?)
+            .optional()
+    }
+
+    fn purge_trashed(
+        &self,
+        now: chrono::DateTime<chrono::Utc>,
+        batch_size: i64,
+    ) -> StoreResult<usize> {
+        self.connection()?.transaction(|connection| {
+            let due: Vec<Secret> = secret
+                .filter(trash_after.le(database_timestamp(now)))
+                .limit(batch_size)
+                .for_update()
+                .load(connection)?;
+            delete_logged(connection, &due)
+        })
+    }
+
+    fn purge_expired(
+        &self,
+        now: chrono::DateTime<chrono::Utc>,
+        batch_size: i64,
+    ) -> StoreResult<usize> {
+        self.connection()?.transaction(|connection| {
+            let due: Vec<Secret> = secret
+                .filter(expires_at.le(database_timestamp(now)))
+                .limit(batch_size)
+                .for_update()
+                .load(connection)?;
+            delete_logged(connection, &due)
+        })
+    }
+
+    fn read_log_leaves(&self, from_index: i64, limit: i64) -> StoreResult<Vec<LogLeaf>> {
+        use crate::schema::log_leaf;
+        log_leaf::table
+            .filter(log_leaf::leaf_index.ge(from_index))
+            .order(log_leaf::leaf_index.asc())
+            .limit(limit)
+            .select(diesel::SelectableHelper::as_select())
+            .load(&mut self.connection()?)
+    }
+
+    #[cfg(test)]
+    fn execute_raw(&self, sql: &str) -> StoreResult<usize> {
+        sql_query(sql).execute(&mut self.connection()?)
+    }
+}
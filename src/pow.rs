@@ -0,0 +1,123 @@
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::models::ProofOfWork;
+
+/// Hard ceiling of the escalated difficulty. Beyond this a puzzle is
+/// impractical to solve: the ceiling keeps escalation meaningful without the
+/// arithmetic ever exceeding the 256-bit digest.
+pub const MAX_POW_DIFFICULTY_BITS: u32 = 64;
+
+/// Upper bound of a client nonce, so verification cost stays constant.
+const MAX_NONCE_LENGTH: usize = 64;
+
+/// Issues a stateless challenge: the issue time and an HMAC over it under a
+/// key drawn at startup. Nothing is stored per challenge, so `/challenge`
+/// needs no rate-limit state; a restart invalidates outstanding challenges.
+pub fn issue_challenge(key: &[u8; 32], issued_at: chrono::DateTime<chrono::Utc>) -> String {
+    let issued_at = issued_at.timestamp();
+    format!(
+        "{issued_at}.{}",
+        hex::encode(challenge_mac(key, issued_at).finalize().into_bytes())
+    )
+}
+
+fn challenge_mac(key: &[u8; 32], issued_at: i64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(issued_at.to_string().as_bytes());
+    mac
+}
+
+/// Checks that the challenge was issued by this server and has not expired,
+/// and that the nonce is bounded. Stateless: this runs before the rate-limit
+/// lock is taken.
+pub fn verify_challenge(
+    key: &[u8; 32],
+    proof: &ProofOfWork,
+    lifetime: chrono::TimeDelta,
+    now: chrono::DateTime<chrono::Utc>,
+) -> bool {
+    if proof.nonce.is_empty() || proof.nonce.len() > MAX_NONCE_LENGTH {
+        return false;
+    }
+    let Some((issued_at, mac)) = proof.challenge.split_once('.') else {
+        return false;
+    };
+    let (Ok(issued_at), Ok(mac)) = (issued_at.parse::<i64>(), hex::decode(mac)) else {
+        return false;
+    };
+    let Some(issued) = chrono::DateTime::from_timestamp(issued_at, 0) else {
+        return false;
+    };
+    // Issue times are truncated to the second and therefore never after
+    // `now` on this server.
+    if issued > now || now - issued > lifetime {
+        return false;
+    }
+    // Constant-time comparison.
+    challenge_mac(key, issued_at).verify_slice(&mac).is_ok()
+}
+
+/// Difficulty required from a request on an identifier that already holds
+/// `candidate_count` distinct candidates: every admitted candidate makes the
+/// next one `2^step` times more expensive.
+pub fn difficulty(base_bits: u32, step_bits: u32, candidate_count: u8) -> u32 {
+    base_bits
+        .saturating_add(step_bits.saturating_mul(u32::from(candidate_count)))
+        .min(MAX_POW_DIFFICULTY_BITS)
+}
+
+/// The puzzle is bound to the identifier bucket and the candidate, so a
+/// solution can neither be reused for another guess nor for another
+/// identifier: `sha256(challenge ":" id_hash ":" candidate ":" nonce)`.
+pub fn solution_digest(challenge: &str, id_hash: &str, candidate: &str, nonce: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(challenge.as_bytes());
+    hasher.update(b":");
+    hasher.update(id_hash.as_bytes());
+    hasher.update(b":");
+    hasher.update(candidate.as_bytes());
+    hasher.update(b":");
+    hasher.update(nonce.as_bytes());
+    hasher.finalize().into()
+}
+
+pub fn leading_zero_bits(digest: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in digest {
+        if *byte == 0 {
+            bits += 8;
+        } else {
+            bits += byte.leading_zeros();
+            break;
+        }
+    }
+    bits
+}
+
+pub fn meets_difficulty(
+    proof: &ProofOfWork,
+    id_hash: &str,
+    candidate: &str,
+    difficulty_bits: u32,
+) -> bool {
+    leading_zero_bits(&solution_digest(
+        &proof.challenge,
+        id_hash,
+        candidate,
+        &proof.nonce,
+    )) >= difficulty_bits
+}
+
+/// Reference solver, as a client would implement it: increments a decimal
+/// nonce until the digest meets the difficulty.
+#[cfg(test)]
+pub fn solve(challenge: &str, id_hash: &str, candidate: &str, difficulty_bits: u32) -> String {
+    (0u64..)
+        .map(|nonce| nonce.to_string())
+        .find(|nonce| {
+            leading_zero_bits(&solution_digest(challenge, id_hash, candidate, nonce))
+                >= difficulty_bits
+        })
+        .expect("a nonce exists for any practical difficulty")
+}
@@ -22,6 +22,18 @@ impl TokenBucket {
         }
     }
 
+    /// Applies new parameters (configuration reload). Tokens accrued so far
+    /// are kept, capped at the new capacity: shrinking the bucket during an
+    /// attack takes effect at once, growing it grants no instant burst.
+    pub fn reconfigure(&mut self, capacity: f64, refill_per_second: f64) {
+        let now = std::time::Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_second).min(capacity);
+        self.last_refill = now;
+        self.capacity = capacity;
+        self.refill_per_second = refill_per_second;
+    }
+
     /// Refills the tokens elapsed since the last call, then tries to
     /// consume one. Returns false when the bucket is empty.
     pub fn try_consume(&mut self) -> bool {
@@ -53,19 +65,24 @@ pub async fn sweep_expired_identifiers(state: &AppState) {
         now.signed_duration_since(info.last_candidate_at) <= state.rate_limit_cooldown
     });
     let remaining = identifier_rate_limit.len();
+    state.metrics.sweeper_removals.fetch_add(
+        (before - remaining) as u64,
+        std::sync::atomic::Ordering::Relaxed,
+    );
     // Log discipline: counts only, never identifiers.
     tracing::info!(swept = before - remaining, remaining, "rate-limit sweep");
 }
 
 /// Spawns the background task that sweeps expired rate-limit entries, so
 /// identifiers are forgotten after the cooldown even if they are never
-/// requested again.
+/// requested again, and abandoned OPAQUE logins and registrations.
 pub fn spawn_sweeper(state: AppState) {
     tokio::spawn(async move {
         let mut interval = tokio::time::interval(SWEEP_INTERVAL);
         loop {
             interval.tick().await;
             sweep_expired_identifiers(&state).await;
+            crate::handlers::opaque::sweep_expired_logins(&state).await;
         }
     });
 }
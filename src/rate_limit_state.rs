@@ -0,0 +1,383 @@
+//! Opt-in persistence of the rate-limit windows (`RATE_LIMIT_STATE_URL`).
+//!
+//! Without it, a restart — or a crash loop provoked by an attacker — wipes
+//! every window and refunds every guessed candidate. The state lives in its
+//! own SQLite file, never in the secret database: it holds `id_hash` buckets
+//! and derived CandidateTags only, never raw identifiers or keys, and it
+//! follows the same expiry as the in-memory map.
+
+use std::collections::HashMap;
+
+use diesel::{sql_query, Connection, QueryableByName, RunQueryDsl, SqliteConnection};
+
+use crate::models::{CandidateState, CandidateTag, RateLimitInfo};
+
+/// How often the whole map is written out. Candidate admissions are written
+/// through immediately (they are the budget); this interval only bounds how
+/// much telemetry (`failed_attempts`, `total_requests`) a crash can lose.
+pub const FLUSH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+const COLLECTION_STARTED_AT: &str = "collection_started_at";
+
+#[derive(QueryableByName)]
+struct WindowRow {
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    id_hash: String,
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    window_started_at: String,
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    last_candidate_at: String,
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    last_request_at: String,
+    #[diesel(sql_type = diesel::sql_types::Integer)]
+    failed_candidates: i32,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    total_requests: i64,
+}
+
+#[derive(QueryableByName)]
+struct CandidateRow {
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    id_hash: String,
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    candidate: String,
+}
+
+#[derive(QueryableByName)]
+struct MetaRow {
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    value: String,
+}
+
+/// The state file, shared by the write-through path and the periodic flush.
+/// One connection, opened on first use and kept; its lock also orders the
+/// writes.
+pub struct StateFile {
+    pub url: String,
+    connection: std::sync::Arc<tokio::sync::Mutex<Option<SqliteConnection>>>,
+}
+
+impl StateFile {
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            connection: std::sync::Arc::new(tokio::sync::Mutex::new(None)),
+        }
+    }
+
+    /// Writes one window through, before the candidate it admitted reaches
+    /// the secret database: a crash after the lookup can then never refund
+    /// the guess. Blocking: call it from the database blocking task.
+    pub fn persist_entry(
+        &self,
+        id_hash: &str,
+        info: &RateLimitInfo,
+    ) -> Result<(), diesel::result::Error> {
+        let mut connection = self.connection.blocking_lock();
+        with_connection(&mut connection, &self.url, |connection| {
+            persist_entry(connection, id_hash, info)
+        })
+    }
+}
+
+/// Runs `task` on the kept connection, opening it first if needed. A failed
+/// task drops the connection: the next call starts from a fresh one.
+fn with_connection<T>(
+    slot: &mut Option<SqliteConnection>,
+    state_url: &str,
+    task: impl FnOnce(&mut SqliteConnection) -> Result<T, diesel::result::Error>,
+) -> Result<T, diesel::result::Error> {
+    let connection = match slot {
+        Some(connection) => connection,
+        None => slot.insert(establish_connection(state_url)?),
+    };
+    let result = task(connection);
+    if result.is_err() {
+        *slot = None;
+    }
+    result
+}
+
+/// Restored state: the live windows and the start of the collection they
+/// belong to.
+pub struct LoadedState {
+    pub windows: HashMap<String, RateLimitInfo>,
+    pub collection_started_at: chrono::DateTime<chrono::Utc>,
+}
+
+fn timestamp(value: &chrono::DateTime<chrono::Utc>) -> String {
+    value.to_rfc3339_opts(chrono::SecondsFormat::Nanos, true)
+}
+
+fn parse_timestamp(value: &str) -> Result<chrono::DateTime<chrono::Utc>, diesel::result::Error> {
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|value| value.with_timezone(&chrono::Utc))
+        .map_err(|error| diesel::result::Error::DeserializationError(Box::new(error)))
+}
+
+pub fn establish_connection(state_url: &str) -> Result<SqliteConnection, diesel::result::Error> {
+    let mut connection = SqliteConnection::establish(state_url).map_err(|error| {
+        diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::ClosedConnection,
+            Box::new(error.to_string()),
+        )
+    })?;
+    // Same reasoning as the secret database: write-through admissions and
+    // the periodic flush may overlap.
+    sql_query("PRAGMA busy_timeout = 5000;").execute(&mut connection)?;
+    Ok(connection)
+}
+
+/// Creates the state tables when missing. The schema is private to this
+/// file and versioned by its table names, not by the secret migrations.
+pub fn init(connection: &mut SqliteConnection) -> Result<(), diesel::result::Error> {
+    sql_query("PRAGMA journal_mode = WAL;").execute(connection)?;
+    connection.immediate_transaction(|connection| {
+        sql_query(
+            "CREATE TABLE IF NOT EXISTS rate_limit_window (\
+             id_hash TEXT PRIMARY KEY NOT NULL,\
+             window_started_at TEXT NOT NULL,\
+             last_candidate_at TEXT NOT NULL,\
+             last_request_at TEXT NOT NULL,\
+             failed_candidates INTEGER NOT NULL,\
+             total_requests INTEGER NOT NULL\
+             )",
+        )
+        .execute(connection)?;
+        sql_query(
+            "CREATE TABLE IF NOT EXISTS rate_limit_candidate (\
+             id_hash TEXT NOT NULL,\
+             candidate TEXT NOT NULL,\
+             PRIMARY KEY (id_hash, candidate)\
+             )",
+        )
+        .execute(connection)?;
+        sql_query(
+            "CREATE TABLE IF NOT EXISTS rate_limit_meta (\
+             key TEXT PRIMARY KEY NOT NULL,\
+             value TEXT NOT NULL\
+             )",
+        )
+        .execute(connection)?;
+        Ok(())
+    })
+}
+
+/// Loads the windows still inside their cooldown and drops the expired ones
+/// from the file, so restored state obeys the same daily wipe as the map.
+/// Candidates come back `Committed`, with their tags: replaying one from
+/// before the restart is the same free replay it was before. A candidate
+/// still `Pending` on disk was interrupted by the restart; it may have
+/// reached the database and its response is lost, so it too comes back
+/// `Committed`: it keeps consuming budget, is not counted as a failure, and
+/// its replay returns the answer that was paid for.
+pub fn load(
+    connection: &mut SqliteConnection,
+    now: chrono::DateTime<chrono::Utc>,
+    cooldown: chrono::TimeDelta,
+) -> Result<LoadedState, diesel::result::Error> {
+    connection.immediate_transaction(|connection| {
+        let rows = sql_query(
+            "SELECT id_hash, window_started_at, last_candidate_at, last_request_at, \
+             failed_candidates, total_requests FROM rate_limit_window",
+        )
+        .load::<WindowRow>(connection)?;
+        let mut windows = HashMap::new();
+        for row in rows {
+            let last_candidate_at = parse_timestamp(&row.last_candidate_at)?;
+            if now.signed_duration_since(last_candidate_at) > cooldown {
+                continue;
+            }
+            windows.insert(
+                row.id_hash,
+                RateLimitInfo {
+                    window_started_at: parse_timestamp(&row.window_started_at)?,
+                    last_candidate_at,
+                    last_request_at: parse_timestamp(&row.last_request_at)?,
+                    candidates: HashMap::new(),
+                    failed_candidates: u8::try_from(row.failed_candidates).unwrap_or(u8::MAX),
+                    total_requests: u64::try_from(row.total_requests).unwrap_or_default(),
+                },
+            );
+        }
+        for row in sql_query("SELECT id_hash, candidate FROM rate_limit_candidate")
+            .load::<CandidateRow>(connection)?
+        {
+            if let Some(info) = windows.get_mut(&row.id_hash) {
+                // The set is bounded by the u8 candidate counter.
+                if info.candidates.len() < usize::from(u8::MAX) {
+                    info.candidates
+                        .insert(row.candidate, CandidateState::Committed);
+                }
+            }
+        }
+
+        let stored = sql_query("SELECT value FROM rate_limit_meta WHERE key = ?")
+            .bind::<diesel::sql_types::Text, _>(COLLECTION_STARTED_AT)
+            .load::<MetaRow>(connection)?
+            .into_iter()
+            .next();
+        let collection_started_at = match stored {
+            Some(row) => parse_timestamp(&row.value)?,
+            None => now,
+        };
+
+        replace_all(connection, &windows, collection_started_at)?;
+        Ok(LoadedState {
+            windows,
+            collection_started_at,
+        })
+    })
+}
+
+/// Opens (creating if needed) the state file and loads it. When the file
+/// holds more live windows than `max_identifiers` allows, the most recently
+/// used ones are kept, as the in-memory map would have done.
+pub fn restore(
+    state_url: &str,
+    now: chrono::DateTime<chrono::Utc>,
+    cooldown: chrono::TimeDelta,
+    max_identifiers: usize,
+) -> Result<LoadedState, diesel::result::Error> {
+    let mut connection = establish_connection(state_url)?;
+    init(&mut connection)?;
+    let mut loaded = load(&mut connection, now, cooldown)?;
+    if loaded.windows.len() > max_identifiers {
+        let mut windows: Vec<_> = loaded.windows.drain().collect();
+        windows.sort_by_key(|(_, info)| std::cmp::Reverse(info.last_request_at));
+        windows.truncate(max_identifiers);
+        loaded.windows = windows.into_iter().collect();
+    }
+    Ok(loaded)
+}
+
+fn insert_window(
+    connection: &mut SqliteConnection,
+    id_hash: &str,
+    info: &RateLimitInfo,
+) -> Result<(), diesel::result::Error> {
+    sql_query(
+        "INSERT INTO rate_limit_window (id_hash, window_started_at, last_candidate_at, \
+         last_request_at, failed_candidates, total_requests) VALUES (?, ?, ?, ?, ?, ?) \
+         ON CONFLICT (id_hash) DO UPDATE SET \
+         window_started_at = excluded.window_started_at, \
+         last_candidate_at = excluded.last_candidate_at, \
+         last_request_at = excluded.last_request_at, \
+         failed_candidates = excluded.failed_candidates, \
+         total_requests = excluded.total_requests",
+    )
+    .bind::<diesel::sql_types::Text, _>(id_hash)
+    .bind::<diesel::sql_types::Text, _>(timestamp(&info.window_started_at))
+    .bind::<diesel::sql_types::Text, _>(timestamp(&info.last_candidate_at))
+    .bind::<diesel::sql_types::Text, _>(timestamp(&info.last_request_at))
+    .bind::<diesel::sql_types::Integer, _>(i32::from(info.failed_candidates))
+    .bind::<diesel::sql_types::BigInt, _>(i64::try_from(info.total_requests).unwrap_or(i64::MAX))
+    .execute(connection)?;
+    for candidate in info.candidates.keys() {
+        insert_candidate(connection, id_hash, candidate)?;
+    }
+    Ok(())
+}
+
+fn insert_candidate(
+    connection: &mut SqliteConnection,
+    id_hash: &str,
+    candidate: &CandidateTag,
+) -> Result<(), diesel::result::Error> {
+    sql_query("INSERT OR IGNORE INTO rate_limit_candidate (id_hash, candidate) VALUES (?, ?)")
+        .bind::<diesel::sql_types::Text, _>(id_hash)
+        .bind::<diesel::sql_types::Text, _>(candidate)
+        .execute(connection)?;
+    Ok(())
+}
+
+/// Upserts one window. Candidates are only ever added here; the periodic
+/// flush is what removes refunded or expired ones.
+pub fn persist_entry(
+    connection: &mut SqliteConnection,
+    id_hash: &str,
+    info: &RateLimitInfo,
+) -> Result<(), diesel::result::Error> {
+    connection.immediate_transaction(|connection| insert_window(connection, id_hash, info))
+}
+
+fn replace_all(
+    connection: &mut SqliteConnection,
+    windows: &HashMap<String, RateLimitInfo>,
+    collection_started_at: chrono::DateTime<chrono::Utc>,
+) -> Result<(), diesel::result::Error> {
+    sql_query("DELETE FROM rate_limit_candidate").execute(connection)?;
+    sql_query("DELETE FROM rate_limit_window").execute(connection)?;
+    for (id_hash, info) in windows {
+        insert_window(connection, id_hash, info)?;
+    }
+    sql_query(
+        "INSERT INTO rate_limit_meta (key, value) VALUES (?, ?) \
+         ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+    )
+    .bind::<diesel::sql_types::Text, _>(COLLECTION_STARTED_AT)
+    .bind::<diesel::sql_types::Text, _>(timestamp(&collection_started_at))
+    .execute(connection)?;
+    Ok(())
+}
+
+/// Replaces the file content with a copy of the map: swept windows and
+/// refunded candidates disappear from disk exactly as they did from memory.
+pub fn save(
+    connection: &mut SqliteConnection,
+    windows: &HashMap<String, RateLimitInfo>,
+    collection_started_at: chrono::DateTime<chrono::Utc>,
+) -> Result<(), diesel::result::Error> {
+    connection
+        .immediate_transaction(|connection| replace_all(connection, windows, collection_started_at))
+}
+
+/// Copies the map under its lock, then writes it on a blocking thread after
+/// releasing the lock. No-op when persistence is disabled.
+pub async fn flush(state: &crate::AppState) {
+    let Some(state_file) = state.rate_limit_state.clone() else {
+        return;
+    };
+    // Held from before the copy until the write completes: a write-through
+    // admitted after the copy waits, instead of being erased by it.
+    let mut connection = state_file.connection.clone().lock_owned().await;
+    let now = chrono::Utc::now();
+    let windows: HashMap<String, RateLimitInfo> = {
+        let identifier_rate_limit = state.identifier_rate_limit.lock().await;
+        identifier_rate_limit
+            .iter()
+            .filter(|(_, info)| {
+                now.signed_duration_since(info.last_candidate_at) <= state.rate_limit_cooldown
+            })
+            .map(|(id_hash, info)| (id_hash.clone(), info.clone()))
+            .collect()
+    };
+    let collection_started_at = state.attempts_collection_started_at;
+    let saved = tokio::task::spawn_blocking(move || {
+        with_connection(&mut connection, &state_file.url, |connection| {
+            save(connection, &windows, collection_started_at)
+        })
+        .map(|()| windows.len())
+    })
+    .await;
+    // Log discipline: counts only, never identifiers.
+    match saved {
+        Ok(Ok(windows)) => tracing::debug!(windows, "rate-limit state flushed"),
+        Ok(Err(error)) => tracing::error!(error = %error, "rate-limit state flush failed"),
+        Err(error) => tracing::error!(error = %error, "rate-limit state flush panicked"),
+    }
+}
+
+/// Spawns the periodic flush. No-op when persistence is disabled.
+pub fn spawn_flusher(state: crate::AppState) {
+    if state.rate_limit_state.is_none() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            interval.tick().await;
+            flush(&state).await;
+        }
+    });
+}
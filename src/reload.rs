@@ -0,0 +1,90 @@
+//! Configuration reload on SIGHUP. `.env` and CONFIG_FILE are read again and
+//! validated like at startup; only the token buckets, the `/attempts`
+//! snapshot TTL and the secret length limit are applied. Everything else
+//! (the attempt budget and cooldown above all: in-flight rate-limit windows
+//! were opened under them) needs a restart, and a reload that changes it is
+//! refused as a whole, keeping the running configuration.
+
+use std::sync::atomic::Ordering;
+
+use crate::AppState;
+
+/// Re-reads and applies the configuration. Returns the reloadable keys that
+/// changed, or why nothing was applied. `identifier_rate_limit` is never
+/// touched.
+pub async fn reload(state: &AppState) -> Result<Vec<&'static str>, String> {
+    let updated = state.config_sources.load()?;
+    let running = state
+        .config
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    let refused = running.restart_required_changes(&updated);
+    if !refused.is_empty() {
+        return Err(format!(
+            "{} cannot change without a restart",
+            refused.join(", ")
+        ));
+    }
+
+    let mut changed = Vec::new();
+    for (key, bucket, running_bucket, updated_bucket) in [
+        (
+            "buckets.store",
+            &state.store_token_bucket,
+            running.buckets.store,
+            updated.buckets.store,
+        ),
+        (
+            "buckets.lookup",
+            &state.lookup_token_bucket,
+            running.buckets.lookup,
+            updated.buckets.lookup,
+        ),
+        (
+            "buckets.attempts",
+            &state.attempts_token_bucket,
+            running.buckets.attempts,
+            updated.buckets.attempts,
+        ),
+    ] {
+        if running_bucket.burst != updated_bucket.burst
+            || running_bucket.refill_per_second != updated_bucket.refill_per_second
+        {
+            bucket
+                .lock()
+                .await
+                .reconfigure(updated_bucket.burst, updated_bucket.refill_per_second);
+            changed.push(key);
+        }
+    }
+    if running.telemetry.snapshot_ttl_seconds != updated.telemetry.snapshot_ttl_seconds {
+        state
+            .attempts_snapshot_ttl_seconds
+            .store(updated.telemetry.snapshot_ttl_seconds, Ordering::Relaxed);
+        changed.push("telemetry.snapshot_ttl_seconds");
+    }
+    if running.server.secret_max_length != updated.server.secret_max_length {
+        state
+            .secret_max_length
+            .store(updated.server.secret_max_length, Ordering::Relaxed);
+        changed.push("server.secret_max_length");
+    }
+
+    *state.config.lock().unwrap_or_else(|e| e.into_inner()) = updated;
+    Ok(changed)
+}
+
+/// Spawns the task that reloads the configuration on every SIGHUP.
+pub fn spawn_reloader(state: AppState) {
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        .expect("failed to install SIGHUP handler");
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match reload(&state).await {
+                Ok(changed) => tracing::info!(?changed, "configuration reloaded"),
+                Err(reason) => tracing::warn!(%reason, "configuration reload refused"),
+            }
+        }
+    });
+}
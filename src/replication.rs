@@ -0,0 +1,305 @@
+//! Push replication of the `secret` table to one peer (REPLICATION_PEER_URL),
+//! for a warm standby or an active pair. Each side logs its own changes in
+//! `replication_log`, in the transaction that makes them: a record inserted
+//! by `/store` or `/rotate`, and a tombstone for a record deleted by
+//! `/trash`, `/rotate`, the purge at the end of a grace period or
+//! `keychain-admin purge`. A
+//! background task ships the log in order to the peer's `POST /replication`
+//! and drops what the peer acknowledged; a failed push is retried on the
+//! next tick, so a peer that was down catches up when it is back.
+//!
+//! Batches carry `HMAC-SHA256(key, CONTEXT || body)` in the
+//! `Replication-Signature` header, under a 32-byte key shared by both
+//! peers. The link is authenticated, not encrypted: records are client-side
+//! ciphertext already, but their ids and timestamps travel in clear, so the
+//! peers should talk over a private network.
+//!
+//! A batch also signs its `sent_at`. The receiver refuses one sent at or
+//! before the last batch it applied, or more than `BATCH_MAX_AGE` ago, with
+//! `409`: a captured batch cannot be replayed, for instance to bring back a
+//! record once its tombstone is pruned. A batch whose acknowledgement was
+//! lost is simply sent again, with a new `sent_at`. The peers' clocks must
+//! agree within `BATCH_MAX_AGE`.
+//!
+//! What does not replicate: rate-limit windows (each peer keeps its own
+//! budget), OPAQUE registrations, delayed-deletion marks and restores (the
+//! trash reaches the peer when the purge deletes the record), and expiry
+//! (each peer purges expired records on its own).
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::models::{ReplicationBatch, ReplicationEntry, ReplicationLogRow};
+use crate::secret_store::{SecretStore, StoreResult};
+use crate::AppState;
+
+pub const SIGNATURE_HEADER: &str = "replication-signature";
+
+/// Domain separation of the batch signatures.
+const CONTEXT: &[u8] = b"keychain replication v1";
+
+const SHIP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Connection, request and response each, to the peer.
+const SHIP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Log entries per batch, and the most a receiver accepts in one.
+const BATCH_ENTRIES: usize = 100;
+
+/// A batch stops growing past this size. With records capped at
+/// `MAX_REPLICATED_SECRET_LENGTH`, a batch stays under `MAX_BATCH_BODY`.
+const BATCH_BYTES: usize = 512 * 1024;
+
+/// Body limit of `POST /replication`.
+pub const MAX_BATCH_BODY: usize = 1024 * 1024;
+
+/// Token bucket of the replication listener: the peer ships one batch every
+/// `SHIP_INTERVAL`, and several back to back only to catch up.
+pub const RECEIVE_BURST: f64 = 10.0;
+pub const RECEIVE_REFILL_PER_SECOND: f64 = 1.0;
+
+/// Oldest `sent_at` a receiver accepts, well below `TOMBSTONE_RETENTION`:
+/// a receiver that has not applied any batch yet has no `sent_at` to
+/// compare with.
+pub const BATCH_MAX_AGE: chrono::Duration = chrono::Duration::hours(1);
+
+/// How long a deletion is remembered. A write of the deleted record still
+/// in flight (the peer down, then back) is refused until then.
+const TOMBSTONE_RETENTION: chrono::Duration = chrono::Duration::days(30);
+
+/// The configured peer and the key shared with it.
+pub struct Replication {
+    /// `host:port` to connect to.
+    address: String,
+    /// `Host` header of the requests.
+    authority: String,
+    key: [u8; 32],
+}
+
+impl Replication {
+    pub fn new(peer_url: &str, key: [u8; 32]) -> Result<Self, String> {
+        let (address, authority) = parse_peer_url(peer_url)?;
+        Ok(Self {
+            address,
+            authority,
+            key,
+        })
+    }
+
+    pub fn sign(&self, body: &[u8]) -> String {
+        hex::encode(self.mac(body).finalize().into_bytes())
+    }
+
+    /// Constant-time check of a `Replication-Signature` header.
+    pub fn verify(&self, body: &[u8], signature: &str) -> bool {
+        hex::decode(signature)
+            .is_ok_and(|signature| self.mac(body).verify_slice(&signature).is_ok())
+    }
+
+    fn mac(&self, body: &[u8]) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(CONTEXT);
+        mac.update(body);
+        mac
+    }
+}
+
+/// `http://host:port`, without path, query or credentials. Returns the
+/// address to connect to (port 80 by default) and the authority.
+pub fn parse_peer_url(peer_url: &str) -> Result<(String, String), String> {
+    let invalid = || format!("REPLICATION_PEER_URL must be http://host:port, got {peer_url:?}");
+    let uri: axum::http::Uri = peer_url.parse().map_err(|_| invalid())?;
+    if uri.scheme_str() != Some("http") || uri.query().is_some() || !matches!(uri.path(), "" | "/")
+    {
+        return Err(invalid());
+    }
+    let authority = uri.authority().ok_or_else(invalid)?;
+    if authority.as_str().contains('@') || authority.host().is_empty() {
+        return Err(invalid());
+    }
+    Ok((
+        format!(
+            "{}:{}",
+            authority.host(),
+            authority.port_u16().unwrap_or(80)
+        ),
+        authority.to_string(),
+    ))
+}
+
+/// Checks the shape of a received batch. The signature vouches for the
+/// sender, not for its bugs: a malformed batch is refused whole. Secrets get
+/// the checks of `/store`, length before base64.
+pub fn validate_batch(batch: &ReplicationBatch, secret_max_length: usize) -> Result<(), String> {
+    let is_time = |value: &str| chrono::DateTime::parse_from_rfc3339(value).is_ok();
+    if !is_time(&batch.sent_at) {
+        return Err("malformed replication batch".to_string());
+    }
+    if batch.entries.len() > BATCH_ENTRIES {
+        return Err(format!("replication batch exceeds {BATCH_ENTRIES} entries"));
+    }
+    for entry in &batch.entries {
+        let valid = match entry {
+            ReplicationEntry::Write(record) => {
+                crate::utils::is_256bits_hex_hash(&record.id)
+                    && is_time(&record.created_at)
+                    && !record.encrypted_secret.is_empty()
+                    && record.encrypted_secret.len() <= secret_max_length
+                    && crate::utils::is_base64(&record.encrypted_secret)
+            }
+            ReplicationEntry::Tombstone { id, deleted_at } => {
+                crate::utils::is_256bits_hex_hash(id) && is_time(deleted_at)
+            }
+        };
+        if !valid {
+            return Err("malformed replication entry".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Whether a batch sent at `sent_at`, already validated, is older than
+/// `BATCH_MAX_AGE` at `now`.
+pub fn is_stale(sent_at: &str, now: chrono::DateTime<chrono::Utc>) -> bool {
+    chrono::DateTime::parse_from_rfc3339(sent_at).is_ok_and(|sent_at| sent_at < now - BATCH_MAX_AGE)
+}
+
+/// Runs `task` on a blocking thread under a database permit, like any
+/// request.
+async fn with_store<T: Send + 'static>(
+    state: &AppState,
+    task: impl FnOnce(&dyn SecretStore) -> StoreResult<T> + Send + 'static,
+) -> Result<T, String> {
+    let permit = state
+        .database_semaphore
+        .clone()
+        .acquire_owned()
+        .await
+        .map_err(|error| error.to_string())?;
+    let secret_store = state.secret_store.clone();
+    #[cfg(test)]
+    let test_database_guard = state._test_database_guard.clone();
+    tokio::task::spawn_blocking(move || {
+        #[cfg(test)]
+        let _test_database_guard = test_database_guard;
+        let _database_permit = permit;
+        task(&*secret_store)
+    })
+    .await
+    .map_err(|error| error.to_string())?
+    .map_err(|error| error.to_string())
+}
+
+/// Builds the next batch from the oldest log entries. Returns the body and
+/// the last `seq` it covers.
+fn batch(rows: Vec<ReplicationLogRow>) -> Result<Option<(String, i32)>, String> {
+    let mut entries = Vec::new();
+    let mut size = 0;
+    let mut last_seq = None;
+    for row in rows {
+        let entry = match (row.record, row.deleted_at) {
+            (Some(record), _) => ReplicationEntry::Write(
+                serde_json::from_str(&record).map_err(|error| error.to_string())?,
+            ),
+            (None, Some(deleted_at)) => ReplicationEntry::Tombstone {
+                id: row.secret_id,
+                deleted_at,
+            },
+            (None, None) => return Err(format!("replication log entry {} is empty", row.seq)),
+        };
+        let entry_size = serde_json::to_vec(&entry)
+            .map_err(|error| error.to_string())?
+            .len();
+        if last_seq.is_some() && size + entry_size > BATCH_BYTES {
+            break;
+        }
+        size += entry_size;
+        entries.push(entry);
+        last_seq = Some(row.seq);
+    }
+    let Some(last_seq) = last_seq else {
+        return Ok(None);
+    };
+    let body = serde_json::to_string(&ReplicationBatch {
+        sent_at: crate::database::replication_timestamp(chrono::Utc::now()),
+        entries,
+    })
+    .map_err(|error| error.to_string())?;
+    Ok(Some((body, last_seq)))
+}
+
+async fn push(replication: &Replication, body: String) -> Result<(), String> {
+    let signature = replication.sign(body.as_bytes());
+    let stream = tokio::time::timeout(
+        SHIP_TIMEOUT,
+        tokio::net::TcpStream::connect(&replication.address),
+    )
+    .await
+    .map_err(|_| "connection timed out".to_string())?
+    .map_err(|error| error.to_string())?;
+    let (mut sender, connection) =
+        hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(stream))
+            .await
+            .map_err(|error| error.to_string())?;
+    tokio::spawn(async move {
+        let _ = connection.await;
+    });
+    let request = axum::http::Request::post("/replication")
+        .header(axum::http::header::HOST, &replication.authority)
+        .header(axum::http::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, signature)
+        .body(body)
+        .map_err(|error| error.to_string())?;
+    let response = tokio::time::timeout(SHIP_TIMEOUT, sender.send_request(request))
+        .await
+        .map_err(|_| "request timed out".to_string())?
+        .map_err(|error| error.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("the peer answered {}", response.status()));
+    }
+    Ok(())
+}
+
+/// Ships the whole log to the peer, batch by batch, dropping each batch
+/// once the peer has applied it. Returns the entries shipped; on error, the
+/// unshipped entries stay for the next call.
+pub async fn ship(state: &AppState) -> Result<usize, String> {
+    let Some(replication) = state.replication.clone() else {
+        return Ok(0);
+    };
+    let mut shipped = 0;
+    loop {
+        let rows = with_store(state, |store| {
+            store.read_replication_log(BATCH_ENTRIES as i64)
+        })
+        .await?;
+        let Some((body, last_seq)) = batch(rows)? else {
+            return Ok(shipped);
+        };
+        push(&replication, body).await?;
+        shipped += with_store(state, move |store| store.acknowledge_replication(last_seq)).await?;
+    }
+}
+
+/// Spawns the periodic shipping, and the pruning of old tombstones.
+pub fn spawn_shipper(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SHIP_INTERVAL);
+        loop {
+            interval.tick().await;
+            // Log discipline: counts only, never ids.
+            match ship(&state).await {
+                Ok(0) => {}
+                Ok(entries) => tracing::debug!(entries, "replication batch shipped"),
+                Err(error) => tracing::warn!(error = %error, "replication to the peer failed"),
+            }
+            let cutoff = chrono::Utc::now() - TOMBSTONE_RETENTION;
+            if let Err(error) =
+                with_store(&state, move |store| store.prune_tombstones(cutoff)).await
+            {
+                tracing::error!(error = %error, "tombstone pruning failed");
+            }
+        }
+    });
+}
@@ -0,0 +1,67 @@
+//! Background deletion of records that are due: trashed records whose grace
+//! period has elapsed, and records past the `expires_at` requested with
+//! `ttl_days`. Reads already ignore them; the purge reclaims space.
+
+use crate::AppState;
+
+const PURGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(600);
+
+/// Rows deleted per statement: each batch is its own short write, so
+/// concurrent `/store` requests wait at most one batch for the lock.
+const PURGE_BATCH_SIZE: i64 = 500;
+
+/// Runs `purge_batch` until a batch comes back short. Returns the total.
+/// `keychain-admin purge` uses the same batching.
+pub(crate) fn purge_all(
+    mut purge_batch: impl FnMut(i64) -> Result<usize, diesel::result::Error>,
+) -> Result<usize, diesel::result::Error> {
+    let mut total = 0;
+    loop {
+        let purged = purge_batch(PURGE_BATCH_SIZE)?;
+        total += purged;
+        if (purged as i64) < PURGE_BATCH_SIZE {
+            return Ok(total);
+        }
+    }
+}
+
+pub async fn purge(state: &AppState) {
+    // Background work waits for a slot like any request: it must not push
+    // the database past the configured concurrency.
+    let Ok(permit) = state.database_semaphore.clone().acquire_owned().await else {
+        return;
+    };
+    let secret_store = state.secret_store.clone();
+    #[cfg(test)]
+    let test_database_guard = state._test_database_guard.clone();
+    let purged = tokio::task::spawn_blocking(move || {
+        #[cfg(test)]
+        let _test_database_guard = test_database_guard;
+        let _database_permit = permit;
+        let now = chrono::Utc::now();
+        let trashed = purge_all(|batch_size| secret_store.purge_trashed(now, batch_size))?;
+        let expired = purge_all(|batch_size| secret_store.purge_expired(now, batch_size))?;
+        Ok::<_, diesel::result::Error>((trashed, expired))
+    })
+    .await;
+    // Log discipline: counts only, never ids.
+    match purged {
+        Ok(Ok((0, 0))) => {}
+        Ok(Ok((trashed, expired))) => tracing::info!(trashed, expired, "secrets purged"),
+        Ok(Err(error)) => tracing::error!(error = %error, "secret purge failed"),
+        Err(error) => tracing::error!(error = %error, "secret purge panicked"),
+    }
+}
+
+/// Spawns the periodic purge. It also runs with both policies disabled:
+/// records trashed or given a TTL under an earlier configuration still
+/// become due.
+pub fn spawn_purger(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            purge(&state).await;
+        }
+    });
+}
@@ -5,7 +5,10 @@ use axum::{
 };
 
 use crate::{
-    handlers::{attempts, fetch, info, store},
+    handlers::{
+        attempts, challenge, fetch, health, info, log, opaque, oprf, replication, restore, rotate,
+        store,
+    },
     models::FetchSecret,
     AppState,
 };
@@ -36,12 +39,82 @@ pub fn new(app_state: AppState) -> Router {
             }),
         )
         .with_state(app_state.clone())
+        .route("/rotate", post(rotate::rotate_secret))
+        .with_state(app_state.clone())
+        .route("/restore", post(restore::restore_secret))
+        .with_state(app_state.clone())
+        .route("/oprf", post(oprf::evaluate))
+        .with_state(app_state.clone())
+        .route("/opaque/register/start", post(opaque::register_start))
+        .with_state(app_state.clone())
+        // The record carries a secret of up to `secret_max_length`, which a
+        // reload can raise: the limit is read on each request instead.
+        .route(
+            "/opaque/register/finish",
+            post(opaque::register_finish)
+                .layer(DefaultBodyLimit::disable())
+                .layer(axum::middleware::from_fn_with_state(
+                    app_state.clone(),
+                    opaque::limit_register_finish_body,
+                ))
+                .with_state(app_state.clone()),
+        )
+        .route("/opaque/login/start", post(opaque::login_start))
+        .with_state(app_state.clone())
+        .route("/opaque/login/finish", post(opaque::login_finish))
+        .with_state(app_state.clone())
         .route("/info", get(info::get_info))
         .with_state(app_state.clone())
+        .route("/challenge", get(challenge::get_challenge))
+        .with_state(app_state.clone())
         .route("/attempts", get(attempts::get_attempts))
-        .with_state(app_state)
-        // Legitimate JSON requests are below 320 bytes. Keep modest headroom
-        // while rejecting oversized bodies before deserialization.
+        .with_state(app_state.clone())
+        .route("/attempts/delta", get(attempts::get_attempts_delta))
+        .with_state(app_state.clone())
+        .route(
+            "/attempts/bucket/:prefix",
+            get(attempts::get_attempts_bucket),
+        )
+        .with_state(app_state.clone())
+        .route("/attempts/filter", get(attempts::get_attempts_filter))
+        .with_state(app_state.clone())
+        .route("/log/head", get(log::get_head))
+        .with_state(app_state.clone())
+        .route("/log/inclusion", get(log::get_inclusion))
+        .with_state(app_state.clone())
+        .route("/log/consistency", get(log::get_consistency))
+        .with_state(app_state.clone())
+        // Legitimate JSON requests are below 320 bytes (about 640 for
+        // `/rotate`, which carries two credential pairs and a secret). Keep
+        // modest headroom while rejecting oversized bodies before
+        // deserialization.
         .layer(DefaultBodyLimit::max(1024))
         .layer(timeout)
+        // Outermost, so timeouts are counted too.
+        .layer(axum::middleware::from_fn_with_state(
+            app_state,
+            crate::metrics::count_responses,
+        ))
+}
+
+/// Router of the operator listener (OPERATOR_ADDRESS, loopback only): never
+/// merged into `new`, so none of this is reachable through the onion service.
+pub fn operator(app_state: AppState) -> Router {
+    Router::new()
+        .route("/metrics", get(crate::metrics::get_metrics))
+        .route("/healthz", get(health::get_healthz))
+        .route("/readyz", get(health::get_readyz))
+        .with_state(app_state)
+}
+
+/// Router of the replication listener (REPLICATION_LISTEN_ADDRESS): never
+/// merged into `new`, so the onion service does not reach it. Its body limit
+/// fits a batch of up to hundreds of records.
+pub fn replication(app_state: AppState) -> Router {
+    let timeout = tower_http::timeout::TimeoutLayer::new(std::time::Duration::from_secs(30));
+    Router::new()
+        .route("/replication", post(replication::receive_batch))
+        .layer(DefaultBodyLimit::max(crate::replication::MAX_BATCH_BODY))
+        .layer(timeout)
+        .with_state(app_state)
 }
@@ -5,5 +5,57 @@ diesel::table! {
         id -> Text,
         created_at -> Text,
         encrypted_secret -> Text,
+        trash_after -> Nullable<Text>,
+        expires_at -> Nullable<Text>,
+        share_index -> Nullable<Integer>,
+        federation_id -> Nullable<Text>,
     }
 }
+
+diesel::table! {
+    log_leaf (leaf_index) {
+        leaf_index -> BigInt,
+        leaf_hash -> Text,
+    }
+}
+
+diesel::table! {
+    opaque_record (credential_id) {
+        credential_id -> Text,
+        created_at -> Text,
+        registration_record -> Text,
+        encrypted_secret -> Text,
+    }
+}
+
+diesel::table! {
+    replication_log (seq) {
+        seq -> Integer,
+        secret_id -> Text,
+        record -> Nullable<Text>,
+        deleted_at -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    replication_received (peer) {
+        peer -> Integer,
+        sent_at -> Text,
+    }
+}
+
+diesel::table! {
+    replication_tombstone (secret_id) {
+        secret_id -> Text,
+        deleted_at -> Text,
+    }
+}
+
+diesel::allow_tables_to_appear_in_same_query!(
+    log_leaf,
+    opaque_record,
+    replication_log,
+    replication_received,
+    replication_tombstone,
+    secret,
+);
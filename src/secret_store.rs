@@ -0,0 +1,280 @@
+//! Storage backend of the `secret` records. The handlers only see
+//! [`SecretStore`]; SQLite is the default backend, PostgreSQL is available
+//! behind the `postgres` cargo feature (see `postgres.rs`).
+//!
+//! Every backend must keep the same guarantees: `write` is idempotent and
+//! never overwrites (`/store` must not be an existence oracle), the
+//! read-and-modify operations are atomic, and records past `trash_after` or
+//! `expires_at` are invisible to every read. Every change a client or the
+//! purge makes also appends its event to the transparency log (see
+//! transparency.rs), in the same transaction.
+
+use std::sync::Arc;
+
+use crate::database::{self, pooled_connection, DatabasePool};
+use crate::models::{
+    LogLeaf, OpaqueRecord, ReplicationApplied, ReplicationEntry, ReplicationLogRow, Secret,
+};
+
+pub type StoreResult<T> = Result<T, diesel::result::Error>;
+
+pub trait SecretStore: Send + Sync {
+    /// Brings the schema up to date. Runs once, at startup.
+    fn migrate(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    /// Readiness probe: opens a fresh connection (not a pooled one), queries
+    /// it and checks the schema is current. Returns the failed check.
+    fn readiness(&self) -> Result<(), &'static str>;
+
+    /// Inserts `secret` unless its id already exists, which is not an error.
+    /// The store is logged either way.
+    fn write(&self, secret: &Secret) -> StoreResult<()>;
+
+    fn read(&self, secret_id: &str) -> StoreResult<Option<Secret>>;
+
+    /// Deletes the record and returns it, atomically.
+    fn read_and_trash(&self, secret_id: &str) -> StoreResult<Option<Secret>>;
+
+    /// Marks the record for deletion at `deletion_at`, keeping an earlier
+    /// deadline, and returns it.
+    fn read_and_mark(&self, secret_id: &str, deletion_at: &str) -> StoreResult<Option<Secret>>;
+
+    /// Cancels a pending deletion and returns the record.
+    fn restore(&self, secret_id: &str) -> StoreResult<Option<Secret>>;
+
+    /// Replaces `old_secret_id` with `new_secret` in one transaction; see
+    /// `database::rotate_secret_by_id`.
+    fn rotate(
+        &self,
+        old_secret_id: &str,
+        new_secret: &Secret,
+        deletion_at: Option<&str>,
+    ) -> StoreResult<Option<Secret>>;
+
+    /// Inserts an OPAQUE registration unless its credential exists; `false`
+    /// when it did, leaving the stored record untouched.
+    fn write_opaque(&self, record: &OpaqueRecord) -> StoreResult<bool>;
+
+    fn read_opaque(&self, credential_id: &str) -> StoreResult<Option<OpaqueRecord>>;
+
+    /// Deletes up to `batch_size` records whose grace period has elapsed.
+    fn purge_trashed(
+        &self,
+        now: chrono::DateTime<chrono::Utc>,
+        batch_size: i64,
+    ) -> StoreResult<usize>;
+
+    /// Deletes up to `batch_size` records past their `expires_at`.
+    fn purge_expired(
+        &self,
+        now: chrono::DateTime<chrono::Utc>,
+        batch_size: i64,
+    ) -> StoreResult<usize>;
+
+    /// Transparency log leaves from `from_index` on, in order, up to
+    /// `limit`.
+    fn read_log_leaves(&self, from_index: i64, limit: i64) -> StoreResult<Vec<LogLeaf>>;
+
+    /// Peer replication (see replication.rs). Only SQLite keeps a
+    /// replication log: the configuration refuses replication with any
+    /// other backend, which would never be asked.
+    fn read_replication_log(&self, _limit: i64) -> StoreResult<Vec<ReplicationLogRow>> {
+        Ok(Vec::new())
+    }
+
+    /// Drops the log entries up to `last_seq` once the peer has them.
+    fn acknowledge_replication(&self, _last_seq: i32) -> StoreResult<usize> {
+        Ok(0)
+    }
+
+    /// Applies a batch received from the peer; see
+    /// `database::apply_replication_entries`.
+    fn apply_replication(
+        &self,
+        _sent_at: &str,
+        _entries: &[ReplicationEntry],
+    ) -> StoreResult<Option<ReplicationApplied>> {
+        Err(diesel::result::Error::QueryBuilderError(
+            "replication needs the SQLite backend".into(),
+        ))
+    }
+
+    /// Forgets the tombstones of deletions before `cutoff`.
+    fn prune_tombstones(&self, _cutoff: chrono::DateTime<chrono::Utc>) -> StoreResult<usize> {
+        Ok(0)
+    }
+
+    /// Runs raw SQL, for tests that inspect or break the backend.
+    #[cfg(test)]
+    fn execute_raw(&self, sql: &str) -> StoreResult<usize>;
+}
+
+/// Selects the backend from DATABASE_URL: a `postgres://` or
+/// `postgresql://` URL needs the `postgres` feature, anything else is a
+/// SQLite path. `replicate` turns on the replication log, SQLite only.
+pub fn new_secret_store(
+    database_url: &str,
+    max_connections: u32,
+    replicate: bool,
+) -> Result<Arc<dyn SecretStore>, String> {
+    if database_url.starts_with("postgres://") || database_url.starts_with("postgresql://") {
+        #[cfg(feature = "postgres")]
+        return Ok(Arc::new(crate::postgres::PostgresStore::new(
+            database_url,
+            max_connections,
+        )));
+        #[cfg(not(feature = "postgres"))]
+        return Err(
+            "DATABASE_URL is a PostgreSQL URL, but the server was built without the `postgres` feature"
+                .to_string(),
+        );
+    }
+    Ok(Arc::new(SqliteStore::new(
+        database_url,
+        max_connections,
+        replicate,
+    )))
+}
+
+/// The default backend: `database.rs` over a pool of SQLite connections.
+pub struct SqliteStore {
+    database_url: String,
+    pool: DatabasePool,
+    /// Whether writes and deletions are appended to the replication log.
+    replicate: bool,
+}
+
+impl SqliteStore {
+    pub fn new(database_url: &str, max_connections: u32, replicate: bool) -> Self {
+        Self {
+            database_url: database_url.to_string(),
+            pool: database::new_pool(database_url, max_connections),
+            replicate,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn pool(&self) -> &DatabasePool {
+        &self.pool
+    }
+}
+
+impl SecretStore for SqliteStore {
+    fn migrate(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut connection = pooled_connection(&self.pool)?;
+        database::run_migrations(&mut connection)?;
+        database::enable_wal(&mut connection)?;
+        Ok(())
+    }
+
+    fn readiness(&self) -> Result<(), &'static str> {
+        use diesel::Connection;
+        // Same path as `establish_connection`: open, then apply the pragmas.
+        let mut connection =
+            diesel::SqliteConnection::establish(&self.database_url).map_err(|_| "connection")?;
+        database::apply_connection_pragmas(&mut connection).map_err(|_| "connection")?;
+        database::check_ready(&mut connection)
+    }
+
+    fn write(&self, secret: &Secret) -> StoreResult<()> {
+        database::insert_secret(&mut *pooled_connection(&self.pool)?, secret, self.replicate)
+    }
+
+    fn read(&self, secret_id: &str) -> StoreResult<Option<Secret>> {
+        database::read_secret_by_id(&mut *pooled_connection(&self.pool)?, secret_id)
+    }
+
+    fn read_and_trash(&self, secret_id: &str) -> StoreResult<Option<Secret>> {
+        database::read_and_trash_secret_by_id(
+            &mut *pooled_connection(&self.pool)?,
+            secret_id,
+            self.replicate,
+        )
+    }
+
+    fn read_and_mark(&self, secret_id: &str, deletion_at: &str) -> StoreResult<Option<Secret>> {
+        database::read_and_mark_secret_by_id(
+            &mut *pooled_connection(&self.pool)?,
+            secret_id,
+            deletion_at,
+        )
+    }
+
+    fn restore(&self, secret_id: &str) -> StoreResult<Option<Secret>> {
+        database::restore_secret_by_id(&mut *pooled_connection(&self.pool)?, secret_id)
+    }
+
+    fn rotate(
+        &self,
+        old_secret_id: &str,
+        new_secret: &Secret,
+        deletion_at: Option<&str>,
+    ) -> StoreResult<Option<Secret>> {
+        database::rotate_secret_by_id(
+            &mut *pooled_connection(&self.pool)?,
+            old_secret_id,
+            new_secret,
+            deletion_at,
+            self.replicate,
+        )
+    }
+
+    fn write_opaque(&self, record: &OpaqueRecord) -> StoreResult<bool> {
+        database::insert_opaque_record(&mut *pooled_connection(&self.pool)?, record)
+    }
+
+    fn read_opaque(&self, credential_id: &str) -> StoreResult<Option<OpaqueRecord>> {
+        database::read_opaque_record(&mut *pooled_connection(&self.pool)?, credential_id)
+    }
+
+    fn purge_trashed(
+        &self,
+        now: chrono::DateTime<chrono::Utc>,
+        batch_size: i64,
+    ) -> StoreResult<usize> {
+        database::purge_trashed_secrets(
+            &mut *pooled_connection(&self.pool)?,
+            now,
+            batch_size,
+            self.replicate,
+        )
+    }
+
+    fn purge_expired(
+        &self,
+        now: chrono::DateTime<chrono::Utc>,
+        batch_size: i64,
+    ) -> StoreResult<usize> {
+        database::purge_expired_secrets(&mut *pooled_connection(&self.pool)?, now, batch_size)
+    }
+
+    fn read_log_leaves(&self, from_index: i64, limit: i64) -> StoreResult<Vec<LogLeaf>> {
+        database::read_log_leaves(&mut *pooled_connection(&self.pool)?, from_index, limit)
+    }
+
+    fn read_replication_log(&self, limit: i64) -> StoreResult<Vec<ReplicationLogRow>> {
+        database::read_replication_log(&mut *pooled_connection(&self.pool)?, limit)
+    }
+
+    fn acknowledge_replication(&self, last_seq: i32) -> StoreResult<usize> {
+        database::acknowledge_replication_log(&mut *pooled_connection(&self.pool)?, last_seq)
+    }
+
+    fn apply_replication(
+        &self,
+        sent_at: &str,
+        entries: &[ReplicationEntry],
+    ) -> StoreResult<Option<ReplicationApplied>> {
+        database::apply_replication_entries(&mut *pooled_connection(&self.pool)?, sent_at, entries)
+    }
+
+    fn prune_tombstones(&self, cutoff: chrono::DateTime<chrono::Utc>) -> StoreResult<usize> {
+        database::prune_replication_tombstones(&mut *pooled_connection(&self.pool)?, cutoff)
+    }
+
+    #[cfg(test)]
+    fn execute_raw(&self, sql: &str) -> StoreResult<usize> {
+        use diesel::RunQueryDsl;
+        diesel::sql_query(sql).execute(&mut *pooled_connection(&self.pool)?)
+    }
+}
@@ -0,0 +1,157 @@
+//! Client helper of the federation mode: Shamir secret sharing over
+//! GF(2^8), byte by byte, so that any `threshold` of the Key Servers of a
+//! federation can recover a secret and fewer learn nothing about it. The
+//! server never calls this module; it only stores a share's index next to
+//! its `encrypted_secret` (see `/store`).
+//!
+//! Field: GF(2^8) with the AES polynomial `x^8 + x^4 + x^3 + x + 1`
+//! (`0x11b`). Share `i` (1 to 255) of byte `s` is `p(i)`, where
+//! `p(x) = s + a_1 x + … + a_{t-1} x^{t-1}`.
+//!
+//! Splitting is deterministic: the coefficients are the stream
+//! `HMAC-SHA512(seed, CONTEXT || t || counter || secret)`, `counter` a
+//! big-endian u32 from 0, byte `i` taking coefficients `a_1 … a_{t-1}` at
+//! offset `i·(t-1)`. The same seed and secret always give the same shares,
+//! so a client can re-create a lost share without weakening the others;
+//! the seed must be as secret as the secret itself.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha512;
+
+/// Domain separation of the coefficient stream.
+const CONTEXT: &[u8] = b"keychain shamir v1";
+
+/// One share: its x-coordinate, 1 to 255, and one byte per secret byte.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Share {
+    pub index: u8,
+    pub value: Vec<u8>,
+}
+
+fn multiply(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    for _ in 0..8 {
+        product ^= a & (b & 1).wrapping_neg();
+        let carry = (a >> 7).wrapping_neg();
+        a = (a << 1) ^ (carry & 0x1b);
+        b >>= 1;
+    }
+    product
+}
+
+/// `a^254 = a^-1` for a non-zero `a`.
+fn inverse(a: u8) -> u8 {
+    let mut result = 1;
+    let mut power = a;
+    let mut exponent = 254u8;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = multiply(result, power);
+        }
+        power = multiply(power, power);
+        exponent >>= 1;
+    }
+    result
+}
+
+fn coefficients(secret: &[u8], threshold: u8, seed: &[u8; 32]) -> Vec<u8> {
+    let length = secret.len() * usize::from(threshold - 1);
+    let mut stream = Vec::with_capacity(length + 64);
+    let mut counter = 0u32;
+    while stream.len() < length {
+        let mut mac =
+            Hmac::<Sha512>::new_from_slice(seed).expect("HMAC accepts keys of any length");
+        mac.update(CONTEXT);
+        mac.update(&[threshold]);
+        mac.update(&counter.to_be_bytes());
+        mac.update(secret);
+        stream.extend_from_slice(&mac.finalize().into_bytes());
+        counter += 1;
+    }
+    stream.truncate(length);
+    stream
+}
+
+/// Splits `secret` into `shares` shares with indexes 1 to `shares`, any
+/// `threshold` of which recombine to it.
+pub fn split(
+    secret: &[u8],
+    threshold: u8,
+    shares: u8,
+    seed: &[u8; 32],
+) -> Result<Vec<Share>, String> {
+    if secret.is_empty() {
+        return Err("the secret must not be empty".to_string());
+    }
+    if threshold < 2 || threshold > shares {
+        return Err(format!(
+            "the threshold must be between 2 and the share count {shares}, got {threshold}"
+        ));
+    }
+    let coefficients = coefficients(secret, threshold, seed);
+    let degree = usize::from(threshold - 1);
+    Ok((1..=shares)
+        .map(|index| Share {
+            index,
+            value: secret
+                .iter()
+                .enumerate()
+                .map(|(position, byte)| {
+                    // Horner, from the highest coefficient down to the secret.
+                    coefficients[position * degree..(position + 1) * degree]
+                        .iter()
+                        .rev()
+                        .chain(std::iter::once(byte))
+                        .fold(0, |value, coefficient| multiply(value, index) ^ coefficient)
+                })
+                .collect(),
+        })
+        .collect())
+}
+
+/// Recombines the secret from `shares` (Lagrange interpolation at 0). With
+/// fewer shares than the threshold the result is a wrong secret, not an
+/// error: the shares alone cannot tell, so the secret must be checked (its
+/// decryption authenticates it).
+pub fn combine(shares: &[Share]) -> Result<Vec<u8>, String> {
+    let Some(first) = shares.first() else {
+        return Err("no shares".to_string());
+    };
+    for (position, share) in shares.iter().enumerate() {
+        if share.index == 0 {
+            return Err("share index 0 is not a share".to_string());
+        }
+        if share.value.len() != first.value.len() {
+            return Err("shares of different lengths".to_string());
+        }
+        if shares[..position]
+            .iter()
+            .any(|other| other.index == share.index)
+        {
+            return Err(format!("share {} is given twice", share.index));
+        }
+    }
+    // Lagrange basis at 0: l_j = prod_{m != j} x_m / (x_m - x_j), and
+    // subtraction is XOR.
+    let basis: Vec<u8> = shares
+        .iter()
+        .map(|share| {
+            shares
+                .iter()
+                .filter(|other| other.index != share.index)
+                .fold(1, |basis, other| {
+                    multiply(
+                        basis,
+                        multiply(other.index, inverse(other.index ^ share.index)),
+                    )
+                })
+        })
+        .collect();
+    Ok((0..first.value.len())
+        .map(|position| {
+            shares.iter().zip(&basis).fold(0, |secret, (share, basis)| {
+                secret ^ multiply(share.value[position], *basis)
+            })
+        })
+        .collect())
+}
@@ -1,18 +1,41 @@
+pub mod test_admin;
 pub mod test_adversarial;
 pub mod test_attempts;
+pub mod test_attempts_bucket;
+pub mod test_attempts_delta;
+pub mod test_attempts_filter;
 pub mod test_audit_claims;
+pub mod test_backoff;
 pub mod test_concurrency;
+pub mod test_config;
 pub mod test_contract;
 pub mod test_db_errors;
 pub mod test_distinct_candidates;
 pub mod test_env;
+pub mod test_export;
+pub mod test_federation;
 pub mod test_fetch;
+pub mod test_health;
 pub mod test_info;
+pub mod test_metrics;
 pub mod test_migrations;
+pub mod test_opaque;
+pub mod test_oprf;
+pub mod test_pool;
+pub mod test_pow;
 pub mod test_rate_limit;
+pub mod test_rate_limit_state;
+pub mod test_reload;
+pub mod test_replication;
+pub mod test_retention;
+pub mod test_rotate;
 pub mod test_server;
+pub mod test_signed_canary;
 pub mod test_store;
+pub mod test_transparency;
 pub mod test_trash;
+pub mod test_trash_grace;
+pub mod test_unix_socket;
 
 static SHA256_111111: &str = "bcb15f821479b4d5772bd0ca866c00ad5f926e3580720659cc80d39c9d09802a";
 static SHA256_222222: &str = "4cc8f4d609b717356701c57a03e737e5ac8fe885da8c7163d3de47e01849c635";
@@ -0,0 +1,137 @@
+use crate::{
+    admin::{execute, parse_args, stats, Command, Stats},
+    models::Secret,
+    tests::{distinct_candidate, BASE64_ENCRYPTED_SECRET},
+};
+use diesel::{Connection, SqliteConnection};
+
+fn migrated_connection() -> SqliteConnection {
+    let mut connection =
+        SqliteConnection::establish(":memory:").expect("failed to create in-memory database");
+    crate::database::run_migrations(&mut connection).unwrap();
+    connection
+}
+
+fn insert(connection: &mut SqliteConnection, index: usize, created_at: &str, encrypted: &str) {
+    assert!(crate::database::write(
+        connection,
+        &Secret {
+            id: distinct_candidate(index),
+            created_at: created_at.to_string(),
+            encrypted_secret: encrypted.to_string(),
+            trash_after: None,
+            expires_at: None,
+            share_index: None,
+            federation_id: None,
+        }
+    ));
+}
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn test_parse_args() {
+    assert_eq!(parse_args(&args(&["migrate"])), Ok(Command::Migrate));
+    assert_eq!(
+        parse_args(&args(&["integrity-check"])),
+        Ok(Command::IntegrityCheck)
+    );
+    assert_eq!(
+        parse_args(&args(&["purge", "--older-than", "365"])),
+        Ok(Command::Purge {
+            older_than_days: 365
+        })
+    );
+    for invalid in [
+        &[][..],
+        &["purge"],
+        &["purge", "--older-than", "0"],
+        &["purge", "--older-than", "-1"],
+        &["stats", "--verbose"],
+    ] {
+        assert!(parse_args(&args(invalid)).is_err(), "{invalid:?}");
+    }
+}
+
+#[test]
+fn test_migrate_reports_applied_migrations() {
+    let mut connection =
+        SqliteConnection::establish(":memory:").expect("failed to create in-memory database");
+    let report = execute(&mut connection, &Command::Migrate, false).unwrap();
+    assert_ne!(report, "migrations applied: 0\n");
+    let report = execute(&mut connection, &Command::Migrate, false).unwrap();
+    assert_eq!(report, "migrations applied: 0\n");
+}
+
+/// Stats are aggregates only: the report never contains a secret_id, a
+/// full timestamp or a secret.
+#[test]
+fn test_stats_report_counts_only() {
+    let mut connection = migrated_connection();
+    insert(&mut connection, 0, "2025-01-10T10:00:00+00:00", "YQ==");
+    insert(&mut connection, 1, "2025-01-20T10:00:00+00:00", "YWJjZA==");
+    insert(
+        &mut connection,
+        2,
+        "2025-03-01T10:00:00+00:00",
+        BASE64_ENCRYPTED_SECRET,
+    );
+
+    assert_eq!(
+        stats(&mut connection).unwrap(),
+        Stats {
+            secrets: 3,
+            pending_deletion: 0,
+            expiring: 0,
+            size_histogram: vec![(4, 1), (8, 1), (128, 1)],
+            created_by_month: vec![("2025-01".to_string(), 2), ("2025-03".to_string(), 1)],
+        }
+    );
+
+    let report = execute(&mut connection, &Command::Stats, false).unwrap();
+    assert!(report.contains("secrets: 3"));
+    for index in 0..3 {
+        assert!(!report.contains(&distinct_candidate(index)));
+    }
+    assert!(!report.contains("2025-01-10"));
+    assert!(!report.contains(BASE64_ENCRYPTED_SECRET));
+}
+
+#[test]
+fn test_purge_deletes_only_records_older_than_the_cutoff() {
+    let mut connection = migrated_connection();
+    insert(&mut connection, 0, "2000-01-01T00:00:00+00:00", "YQ==");
+    insert(
+        &mut connection,
+        1,
+        "2000-01-02T00:00:00.123456789+00:00",
+        "YQ==",
+    );
+    insert(&mut connection, 2, &chrono::Utc::now().to_rfc3339(), "YQ==");
+
+    let report = execute(
+        &mut connection,
+        &Command::Purge {
+            older_than_days: 30,
+        },
+        false,
+    )
+    .unwrap();
+    assert_eq!(report, "secrets purged: 2\n");
+    assert_eq!(stats(&mut connection).unwrap().secrets, 1);
+}
+
+#[test]
+fn test_integrity_check_and_vacuum() {
+    let mut connection = migrated_connection();
+    insert(&mut connection, 0, "2000-01-01T00:00:00+00:00", "YQ==");
+    assert_eq!(
+        execute(&mut connection, &Command::IntegrityCheck, false).unwrap(),
+        "integrity check: ok\n"
+    );
+    let report = execute(&mut connection, &Command::Vacuum, false).unwrap();
+    assert!(report.starts_with("database size: "));
+    assert_eq!(stats(&mut connection).unwrap().secrets, 1);
+}
@@ -49,6 +49,9 @@ async fn test_fetch_wrong_key_unknown_vs_known_identifier_same_shape() {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
             encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         })
         .expect_success()
         .await;
@@ -58,6 +61,7 @@ async fn test_fetch_wrong_key_unknown_vs_known_identifier_same_shape() {
         .json(&FetchSecret {
             identifier: SHA256_222222.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -66,6 +70,7 @@ async fn test_fetch_wrong_key_unknown_vs_known_identifier_same_shape() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -104,6 +109,9 @@ async fn test_trash_wrong_key_unknown_vs_known_identifier_same_status() {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
             encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         })
         .expect_success()
         .await;
@@ -113,6 +121,7 @@ async fn test_trash_wrong_key_unknown_vs_known_identifier_same_status() {
         .json(&FetchSecret {
             identifier: SHA256_222222.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -121,6 +130,7 @@ async fn test_trash_wrong_key_unknown_vs_known_identifier_same_status() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -146,6 +156,9 @@ async fn test_successful_fetch_increments_total_attempts() {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
             encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         })
         .expect_success()
         .await;
@@ -154,6 +167,7 @@ async fn test_successful_fetch_increments_total_attempts() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .expect_success()
         .await;
@@ -183,6 +197,9 @@ async fn test_success_does_not_reset_the_counter() {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
             encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         })
         .expect_success()
         .await;
@@ -191,6 +208,7 @@ async fn test_success_does_not_reset_the_counter() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .expect_success()
         .await;
@@ -200,6 +218,7 @@ async fn test_success_does_not_reset_the_counter() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -224,6 +243,9 @@ async fn test_successful_fetch_is_not_refunded() {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
             encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         })
         .expect_success()
         .await;
@@ -232,6 +254,7 @@ async fn test_successful_fetch_is_not_refunded() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .expect_success()
         .await;
@@ -254,6 +277,7 @@ async fn test_429_does_not_consume_budget() {
             .json(&FetchSecret {
                 identifier: SHA256_111111.to_string(),
                 authentication_key: crate::tests::distinct_candidate(index),
+                pow: None,
             })
             .expect_failure()
             .await;
@@ -265,6 +289,7 @@ async fn test_429_does_not_consume_budget() {
             // Replay an already admitted key: saturation must be based on
             // distinct candidates, while this request still counts.
             authentication_key: crate::tests::distinct_candidate(0),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -301,6 +326,7 @@ async fn test_full_map_does_not_evict_protected_identifier() {
             .json(&FetchSecret {
                 identifier: SHA256_111111.to_string(),
                 authentication_key: crate::tests::distinct_candidate(index),
+                pow: None,
             })
             .expect_failure()
             .await;
@@ -312,6 +338,7 @@ async fn test_full_map_does_not_evict_protected_identifier() {
         .json(&FetchSecret {
             identifier: SHA256_222222.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -323,6 +350,7 @@ async fn test_full_map_does_not_evict_protected_identifier() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -346,6 +374,9 @@ async fn test_remaining_attempts_relationship() {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
             encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         })
         .expect_success()
         .await;
@@ -356,6 +387,7 @@ async fn test_remaining_attempts_relationship() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .expect_success()
         .await;
@@ -377,6 +409,7 @@ async fn test_remaining_attempts_relationship() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: crate::tests::distinct_candidate(2),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -385,6 +418,7 @@ async fn test_remaining_attempts_relationship() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .expect_success()
         .await;
@@ -420,6 +454,7 @@ async fn test_unicode_identifier_rejected() {
         .json(&FetchSecret {
             identifier,
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -442,6 +477,7 @@ async fn test_identifier_with_whitespace_rejected() {
         .json(&FetchSecret {
             identifier,
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -465,6 +501,7 @@ async fn test_malformed_identifier_and_key_lengths_rejected() {
             .json(&FetchSecret {
                 identifier,
                 authentication_key: SHA256_222222.to_string(),
+                pow: None,
             })
             .expect_failure()
             .await;
@@ -482,6 +519,7 @@ async fn test_malformed_identifier_and_key_lengths_rejected() {
             .json(&FetchSecret {
                 identifier: SHA256_111111.to_string(),
                 authentication_key: key,
+                pow: None,
             })
             .expect_failure()
             .await;
@@ -580,6 +618,9 @@ async fn test_snapshot_never_contains_secret_material() {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
             encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         })
         .expect_success()
         .await;
@@ -588,6 +629,7 @@ async fn test_snapshot_never_contains_secret_material() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .expect_success()
         .await;
@@ -614,6 +656,9 @@ async fn test_error_responses_leak_no_secret_material() {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
             encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         })
         .expect_success()
         .await;
@@ -624,6 +669,7 @@ async fn test_error_responses_leak_no_secret_material() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -638,6 +684,7 @@ async fn test_error_responses_leak_no_secret_material() {
             .json(&FetchSecret {
                 identifier: SHA256_111111.to_string(),
                 authentication_key: crate::tests::distinct_candidate(index),
+                pow: None,
             })
             .expect_failure()
             .await;
@@ -647,6 +694,7 @@ async fn test_error_responses_leak_no_secret_material() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -668,6 +716,7 @@ async fn test_401_requested_at_is_the_callers_own_time() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -706,6 +755,7 @@ async fn test_etag_is_fixed_length_regardless_of_content() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -729,6 +779,7 @@ async fn test_snapshot_entries_are_sorted_by_id_hash() {
             .json(&FetchSecret {
                 identifier: identifier.to_string(),
                 authentication_key: NOT_PASSWORD_HASH.to_string(),
+                pow: None,
             })
             .expect_failure()
             .await;
@@ -764,6 +815,9 @@ async fn test_attempt_status_and_snapshot_counters_agree() {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
             encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         })
         .expect_success()
         .await;
@@ -772,6 +826,7 @@ async fn test_attempt_status_and_snapshot_counters_agree() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .expect_success()
         .await;
@@ -801,6 +856,7 @@ async fn test_401_attempts_and_snapshot_counters_agree() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -831,6 +887,9 @@ async fn test_resets_at_advances_with_each_distinct_candidate() {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
             encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         })
         .expect_success()
         .await;
@@ -840,6 +899,7 @@ async fn test_resets_at_advances_with_each_distinct_candidate() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .expect_success()
         .await;
@@ -854,6 +914,7 @@ async fn test_resets_at_advances_with_each_distinct_candidate() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .expect_success()
         .await;
@@ -875,6 +936,9 @@ async fn test_resets_at_advances_with_each_distinct_candidate() {
             identifier: SHA256_111111.to_string(),
             authentication_key: crate::tests::distinct_candidate(1),
             encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         })
         .expect_success()
         .await;
@@ -884,6 +948,7 @@ async fn test_resets_at_advances_with_each_distinct_candidate() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: crate::tests::distinct_candidate(1),
+            pow: None,
         })
         .expect_success()
         .await;
@@ -912,6 +977,7 @@ async fn test_429_requested_at_is_the_last_admitted_attempt() {
             .json(&FetchSecret {
                 identifier: SHA256_111111.to_string(),
                 authentication_key: crate::tests::distinct_candidate(index),
+                pow: None,
             })
             .expect_failure()
             .await;
@@ -923,6 +989,7 @@ async fn test_429_requested_at_is_the_last_admitted_attempt() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -989,6 +1056,9 @@ async fn test_trash_does_not_reset_the_counter() {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
             encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         })
         .expect_success()
         .await;
@@ -997,6 +1067,7 @@ async fn test_trash_does_not_reset_the_counter() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .expect_success()
         .await;
@@ -1008,6 +1079,7 @@ async fn test_trash_does_not_reset_the_counter() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -1135,6 +1207,9 @@ async fn test_lookup_and_store_buckets_are_independent() {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
             encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         })
         .expect_success()
         .await;
@@ -1149,6 +1224,7 @@ async fn test_lookup_and_store_buckets_are_independent() {
         .json(&FetchSecret {
             identifier: SHA256_222222.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -1187,6 +1263,9 @@ async fn test_same_identifier_different_keys_are_independent_rows() {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
             encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         })
         .expect_success()
         .await;
@@ -1196,6 +1275,9 @@ async fn test_same_identifier_different_keys_are_independent_rows() {
             identifier: SHA256_111111.to_string(),
             authentication_key: other_key.to_string(),
             encrypted_secret: other_secret.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         })
         .expect_success()
         .await;
@@ -1205,6 +1287,7 @@ async fn test_same_identifier_different_keys_are_independent_rows() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .expect_success()
         .await;
@@ -1218,6 +1301,7 @@ async fn test_same_identifier_different_keys_are_independent_rows() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: other_key.to_string(),
+            pow: None,
         })
         .expect_success()
         .await;
@@ -1239,6 +1323,9 @@ async fn test_cross_key_fetch_fails() {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
             encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         })
         .expect_success()
         .await;
@@ -1248,6 +1335,7 @@ async fn test_cross_key_fetch_fails() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -1261,25 +1349,40 @@ async fn test_encrypted_secret_length_boundary() {
     let (server, state) = crate::tests::test_server::new_test_server().await;
 
     // exactly at the limit: accepted
-    let at_limit = "A".repeat(state.secret_max_length);
+    let at_limit = "A".repeat(
+        state
+            .secret_max_length
+            .load(std::sync::atomic::Ordering::Relaxed),
+    );
     let response = server
         .post("/store")
         .json(&StoreSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
             encrypted_secret: at_limit,
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         })
         .await;
     assert_eq!(response.status_code(), StatusCode::CREATED);
 
     // one valid base64 quantum over the limit: rejected
-    let over_limit = "A".repeat(state.secret_max_length + 4);
+    let over_limit = "A".repeat(
+        state
+            .secret_max_length
+            .load(std::sync::atomic::Ordering::Relaxed)
+            + 4,
+    );
     let response = server
         .post("/store")
         .json(&StoreSecret {
             identifier: SHA256_222222.to_string(),
             authentication_key: SHA256_111111.to_string(),
             encrypted_secret: over_limit,
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         })
         .expect_failure()
         .await;
@@ -1341,6 +1444,7 @@ async fn test_500_does_not_leak_internals() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -1371,6 +1475,7 @@ async fn test_lockout_boundary_is_exact() {
             .json(&FetchSecret {
                 identifier: SHA256_111111.to_string(),
                 authentication_key: crate::tests::distinct_candidate(i),
+                pow: None,
             })
             .expect_failure()
             .await;
@@ -1387,6 +1492,7 @@ async fn test_lockout_boundary_is_exact() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -1485,6 +1591,7 @@ async fn test_snapshot_last_attempt_not_before_window_start() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -1520,6 +1627,9 @@ async fn test_resets_at_is_in_the_future_for_active_entry() {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
             encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         })
         .expect_success()
         .await;
@@ -1528,6 +1638,7 @@ async fn test_resets_at_is_in_the_future_for_active_entry() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .expect_success()
         .await;
@@ -1586,6 +1697,7 @@ async fn test_attempts_counter_does_not_overflow_at_u8_max() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -1597,6 +1709,7 @@ async fn test_attempts_counter_does_not_overflow_at_u8_max() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -33,6 +33,7 @@ async fn test_attempts_publish_hashed_identifier_with_counters() {
             .json(&FetchSecret {
                 identifier: SHA256_111111.to_string(),
                 authentication_key: crate::tests::distinct_candidate(index),
+                pow: None,
             })
             .expect_failure()
             .await;
@@ -82,6 +83,9 @@ async fn test_attempts_count_hits_and_planted_rows() {
         identifier: SHA256_111111.to_string(),
         authentication_key: SHA256_222222.to_string(),
         encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+        ttl_days: None,
+        share_index: None,
+        federation_id: None,
     };
     server.post("/store").json(&store).expect_success().await;
 
@@ -93,6 +97,7 @@ async fn test_attempts_count_hits_and_planted_rows() {
             .json(&FetchSecret {
                 identifier: SHA256_111111.to_string(),
                 authentication_key: crate::tests::distinct_candidate(index),
+                pow: None,
             })
             .expect_failure()
             .await;
@@ -102,6 +107,7 @@ async fn test_attempts_count_hits_and_planted_rows() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .expect_success()
         .await;
@@ -122,6 +128,9 @@ async fn test_fetch_success_reports_status_without_resetting_attempt_budget() {
         identifier: SHA256_111111.to_string(),
         authentication_key: SHA256_222222.to_string(),
         encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+        ttl_days: None,
+        share_index: None,
+        federation_id: None,
     };
     server.post("/store").json(&store).expect_success().await;
 
@@ -132,6 +141,7 @@ async fn test_fetch_success_reports_status_without_resetting_attempt_budget() {
             .json(&FetchSecret {
                 identifier: SHA256_111111.to_string(),
                 authentication_key: crate::tests::distinct_candidate(index),
+                pow: None,
             })
             .expect_failure()
             .await;
@@ -143,6 +153,7 @@ async fn test_fetch_success_reports_status_without_resetting_attempt_budget() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .expect_success()
         .await;
@@ -171,6 +182,7 @@ async fn test_fetch_success_reports_status_without_resetting_attempt_budget() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -224,9 +236,11 @@ async fn test_attempts_snapshot_etag_and_conditional_requests() {
 
 #[tokio::test]
 async fn test_attempts_snapshot_rebuild_is_deterministic() {
-    let mut state = crate::env::init();
+    let state = crate::env::init();
     // force a rebuild on every request
-    state.attempts_snapshot_ttl = std::time::Duration::ZERO;
+    state
+        .attempts_snapshot_ttl_seconds
+        .store(0, std::sync::atomic::Ordering::Relaxed);
     crate::database::init_db(state.clone());
     let server = axum_test::TestServer::new(crate::router::new(state)).unwrap();
 
@@ -241,6 +255,7 @@ async fn test_attempts_snapshot_rebuild_is_deterministic() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -248,6 +263,66 @@ async fn test_attempts_snapshot_rebuild_is_deterministic() {
     assert_ne!(first.header("etag"), third.header("etag"));
 }
 
+/// The signature covers the uncompressed body and verifies with the key
+/// from `/info`; like the ETag, it survives rebuilds of unchanged activity
+/// and is repeated on `304` so a cache never pairs a body with a stale one.
+#[tokio::test]
+async fn test_attempts_snapshot_is_signed_reproducibly() {
+    let state = crate::env::init();
+    state
+        .attempts_snapshot_ttl_seconds
+        .store(0, std::sync::atomic::Ordering::Relaxed);
+    crate::database::init_db(state.clone());
+    let server = axum_test::TestServer::new(crate::router::new(state)).unwrap();
+
+    let info = server
+        .get("/info")
+        .expect_success()
+        .await
+        .json::<crate::models::Info>();
+    let public_key: [u8; 32] = hex::decode(info.attempts_public_key)
+        .unwrap()
+        .try_into()
+        .unwrap();
+    let public_key = ed25519_dalek::VerifyingKey::from_bytes(&public_key).unwrap();
+
+    let first = server.get("/attempts").expect_success().await;
+    let signature = first
+        .header("attempts-signature")
+        .to_str()
+        .unwrap()
+        .to_owned();
+    let (body, _) = decode_gzip(first.as_bytes());
+    let signature: [u8; 64] =
+        base64::Engine::decode(&base64::engine::general_purpose::STANDARD, &signature)
+            .unwrap()
+            .try_into()
+            .unwrap();
+    let message = [
+        crate::handlers::attempts::SIGNATURE_CONTEXT,
+        body.as_bytes(),
+    ]
+    .concat();
+    public_key
+        .verify_strict(&message, &ed25519_dalek::Signature::from_bytes(&signature))
+        .expect("the snapshot verifies with the /info key");
+
+    let second = server.get("/attempts").expect_success().await;
+    assert_eq!(
+        first.header("attempts-signature"),
+        second.header("attempts-signature")
+    );
+    let not_modified = server
+        .get("/attempts")
+        .add_header("If-None-Match", first.header("etag"))
+        .await;
+    assert_eq!(not_modified.status_code(), StatusCode::NOT_MODIFIED);
+    assert_eq!(
+        not_modified.header("attempts-signature"),
+        first.header("attempts-signature")
+    );
+}
+
 #[tokio::test]
 async fn test_attempts_entries_are_sorted_by_id_hash() {
     let (server, _) = crate::tests::test_server::new_test_server().await;
@@ -258,6 +333,7 @@ async fn test_attempts_entries_are_sorted_by_id_hash() {
             .json(&FetchSecret {
                 identifier: identifier.to_string(),
                 authentication_key: NOT_PASSWORD_HASH.to_string(),
+                pow: None,
             })
             .expect_failure()
             .await;
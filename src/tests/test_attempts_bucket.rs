@@ -0,0 +1,136 @@
+use std::io::Read;
+
+use axum::http::StatusCode;
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::handlers::attempts::{BUCKET_ENTRIES, BUCKET_SIGNATURE_CONTEXT};
+use crate::models::{AttemptsBucket, Info, RateLimitInfo};
+
+fn window(total_requests: u64) -> RateLimitInfo {
+    let now = chrono::Utc::now();
+    RateLimitInfo {
+        window_started_at: now,
+        last_candidate_at: now,
+        last_request_at: now,
+        candidates: std::collections::HashMap::new(),
+        failed_candidates: 0,
+        total_requests,
+    }
+}
+
+fn gunzip(body: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    flate2::read::GzDecoder::new(body)
+        .read_to_end(&mut decoded)
+        .unwrap();
+    decoded
+}
+
+/// Only the entries under the prefix are served, among padding entries with
+/// zero counters, sorted, signed, and identical for every request against
+/// the same snapshot.
+#[tokio::test]
+async fn test_attempts_bucket_serves_the_prefix_range_padded() {
+    let (server, state) = crate::tests::test_server::new_test_server().await;
+    let inside = [format!("abc{:061x}", 1), format!("abc{:061x}", 2)];
+    {
+        let mut map = state.identifier_rate_limit.lock().await;
+        for id_hash in &inside {
+            map.insert(id_hash.clone(), window(3));
+        }
+        map.insert(format!("abd{:061x}", 1), window(3));
+        map.insert(format!("ab{:062x}", 1), window(3));
+    }
+
+    // Hex case is canonicalized, like every other hex input.
+    let response = server.get("/attempts/bucket/ABC").expect_success().await;
+    let raw = gunzip(response.as_bytes());
+    let bucket: AttemptsBucket = serde_json::from_slice(&raw).unwrap();
+    assert_eq!(bucket.prefix, "abc");
+    assert_eq!(bucket.entries.len(), BUCKET_ENTRIES);
+    assert!(bucket
+        .entries
+        .iter()
+        .all(|entry| entry.id_hash.starts_with("abc") && entry.id_hash.len() == 64));
+    assert!(bucket
+        .entries
+        .windows(2)
+        .all(|pair| pair[0].id_hash < pair[1].id_hash));
+    let real: Vec<&str> = bucket
+        .entries
+        .iter()
+        .filter(|entry| entry.total_requests > 0)
+        .map(|entry| entry.id_hash.as_str())
+        .collect();
+    assert_eq!(real, [inside[0].as_str(), inside[1].as_str()]);
+
+    let snapshot = server.get("/attempts").expect_success().await;
+    assert_eq!(snapshot.header("etag"), bucket.etag.as_str());
+    let again = server.get("/attempts/bucket/abc").expect_success().await;
+    assert_eq!(again.header("etag"), response.header("etag"));
+    assert_eq!(again.as_bytes(), response.as_bytes());
+
+    let info = server.get("/info").await.json::<Info>();
+    assert_eq!(info.attempts_bucket_prefix_length, 3);
+    let public_key: [u8; 32] = hex::decode(info.attempts_public_key)
+        .unwrap()
+        .try_into()
+        .unwrap();
+    let signature: [u8; 64] = STANDARD
+        .decode(response.header("attempts-signature").to_str().unwrap())
+        .unwrap()
+        .try_into()
+        .unwrap();
+    ed25519_dalek::VerifyingKey::from_bytes(&public_key)
+        .unwrap()
+        .verify_strict(
+            &[BUCKET_SIGNATURE_CONTEXT, &raw].concat(),
+            &ed25519_dalek::Signature::from_bytes(&signature),
+        )
+        .expect("the bucket verifies with the /info key");
+}
+
+/// A full bucket is served at the same size as an empty one; a fuller one is
+/// refused rather than truncated, which would hide its owner's attempts.
+#[tokio::test]
+async fn test_attempts_bucket_has_a_fixed_size() {
+    let (server, state) = crate::tests::test_server::new_test_server().await;
+    {
+        let mut map = state.identifier_rate_limit.lock().await;
+        for index in 0..BUCKET_ENTRIES {
+            map.insert(format!("0f0{index:061x}"), window(1));
+            map.insert(format!("0f1{index:061x}"), window(1));
+        }
+        map.insert(format!("0f1{BUCKET_ENTRIES:061x}"), window(1));
+    }
+    for prefix in ["0f0", "0f2"] {
+        let response = server
+            .get(&format!("/attempts/bucket/{prefix}"))
+            .expect_success()
+            .await;
+        let bucket: AttemptsBucket = serde_json::from_slice(&gunzip(response.as_bytes())).unwrap();
+        assert_eq!(bucket.entries.len(), BUCKET_ENTRIES, "{prefix}");
+    }
+    let response = server.get("/attempts/bucket/0f1").await;
+    assert_eq!(response.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+/// Any other prefix length would reveal more of the identifier or return a
+/// larger range: exactly `BUCKET_PREFIX_LENGTH` hex characters are accepted.
+/// Valid queries share the `/attempts` token bucket.
+#[tokio::test]
+async fn test_attempts_bucket_validates_prefix_and_shares_the_bucket() {
+    let (server, mut state) = crate::tests::test_server::new_test_server().await;
+    for prefix in ["ab", "abcd", "xyz", &"a".repeat(64)] {
+        let response = server.get(&format!("/attempts/bucket/{prefix}")).await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST, "{prefix}");
+    }
+
+    state.attempts_token_bucket = std::sync::Arc::new(tokio::sync::Mutex::new(
+        crate::rate_limit::TokenBucket::new(1.0, 0.001),
+    ));
+    let server = axum_test::TestServer::new(crate::router::new(state)).unwrap();
+    server.get("/attempts").expect_success().await;
+    let response = server.get("/attempts/bucket/abc").await;
+    assert_eq!(response.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+}
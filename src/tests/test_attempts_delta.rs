@@ -0,0 +1,230 @@
+use std::io::Read;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::attempts_delta::{diff, History};
+use crate::models::{AttemptEntry, AttemptsDelta, AttemptsSnapshot, Info, RateLimitInfo};
+use crate::tests::test_server::configured_test_server;
+
+fn gunzip(body: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    flate2::read::GzDecoder::new(body)
+        .read_to_end(&mut decoded)
+        .unwrap();
+    decoded
+}
+
+fn window(total_requests: u64) -> RateLimitInfo {
+    let now = chrono::Utc::now();
+    RateLimitInfo {
+        window_started_at: now,
+        last_candidate_at: now,
+        last_request_at: now,
+        candidates: std::collections::HashMap::new(),
+        failed_candidates: 0,
+        total_requests,
+    }
+}
+
+fn id_hash(index: u8) -> String {
+    format!("{index:064x}")
+}
+
+/// A server rebuilding its snapshot on every request, so each call sees
+/// the map as it is now.
+async fn delta_server() -> (axum_test::TestServer, crate::AppState) {
+    configured_test_server(|state| {
+        state
+            .attempts_snapshot_ttl_seconds
+            .store(0, std::sync::atomic::Ordering::Relaxed);
+    })
+    .await
+}
+
+async fn snapshot(server: &axum_test::TestServer) -> (String, AttemptsSnapshot) {
+    let response = server.get("/attempts").expect_success().await;
+    let etag = response.header("etag").to_str().unwrap().to_owned();
+    (
+        etag,
+        serde_json::from_slice(&gunzip(response.as_bytes())).unwrap(),
+    )
+}
+
+/// Applying a delta to its base snapshot yields the current snapshot, and
+/// the delta is signed like the snapshot, under its own context.
+#[tokio::test]
+async fn test_attempts_delta_reports_added_changed_and_removed() {
+    let (server, state) = delta_server().await;
+    {
+        let mut map = state.identifier_rate_limit.lock().await;
+        for index in 1..=3 {
+            map.insert(id_hash(index), window(1));
+        }
+    }
+    let (base_etag, base) = snapshot(&server).await;
+
+    {
+        let mut map = state.identifier_rate_limit.lock().await;
+        map.get_mut(&id_hash(2)).unwrap().total_requests = 5;
+        map.remove(&id_hash(3));
+        map.insert(id_hash(4), window(1));
+    }
+    // The ETag is accepted with or without its quotes.
+    let response = server
+        .get("/attempts/delta")
+        .add_query_param("since", base_etag.trim_matches('"'))
+        .expect_success()
+        .await;
+    let raw = gunzip(response.as_bytes());
+    let delta: AttemptsDelta = serde_json::from_slice(&raw).unwrap();
+    assert_eq!(delta.version, 1);
+    assert_eq!(delta.base_etag, base_etag);
+    let ids = |entries: &[AttemptEntry]| -> Vec<String> {
+        entries.iter().map(|entry| entry.id_hash.clone()).collect()
+    };
+    assert_eq!(ids(&delta.added), [id_hash(4)]);
+    assert_eq!(ids(&delta.changed), [id_hash(2)]);
+    assert_eq!(delta.removed, [id_hash(3)]);
+
+    let (current_etag, current) = snapshot(&server).await;
+    assert_eq!(delta.etag, current_etag);
+    let mut applied: Vec<AttemptEntry> = base
+        .entries
+        .into_iter()
+        .filter(|entry| !delta.removed.contains(&entry.id_hash))
+        .filter(|entry| !delta.changed.iter().any(|c| c.id_hash == entry.id_hash))
+        .chain(delta.added.iter().cloned())
+        .chain(delta.changed.iter().cloned())
+        .collect();
+    applied.sort_by(|a, b| a.id_hash.cmp(&b.id_hash));
+    assert!(applied == current.entries);
+
+    let info = server.get("/info").await.json::<Info>();
+    let public_key: [u8; 32] = hex::decode(info.attempts_public_key)
+        .unwrap()
+        .try_into()
+        .unwrap();
+    let signature: [u8; 64] = STANDARD
+        .decode(response.header("attempts-signature").to_str().unwrap())
+        .unwrap()
+        .try_into()
+        .unwrap();
+    ed25519_dalek::VerifyingKey::from_bytes(&public_key)
+        .unwrap()
+        .verify_strict(
+            &[crate::handlers::attempts::DELTA_SIGNATURE_CONTEXT, &raw].concat(),
+            &ed25519_dalek::Signature::from_bytes(&signature),
+        )
+        .expect("the delta verifies with the /info key");
+}
+
+/// Deltas compose over several rebuilds; the current ETag gets an empty
+/// delta; an unknown base gets the full snapshot. Each delta body is built
+/// once per snapshot and then served from the cache.
+#[tokio::test]
+async fn test_attempts_delta_composes_and_falls_back_to_the_snapshot() {
+    let (server, state) = delta_server().await;
+    state
+        .identifier_rate_limit
+        .lock()
+        .await
+        .insert(id_hash(1), window(1));
+    let (first_etag, _) = snapshot(&server).await;
+
+    // an entry that appears and expires between two client polls is absent
+    state
+        .identifier_rate_limit
+        .lock()
+        .await
+        .insert(id_hash(2), window(1));
+    snapshot(&server).await;
+    {
+        let mut map = state.identifier_rate_limit.lock().await;
+        map.remove(&id_hash(2));
+        map.insert(id_hash(3), window(1));
+        map.get_mut(&id_hash(1)).unwrap().total_requests = 2;
+    }
+    let (current_etag, _) = snapshot(&server).await;
+    // Freeze the snapshot: the following requests share one build.
+    state
+        .attempts_snapshot_ttl_seconds
+        .store(60, std::sync::atomic::Ordering::Relaxed);
+
+    let delta: AttemptsDelta = serde_json::from_slice(&gunzip(
+        server
+            .get("/attempts/delta")
+            .add_query_param("since", &first_etag)
+            .expect_success()
+            .await
+            .as_bytes(),
+    ))
+    .unwrap();
+    assert_eq!(delta.added.len(), 1);
+    assert_eq!(delta.added[0].id_hash, id_hash(3));
+    assert_eq!(delta.changed.len(), 1);
+    assert_eq!(delta.changed[0].id_hash, id_hash(1));
+    assert!(delta.removed.is_empty());
+
+    let unchanged: AttemptsDelta = serde_json::from_slice(&gunzip(
+        server
+            .get("/attempts/delta")
+            .add_query_param("since", &current_etag)
+            .expect_success()
+            .await
+            .as_bytes(),
+    ))
+    .unwrap();
+    assert!(unchanged.added.is_empty() && unchanged.changed.is_empty());
+    assert!(unchanged.removed.is_empty());
+    assert_eq!(unchanged.etag, current_etag);
+
+    let fallback = server
+        .get("/attempts/delta")
+        .add_query_param("since", "\"unknown\"")
+        .expect_success()
+        .await;
+    assert_eq!(fallback.header("etag"), current_etag.as_str());
+    let full: AttemptsSnapshot = serde_json::from_slice(&gunzip(fallback.as_bytes())).unwrap();
+    assert_eq!(full.entries.len(), 2);
+
+    let cached = state.attempts_snapshot.lock().await;
+    let cached = cached.as_ref().unwrap();
+    assert_eq!(cached.etag, current_etag);
+    assert_eq!(
+        cached.delta_bodies.len(),
+        2,
+        "one body per known base, none for the unknown one"
+    );
+}
+
+/// The history keeps at most `budget` changes: the oldest transitions go
+/// first, and a base that fell out of it is unknown.
+#[test]
+fn test_attempts_delta_history_is_bounded() {
+    let entry = |index: u8, total_requests: u64| AttemptEntry {
+        id_hash: id_hash(index),
+        total_attempts: 0,
+        failed_attempts: 0,
+        total_requests,
+        window_started_at: chrono::DateTime::UNIX_EPOCH,
+        last_attempt_at: chrono::DateTime::UNIX_EPOCH,
+    };
+    let snapshots = [
+        vec![],
+        vec![entry(1, 1), entry(2, 1)],
+        vec![entry(1, 2), entry(2, 1)],
+        vec![entry(1, 3)],
+    ];
+    let mut history = History::default();
+    for (index, pair) in snapshots.windows(2).enumerate() {
+        history.push(diff(&format!("\"{index}\""), &pair[0], &pair[1]), 3);
+    }
+
+    // 2 + 1 + 2 changes: the first transition was dropped to fit 3.
+    assert!(history.since("\"0\"").is_none());
+    let composed = history.since("\"1\"").unwrap();
+    assert!(composed.changed == [entry(1, 3)]);
+    assert_eq!(composed.removed, [id_hash(2)]);
+    assert!(composed.added.is_empty());
+    assert!(history.since("\"2\"").is_some());
+}
@@ -0,0 +1,111 @@
+use axum::http::StatusCode;
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::handlers::attempts::FILTER_SIGNATURE_CONTEXT;
+use crate::models::{Info, RateLimitInfo};
+use crate::xor_filter::XorFilter;
+
+/// The vector documented in the README: keys `01…01`, `02…02`, `03…03`.
+const VECTOR: &str = "4b435846010800000000000000000b00000003000000000041000076000000009e00000000000000000000000000000000000000000000";
+
+fn window(failed_candidates: u8) -> RateLimitInfo {
+    let now = chrono::Utc::now();
+    RateLimitInfo {
+        window_started_at: now,
+        last_candidate_at: now,
+        last_request_at: now,
+        candidates: std::collections::HashMap::new(),
+        failed_candidates,
+        total_requests: 1,
+    }
+}
+
+fn key(index: u32) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    key[..4].copy_from_slice(&index.to_be_bytes());
+    key
+}
+
+/// The filter holds exactly the identifiers with failed attempts, is signed
+/// over the served bytes, and revalidates like the snapshot.
+#[tokio::test]
+async fn test_attempts_filter_holds_failed_identifiers() {
+    let (server, state) = crate::tests::test_server::new_test_server().await;
+    {
+        let mut map = state.identifier_rate_limit.lock().await;
+        for index in 0..50 {
+            map.insert(hex::encode(key(index)), window(1));
+        }
+        for index in 50..100 {
+            map.insert(hex::encode(key(index)), window(0));
+        }
+    }
+
+    let response = server.get("/attempts/filter").expect_success().await;
+    assert_eq!(response.header("content-type"), "application/octet-stream");
+    assert!(response.maybe_header("content-encoding").is_none());
+    let filter = XorFilter::from_bytes(response.as_bytes()).unwrap();
+    assert_eq!(filter.key_count(), 50);
+    assert!((0..50).all(|index| filter.contains(&key(index))));
+    // Deterministic construction: these misses never become flaky.
+    assert!((50..100).all(|index| !filter.contains(&key(index))));
+
+    let info = server.get("/info").await.json::<Info>();
+    let public_key: [u8; 32] = hex::decode(info.attempts_public_key)
+        .unwrap()
+        .try_into()
+        .unwrap();
+    let signature: [u8; 64] = STANDARD
+        .decode(response.header("attempts-signature").to_str().unwrap())
+        .unwrap()
+        .try_into()
+        .unwrap();
+    ed25519_dalek::VerifyingKey::from_bytes(&public_key)
+        .unwrap()
+        .verify_strict(
+            &[FILTER_SIGNATURE_CONTEXT, response.as_bytes()].concat(),
+            &ed25519_dalek::Signature::from_bytes(&signature),
+        )
+        .expect("the filter verifies with the /info key");
+
+    // Its own ETag, stable across rebuilds of the same content.
+    let etag = response.header("etag");
+    let snapshot = server.get("/attempts").expect_success().await;
+    assert_ne!(snapshot.header("etag"), etag);
+    state
+        .attempts_snapshot_ttl_seconds
+        .store(0, std::sync::atomic::Ordering::Relaxed);
+    let revalidated = server
+        .get("/attempts/filter")
+        .add_header(axum::http::header::IF_NONE_MATCH, etag)
+        .await;
+    assert_eq!(revalidated.status_code(), StatusCode::NOT_MODIFIED);
+}
+
+/// The documented test vector, and the properties clients rely on: no
+/// false negatives, about 1/256 false positives, about 1.23 bytes per key.
+#[test]
+fn test_xor_filter_format_and_vector() {
+    let filter = XorFilter::build(&[[3u8; 32], [1u8; 32], [2u8; 32], [1u8; 32]]);
+    assert_eq!(hex::encode(filter.to_bytes()), VECTOR);
+    let parsed = XorFilter::from_bytes(&hex::decode(VECTOR).unwrap()).unwrap();
+    assert!((1..=3).all(|byte| parsed.contains(&[byte; 32])));
+
+    let keys: Vec<[u8; 32]> = (0..10_000).map(key).collect();
+    let filter = XorFilter::build(&keys);
+    assert!(keys.iter().all(|key| filter.contains(key)));
+    let false_positives = (10_000..110_000)
+        .filter(|&index| filter.contains(&key(index)))
+        .count();
+    assert!(
+        (200..600).contains(&false_positives),
+        "{false_positives} false positives in 100000"
+    );
+    assert!(filter.to_bytes().len() < 12_500);
+
+    let mut bytes = hex::decode(VECTOR).unwrap();
+    assert!(XorFilter::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    bytes[4] = 2;
+    assert!(XorFilter::from_bytes(&bytes).is_err());
+    assert!(XorFilter::from_bytes(b"KCXF").is_err());
+}
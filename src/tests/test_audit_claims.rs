@@ -39,6 +39,9 @@ async fn test_audit_f1_store_gives_no_existence_signal() {
         identifier: SHA256_111111.to_string(),
         authentication_key: SHA256_222222.to_string(),
         encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+        ttl_days: None,
+        share_index: None,
+        federation_id: None,
     };
     let first = server.post("/store").json(store).await;
 
@@ -48,6 +51,9 @@ async fn test_audit_f1_store_gives_no_existence_signal() {
             identifier: SHA256_111111.to_string(),
             authentication_key: format!("{:064x}", i + 1),
             encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         };
         let response = server.post("/store").json(guess).await;
         assert_eq!(response.status_code(), StatusCode::CREATED);
@@ -72,6 +78,9 @@ async fn test_audit_f1_planted_rows_cannot_reset_fetch_rate_limit() {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
             encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         })
         .expect_success()
         .await;
@@ -86,6 +95,9 @@ async fn test_audit_f1_planted_rows_cannot_reset_fetch_rate_limit() {
                 identifier: SHA256_111111.to_string(),
                 authentication_key: guessed_key.clone(),
                 encrypted_secret: marker.to_string(),
+                ttl_days: None,
+                share_index: None,
+                federation_id: None,
             })
             .expect_success()
             .await;
@@ -95,6 +107,7 @@ async fn test_audit_f1_planted_rows_cannot_reset_fetch_rate_limit() {
             .json(&FetchSecret {
                 identifier: SHA256_111111.to_string(),
                 authentication_key: guessed_key,
+                pow: None,
             })
             .expect_success()
             .await;
@@ -111,6 +124,7 @@ async fn test_audit_f1_planted_rows_cannot_reset_fetch_rate_limit() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .await;
     assert_eq!(response.status_code(), StatusCode::TOO_MANY_REQUESTS);
@@ -127,6 +141,9 @@ async fn test_audit_f2_attacker_failures_deny_legitimate_owner() {
         identifier: SHA256_111111.to_string(),
         authentication_key: SHA256_222222.to_string(),
         encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+        ttl_days: None,
+        share_index: None,
+        federation_id: None,
     };
     server.post("/store").json(store).expect_success().await;
 
@@ -137,6 +154,7 @@ async fn test_audit_f2_attacker_failures_deny_legitimate_owner() {
             .json(&FetchSecret {
                 identifier: SHA256_111111.to_string(),
                 authentication_key: crate::tests::distinct_candidate(index),
+                pow: None,
             })
             .await;
         assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
@@ -149,6 +167,7 @@ async fn test_audit_f2_attacker_failures_deny_legitimate_owner() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .await;
     assert_eq!(
@@ -185,6 +204,9 @@ async fn test_audit_f9_store_writes_are_token_bucketed() {
             identifier: format!("{:064x}", i + 1),
             authentication_key: format!("{:064x}", i + 1),
             encrypted_secret: "dGVzdA==".to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         };
         let response = server.post("/store").json(store).await;
         if i < 3 {
@@ -213,6 +235,7 @@ async fn test_lookup_flood_is_globally_token_bucketed() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .await;
     assert_eq!(first.status_code(), StatusCode::UNAUTHORIZED);
@@ -222,6 +245,7 @@ async fn test_lookup_flood_is_globally_token_bucketed() {
         .json(&FetchSecret {
             identifier: SHA256_222222.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .await;
     assert_eq!(second.status_code(), StatusCode::SERVICE_UNAVAILABLE);
@@ -260,6 +284,9 @@ async fn test_audit_f12_hex_case_is_canonicalized() {
         identifier: SHA256_111111.to_uppercase(),
         authentication_key: SHA256_222222.to_uppercase(),
         encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+        ttl_days: None,
+        share_index: None,
+        federation_id: None,
     };
     let response = server.post("/store").json(store).await;
     assert_eq!(response.status_code(), StatusCode::CREATED);
@@ -270,6 +297,7 @@ async fn test_audit_f12_hex_case_is_canonicalized() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .await;
     assert_eq!(response.status_code(), StatusCode::OK);
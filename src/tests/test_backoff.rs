@@ -0,0 +1,287 @@
+use crate::{
+    models::{AttemptStatus, RateLimitInfo, ResponseFailedAttempt, StoreSecret},
+    tests::test_server::{configured_test_server, fetch_secret},
+    tests::{distinct_candidate, BASE64_ENCRYPTED_SECRET, SHA256_111111, SHA256_222222},
+    utils::identifier_hash,
+};
+use axum::http::StatusCode;
+
+async fn backoff_server(base_seconds: i64) -> (axum_test::TestServer, crate::AppState) {
+    configured_test_server(|state| {
+        state.rate_limit_max_attempts = 3;
+        state.rate_limit_cooldown = chrono::Duration::minutes(60);
+        state.rate_limit_backoff_base = chrono::Duration::seconds(base_seconds);
+    })
+    .await
+}
+
+/// Moves the last admitted candidate into the past, as if the caller had
+/// waited that long since its previous attempt.
+async fn age_last_candidate(state: &crate::AppState, by: chrono::TimeDelta) {
+    let mut identifier_rate_limit = state.identifier_rate_limit.lock().await;
+    let info = identifier_rate_limit
+        .get_mut(&identifier_hash(SHA256_111111).unwrap())
+        .expect("the identifier must have a rate-limit entry");
+    info.last_candidate_at -= by;
+}
+
+fn retry_after(response: &axum_test::TestResponse) -> i64 {
+    response
+        .header("retry-after")
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[test]
+fn test_backoff_schedule_doubles_per_extra_candidate() {
+    let now = chrono::Utc::now();
+    let base = chrono::Duration::seconds(10);
+    let cooldown = chrono::Duration::minutes(60);
+    let mut info = RateLimitInfo::new(now);
+
+    for index in 0..3 {
+        info.candidates.insert(
+            distinct_candidate(index),
+            crate::models::CandidateState::Committed,
+        );
+        // the budget still has room: nothing to unlock
+        if index < 2 {
+            assert_eq!(info.backoff_unlocks_at(3, base, cooldown), None);
+            assert_eq!(info.next_candidate_at(3, base, cooldown), None);
+        }
+    }
+
+    for (extra, expected_seconds) in [10, 20, 40, 80].into_iter().enumerate() {
+        assert_eq!(
+            info.backoff_unlocks_at(3, base, cooldown),
+            Some(now + chrono::Duration::seconds(expected_seconds)),
+            "candidate {} must unlock after {expected_seconds}s",
+            3 + extra
+        );
+        info.candidates.insert(
+            distinct_candidate(3 + extra),
+            crate::models::CandidateState::Committed,
+        );
+    }
+}
+
+#[test]
+fn test_backoff_never_unlocks_past_the_window_reset() {
+    let now = chrono::Utc::now();
+    let cooldown = chrono::Duration::minutes(1);
+    let mut info = RateLimitInfo::new(now);
+    for index in 0..4 {
+        info.candidates.insert(
+            distinct_candidate(index),
+            crate::models::CandidateState::Committed,
+        );
+    }
+
+    // 40s * 2 reaches the 60s cooldown: the budget only unlocks at the reset
+    let base = chrono::Duration::seconds(40);
+    assert_eq!(info.backoff_unlocks_at(3, base, cooldown), None);
+    assert_eq!(
+        info.next_candidate_at(3, base, cooldown),
+        Some(now + cooldown)
+    );
+
+    // disabled backoff behaves like the hard cap
+    assert_eq!(
+        info.backoff_unlocks_at(3, chrono::TimeDelta::zero(), cooldown),
+        None
+    );
+    assert_eq!(
+        info.next_candidate_at(3, chrono::TimeDelta::zero(), cooldown),
+        Some(now + cooldown)
+    );
+}
+
+#[test]
+fn test_backoff_respects_the_candidate_set_bound() {
+    let now = chrono::Utc::now();
+    let mut info = RateLimitInfo::new(now);
+    for index in 0..usize::from(u8::MAX) {
+        info.candidates.insert(
+            distinct_candidate(index),
+            crate::models::CandidateState::Committed,
+        );
+    }
+    assert_eq!(
+        info.backoff_unlocks_at(
+            u8::MAX - 1,
+            chrono::Duration::seconds(1),
+            chrono::Duration::minutes(525_600)
+        ),
+        None,
+        "the candidate set must never grow past its u8 bound"
+    );
+}
+
+/// With backoff enabled an exhausted budget unlocks one more distinct
+/// candidate after `base`, then `2 * base`, and so on: the lockout delays
+/// recovery but never denies it until the window reset.
+#[tokio::test]
+async fn test_exhausted_budget_unlocks_after_escalating_delays() {
+    let (server, state) = backoff_server(10).await;
+
+    for index in 0..3 {
+        let response = server
+            .post("/fetch")
+            .json(&fetch_secret(&distinct_candidate(index)))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+    }
+
+    let locked = server
+        .post("/fetch")
+        .json(&fetch_secret(&distinct_candidate(3)))
+        .await;
+    assert_eq!(locked.status_code(), StatusCode::TOO_MANY_REQUESTS);
+    let retry = retry_after(&locked);
+    assert!(
+        (9..=10).contains(&retry),
+        "the first extra candidate unlocks after the base delay, got {retry}"
+    );
+    let body = locked.json::<serde_json::Value>();
+    let next_attempt_at = body["next_attempt_at"]
+        .as_str()
+        .unwrap()
+        .parse::<chrono::DateTime<chrono::Utc>>()
+        .unwrap();
+    let requested_at = body["requested_at"]
+        .as_str()
+        .unwrap()
+        .parse::<chrono::DateTime<chrono::Utc>>()
+        .unwrap();
+    assert_eq!(
+        next_attempt_at - requested_at,
+        chrono::Duration::seconds(10)
+    );
+
+    age_last_candidate(&state, chrono::Duration::seconds(10)).await;
+    let admitted = server
+        .post("/fetch")
+        .json(&fetch_secret(&distinct_candidate(3)))
+        .await;
+    assert_eq!(admitted.status_code(), StatusCode::UNAUTHORIZED);
+    let failed = admitted.json::<ResponseFailedAttempt>();
+    assert_eq!(failed.attempts, 4);
+    assert_eq!(
+        failed.next_attempt_at,
+        Some(failed.requested_at + chrono::Duration::seconds(20)),
+        "the next extra candidate waits twice as long"
+    );
+
+    let locked = server
+        .post("/fetch")
+        .json(&fetch_secret(&distinct_candidate(4)))
+        .await;
+    assert_eq!(locked.status_code(), StatusCode::TOO_MANY_REQUESTS);
+    let retry = retry_after(&locked);
+    assert!((19..=20).contains(&retry), "got {retry}");
+}
+
+/// The backoff gate precedes membership exactly like the hard cap: a stored
+/// (known) candidate is rejected while the next slot is still locked.
+#[tokio::test]
+async fn test_backoff_lock_precedes_membership() {
+    let (server, state) = backoff_server(10).await;
+    server
+        .post("/store")
+        .json(&StoreSecret {
+            identifier: SHA256_111111.to_string(),
+            authentication_key: SHA256_222222.to_string(),
+            encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
+        })
+        .expect_success()
+        .await;
+    for index in 0..3 {
+        server
+            .post("/fetch")
+            .json(&fetch_secret(&distinct_candidate(index)))
+            .expect_failure()
+            .await;
+    }
+
+    let locked = server
+        .post("/fetch")
+        .json(&fetch_secret(SHA256_222222))
+        .await;
+    assert_eq!(locked.status_code(), StatusCode::TOO_MANY_REQUESTS);
+
+    age_last_candidate(&state, chrono::Duration::seconds(10)).await;
+    let response = server
+        .post("/fetch")
+        .json(&fetch_secret(SHA256_222222))
+        .expect_success()
+        .await;
+    let status: AttemptStatus =
+        serde_json::from_value(response.json::<serde_json::Value>()["attempt_status"].clone())
+            .unwrap();
+    assert_eq!(status.total_attempts, 4);
+    assert_eq!(status.remaining_attempts, 0);
+    assert!(status.next_attempt_at.is_some());
+}
+
+/// Without backoff the next attempt is the window reset, and `null` while
+/// the budget still has room.
+#[tokio::test]
+async fn test_next_attempt_at_without_backoff_is_the_reset() {
+    let (server, _) = backoff_server(0).await;
+    server
+        .post("/store")
+        .json(&StoreSecret {
+            identifier: SHA256_111111.to_string(),
+            authentication_key: SHA256_222222.to_string(),
+            encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
+        })
+        .expect_success()
+        .await;
+
+    let response = server
+        .post("/fetch")
+        .json(&fetch_secret(SHA256_222222))
+        .expect_success()
+        .await;
+    let status: AttemptStatus =
+        serde_json::from_value(response.json::<serde_json::Value>()["attempt_status"].clone())
+            .unwrap();
+    assert_eq!(status.next_attempt_at, None);
+
+    for index in 0..2 {
+        server
+            .post("/fetch")
+            .json(&fetch_secret(&distinct_candidate(index)))
+            .expect_failure()
+            .await;
+    }
+    let response = server
+        .post("/fetch")
+        .json(&fetch_secret(SHA256_222222))
+        .expect_failure()
+        .await;
+    assert_eq!(response.status_code(), StatusCode::TOO_MANY_REQUESTS);
+    let body = response.json::<serde_json::Value>();
+    let next_attempt_at = body["next_attempt_at"]
+        .as_str()
+        .unwrap()
+        .parse::<chrono::DateTime<chrono::Utc>>()
+        .unwrap();
+    let last_candidate_at = body["requested_at"]
+        .as_str()
+        .unwrap()
+        .parse::<chrono::DateTime<chrono::Utc>>()
+        .unwrap();
+    assert_eq!(
+        next_attempt_at - last_candidate_at,
+        chrono::Duration::minutes(60)
+    );
+}
@@ -1,9 +1,9 @@
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use crate::tests::test_server::{listener_addresses, SpawnedServer};
 
-/// Starts the app on a real TCP listener so requests are truly concurrent,
-/// handled in parallel by the multi-threaded runtime.
-async fn spawn_server() -> (std::net::SocketAddr, crate::AppState) {
-    let mut app_state = crate::env::init();
+/// Starts the app on a real listener (TCP or Unix socket, see
+/// `listener_addresses`) so requests are truly concurrent, handled in
+/// parallel by the multi-threaded runtime.
+async fn spawn_server(mut app_state: crate::AppState, server_address: &str) -> SpawnedServer {
     // Dedicated generous buckets: these tests drive 30-100 requests and must
     // not depend on the environment-provided buckets (code defaults: store
     // burst 10, lookup burst 100) — see SECURITY.md "Test-writing traps".
@@ -15,32 +15,19 @@ async fn spawn_server() -> (std::net::SocketAddr, crate::AppState) {
     ));
     crate::database::init_db(app_state.clone());
     let app = crate::router::new(app_state.clone());
-
-    let mut connection = crate::database::establish_connection(app_state.clone().database_url);
-    crate::tests::test_server::clear_table_secret(&mut connection).await;
-
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
-    let addr = listener.local_addr().unwrap();
-    tokio::spawn(async move {
-        axum::serve(listener, app).await.unwrap();
-    });
-
-    (addr, app_state)
+    crate::tests::test_server::spawn_listener(app, server_address).await
 }
 
-async fn raw_post(addr: std::net::SocketAddr, path: &str, body: String) -> u16 {
-    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
-    let req = format!(
+async fn raw_post(address: std::sync::Arc<str>, path: &str, body: String) -> u16 {
+    let request = format!(
         "POST {} HTTP/1.1\r\nHost: x\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
         path,
         body.len(),
         body
     );
-    stream.write_all(req.as_bytes()).await.unwrap();
-    let mut buf = Vec::new();
-    stream.read_to_end(&mut buf).await.unwrap();
-    let text = String::from_utf8_lossy(&buf);
-    text.split_whitespace()
+    let response = crate::tests::test_server::raw_request(&address, &request).await;
+    response
+        .split_whitespace()
         .nth(1)
         .and_then(|c| c.parse::<u16>().ok())
         .unwrap_or(0)
@@ -48,38 +35,45 @@ async fn raw_post(addr: std::net::SocketAddr, path: &str, body: String) -> u16 {
 
 /// Without a busy_timeout, concurrent writers in WAL mode fail immediately
 /// with SQLITE_BUSY (measured: 46/50 concurrent stores failed with HTTP 400).
-/// With the pragma set, writes are serialized and all succeed.
+/// With the pragma set, writes are serialized and all succeed. Every
+/// backend must take concurrent writers the same way.
 #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
 async fn test_concurrent_store_writes_succeed() {
-    let (addr, _) = spawn_server().await;
-
-    const N: usize = 30;
-    let mut handles = Vec::new();
-    for i in 0..N {
-        handles.push(tokio::spawn(async move {
-            let body = format!(
-                "{{\"identifier\":\"{:064x}\",\"authentication_key\":\"{:064x}\",\"encrypted_secret\":\"dGVzdA==\"}}",
-                i + 1,
-                i + 1
+    for (listener, server_address) in listener_addresses() {
+        for (backend, state) in crate::tests::test_server::backend_states() {
+            let backend = format!("{backend}/{listener}");
+            let server = spawn_server(state.clone(), &server_address).await;
+            let addr: std::sync::Arc<str> = server.address.clone().into();
+
+            const N: usize = 30;
+            let mut handles = Vec::new();
+            for i in 0..N {
+                let addr = addr.clone();
+                handles.push(tokio::spawn(async move {
+                    let body = format!(
+                        "{{\"identifier\":\"{:064x}\",\"authentication_key\":\"{:064x}\",\"encrypted_secret\":\"dGVzdA==\"}}",
+                        i + 1,
+                        i + 1
+                    );
+                    raw_post(addr, "/store", body).await
+                }));
+            }
+
+            let mut created = 0usize;
+            let mut other = Vec::new();
+            for h in handles {
+                match h.await.unwrap() {
+                    201 => created += 1,
+                    code => other.push(code),
+                }
+            }
+
+            assert_eq!(
+                created, N,
+                "{backend}: concurrent stores should all succeed with busy_timeout, got failures: {other:?}"
             );
-            raw_post(addr, "/store", body).await
-        }));
-    }
-
-    let mut created = 0usize;
-    let mut other = Vec::new();
-    for h in handles {
-        match h.await.unwrap() {
-            201 => created += 1,
-            code => other.push(code),
         }
     }
-
-    assert_eq!(
-        created, N,
-        "concurrent stores should all succeed with busy_timeout, got failures: {:?}",
-        other
-    );
 }
 
 /// Regression test: the rate-limit check used to be non-atomic (read the
@@ -91,117 +85,168 @@ async fn test_concurrent_store_writes_succeed() {
 /// concurrency.
 #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
 async fn test_rate_limit_holds_under_concurrency() {
-    let (addr, state) = spawn_server().await;
-
-    let store_body = format!(
-        "{{\"identifier\":\"{}\",\"authentication_key\":\"{}\",\"encrypted_secret\":\"dGVzdA==\"}}",
-        crate::tests::SHA256_111111,
-        crate::tests::SHA256_222222
-    );
-    assert_eq!(raw_post(addr, "/store", store_body).await, 201);
-
-    const N: usize = 100;
-    let mut handles = Vec::new();
-    for index in 0..N {
-        let body = format!(
-            "{{\"identifier\":\"{}\",\"authentication_key\":\"{}\"}}",
-            crate::tests::SHA256_111111,
-            crate::tests::distinct_candidate(index)
-        );
-        handles.push(tokio::spawn(
-            async move { raw_post(addr, "/fetch", body).await },
-        ));
-    }
-
-    let mut unauthorized = 0usize; // 401: a password guess was consumed
-    let mut too_many = 0usize; // 429: rejected by the rate limiter
-    let mut other = 0usize;
-    for h in handles {
-        match h.await.unwrap() {
-            401 => unauthorized += 1,
-            429 => too_many += 1,
-            _ => other += 1,
+    for (listener, server_address) in listener_addresses() {
+        for (backend, state) in crate::tests::test_server::backend_states() {
+            let backend = format!("{backend}/{listener}");
+            let server = spawn_server(state.clone(), &server_address).await;
+            let addr: std::sync::Arc<str> = server.address.clone().into();
+
+            let store_body = format!(
+                "{{\"identifier\":\"{}\",\"authentication_key\":\"{}\",\"encrypted_secret\":\"dGVzdA==\"}}",
+                crate::tests::SHA256_111111,
+                crate::tests::SHA256_222222
+            );
+            assert_eq!(raw_post(addr.clone(), "/store", store_body).await, 201);
+
+            const N: usize = 100;
+            let mut handles = Vec::new();
+            for index in 0..N {
+                let body = format!(
+                    "{{\"identifier\":\"{}\",\"authentication_key\":\"{}\"}}",
+                    crate::tests::SHA256_111111,
+                    crate::tests::distinct_candidate(index)
+                );
+                let addr = addr.clone();
+                handles.push(tokio::spawn(
+                    async move { raw_post(addr, "/fetch", body).await },
+                ));
+            }
+
+            let mut unauthorized = 0usize; // 401: a password guess was consumed
+            let mut too_many = 0usize; // 429: rejected by the rate limiter
+            let mut other = 0usize;
+            for h in handles {
+                match h.await.unwrap() {
+                    401 => unauthorized += 1,
+                    429 => too_many += 1,
+                    _ => other += 1,
+                }
+            }
+
+            assert_eq!(other, 0, "{backend}: unexpected status codes");
+            assert_eq!(
+                unauthorized, state.rate_limit_max_attempts as usize,
+                "{backend}: rate limit bypassed: more guesses consumed than allowed"
+            );
+            assert_eq!(
+                too_many,
+                N - state.rate_limit_max_attempts as usize,
+                "{backend}"
+            );
         }
     }
-
-    assert_eq!(other, 0, "unexpected status codes");
-    assert_eq!(
-        unauthorized, state.rate_limit_max_attempts as usize,
-        "rate limit bypassed: more guesses consumed than allowed"
-    );
-    assert_eq!(too_many, N - state.rate_limit_max_attempts as usize);
 }
 
 /// A duplicate arriving while the first trash lookup is Pending is rejected;
-/// once committed, a replay is allowed to reach SQLite and is indistinguishable
+/// once committed, a replay is allowed to reach the database and is indistinguishable
 /// from a miss (covered by the distinct-candidate tests).
 #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
 async fn test_concurrent_trash_releases_secret_once() {
-    let (addr, _) = spawn_server().await;
-
-    let store_body = format!(
-        "{{\"identifier\":\"{}\",\"authentication_key\":\"{}\",\"encrypted_secret\":\"dGVzdA==\"}}",
-        crate::tests::SHA256_111111,
-        crate::tests::SHA256_222222
-    );
-    assert_eq!(raw_post(addr, "/store", store_body).await, 201);
-
-    let trash_body = format!(
-        "{{\"identifier\":\"{}\",\"authentication_key\":\"{}\"}}",
-        crate::tests::SHA256_111111,
-        crate::tests::SHA256_222222
-    );
-    let first = tokio::spawn(raw_post(addr, "/trash", trash_body.clone()));
-    let second = tokio::spawn(raw_post(addr, "/trash", trash_body));
-    let mut statuses = vec![first.await.unwrap(), second.await.unwrap()];
-    statuses.sort_unstable();
+    for (listener, server_address) in listener_addresses() {
+        for (backend, state) in crate::tests::test_server::backend_states() {
+            let backend = format!("{backend}/{listener}");
+            let server = spawn_server(state.clone(), &server_address).await;
+            let addr: std::sync::Arc<str> = server.address.clone().into();
+
+            let store_body = format!(
+                "{{\"identifier\":\"{}\",\"authentication_key\":\"{}\",\"encrypted_secret\":\"dGVzdA==\"}}",
+                crate::tests::SHA256_111111,
+                crate::tests::SHA256_222222
+            );
+            assert_eq!(raw_post(addr.clone(), "/store", store_body).await, 201);
 
-    assert_eq!(statuses, vec![202, 503]);
+            let trash_body = format!(
+                "{{\"identifier\":\"{}\",\"authentication_key\":\"{}\"}}",
+                crate::tests::SHA256_111111,
+                crate::tests::SHA256_222222
+            );
+            // Hold every database permit so the first lookup stays Pending
+            // (its reservation lands before it waits for a permit) until
+            // the duplicate has been answered; otherwise the race could be
+            // decided by scheduling alone.
+            let permits = state.database_semaphore.available_permits() as u32;
+            let database_permits = state
+                .database_semaphore
+                .clone()
+                .acquire_many_owned(permits)
+                .await
+                .unwrap();
+            let first = tokio::spawn(raw_post(addr.clone(), "/trash", trash_body.clone()));
+            tokio::time::timeout(std::time::Duration::from_millis(500), async {
+                let id_hash = crate::utils::identifier_hash(crate::tests::SHA256_111111).unwrap();
+                while state
+                    .identifier_rate_limit
+                    .lock()
+                    .await
+                    .get(&id_hash)
+                    .is_none_or(|info| info.candidate_count() == 0)
+                {
+                    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+                }
+            })
+            .await
+            .expect("first trash did not reserve its attempt");
+            let second = raw_post(addr, "/trash", trash_body).await;
+            drop(database_permits);
+            let mut statuses = vec![first.await.unwrap(), second];
+            statuses.sort_unstable();
+
+            assert_eq!(statuses, vec![202, 503], "{backend}");
+        }
+    }
 }
 
 /// The F1 fix under race: 50 concurrent /store calls with the SAME payload
 /// must all return 201 (indistinguishable, the oracle stays closed) and
 /// create exactly one row (ON CONFLICT DO NOTHING is idempotent even when
-/// SQLite serializes the writers at the last moment).
+/// the database serializes the writers at the last moment).
 #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
 async fn test_concurrent_identical_store_is_idempotent() {
-    use diesel::{QueryDsl, RunQueryDsl};
-
-    let (addr, app_state) = spawn_server().await;
+    for (listener, server_address) in listener_addresses() {
+        for (backend, app_state) in crate::tests::test_server::backend_states() {
+            let backend = format!("{backend}/{listener}");
+            let server = spawn_server(app_state.clone(), &server_address).await;
+            let addr: std::sync::Arc<str> = server.address.clone().into();
 
-    let body = format!(
-        "{{\"identifier\":\"{}\",\"authentication_key\":\"{}\",\"encrypted_secret\":\"{}\"}}",
-        crate::tests::SHA256_111111,
-        crate::tests::SHA256_222222,
-        crate::tests::BASE64_ENCRYPTED_SECRET
-    );
+            let body = format!(
+                "{{\"identifier\":\"{}\",\"authentication_key\":\"{}\",\"encrypted_secret\":\"{}\"}}",
+                crate::tests::SHA256_111111,
+                crate::tests::SHA256_222222,
+                crate::tests::BASE64_ENCRYPTED_SECRET
+            );
 
-    const N: usize = 50;
-    let mut handles = Vec::new();
-    for _ in 0..N {
-        let body = body.clone();
-        handles.push(tokio::spawn(
-            async move { raw_post(addr, "/store", body).await },
-        ));
-    }
+            const N: usize = 50;
+            let mut handles = Vec::new();
+            for _ in 0..N {
+                let body = body.clone();
+                let addr = addr.clone();
+                handles.push(tokio::spawn(
+                    async move { raw_post(addr, "/store", body).await },
+                ));
+            }
+
+            let mut created = 0usize;
+            let mut other = Vec::new();
+            for h in handles {
+                match h.await.unwrap() {
+                    201 => created += 1,
+                    code => other.push(code),
+                }
+            }
+            assert_eq!(
+                created, N,
+                "{backend}: every concurrent identical store must return 201, got: {other:?}"
+            );
 
-    let mut created = 0usize;
-    let mut other = Vec::new();
-    for h in handles {
-        match h.await.unwrap() {
-            201 => created += 1,
-            code => other.push(code),
+            // An UPDATE reports every row it matched: a backend-neutral count.
+            let rows = app_state
+                .secret_store
+                .execute_raw("UPDATE secret SET created_at = created_at")
+                .unwrap();
+            assert_eq!(
+                rows, 1,
+                "{backend}: concurrent identical stores must create one row"
+            );
         }
     }
-    assert_eq!(
-        created, N,
-        "every concurrent identical store must return 201, got: {other:?}"
-    );
-
-    let mut connection = crate::database::establish_connection(app_state.database_url);
-    let rows: i64 = crate::schema::secret::table
-        .count()
-        .get_result(&mut connection)
-        .unwrap();
-    assert_eq!(rows, 1, "concurrent identical stores must create one row");
 }
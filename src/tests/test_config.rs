@@ -0,0 +1,383 @@
+use std::collections::HashMap;
+
+use crate::config::{redact_password, Config};
+
+const FILE: &str = r#"
+[server]
+address = "127.0.0.1:3001"
+socket_mode = "640"
+secret_max_length = 256
+
+[rate_limit]
+cooldown_minutes = 1440
+max_attempts = 5
+
+[buckets.lookup]
+burst = 50.0
+refill_per_second = 1.5
+
+[telemetry]
+snapshot_ttl_seconds = 30
+
+[database]
+url = "keychain.sqlite3"
+
+[canary]
+value = "file canary"
+"#;
+
+fn load(file: Option<&str>, environment: &[(&str, &str)]) -> Result<Config, String> {
+    let environment: HashMap<String, String> = environment
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    Config::load(file, &|name| Ok(environment.get(name).cloned()))
+}
+
+#[test]
+fn test_file_values_and_defaults() {
+    let config = load(Some(FILE), &[]).unwrap();
+    assert_eq!(config.server.address, "127.0.0.1:3001");
+    assert_eq!(config.server.socket_mode, 0o640);
+    assert_eq!(config.server.secret_max_length, 256);
+    assert_eq!(config.rate_limit.cooldown_minutes, 1440);
+    assert_eq!(config.rate_limit.max_attempts, 5);
+    assert_eq!(config.buckets.lookup.burst, 50.0);
+    assert_eq!(config.buckets.lookup.refill_per_second, 1.5);
+    assert_eq!(config.telemetry.snapshot_ttl_seconds, 30);
+    assert_eq!(config.canary.value, "file canary");
+    assert!(config.canary.from_file);
+    // Keys absent from the file keep the built-in defaults.
+    assert_eq!(config.buckets.store.burst, 10.0);
+    assert_eq!(config.rate_limit.max_identifiers, 100_000);
+    assert_eq!(config.database.max_concurrency, 16);
+    assert_eq!(config.server.operator_address, None);
+}
+
+#[test]
+fn test_environment_overrides_the_file() {
+    let config = load(
+        Some(FILE),
+        &[
+            ("SERVER_ADDRESS", "127.0.0.1:4000"),
+            ("LOOKUP_RATE_LIMIT_BURST", "7"),
+            ("RATE_LIMIT_MAX_FAILED_ATTEMPTS", "2"),
+            ("CANARY", "env canary"),
+        ],
+    )
+    .unwrap();
+    assert_eq!(config.server.address, "127.0.0.1:4000");
+    assert_eq!(config.buckets.lookup.burst, 7.0);
+    assert_eq!(config.buckets.lookup.refill_per_second, 1.5);
+    // The deprecated variable still wins over the file.
+    assert_eq!(config.rate_limit.max_attempts, 2);
+    assert_eq!(config.canary.value, "env canary");
+    assert!(!config.canary.from_file);
+}
+
+const REQUIRED: &[(&str, &str)] = &[
+    ("SERVER_ADDRESS", "127.0.0.1:3001"),
+    ("SECRET_MAX_LENGTH", "128"),
+    ("RATE_LIMIT_COOLDOWN", "1"),
+    ("RATE_LIMIT_MAX_ATTEMPTS", "3"),
+    ("DATABASE_URL", "keychain.sqlite3"),
+    ("CANARY", "🐦"),
+];
+
+#[test]
+fn test_environment_alone_is_enough() {
+    let config = load(None, REQUIRED).unwrap();
+    assert_eq!(config.rate_limit.cooldown_minutes, 1);
+    assert!(!config.canary.from_file);
+}
+
+/// A value from the file goes through the same fail-closed validation as
+/// its environment variable, and the error names the variable.
+#[test]
+fn test_file_values_are_validated_like_the_environment() {
+    let without_cooldown: Vec<_> = REQUIRED
+        .iter()
+        .copied()
+        .filter(|(name, _)| *name != "RATE_LIMIT_COOLDOWN")
+        .collect();
+    for (file, environment, variable) in [
+        (
+            "[buckets.store]\nburst = 0.0",
+            REQUIRED,
+            "STORE_RATE_LIMIT_BURST",
+        ),
+        (
+            "[database]\nmax_concurrency = 0",
+            REQUIRED,
+            "DATABASE_MAX_CONCURRENCY",
+        ),
+        (
+            "[server]\nsocket_mode = \"666\"",
+            REQUIRED,
+            "SERVER_SOCKET_MODE",
+        ),
+        (
+            "[rate_limit]\ncooldown_minutes = 0",
+            without_cooldown.as_slice(),
+            "RATE_LIMIT_COOLDOWN",
+        ),
+    ] {
+        let error = load(Some(file), environment).unwrap_err();
+        assert!(error.contains(variable), "{variable}: {error}");
+    }
+}
+
+#[test]
+fn test_unknown_keys_and_bad_types_are_refused() {
+    for file in [
+        "[server]\nadress = \"127.0.0.1:3001\"",
+        "[buckets.store]\nburts = 5.0",
+        "[logging]\nlevel = \"debug\"",
+        "[rate_limit]\nmax_attempts = \"three\"",
+    ] {
+        let error = load(Some(file), REQUIRED).unwrap_err();
+        assert!(error.starts_with("CONFIG_FILE"), "{error}");
+    }
+}
+
+#[test]
+fn test_missing_required_value_names_both_sources() {
+    let error = load(Some("[server]\nsecret_max_length = 128"), &[]).unwrap_err();
+    assert_eq!(
+        error,
+        "SERVER_ADDRESS (or server.address in CONFIG_FILE) must be set"
+    );
+}
+
+/// `--check-config` prints a file that loads back to the same effective
+/// configuration, with database passwords masked.
+#[test]
+fn test_rendered_configuration_round_trips() {
+    let config = load(Some(FILE), &[("OPERATOR_ADDRESS", "127.0.0.1:9100")]).unwrap();
+    let rendered = config.render();
+    let reloaded = load(Some(&rendered), &[]).unwrap();
+    assert_eq!(reloaded.render(), rendered);
+    assert_eq!(
+        reloaded.server.operator_address.as_deref(),
+        Some("127.0.0.1:9100")
+    );
+
+    assert_eq!(
+        redact_password("postgres://keychain:hunter2@db/keychain"),
+        "postgres://keychain:***@db/keychain"
+    );
+    assert_eq!(
+        redact_password("postgres://db/keychain"),
+        "postgres://db/keychain"
+    );
+    assert_eq!(redact_password("keychain.sqlite3"), "keychain.sqlite3");
+}
+
+/// The signed canary key and document path are set together, and the key
+/// must be a valid Ed25519 point.
+#[test]
+fn test_signed_canary_settings_are_validated() {
+    const KEY: &str = "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a";
+    let config = load(
+        Some(FILE),
+        &[
+            ("CANARY_PUBLIC_KEY", KEY),
+            ("CANARY_SIGNED_PATH", "canary.signed.json"),
+        ],
+    )
+    .unwrap();
+    assert_eq!(config.canary.public_key.as_deref(), Some(KEY));
+
+    let error = load(Some(FILE), &[("CANARY_PUBLIC_KEY", KEY)]).unwrap_err();
+    assert!(error.contains("must be set together"), "{error}");
+    let error = load(
+        Some(FILE),
+        &[
+            ("CANARY_PUBLIC_KEY", &KEY[..62]),
+            ("CANARY_SIGNED_PATH", "canary.signed.json"),
+        ],
+    )
+    .unwrap_err();
+    assert!(error.contains("CANARY_PUBLIC_KEY"), "{error}");
+}
+
+/// The federation is a list of distinct v3 onion hostnames and a threshold
+/// the federation can meet, given together; the environment list is
+/// comma-separated and wins over the file.
+#[test]
+fn test_federation_settings_are_validated() {
+    let b = format!("{}.onion", "b".repeat(56));
+    let c = format!("{}.onion", "c".repeat(56));
+    let file = format!("{FILE}\n[federation]\npeers = [\"{b}\"]\nthreshold = 2\n");
+    let config = load(Some(&file), &[]).unwrap();
+    assert_eq!(config.federation.peers, vec![b.clone()]);
+    assert_eq!(config.federation.threshold, Some(2));
+    let reloaded = load(Some(&config.render()), &[]).unwrap();
+    assert_eq!(reloaded.federation.peers, vec![b.clone()]);
+
+    let peers = format!("{b}, {c}");
+    let config = load(
+        Some(&file),
+        &[("FEDERATION_PEERS", &peers), ("FEDERATION_THRESHOLD", "3")],
+    )
+    .unwrap();
+    assert_eq!(config.federation.peers, vec![b.clone(), c.clone()]);
+    assert_eq!(config.federation.threshold, Some(3));
+    assert!(load(Some(FILE), &[])
+        .unwrap()
+        .federation
+        .threshold
+        .is_none());
+
+    for (environment, expected) in [
+        (
+            vec![("FEDERATION_PEERS", b.as_str())],
+            "FEDERATION_THRESHOLD",
+        ),
+        (vec![("FEDERATION_THRESHOLD", "2")], "FEDERATION_PEERS"),
+        (
+            vec![
+                ("FEDERATION_PEERS", b.as_str()),
+                ("FEDERATION_THRESHOLD", "3"),
+            ],
+            "between 2 and 2",
+        ),
+        (
+            vec![
+                ("FEDERATION_PEERS", b.as_str()),
+                ("FEDERATION_THRESHOLD", "1"),
+            ],
+            "between 2 and 2",
+        ),
+        (
+            vec![
+                ("FEDERATION_PEERS", "peer.onion"),
+                ("FEDERATION_THRESHOLD", "2"),
+            ],
+            "v3 onion",
+        ),
+        (
+            vec![
+                ("FEDERATION_PEERS", "http://example.com"),
+                ("FEDERATION_THRESHOLD", "2"),
+            ],
+            "v3 onion",
+        ),
+    ] {
+        let error = load(Some(FILE), &environment).unwrap_err();
+        assert!(error.contains(expected), "{error}");
+    }
+    let twice = format!("{b},{b}");
+    let error = load(
+        Some(FILE),
+        &[("FEDERATION_PEERS", &twice), ("FEDERATION_THRESHOLD", "2")],
+    )
+    .unwrap_err();
+    assert!(error.contains("twice"), "{error}");
+}
+
+/// Replication takes a plain `http://host:port` peer and a key path,
+/// given together, over SQLite only, with records small enough for a
+/// batch.
+#[test]
+fn test_replication_settings_are_validated() {
+    let file = format!(
+        "{FILE}\n[replication]\npeer_url = \"http://10.0.0.2:3001\"\nkey_path = \"/etc/keychain/replication.key\"\nlisten_address = \"10.0.0.1:3001\"\n"
+    );
+    let config = load(Some(&file), &[]).unwrap();
+    assert_eq!(
+        config.replication.peer_url.as_deref(),
+        Some("http://10.0.0.2:3001")
+    );
+    let reloaded = load(Some(&config.render()), &[]).unwrap();
+    assert_eq!(
+        reloaded.replication.key_path.as_deref(),
+        Some("/etc/keychain/replication.key")
+    );
+    assert!(load(Some(FILE), &[])
+        .unwrap()
+        .replication
+        .peer_url
+        .is_none());
+
+    assert_eq!(
+        reloaded.replication.listen_address.as_deref(),
+        Some("10.0.0.1:3001")
+    );
+
+    let key = ("REPLICATION_KEY_PATH", "/etc/keychain/replication.key");
+    let listen = ("REPLICATION_LISTEN_ADDRESS", "10.0.0.1:3001");
+    for (environment, expected) in [
+        (
+            vec![("REPLICATION_PEER_URL", "http://10.0.0.2:3001"), listen],
+            "REPLICATION_KEY_PATH",
+        ),
+        (vec![key, listen], "REPLICATION_PEER_URL"),
+        (vec![listen], "REPLICATION_PEER_URL"),
+        (
+            vec![("REPLICATION_PEER_URL", "http://10.0.0.2:3001"), key],
+            "REPLICATION_LISTEN_ADDRESS",
+        ),
+        (
+            vec![
+                ("REPLICATION_PEER_URL", "http://10.0.0.2:3001"),
+                key,
+                ("REPLICATION_LISTEN_ADDRESS", "127.0.0.1:3001"),
+            ],
+            "SERVER_ADDRESS",
+        ),
+        (
+            vec![
+                ("REPLICATION_PEER_URL", "http://10.0.0.2:3001"),
+                key,
+                ("REPLICATION_LISTEN_ADDRESS", "127.0.0.1:9100"),
+                ("OPERATOR_ADDRESS", "127.0.0.1:9100"),
+            ],
+            "OPERATOR_ADDRESS",
+        ),
+        (
+            vec![
+                ("REPLICATION_PEER_URL", "http://10.0.0.2:3001"),
+                key,
+                (
+                    "REPLICATION_LISTEN_ADDRESS",
+                    "unix:/run/keychain/replication.sock",
+                ),
+            ],
+            "ip:port",
+        ),
+        (
+            vec![("REPLICATION_PEER_URL", "https://10.0.0.2:3001"), key],
+            "http://host:port",
+        ),
+        (
+            vec![("REPLICATION_PEER_URL", "http://10.0.0.2:3001/sync"), key],
+            "http://host:port",
+        ),
+        (
+            vec![("REPLICATION_PEER_URL", "http://user@10.0.0.2:3001"), key],
+            "http://host:port",
+        ),
+        (
+            vec![
+                ("REPLICATION_PEER_URL", "http://10.0.0.2:3001"),
+                key,
+                listen,
+                ("DATABASE_URL", "postgres://keychain@localhost/keychain"),
+            ],
+            "SQLite",
+        ),
+        (
+            vec![
+                ("REPLICATION_PEER_URL", "http://10.0.0.2:3001"),
+                key,
+                listen,
+                ("SECRET_MAX_LENGTH", "300000"),
+            ],
+            "SECRET_MAX_LENGTH",
+        ),
+    ] {
+        let error = load(Some(FILE), &environment).unwrap_err();
+        assert!(error.contains(expected), "{error}");
+    }
+}
@@ -34,6 +34,7 @@ async fn test_attempt_status_is_exact_while_snapshot_is_hour_truncated() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -44,6 +45,7 @@ async fn test_attempt_status_is_exact_while_snapshot_is_hour_truncated() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -88,8 +90,10 @@ async fn test_attempt_status_is_exact_while_snapshot_is_hour_truncated() {
 async fn test_store_is_not_counted_in_attempts() {
     // a zero snapshot TTL forces a rebuild on every poll, so the test sees
     // fresh state at each step instead of the first cached snapshot
-    let mut state = crate::env::init();
-    state.attempts_snapshot_ttl = std::time::Duration::ZERO;
+    let state = crate::env::init();
+    state
+        .attempts_snapshot_ttl_seconds
+        .store(0, std::sync::atomic::Ordering::Relaxed);
     crate::database::init_db(state.clone());
     let server = axum_test::TestServer::new(crate::router::new(state.clone())).unwrap();
 
@@ -97,6 +101,9 @@ async fn test_store_is_not_counted_in_attempts() {
         identifier: SHA256_111111.to_string(),
         authentication_key: SHA256_222222.to_string(),
         encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+        ttl_days: None,
+        share_index: None,
+        federation_id: None,
     };
 
     // a store alone creates no rate-limit entry
@@ -118,6 +125,7 @@ async fn test_store_is_not_counted_in_attempts() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .expect_success()
         .await;
@@ -149,6 +157,7 @@ async fn test_targeted_429_has_targeted_metadata() {
             .json(&FetchSecret {
                 identifier: SHA256_111111.to_string(),
                 authentication_key: crate::tests::distinct_candidate(index),
+                pow: None,
             })
             .expect_failure()
             .await;
@@ -159,6 +168,7 @@ async fn test_targeted_429_has_targeted_metadata() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -192,6 +202,7 @@ async fn test_global_buckets_use_503_without_targeted_metadata() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -218,6 +229,9 @@ async fn test_global_buckets_use_503_without_targeted_metadata() {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
             encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         })
         .expect_failure()
         .await;
@@ -270,6 +284,7 @@ async fn test_503_responses_have_no_machine_code() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .await;
     let response = server
@@ -277,6 +292,7 @@ async fn test_503_responses_have_no_machine_code() {
         .json(&FetchSecret {
             identifier: SHA256_222222.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .await;
     assert_eq!(response.status_code(), StatusCode::SERVICE_UNAVAILABLE);
@@ -300,6 +316,7 @@ async fn test_503_responses_have_no_machine_code() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .await;
     assert_eq!(response.status_code(), StatusCode::SERVICE_UNAVAILABLE);
@@ -4,7 +4,6 @@ use crate::{
     utils::identifier_hash,
 };
 use axum::http::StatusCode;
-use diesel::RunQueryDsl;
 
 /// A database failure must not be confused with wrong credentials:
 /// the handler must respond 500 (not 401) and must not consume rate-limit
@@ -12,31 +11,38 @@ use diesel::RunQueryDsl;
 /// recovery attempts and locks them out.
 #[tokio::test]
 async fn test_database_error_returns_500_without_consuming_attempts() {
-    let (server, state) = crate::tests::test_server::new_test_server().await;
+    for (backend, state) in crate::tests::test_server::backend_states() {
+        crate::database::init_db(state.clone());
+        let server = axum_test::TestServer::new(crate::router::new(state.clone())).unwrap();
 
-    // Force every subsequent query to fail: drop the table out from under
-    // the server. Database initialization is tested separately.
-    let mut connection = crate::database::establish_connection(state.clone().database_url);
-    diesel::sql_query("DROP TABLE secret")
-        .execute(&mut connection)
-        .expect("failed to drop table");
+        // Force every subsequent query to fail: drop the table out from
+        // under the server. Database initialization is tested separately.
+        state
+            .secret_store
+            .execute_raw("DROP TABLE secret")
+            .expect("failed to drop table");
 
-    let fetch = &FetchSecret {
-        identifier: SHA256_111111.to_string(),
-        authentication_key: SHA256_222222.to_string(),
-    };
+        let fetch = &FetchSecret {
+            identifier: SHA256_111111.to_string(),
+            authentication_key: SHA256_222222.to_string(),
+            pow: None,
+        };
 
-    // More requests than max_failed_attempts: none may be 401 or 429.
-    for _ in 0..(state.rate_limit_max_attempts + 2) {
-        let response = server.post("/fetch").json(fetch).await;
-        assert_eq!(
-            response.status_code(),
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "database errors must be reported as 500, not as invalid credentials"
+        // More requests than max_failed_attempts: none may be 401 or 429.
+        for _ in 0..(state.rate_limit_max_attempts + 2) {
+            let response = server.post("/fetch").json(fetch).await;
+            assert_eq!(
+                response.status_code(),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "{backend}: database errors must be reported as 500, not as invalid credentials"
+            );
+        }
+
+        // No rate-limit entry may remain: attempts were refunded.
+        let identifier_rate_limit = state.identifier_rate_limit.lock().await;
+        assert!(
+            !identifier_rate_limit.contains_key(&identifier_hash(SHA256_111111).unwrap()),
+            "{backend}"
         );
     }
-
-    // No rate-limit entry may remain: attempts were refunded.
-    let identifier_rate_limit = state.identifier_rate_limit.lock().await;
-    assert!(!identifier_rate_limit.contains_key(&identifier_hash(SHA256_111111).unwrap()));
 }
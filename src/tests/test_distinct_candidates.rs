@@ -39,6 +39,7 @@ fn fetch(identifier: &str, authentication_key: &str) -> FetchSecret {
     FetchSecret {
         identifier: identifier.to_owned(),
         authentication_key: authentication_key.to_owned(),
+        pow: None,
     }
 }
 
@@ -47,6 +48,9 @@ fn store(identifier: &str, authentication_key: &str, encrypted_secret: &str) ->
         identifier: identifier.to_owned(),
         authentication_key: authentication_key.to_owned(),
         encrypted_secret: encrypted_secret.to_owned(),
+        ttl_days: None,
+        share_index: None,
+        federation_id: None,
     }
 }
 
@@ -1,7 +1,11 @@
 use crate::env::{
-    canary_file_state, unique_test_database, validate_capacity, validate_config,
-    validate_snapshot_ttl, validate_token_bucket, CanaryFileState, MAX_DATABASE_CONCURRENCY,
-    MAX_RATE_LIMIT_IDENTIFIERS,
+    attempts_signing_key, canary_file_state, opaque_server, oprf_key, parse_server_socket_mode,
+    replication, unique_test_database, validate_backoff, validate_capacity, validate_config,
+    validate_operator_address, validate_pow, validate_rate_limit_state_url,
+    validate_secret_max_ttl, validate_server_address, validate_shutdown_drain,
+    validate_snapshot_ttl, validate_token_bucket, validate_trash_grace_period, CanaryFileState,
+    MAX_DATABASE_CONCURRENCY, MAX_POW_BASE_DIFFICULTY_BITS, MAX_RATE_LIMIT_IDENTIFIERS,
+    MAX_SECRET_TTL_DAYS, MAX_SHUTDOWN_DRAIN_SECONDS, MAX_TRASH_GRACE_PERIOD_HOURS,
 };
 
 #[test]
@@ -160,6 +164,60 @@ fn test_validate_snapshot_ttl_rejects_zero() {
     assert!(validate_snapshot_ttl(0).is_err());
 }
 
+#[test]
+fn test_validate_backoff_accepts_disabled_and_sub_cooldown_values() {
+    assert!(validate_backoff(0, 1440).is_ok());
+    assert!(validate_backoff(60, 1440).is_ok());
+    assert!(validate_backoff(59, 1).is_ok());
+}
+
+#[test]
+fn test_validate_backoff_rejects_base_at_or_beyond_cooldown() {
+    // such a base never unlocks a candidate before the window resets: it
+    // would look enabled while behaving like the hard cap
+    assert!(validate_backoff(60, 1).is_err());
+    assert!(validate_backoff(u64::MAX, 525_600).is_err());
+}
+
+#[test]
+fn test_validate_pow_accepts_valid_values() {
+    // zero difficulty disables proof-of-work
+    assert!(validate_pow(0, 2, 300).is_ok());
+    assert!(validate_pow(MAX_POW_BASE_DIFFICULTY_BITS, 8, 3600).is_ok());
+    assert!(validate_pow(16, 0, 1).is_ok());
+}
+
+#[test]
+fn test_validate_pow_rejects_out_of_range_values() {
+    assert!(validate_pow(MAX_POW_BASE_DIFFICULTY_BITS + 1, 2, 300).is_err());
+    assert!(validate_pow(16, 9, 300).is_err());
+    assert!(validate_pow(16, 2, 0).is_err());
+    assert!(validate_pow(16, 2, 3601).is_err());
+}
+
+#[test]
+fn test_validate_rate_limit_state_url_requires_a_separate_file() {
+    assert!(validate_rate_limit_state_url("rate_limit.sqlite3", "db.sqlite3").is_ok());
+    assert!(validate_rate_limit_state_url("db.sqlite3", "db.sqlite3").is_err());
+    assert!(validate_rate_limit_state_url(" ", "db.sqlite3").is_err());
+}
+
+#[test]
+fn test_validate_trash_grace_period() {
+    // zero keeps the immediate deletion
+    assert!(validate_trash_grace_period(0).is_ok());
+    assert!(validate_trash_grace_period(MAX_TRASH_GRACE_PERIOD_HOURS).is_ok());
+    assert!(validate_trash_grace_period(MAX_TRASH_GRACE_PERIOD_HOURS + 1).is_err());
+}
+
+#[test]
+fn test_validate_secret_max_ttl() {
+    // zero disables expiry
+    assert!(validate_secret_max_ttl(0).is_ok());
+    assert!(validate_secret_max_ttl(MAX_SECRET_TTL_DAYS).is_ok());
+    assert!(validate_secret_max_ttl(MAX_SECRET_TTL_DAYS + 1).is_err());
+}
+
 fn unique_temp_path(tag: &str) -> std::path::PathBuf {
     std::env::temp_dir().join(format!(
         "keychain-test-{}-{}-{}",
@@ -171,3 +229,152 @@ fn unique_temp_path(tag: &str) -> std::path::PathBuf {
             .as_nanos()
     ))
 }
+
+#[test]
+fn test_validate_operator_address_requires_a_separate_loopback_listener() {
+    assert!(validate_operator_address("127.0.0.1:9100", "127.0.0.1:3000").is_ok());
+    assert!(validate_operator_address("[::1]:9100", "127.0.0.1:3000").is_ok());
+    assert!(validate_operator_address("localhost:9100", "127.0.0.1:3000").is_ok());
+    assert!(validate_operator_address("0.0.0.0:9100", "127.0.0.1:3000").is_err());
+    assert!(validate_operator_address("192.168.1.2:9100", "127.0.0.1:3000").is_err());
+    assert!(validate_operator_address("localhost", "127.0.0.1:3000").is_err());
+    assert!(validate_operator_address("127.0.0.1:3000", "127.0.0.1:3000").is_err());
+}
+
+#[test]
+fn test_validate_shutdown_drain() {
+    assert!(validate_shutdown_drain(0).is_ok());
+    assert!(validate_shutdown_drain(MAX_SHUTDOWN_DRAIN_SECONDS).is_ok());
+    assert!(validate_shutdown_drain(MAX_SHUTDOWN_DRAIN_SECONDS + 1).is_err());
+}
+
+#[test]
+fn test_validate_server_address() {
+    assert!(validate_server_address("127.0.0.1:3000").is_ok());
+    assert!(validate_server_address("unix:/run/keychain/keychain.sock").is_ok());
+    assert!(validate_server_address("unix:keychain.sock").is_err());
+    assert!(validate_server_address("unix:").is_err());
+}
+
+#[test]
+fn test_parse_server_socket_mode() {
+    assert_eq!(parse_server_socket_mode("660"), Ok(0o660));
+    assert_eq!(parse_server_socket_mode("0600"), Ok(0o600));
+    assert_eq!(parse_server_socket_mode("664"), Ok(0o664));
+    // Write permission for others would let any local user bypass the proxy.
+    assert!(parse_server_socket_mode("666").is_err());
+    assert!(parse_server_socket_mode("777").is_err());
+    assert!(parse_server_socket_mode("1660").is_err());
+    assert!(parse_server_socket_mode("rw-rw----").is_err());
+}
+
+#[test]
+fn test_attempts_signing_key_file() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!(
+        "keychain-test-attempts-key-{}",
+        crate::env::unique_test_suffix()
+    ));
+    let path_str = path.to_str().unwrap();
+    std::fs::write(&path, format!("{}\n", "07".repeat(32))).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+    let key = attempts_signing_key(Some(path_str)).unwrap();
+    assert_eq!(
+        key.to_bytes(),
+        [7u8; 32],
+        "the same file gives the same key"
+    );
+
+    // A key other local users can read would let them forge snapshots.
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+    assert!(attempts_signing_key(Some(path_str)).is_err());
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+    std::fs::write(&path, "not hex").unwrap();
+    assert!(attempts_signing_key(Some(path_str)).is_err());
+    let _ = std::fs::remove_file(&path);
+    assert!(attempts_signing_key(Some(path_str)).is_err());
+
+    // Without a file, every boot draws its own key.
+    assert_ne!(
+        attempts_signing_key(None).unwrap().to_bytes(),
+        attempts_signing_key(None).unwrap().to_bytes()
+    );
+}
+
+/// The OPRF key has no per-boot fallback: a missing, unreadable or exposed
+/// seed file stops the server rather than silently changing every output.
+#[test]
+fn test_oprf_key_file() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!(
+        "keychain-test-oprf-key-{}",
+        crate::env::unique_test_suffix()
+    ));
+    let path_str = path.to_str().unwrap();
+    std::fs::write(&path, format!("{}\n", "09".repeat(32))).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+    assert_eq!(
+        oprf_key(path_str).unwrap().public_key_hex(),
+        crate::oprf::OprfKey::derive(&[9u8; 32]).public_key_hex()
+    );
+
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+    assert!(oprf_key(path_str).err().unwrap().contains("OPRF_KEY_PATH"));
+    let _ = std::fs::remove_file(&path);
+    assert!(oprf_key(path_str).is_err());
+}
+
+/// Like the OPRF key, the OPAQUE seed has no per-boot fallback: a new seed
+/// would lock every registered client out.
+#[test]
+fn test_opaque_key_file() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!(
+        "keychain-test-opaque-key-{}",
+        crate::env::unique_test_suffix()
+    ));
+    let path_str = path.to_str().unwrap();
+    std::fs::write(&path, format!("{}\n", "07".repeat(32))).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+    assert_eq!(
+        opaque_server(path_str).unwrap().public_key_hex(),
+        crate::opaque::OpaqueServer::derive(&[7u8; 32]).public_key_hex()
+    );
+
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+    assert!(opaque_server(path_str)
+        .err()
+        .unwrap()
+        .contains("OPAQUE_KEY_PATH"));
+    let _ = std::fs::remove_file(&path);
+    assert!(opaque_server(path_str).is_err());
+}
+
+/// The replication key is a seed file like the others, shared by both
+/// peers: batches signed under it verify on either side.
+#[test]
+fn test_replication_key_file() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!(
+        "keychain-test-replication-key-{}",
+        crate::env::unique_test_suffix()
+    ));
+    let path_str = path.to_str().unwrap();
+    std::fs::write(&path, format!("{}\n", "07".repeat(32))).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+    let loaded = replication("http://127.0.0.1:3001", path_str).unwrap();
+    let expected = crate::replication::Replication::new("http://[::1]:3002", [7u8; 32]).unwrap();
+    assert!(expected.verify(b"batch", &loaded.sign(b"batch")));
+    assert!(!expected.verify(b"other batch", &loaded.sign(b"batch")));
+
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+    assert!(replication("http://127.0.0.1:3001", path_str)
+        .err()
+        .unwrap()
+        .contains("REPLICATION_KEY_PATH"));
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,226 @@
+use crate::{
+    admin::{execute, Command},
+    env::unique_test_database,
+    export::{export, import, ImportSummary},
+    models::{OpaqueRecord, Secret},
+    tests::{distinct_candidate, BASE64_ENCRYPTED_SECRET},
+};
+use age::secrecy::ExposeSecret;
+use diesel::{sql_query, Connection, QueryDsl, RunQueryDsl, SqliteConnection};
+
+fn connection() -> SqliteConnection {
+    SqliteConnection::establish(":memory:").expect("failed to create in-memory database")
+}
+
+/// The legacy fixture of `test_migrations`, adopted and migrated, plus
+/// records using every later column and an OPAQUE registration.
+fn source_database() -> SqliteConnection {
+    let mut connection = connection();
+    sql_query("CREATE TABLE secret (id TEXT PRIMARY KEY NOT NULL, created_at TEXT NOT NULL, encrypted_secret TEXT NOT NULL)")
+        .execute(&mut connection)
+        .unwrap();
+    sql_query("INSERT INTO secret VALUES ('id', 'time', 'cipher')")
+        .execute(&mut connection)
+        .unwrap();
+    crate::database::run_migrations(&mut connection).unwrap();
+
+    for (index, (trash_after, expires_at)) in [
+        (None, None),
+        (Some("2030-01-01T00:00:00Z"), None),
+        (None, Some("2031-01-01T00:00:00Z")),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        assert!(crate::database::write(
+            &mut connection,
+            &Secret {
+                id: distinct_candidate(index),
+                created_at: chrono::Utc::now().to_rfc3339(),
+                encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+                trash_after: trash_after.map(str::to_string),
+                expires_at: expires_at.map(str::to_string),
+                share_index: None,
+                federation_id: None,
+            }
+        ));
+    }
+    crate::database::insert_opaque_record(
+        &mut connection,
+        &OpaqueRecord {
+            credential_id: distinct_candidate(9),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            registration_record: "ab".repeat(crate::opaque::RECORD_LENGTH),
+            encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+        },
+    )
+    .unwrap();
+    connection
+}
+
+fn migrated_database() -> SqliteConnection {
+    let mut connection = connection();
+    crate::database::run_migrations(&mut connection).unwrap();
+    connection
+}
+
+/// Every row, every column, in id order, then the OPAQUE records.
+fn rows(connection: &mut SqliteConnection) -> Vec<serde_json::Value> {
+    use crate::schema::{opaque_record, secret};
+    let secrets = secret::table
+        .order(secret::id)
+        .load::<Secret>(connection)
+        .unwrap();
+    let records = opaque_record::table
+        .order(opaque_record::credential_id)
+        .load::<OpaqueRecord>(connection)
+        .unwrap();
+    secrets
+        .iter()
+        .map(|row| serde_json::to_value(row).unwrap())
+        .chain(records.iter().map(|row| serde_json::to_value(row).unwrap()))
+        .collect()
+}
+
+fn exported(connection: &mut SqliteConnection) -> Vec<u8> {
+    let mut output = Vec::new();
+    export(connection, &mut output).unwrap();
+    output
+}
+
+#[test]
+fn test_round_trip_keeps_every_row_unaltered() {
+    let mut source = source_database();
+    let dump = exported(&mut source);
+
+    let mut target = migrated_database();
+    let summary = import(&mut target, &mut dump.as_slice()).unwrap();
+    assert_eq!(
+        summary,
+        ImportSummary {
+            records: 5,
+            inserted: 5
+        }
+    );
+    assert_eq!(rows(&mut target), rows(&mut source));
+}
+
+/// `database::write` semantics: a second import inserts nothing, and an
+/// existing record with the same id is not overwritten.
+#[test]
+fn test_import_is_idempotent_and_keeps_existing_records() {
+    let mut source = source_database();
+    let dump = exported(&mut source);
+
+    let mut target = migrated_database();
+    sql_query("INSERT INTO secret (id, created_at, encrypted_secret) VALUES ('id', 'now', 'kept')")
+        .execute(&mut target)
+        .unwrap();
+    let summary = import(&mut target, &mut dump.as_slice()).unwrap();
+    assert_eq!(summary.inserted, 4);
+    let summary = import(&mut target, &mut dump.as_slice()).unwrap();
+    assert_eq!(summary.inserted, 0);
+    assert_eq!(rows(&mut target)[3]["encrypted_secret"], "kept");
+}
+
+/// A corrupted, truncated or extended export imports nothing.
+#[test]
+fn test_damaged_export_is_rolled_back() {
+    let dump = exported(&mut source_database());
+
+    let mut flipped = dump.clone();
+    let last = flipped.len() - 3;
+    flipped[last] ^= 0x01;
+    let mut truncated = dump.clone();
+    truncated.pop();
+    let mut extended = dump.clone();
+    extended.push(0);
+
+    for damaged in [flipped, truncated, extended] {
+        let mut target = migrated_database();
+        assert!(import(&mut target, &mut damaged.as_slice()).is_err());
+        assert!(rows(&mut target).is_empty());
+    }
+}
+
+#[test]
+fn test_newer_schema_version_is_refused() {
+    let mut dump = exported(&mut source_database());
+    // magic (16) + format version (2) + version length (2) + "0005"
+    dump[20..24].copy_from_slice(b"9999");
+
+    let error = import(&mut migrated_database(), &mut dump.as_slice()).unwrap_err();
+    assert!(error.to_string().contains("newer than this build"));
+}
+
+/// The admin commands write a new private file, encrypted to an age
+/// recipient, and read it back with the matching identity.
+#[test]
+fn test_encrypted_export_through_admin_commands() {
+    let (export_path, _export_guard) = unique_test_database();
+    let (identity_path, _identity_guard) = unique_test_database();
+    let identity = age::x25519::Identity::generate();
+    std::fs::write(&identity_path, identity.to_string().expose_secret()).unwrap();
+
+    let mut source = source_database();
+    let report = execute(
+        &mut source,
+        &Command::Export {
+            path: export_path.clone().into(),
+            recipient: Some(identity.to_public().to_string()),
+        },
+        false,
+    )
+    .unwrap();
+    assert_eq!(report, "secrets exported: 5\n");
+    let dump = std::fs::read(&export_path).unwrap();
+    assert!(!dump
+        .windows(BASE64_ENCRYPTED_SECRET.len())
+        .any(|window| window == BASE64_ENCRYPTED_SECRET.as_bytes()));
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&export_path)
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+    assert!(
+        execute(
+            &mut source,
+            &Command::Export {
+                path: export_path.clone().into(),
+                recipient: None,
+            },
+            false
+        )
+        .is_err(),
+        "an existing file is never overwritten"
+    );
+
+    let mut target = connection();
+    let error = execute(
+        &mut target,
+        &Command::Import {
+            path: export_path.clone().into(),
+            identity: None,
+        },
+        false,
+    )
+    .unwrap_err();
+    assert!(error.to_string().contains("--identity"));
+    let report = execute(
+        &mut target,
+        &Command::Import {
+            path: export_path.into(),
+            identity: Some(identity_path.into()),
+        },
+        false,
+    )
+    .unwrap();
+    assert_eq!(
+        report,
+        "secrets imported: 5 of 5 (the others were already present)\n"
+    );
+    assert_eq!(rows(&mut target), rows(&mut source));
+}
@@ -0,0 +1,246 @@
+use axum::http::StatusCode;
+use base64::Engine;
+
+use crate::models::{Info, StoreSecret};
+use crate::shamir::{combine, split, Share};
+use crate::tests::test_server::{configured_test_server, fetch_secret};
+use crate::tests::{distinct_candidate, SHA256_111111, SHA256_222222};
+
+const FEDERATION_ID: &str = "ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb";
+
+fn peer(letter: char) -> String {
+    format!("{}.onion", letter.to_string().repeat(56))
+}
+
+async fn federated_server() -> (axum_test::TestServer, crate::AppState) {
+    configured_test_server(|state| {
+        state.federation = Some(std::sync::Arc::new(crate::models::Federation {
+            peers: vec![peer('b'), peer('c')],
+            threshold: 2,
+        }));
+    })
+    .await
+}
+
+fn store_share(share: &Share) -> StoreSecret {
+    StoreSecret {
+        identifier: SHA256_111111.to_string(),
+        authentication_key: SHA256_222222.to_string(),
+        encrypted_secret: base64::engine::general_purpose::STANDARD.encode(&share.value),
+        ttl_days: None,
+        share_index: Some(u32::from(share.index)),
+        federation_id: Some(FEDERATION_ID.to_string()),
+    }
+}
+
+fn share(index: u8, value: &str) -> Share {
+    Share {
+        index,
+        value: hex::decode(value).unwrap(),
+    }
+}
+
+/// Pinned shares, cross-checked against an independent implementation
+/// (power-basis evaluation in Python): clients must be able to re-create a
+/// share with any version of this helper.
+#[test]
+fn test_shamir_vectors() {
+    let shares = split(b"keychain federation", 2, 3, &[0x42; 32]).unwrap();
+    assert_eq!(
+        shares,
+        vec![
+            share(1, "8b9c46c854f8bae36f180aaf45f701d5fbdd84"),
+            share(2, "b08c072e1048d46fbe9abbe92563a12d5610a1"),
+            share(3, "507538852cd107e2f1e4d42205e6c18cc4a24b"),
+        ]
+    );
+
+    let secret: Vec<u8> = (0..32).collect();
+    let seed: [u8; 32] = std::array::from_fn(|i| 32 + i as u8);
+    let shares = split(&secret, 3, 5, &seed).unwrap();
+    assert_eq!(
+        shares,
+        vec![
+            share(
+                1,
+                "e570d8f910198c120bd79dcd81dff3fc65cabcae100fa6fd93ae2ac6d11ca5c1"
+            ),
+            share(
+                2,
+                "cf38e56c64b6ba08ac37d3db1c86bbd32bf6fe0068249d75d5b1e9d202e2dec9"
+            ),
+            share(
+                3,
+                "2a493f9670aa301dafe9441d915446205e2d50bd6c3e2d9f5e06d90fcfe36517"
+            ),
+            share(
+                4,
+                "fd32ecebff629f8de5273eb768d034280ff74be62763f651b40b9d296a210777"
+            ),
+            share(
+                5,
+                "18433611eb7e1598e6f9a971e502c9db7a2ce55b237946bb3fbcadf4a720bca9"
+            ),
+        ]
+    );
+
+    // Any three of the five recombine; two give another value.
+    for a in 0..5 {
+        for b in a + 1..5 {
+            let pair = [shares[a].clone(), shares[b].clone()];
+            assert_ne!(combine(&pair).unwrap(), secret);
+            for c in b + 1..5 {
+                let triple = [shares[c].clone(), shares[a].clone(), shares[b].clone()];
+                assert_eq!(combine(&triple).unwrap(), secret);
+            }
+        }
+    }
+    assert_eq!(combine(&shares).unwrap(), secret);
+}
+
+#[test]
+fn test_shamir_refuses_malformed_input() {
+    assert!(split(b"", 2, 3, &[0; 32]).is_err());
+    assert!(split(b"secret", 1, 3, &[0; 32]).is_err());
+    assert!(split(b"secret", 4, 3, &[0; 32]).is_err());
+    // Another secret under the same seed draws other coefficients: with a
+    // threshold of 2, share 1 is `secret ^ a_1`.
+    let coefficient = |secret: &[u8]| -> Vec<u8> {
+        let shares = split(secret, 2, 2, &[0; 32]).unwrap();
+        shares[0]
+            .value
+            .iter()
+            .zip(secret)
+            .map(|(y, s)| y ^ s)
+            .collect()
+    };
+    assert_ne!(coefficient(b"secret"), coefficient(b"secreT"));
+
+    let shares = split(b"secret", 2, 3, &[0; 32]).unwrap();
+    assert!(combine(&[]).is_err());
+    assert!(combine(&[shares[0].clone(), shares[0].clone()]).is_err());
+    assert!(combine(&[shares[0].clone(), share(0, "000000000000")]).is_err());
+    assert!(combine(&[shares[0].clone(), share(2, "00")]).is_err());
+}
+
+/// A federated record keeps its share index and federation id, returned
+/// by `/fetch`; `/info` lists the peers and the threshold.
+#[tokio::test]
+async fn test_federated_store_and_fetch() {
+    let (server, _) = federated_server().await;
+    let info = server.get("/info").expect_success().await.json::<Info>();
+    let federation = info.federation.unwrap();
+    assert_eq!(federation.peers, vec![peer('b'), peer('c')]);
+    assert_eq!(federation.threshold, 2);
+
+    let shares = split(b"secret", 2, 3, &[7; 32]).unwrap();
+    server
+        .post("/store")
+        .json(&store_share(&shares[2]))
+        .expect_success()
+        .await;
+    let body = server
+        .post("/fetch")
+        .json(&fetch_secret(SHA256_222222))
+        .expect_success()
+        .await
+        .json::<serde_json::Value>();
+    assert_eq!(body["share_index"], 3);
+    assert_eq!(body["federation_id"], FEDERATION_ID);
+}
+
+/// Shares are refused by a server outside a federation, and out of range
+/// or half-given share fields are refused everywhere. Records without them
+/// keep working, with no share fields in `/fetch`.
+#[tokio::test]
+async fn test_store_validates_share_fields() {
+    let (server, _) = crate::tests::test_server::new_test_server().await;
+    let info = server.get("/info").expect_success().await.json::<Info>();
+    assert!(info.federation.is_none());
+    let shares = split(b"secret", 2, 3, &[7; 32]).unwrap();
+    let response = server.post("/store").json(&store_share(&shares[0])).await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+
+    let (server, _) = federated_server().await;
+    let mut invalid = Vec::new();
+    for share_index in [0, 4, 256] {
+        let mut request = store_share(&shares[0]);
+        request.share_index = Some(share_index);
+        invalid.push(request);
+    }
+    let mut request = store_share(&shares[0]);
+    request.federation_id = Some("not hex".to_string());
+    invalid.push(request);
+    let mut request = store_share(&shares[0]);
+    request.federation_id = None;
+    invalid.push(request);
+    let mut request = store_share(&shares[0]);
+    request.share_index = None;
+    invalid.push(request);
+    for request in invalid {
+        let response = server.post("/store").json(&request).await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    let mut request = store_share(&shares[0]);
+    request.share_index = None;
+    request.federation_id = None;
+    server.post("/store").json(&request).expect_success().await;
+    let body = server
+        .post("/fetch")
+        .json(&fetch_secret(SHA256_222222))
+        .expect_success()
+        .await
+        .json::<serde_json::Value>();
+    assert!(body.get("share_index").is_none());
+    assert!(body.get("federation_id").is_none());
+}
+
+/// The servers of a federation keep independent budgets: locking the
+/// identifier out of one leaves `threshold` others to recover from.
+#[tokio::test]
+async fn test_locked_out_server_does_not_block_recovery() {
+    let secret = b"the backup key";
+    let shares = split(secret, 2, 3, &[9; 32]).unwrap();
+    let mut servers = Vec::new();
+    for share in &shares {
+        let (server, state) = federated_server().await;
+        server
+            .post("/store")
+            .json(&store_share(share))
+            .expect_success()
+            .await;
+        servers.push((server, state));
+    }
+
+    let (locked, state) = &servers[0];
+    for index in 0..usize::from(state.rate_limit_max_attempts) {
+        let response = locked
+            .post("/fetch")
+            .json(&fetch_secret(&distinct_candidate(index)))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+    }
+    let response = locked
+        .post("/fetch")
+        .json(&fetch_secret(SHA256_222222))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::TOO_MANY_REQUESTS);
+
+    let mut recovered = Vec::new();
+    for (server, _) in &servers[1..] {
+        let body = server
+            .post("/fetch")
+            .json(&fetch_secret(SHA256_222222))
+            .expect_success()
+            .await
+            .json::<serde_json::Value>();
+        recovered.push(Share {
+            index: body["share_index"].as_u64().unwrap() as u8,
+            value: base64::engine::general_purpose::STANDARD
+                .decode(body["encrypted_secret"].as_str().unwrap())
+                .unwrap(),
+        });
+    }
+    assert_eq!(combine(&recovered).unwrap(), secret);
+}
@@ -13,6 +13,9 @@ async fn test_fetch_success() {
         identifier: SHA256_111111.to_string(),
         authentication_key: SHA256_222222.to_string(),
         encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+        ttl_days: None,
+        share_index: None,
+        federation_id: None,
     };
 
     server.post("/store").json(&store).expect_success().await;
@@ -20,6 +23,7 @@ async fn test_fetch_success() {
     let fetch = &FetchSecret {
         identifier: SHA256_111111.to_string(),
         authentication_key: SHA256_222222.to_string(),
+        pow: None,
     };
 
     let response = server.post("/fetch").json(&fetch).expect_success().await;
@@ -39,12 +43,16 @@ async fn test_fetch_success_reports_exact_attempt_status() {
         identifier: SHA256_111111.to_string(),
         authentication_key: SHA256_222222.to_string(),
         encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+        ttl_days: None,
+        share_index: None,
+        federation_id: None,
     };
     server.post("/store").json(&store).expect_success().await;
 
     let fetch = &FetchSecret {
         identifier: SHA256_111111.to_string(),
         authentication_key: SHA256_222222.to_string(),
+        pow: None,
     };
 
     // first lookup ever: no previous attempt, full budget remaining
@@ -105,6 +113,7 @@ async fn test_fetch_key_failure_invalid_hash_for_format_identifier() {
     let fetch = &FetchSecret {
         identifier: "not_a_hash".to_string(),
         authentication_key: SHA256_111111.to_string(),
+        pow: None,
     };
 
     let response = server.post("/fetch").json(&fetch).expect_failure().await;
@@ -119,6 +128,7 @@ async fn test_fetch_failure_invalid_hash_format_for_authentication_key() {
     let fetch = &FetchSecret {
         identifier: SHA256_111111.to_string(),
         authentication_key: "not_a_hash".to_string(),
+        pow: None,
     };
 
     let response = server.post("/fetch").json(&fetch).expect_failure().await;
@@ -134,6 +144,9 @@ async fn test_fetch_rate_limit_enforced_and_reset_after_cooldown() {
         identifier: SHA256_111111.to_string(),
         authentication_key: SHA256_222222.to_string(),
         encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+        ttl_days: None,
+        share_index: None,
+        federation_id: None,
     };
 
     server.post("/store").json(&store).expect_success().await;
@@ -143,6 +156,7 @@ async fn test_fetch_rate_limit_enforced_and_reset_after_cooldown() {
         let fetch_wrong_authentication_key = FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: crate::tests::distinct_candidate(i),
+            pow: None,
         };
         let response = server
             .post("/fetch")
@@ -161,6 +175,7 @@ async fn test_fetch_rate_limit_enforced_and_reset_after_cooldown() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: crate::tests::distinct_candidate(0),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -189,6 +204,7 @@ async fn test_fetch_rate_limit_enforced_and_reset_after_cooldown() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .expect_success()
         .await;
@@ -0,0 +1,83 @@
+use crate::{models::Readiness, tests::test_server::new_test_server};
+use axum::http::StatusCode;
+use std::sync::{atomic::Ordering, Arc};
+
+async fn readiness(state: &crate::AppState) -> (StatusCode, Readiness) {
+    let operator = axum_test::TestServer::new(crate::router::operator(state.clone())).unwrap();
+    let response = operator.get("/readyz").await;
+    (response.status_code(), response.json::<Readiness>())
+}
+
+#[tokio::test]
+async fn test_ready_server_reports_map_fullness() {
+    let (_, state) = new_test_server().await;
+    let operator = axum_test::TestServer::new(crate::router::operator(state.clone())).unwrap();
+    operator.get("/healthz").await.assert_status_ok();
+
+    let (status, body) = readiness(&state).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.ready);
+    assert_eq!(body.failed_check, None);
+    assert_eq!(body.identifier_rate_limit_entries, 0);
+    assert_eq!(
+        body.identifier_rate_limit_capacity,
+        state.rate_limit_max_identifiers
+    );
+}
+
+/// Each failed check makes `/readyz` a `503` naming it, while `/healthz`
+/// keeps answering.
+#[tokio::test]
+async fn test_readiness_fails_on_each_database_check() {
+    // never initialized: journal mode is not WAL
+    let state = crate::env::init();
+    assert_eq!(
+        readiness(&state).await.1.failed_check.as_deref(),
+        Some("wal")
+    );
+
+    // the newest migration is missing from the ledger
+    crate::database::init_db(state.clone());
+    state
+        .secret_store
+        .execute_raw(
+            "DELETE FROM __diesel_schema_migrations \
+             WHERE version = (SELECT MAX(version) FROM __diesel_schema_migrations)",
+        )
+        .unwrap();
+    let (status, body) = readiness(&state).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body.failed_check.as_deref(), Some("migrations"));
+
+    // a directory cannot be opened as a SQLite file
+    let mut state = crate::env::init();
+    state.secret_store = Arc::new(crate::secret_store::SqliteStore::new(
+        &std::env::temp_dir().to_string_lossy(),
+        1,
+        false,
+    ));
+    assert_eq!(
+        readiness(&state).await.1.failed_check.as_deref(),
+        Some("connection")
+    );
+    let operator = axum_test::TestServer::new(crate::router::operator(state)).unwrap();
+    operator.get("/healthz").await.assert_status_ok();
+}
+
+/// The shutdown signal flips readiness before anything else.
+#[tokio::test]
+async fn test_readiness_fails_while_shutting_down() {
+    let (_, state) = new_test_server().await;
+    state.shutting_down.store(true, Ordering::SeqCst);
+    let (status, body) = readiness(&state).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body.failed_check.as_deref(), Some("shutting down"));
+}
+
+#[tokio::test]
+async fn test_probes_are_not_served_by_the_public_router() {
+    let (server, _) = new_test_server().await;
+    for path in ["/healthz", "/readyz"] {
+        assert_eq!(server.get(path).await.status_code(), StatusCode::NOT_FOUND);
+    }
+}
@@ -13,7 +13,12 @@ async fn test_info_success() {
         info.rate_limit_cooldown,
         state.rate_limit_cooldown.num_minutes() as u64
     );
-    assert_eq!(info.secret_max_length, state.secret_max_length);
+    assert_eq!(
+        info.secret_max_length,
+        state
+            .secret_max_length
+            .load(std::sync::atomic::Ordering::Relaxed)
+    );
     assert_eq!(info.canary, "🐦");
     assert_eq!(info.rate_limit_max_attempts, state.rate_limit_max_attempts);
     assert_eq!(
@@ -24,6 +29,10 @@ async fn test_info_success() {
         info.max_attempt_identifiers,
         state.rate_limit_max_identifiers
     );
+    assert_eq!(
+        info.rate_limit_backoff_base_seconds,
+        state.rate_limit_backoff_base.num_seconds() as u64
+    );
 
     // hour-truncated, and consistent with the in-memory collection start
     assert_eq!(info.attempts_collection_started_at.minute(), 0);
@@ -0,0 +1,113 @@
+use crate::{
+    models::{FetchSecret, StoreSecret},
+    tests::{test_server::new_test_server, BASE64_ENCRYPTED_SECRET, SHA256_111111, SHA256_222222},
+    utils::{generate_secret_id, identifier_hash},
+};
+use axum::http::StatusCode;
+
+async fn scrape(state: &crate::AppState) -> String {
+    let metrics = axum_test::TestServer::new(crate::router::operator(state.clone())).unwrap();
+    let response = metrics.get("/metrics").await;
+    response.assert_status_ok();
+    assert!(response
+        .header("content-type")
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain; version=0.0.4"));
+    response.text()
+}
+
+/// Responses are counted by route template and status; a client-chosen
+/// path never becomes a label, and no identifier, id_hash or secret_id
+/// appears anywhere in the exposition.
+#[tokio::test]
+async fn test_metrics_count_routes_without_request_data() {
+    let (server, state) = new_test_server().await;
+
+    server
+        .post("/store")
+        .json(&StoreSecret {
+            identifier: SHA256_111111.to_string(),
+            authentication_key: SHA256_222222.to_string(),
+            encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
+        })
+        .expect_success()
+        .await;
+    for _ in 0..2 {
+        server
+            .post("/fetch")
+            .json(&FetchSecret {
+                identifier: SHA256_111111.to_string(),
+                authentication_key: SHA256_222222.to_string(),
+                pow: None,
+            })
+            .expect_success()
+            .await;
+    }
+    server
+        .get(&format!("/{SHA256_111111}"))
+        .expect_failure()
+        .await;
+
+    let exposition = scrape(&state).await;
+    assert!(
+        exposition.contains("keychain_http_responses_total{route=\"/store\",status=\"201\"} 1\n")
+    );
+    assert!(
+        exposition.contains("keychain_http_responses_total{route=\"/fetch\",status=\"200\"} 2\n")
+    );
+    assert!(
+        exposition.contains("keychain_http_responses_total{route=\"other\",status=\"404\"} 1\n")
+    );
+    assert!(exposition.contains("keychain_identifier_rate_limit_entries 1\n"));
+    assert!(exposition.contains("keychain_database_permit_wait_seconds_count 3\n"));
+    for secret in [
+        SHA256_111111.to_string(),
+        SHA256_222222.to_string(),
+        identifier_hash(SHA256_111111).unwrap(),
+        generate_secret_id(SHA256_111111, SHA256_222222),
+    ] {
+        assert!(!exposition.contains(&secret));
+    }
+}
+
+#[tokio::test]
+async fn test_metrics_count_token_bucket_rejections() {
+    let (_, mut state) = new_test_server().await;
+    state.store_token_bucket = std::sync::Arc::new(tokio::sync::Mutex::new(
+        crate::rate_limit::TokenBucket::new(1.0, 0.0),
+    ));
+    let server = axum_test::TestServer::new(crate::router::new(state.clone())).unwrap();
+
+    for _ in 0..3 {
+        server
+            .post("/store")
+            .json(&StoreSecret {
+                identifier: SHA256_111111.to_string(),
+                authentication_key: SHA256_222222.to_string(),
+                encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+                ttl_days: None,
+                share_index: None,
+                federation_id: None,
+            })
+            .await;
+    }
+
+    let exposition = scrape(&state).await;
+    assert!(exposition.contains("keychain_token_bucket_rejections_total{bucket=\"store\"} 2\n"));
+    assert!(exposition.contains("keychain_token_bucket_rejections_total{bucket=\"lookup\"} 0\n"));
+    assert!(
+        exposition.contains("keychain_http_responses_total{route=\"/store\",status=\"503\"} 2\n")
+    );
+}
+
+/// `/metrics` lives on the operator listener only.
+#[tokio::test]
+async fn test_metrics_are_not_served_by_the_public_router() {
+    let (server, _) = new_test_server().await;
+    let response = server.get("/metrics").await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+}
@@ -1,9 +1,17 @@
+use diesel::migration::MigrationSource;
 use diesel::{sql_query, Connection, QueryableByName, RunQueryDsl, SqliteConnection};
 
 fn connection() -> SqliteConnection {
     SqliteConnection::establish(":memory:").expect("failed to create in-memory database")
 }
 
+/// Every embedded migration leaves one ledger row.
+fn migration_count() -> i64 {
+    MigrationSource::<diesel::sqlite::Sqlite>::migrations(&crate::database::MIGRATIONS)
+        .unwrap()
+        .len() as i64
+}
+
 #[derive(QueryableByName)]
 struct Count {
     #[diesel(sql_type = diesel::sql_types::BigInt)]
@@ -27,7 +35,7 @@ fn empty_database_gets_secret_and_ledger() {
             .get_result::<Count>(&mut connection)
             .unwrap()
             .value,
-        1
+        migration_count()
     );
 }
 
@@ -42,7 +50,7 @@ fn migrations_are_idempotent() {
             .get_result::<Count>(&mut connection)
             .unwrap()
             .value,
-        1
+        migration_count()
     );
 }
 
@@ -64,13 +72,25 @@ fn exact_legacy_table_is_adopted_without_touching_data() {
     assert_eq!(row.id, "id");
     assert_eq!(row.created_at, "time");
     assert_eq!(row.encrypted_secret, "cipher");
-    let version: String = sql_query("SELECT version FROM __diesel_schema_migrations")
-        .get_result::<Version>(&mut connection)
+    let trash_after = sql_query("SELECT trash_after AS value FROM secret")
+        .get_result::<NullableText>(&mut connection)
         .unwrap()
-        .version;
+        .value;
+    assert_eq!(trash_after, None, "legacy rows are not trashed");
+    let version: String =
+        sql_query("SELECT version FROM __diesel_schema_migrations ORDER BY version")
+            .get_result::<Version>(&mut connection)
+            .unwrap()
+            .version;
     assert_eq!(version, "0001");
 }
 
+#[derive(QueryableByName)]
+struct NullableText {
+    #[diesel(sql_type = diesel::sql_types::Nullable<diesel::sql_types::Text>)]
+    value: Option<String>,
+}
+
 #[derive(QueryableByName)]
 struct Version {
     #[diesel(sql_type = diesel::sql_types::Text)]
@@ -0,0 +1,403 @@
+use axum::http::StatusCode;
+use curve25519_dalek::scalar::Scalar;
+use serde_json::json;
+
+use crate::models::{Info, OpaqueLoginChallenge, OpaqueLoginResult, OpaqueRegistrationResponse};
+use crate::opaque::{blind, finish_login, finish_registration, start_login, OpaqueServer, CONTEXT};
+use crate::tests::test_server::configured_test_server;
+use crate::tests::BASE64_ENCRYPTED_SECRET;
+
+const IDENTIFIER: &str = "0101010101010101010101010101010101010101010101010101010101010101";
+
+fn bytes<const N: usize>(hex_value: &str) -> [u8; N] {
+    hex::decode(hex_value).unwrap().try_into().unwrap()
+}
+
+fn scalar(hex_value: &str) -> Scalar {
+    Scalar::from_canonical_bytes(bytes(hex_value)).unwrap()
+}
+
+fn enable_opaque(state: &mut crate::AppState) {
+    state.opaque_server = Some(std::sync::Arc::new(OpaqueServer::derive(&[7u8; 32])));
+}
+
+async fn opaque_server() -> (axum_test::TestServer, crate::AppState) {
+    configured_test_server(enable_opaque).await
+}
+
+/// Client side of a registration, through the HTTP routes.
+async fn register(server: &axum_test::TestServer, password: &[u8], r: u64) {
+    let response = register_secret(server, password, r, BASE64_ENCRYPTED_SECRET).await;
+    assert_eq!(response.status_code(), StatusCode::CREATED);
+}
+
+/// `register`, returning the `/opaque/register/finish` response.
+async fn register_secret(
+    server: &axum_test::TestServer,
+    password: &[u8],
+    r: u64,
+    encrypted_secret: &str,
+) -> axum_test::TestResponse {
+    let r = Scalar::from(r);
+    let response = server
+        .post("/opaque/register/start")
+        .json(&json!({
+            "identifier": IDENTIFIER,
+            "blinded_element": hex::encode(blind(password, &r)),
+        }))
+        .expect_success()
+        .await
+        .json::<OpaqueRegistrationResponse>();
+    let (record, _) = finish_registration(
+        password,
+        &r,
+        &bytes(&response.registration_response),
+        &[5u8; 32],
+    )
+    .unwrap();
+    server
+        .post("/opaque/register/finish")
+        .json(&json!({
+            "registration_id": response.registration_id,
+            "identifier": IDENTIFIER,
+            "registration_record": hex::encode(record),
+            "encrypted_secret": encrypted_secret,
+        }))
+        .await
+}
+
+/// Client side of a login: KE1, KE2, then KE3 when the envelope opens, or
+/// the random MAC a client without the password could only send.
+async fn login(server: &axum_test::TestServer, password: &[u8], r: u64) -> axum_test::TestResponse {
+    let client = start_login(password, &Scalar::from(r), &[1u8; 32], &[2u8; 32]);
+    let challenge = server
+        .post("/opaque/login/start")
+        .json(&json!({ "identifier": IDENTIFIER, "ke1": hex::encode(client.ke1) }))
+        .expect_success()
+        .await
+        .json::<OpaqueLoginChallenge>();
+    let ke3 = finish_login(&client, CONTEXT, &bytes(&challenge.ke2))
+        .map(|(ke3, _)| ke3)
+        .unwrap_or([0xab; 64]);
+    server
+        .post("/opaque/login/finish")
+        .json(&json!({ "login_id": challenge.login_id, "ke3": hex::encode(ke3) }))
+        .await
+}
+
+async fn failed_attempts(state: &crate::AppState) -> u8 {
+    let id_hash = crate::utils::identifier_hash(IDENTIFIER).unwrap();
+    state.identifier_rate_limit.lock().await[&id_hash].failed_candidates
+}
+
+/// The inputs of RFC 9807 appendix C.1.1 (OPAQUE-3DH real test vector 1,
+/// ristretto255-SHA512, identity KSF, context `OPAQUE-POC`). The
+/// registration outputs are the RFC's; KE1 and KE2 pin this implementation,
+/// and the login is checked by its round trip to the same export key.
+#[test]
+fn test_opaque_matches_the_rfc_vectors() {
+    let password = b"CorrectHorseBatteryStaple";
+    let server = OpaqueServer::from_parts(
+        bytes(
+            "f433d0227b0b9dd54f7c4422b600e764e47fb503f1f9a0f0a47c6606b054a7fd\
+             c65347f1a08f277e22358bbabe26f823fca82c7848e9a75661f4ec5d5c1989ef",
+        ),
+        scalar("47451a85372f8b3537e249d7b54188091fb18edde78094b43e2ba42b5eb89f0d"),
+        b"OPAQUE-POC",
+    );
+    assert_eq!(
+        server.public_key_hex(),
+        "b2fe7af9f48cc502d016729d2fe25cdd433f2c4bc904660b2a382c9b79df1a78"
+    );
+    let credential_identifier = b"1234";
+
+    let r = scalar("76cfbfe758db884bebb33582331ba9f159720ca8784a2a070a265d9c2d6abe01");
+    let request = blind(password, &r);
+    assert_eq!(
+        hex::encode(request),
+        "5059ff249eb1551b7ce4991f3336205bde44a105a032e747d21bf382e75f7a71"
+    );
+    let response = server
+        .registration_response(credential_identifier, &request)
+        .unwrap();
+    assert_eq!(
+        hex::encode(response),
+        "7408a268083e03abc7097fc05b587834539065e86fb0c7b6342fcf5e01e5b019\
+         b2fe7af9f48cc502d016729d2fe25cdd433f2c4bc904660b2a382c9b79df1a78"
+    );
+    let (record, export_key) = finish_registration(
+        password,
+        &r,
+        &response,
+        &bytes("ac13171b2f17bc2c74997f0fce1e1f35bec6b91fe2e12dbd323d23ba7a38dfec"),
+    )
+    .unwrap();
+    assert_eq!(
+        hex::encode(record),
+        "76a845464c68a5d2f7e442436bb1424953b17d3e2e289ccbaccafb57ac5c3675\
+         1ac5844383c7708077dea41cbefe2fa15724f449e535dd7dd562e66f5ecfb958\
+         64eadddec9db5874959905117dad40a4524111849799281fefe3c51fa82785c5\
+         ac13171b2f17bc2c74997f0fce1e1f35bec6b91fe2e12dbd323d23ba7a38dfec\
+         634b0f5b96109c198a8027da51854c35bee90d1e1c781806d07d49b76de6a28b\
+         8d9e9b6c93b9f8b64d16dddd9c5bfb5fea48ee8fd2f75012a8b308605cdd8ba5"
+    );
+    assert_eq!(
+        hex::encode(export_key),
+        "1ef15b4fa99e8a852412450ab78713aad30d21fa6966c9b8c9fb3262a970dc62\
+         950d4dd4ed62598229b1b72794fc0335199d9f7fcc6eaedde92cc04870e63f16"
+    );
+
+    let client = start_login(
+        password,
+        &scalar("6ecc102d2e7a7cf49617aad7bbe188556792d4acd60a1a8a8d2b65d4b0790308"),
+        &bytes("da7e07376d6d6f034cfa9bb537d11b8c6b4238c334333d1f0aebb380cae6a6cc"),
+        &bytes("82850a697b42a505f5b68fcdafce8c31f0af2b581f063cf1091933541936304b"),
+    );
+    assert_eq!(
+        hex::encode(client.ke1),
+        "c4dedb0ba6ed5d965d6f250fbe554cd45cba5dfcce3ce836e4aee778aa3cd44d\
+         da7e07376d6d6f034cfa9bb537d11b8c6b4238c334333d1f0aebb380cae6a6cc\
+         6e29bee50701498605b2c085d7b241ca15ba5c32027dd21ba420b94ce60da326"
+    );
+    let randomness = bytes(
+        "38fe59af0df2c79f57b8780278f5ae47355fe1f817119041951c80f612fdfc6d\
+         71cd9960ecef2fe0d0f7494986fa3d8b2bb01963537e60efb13981e138e3d4a1\
+         05a4f54206eef1ba2f615bc0aa285cb22f26d1153b5b40a1e85ff80da12f982f",
+    );
+    let (ke2, server_login) = server
+        .login_with(
+            credential_identifier,
+            Some(&record),
+            &client.ke1,
+            &randomness,
+        )
+        .unwrap();
+    assert_eq!(
+        hex::encode(ke2),
+        "7e308140890bcde30cbcea28b01ea1ecfbd077cff62c4def8efa075aabcbb471\
+         38fe59af0df2c79f57b8780278f5ae47355fe1f817119041951c80f612fdfc6d\
+         d6ec60bcdb26dc455ddf3e718f1020490c192d70dfc7e403981179d8073d1146\
+         a4f9aa1ced4e4cd984c657eb3b54ced3848326f70331953d91b02535af44d9fe\
+         dc80188ca46743c52786e0382f95ad85c08f6afcd1ccfbff95e2bdeb015b166c\
+         6b20b92f832cc6df01e0b86a7efd92c1c804ff865781fa93f2f20b446c8371b6\
+         71cd9960ecef2fe0d0f7494986fa3d8b2bb01963537e60efb13981e138e3d4a1\
+         c4f62198a9d6fa9170c42c3c71f1971b29eb1d5d0bd733e40816c91f7912cc4a\
+         660c48dae03e57aaa38f3d0cffcfc21852ebc8b405d15bd6744945ba1a93438a\
+         162b6111699d98a16bb55b7bdddfe0fc5608b23da246e7bd73b47369169c5c90"
+    );
+    let (ke3, login_export_key) = finish_login(&client, b"OPAQUE-POC", &ke2).unwrap();
+    assert_eq!(login_export_key, export_key);
+    assert!(server_login.finish(&ke3));
+    assert!(!server_login.finish(&[0u8; 64]));
+
+    // Another password does not open the envelope.
+    let client = start_login(b"wrong", &Scalar::from(3u64), &[1u8; 32], &[2u8; 32]);
+    let (ke2, _) = server
+        .login(credential_identifier, Some(&record), &client.ke1)
+        .unwrap();
+    assert!(finish_login(&client, b"OPAQUE-POC", &ke2).is_none());
+}
+
+/// Registration then login, on every backend: the secret is released only
+/// after a valid KE3. A login counts as failed until then, so the wrong
+/// password stays counted and the right one does not.
+#[tokio::test]
+async fn test_opaque_registration_and_login() {
+    for (backend, mut state) in crate::tests::test_server::backend_states() {
+        enable_opaque(&mut state);
+        crate::database::init_db(state.clone());
+        let server = axum_test::TestServer::new(crate::router::new(state.clone())).unwrap();
+        register(&server, b"password", 1).await;
+
+        let wrong = login(&server, b"wrong password", 2).await;
+        assert_eq!(wrong.status_code(), StatusCode::UNAUTHORIZED, "{backend}");
+        assert_eq!(failed_attempts(&state).await, 1, "{backend}");
+
+        let right = login(&server, b"password", 3).await;
+        assert_eq!(right.status_code(), StatusCode::OK, "{backend}");
+        let result = right.json::<OpaqueLoginResult>();
+        assert_eq!(result.encrypted_secret, BASE64_ENCRYPTED_SECRET);
+        assert_eq!(result.attempt_status.total_attempts, 3, "{backend}");
+        assert_eq!(failed_attempts(&state).await, 1, "{backend}");
+    }
+}
+
+/// A credential is registered once: a second registration is refused with
+/// `409` and does not replace the first record.
+#[tokio::test]
+async fn test_opaque_registration_is_not_overwritten() {
+    let (server, _) = opaque_server().await;
+    register(&server, b"password", 1).await;
+    let second = register_secret(&server, b"other password", 2, BASE64_ENCRYPTED_SECRET).await;
+    assert_eq!(second.status_code(), StatusCode::CONFLICT);
+    let response = login(&server, b"password", 3).await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+}
+
+/// A record needs the `registration_id` of a started registration for the
+/// same identifier, used once: nobody stores one without taking a slot of
+/// the identifier's budget.
+#[tokio::test]
+async fn test_opaque_registration_requires_a_started_registration() {
+    let (server, _) = opaque_server().await;
+    let r = Scalar::from(1u64);
+    let started = server
+        .post("/opaque/register/start")
+        .json(&json!({
+            "identifier": IDENTIFIER,
+            "blinded_element": hex::encode(blind(b"password", &r)),
+        }))
+        .expect_success()
+        .await
+        .json::<OpaqueRegistrationResponse>();
+    let (record, _) = finish_registration(
+        b"password",
+        &r,
+        &bytes(&started.registration_response),
+        &[5u8; 32],
+    )
+    .unwrap();
+    let finish = |registration_id: &str, identifier: &str| {
+        server.post("/opaque/register/finish").json(&json!({
+            "registration_id": registration_id,
+            "identifier": identifier,
+            "registration_record": hex::encode(record),
+            "encrypted_secret": BASE64_ENCRYPTED_SECRET,
+        }))
+    };
+
+    let unknown = finish(&"00".repeat(16), IDENTIFIER).await;
+    assert_eq!(unknown.status_code(), StatusCode::NOT_FOUND);
+    let other_identifier = finish(&started.registration_id, &"02".repeat(32)).await;
+    assert_eq!(other_identifier.status_code(), StatusCode::NOT_FOUND);
+    // The mismatch used the registration up.
+    let reused = finish(&started.registration_id, IDENTIFIER).await;
+    assert_eq!(reused.status_code(), StatusCode::NOT_FOUND);
+    let response = login(&server, b"password", 2).await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+}
+
+/// The body limit of `/opaque/register/finish` follows `secret_max_length`,
+/// including after a reload raises it past the default limit.
+#[tokio::test]
+async fn test_opaque_registration_takes_a_secret_of_the_maximum_length() {
+    let (server, state) = opaque_server().await;
+    state
+        .secret_max_length
+        .store(4096, std::sync::atomic::Ordering::Relaxed);
+    let response = register_secret(&server, b"password", 1, &"A".repeat(4096)).await;
+    assert_eq!(response.status_code(), StatusCode::CREATED);
+    let response = login(&server, b"password", 2).await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert_eq!(
+        response.json::<OpaqueLoginResult>().encrypted_secret.len(),
+        4096
+    );
+
+    let oversized = "A".repeat(crate::handlers::opaque::register_finish_body_limit(4096));
+    let response = register_secret(&server, b"password", 3, &oversized).await;
+    assert_eq!(response.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+/// An unregistered identifier answers like a wrong password: a KE2 of the
+/// same shape, then `401`. A `login_id` is single use.
+#[tokio::test]
+async fn test_opaque_login_does_not_reveal_registration() {
+    let (server, _) = opaque_server().await;
+    let client = start_login(b"password", &Scalar::from(1u64), &[1u8; 32], &[2u8; 32]);
+    let challenge = server
+        .post("/opaque/login/start")
+        .json(&json!({ "identifier": IDENTIFIER, "ke1": hex::encode(client.ke1) }))
+        .expect_success()
+        .await
+        .json::<OpaqueLoginChallenge>();
+    assert_eq!(challenge.ke2.len(), 2 * crate::opaque::KE2_LENGTH);
+    assert!(finish_login(&client, CONTEXT, &bytes(&challenge.ke2)).is_none());
+    let finish = json!({ "login_id": challenge.login_id, "ke3": "ab".repeat(64) });
+    let response = server.post("/opaque/login/finish").json(&finish).await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+    let response = server.post("/opaque/login/finish").json(&finish).await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+}
+
+/// Registration and login evaluations spend the `/fetch` budget: a retried
+/// KE1 is a free replay, malformed requests cost nothing, and a saturated
+/// identifier is refused on every route.
+#[tokio::test]
+async fn test_opaque_shares_the_fetch_budget() {
+    let (server, state) = opaque_server().await;
+    let ke1 = |r: u64| {
+        hex::encode(start_login(b"password", &Scalar::from(r), &[1u8; 32], &[2u8; 32]).ke1)
+    };
+    for invalid in ["00".repeat(96), ke1(1)[..128].to_string(), "zz".to_string()] {
+        let response = server
+            .post("/opaque/login/start")
+            .json(&json!({ "identifier": IDENTIFIER, "ke1": invalid }))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    }
+    let id_hash = crate::utils::identifier_hash(IDENTIFIER).unwrap();
+    assert!(!state
+        .identifier_rate_limit
+        .lock()
+        .await
+        .contains_key(&id_hash));
+
+    register(&server, b"password", 100).await;
+    for r in [1, 1] {
+        server
+            .post("/opaque/login/start")
+            .json(&json!({ "identifier": IDENTIFIER, "ke1": ke1(r) }))
+            .expect_success()
+            .await;
+    }
+    let window = state.identifier_rate_limit.lock().await[&id_hash].clone();
+    assert_eq!(window.candidate_count(), 2);
+    assert_eq!(window.failed_candidates, 1);
+
+    for r in 3..=u64::from(state.rate_limit_max_attempts) {
+        server
+            .post("/opaque/login/start")
+            .json(&json!({ "identifier": IDENTIFIER, "ke1": ke1(r) }))
+            .expect_success()
+            .await;
+    }
+    let saturated = server
+        .post("/opaque/login/start")
+        .json(&json!({ "identifier": IDENTIFIER, "ke1": ke1(200) }))
+        .await;
+    assert_eq!(saturated.status_code(), StatusCode::TOO_MANY_REQUESTS);
+    let registration = server
+        .post("/opaque/register/start")
+        .json(&json!({
+            "identifier": IDENTIFIER,
+            "blinded_element": hex::encode(blind(b"password", &Scalar::from(201u64))),
+        }))
+        .await;
+    assert_eq!(registration.status_code(), StatusCode::TOO_MANY_REQUESTS);
+    let fetch = server
+        .post("/fetch")
+        .json(&json!({ "identifier": IDENTIFIER, "authentication_key": IDENTIFIER }))
+        .await;
+    assert_eq!(fetch.status_code(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+/// Without OPAQUE_KEY_PATH the routes and the `/info` key are absent.
+#[tokio::test]
+async fn test_opaque_is_disabled_by_default() {
+    let (server, _) = crate::tests::test_server::new_test_server().await;
+    let response = server
+        .post("/opaque/login/start")
+        .json(&json!({ "identifier": IDENTIFIER, "ke1": "00".repeat(96) }))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+    let info = server.get("/info").expect_success().await.json::<Info>();
+    assert!(info.opaque_public_key.is_none());
+
+    let (server, _) = opaque_server().await;
+    let info = server.get("/info").await.json::<Info>();
+    assert_eq!(
+        info.opaque_public_key.unwrap(),
+        OpaqueServer::derive(&[7u8; 32]).public_key_hex()
+    );
+}
@@ -0,0 +1,249 @@
+use axum::http::StatusCode;
+use curve25519_dalek::scalar::Scalar;
+use serde_json::json;
+
+use crate::handlers::oprf::oprf_candidate;
+use crate::models::{Info, OprfEvaluation};
+use crate::oprf::{blind, finalize, OprfKey};
+use crate::tests::test_server::configured_test_server;
+
+const IDENTIFIER: &str = "0101010101010101010101010101010101010101010101010101010101010101";
+
+fn scalar(hex_value: &str) -> Scalar {
+    Scalar::from_canonical_bytes(hex::decode(hex_value).unwrap().try_into().unwrap()).unwrap()
+}
+
+/// The POPRF `info` of `identifier`: the raw `sha256(identifier)`.
+fn sha256(identifier: &str) -> Vec<u8> {
+    hex::decode(crate::utils::identifier_hash(identifier).unwrap()).unwrap()
+}
+
+fn bytes<const N: usize>(hex_value: &str) -> [u8; N] {
+    hex::decode(hex_value).unwrap().try_into().unwrap()
+}
+
+async fn oprf_server() -> (axum_test::TestServer, crate::AppState) {
+    configured_test_server(|state| {
+        state.oprf_key = Some(std::sync::Arc::new(OprfKey::derive(&[9u8; 32])));
+    })
+    .await
+}
+
+/// Runs the client side of one evaluation for `identifier`: blind, POST,
+/// verify against the pinned `/info` key, finalize.
+async fn client_output(
+    server: &axum_test::TestServer,
+    identifier: &str,
+    input: &[u8],
+    r: &Scalar,
+) -> [u8; 64] {
+    let info = server.get("/info").await.json::<Info>();
+    let public_key = bytes::<32>(&info.oprf_public_key.unwrap());
+    let blinded = blind(input, r);
+    let evaluation = server
+        .post("/oprf")
+        .json(&json!({
+            "identifier": identifier,
+            "blinded_element": hex::encode(blinded),
+        }))
+        .expect_success()
+        .await
+        .json::<OprfEvaluation>();
+    finalize(
+        &public_key,
+        input,
+        &sha256(identifier),
+        r,
+        &blinded,
+        &bytes(&evaluation.evaluated_element),
+        &bytes(&evaluation.proof),
+    )
+    .expect("the proof verifies against the /info key")
+}
+
+/// RFC 9497 appendix A.1.3 (POPRF, ristretto255-SHA512), test vector 1.
+#[test]
+fn test_oprf_matches_the_rfc_vectors() {
+    let key = OprfKey::derive_key_pair(&[0xa3; 32], b"test key");
+    assert_eq!(
+        key.public_key_hex(),
+        "c647bef38497bc6ec077c22af65b696efa43bff3b4a1975a3e8e0a1c5a79d631"
+    );
+    let info = b"test info";
+    let r = scalar("64d37aed22a27f5191de1c1d69fadb899d8862b58eb4220029e036ec4c1f6706");
+    let blinded = blind(&[0x00], &r);
+    assert_eq!(
+        hex::encode(blinded),
+        "c8713aa89241d6989ac142f22dba30596db635c772cbf25021fdd8f3d461f715"
+    );
+    let (evaluated, proof) = key
+        .evaluate_with(
+            &blinded,
+            info,
+            scalar("222a5e897cf59db8145db8d16e597e8facb80ae7d4e26d9881aa6f61d645fc0e"),
+        )
+        .unwrap();
+    assert_eq!(
+        hex::encode(evaluated),
+        "1a4b860d808ff19624731e67b5eff20ceb2df3c3c03b906f5693e2078450d874"
+    );
+    assert_eq!(
+        hex::encode(proof),
+        "41ad1a291aa02c80b0915fbfbb0c0afa15a57e2970067a602ddb9e8fd6b7100d\
+         e32e1ecff943a36f0b10e3dae6bd266cdeb8adf825d86ef27dbc6c0e30c52206"
+    );
+    let public_key = bytes::<32>(&key.public_key_hex());
+    let output = finalize(&public_key, &[0x00], info, &r, &blinded, &evaluated, &proof).unwrap();
+    assert_eq!(
+        hex::encode(output),
+        "ca688351e88afb1d841fde4401c79efebb2eb75e7998fa9737bd5a82a152406d\
+         38bd29f680504e54fd4587eddcf2f37a2617ac2fbd2993f7bdf45442ace7d221"
+    );
+
+    // A proof made with another key, or under another info, fails against
+    // the pinned key.
+    let other = OprfKey::derive(&[1u8; 32]);
+    let (evaluated, proof) = other.evaluate(&blinded, info).unwrap();
+    assert!(finalize(&public_key, &[0x00], info, &r, &blinded, &evaluated, &proof).is_none());
+    let (evaluated, proof) = key.evaluate(&blinded, b"other info").unwrap();
+    assert!(finalize(&public_key, &[0x00], info, &r, &blinded, &evaluated, &proof).is_none());
+}
+
+/// The output depends only on the input and the server key, not on the
+/// blind: the client derives the same keys at store and at fetch time.
+#[tokio::test]
+async fn test_oprf_output_is_verifiable_and_stable() {
+    let (server, _) = oprf_server().await;
+    let input = b"argon2 output of the password";
+    let first = client_output(&server, IDENTIFIER, input, &Scalar::from(7u64)).await;
+    let second = client_output(&server, IDENTIFIER, input, &Scalar::from(11u64)).await;
+    assert_eq!(first, second);
+    let other = client_output(
+        &server,
+        IDENTIFIER,
+        b"another password",
+        &Scalar::from(7u64),
+    )
+    .await;
+    assert_ne!(first, other);
+}
+
+/// An evaluation is bound to its identifier: the same blinded element sent
+/// under another identifier is evaluated under another key, so evaluations
+/// bought with throwaway identifiers are worthless against a victim's
+/// records.
+#[tokio::test]
+async fn test_oprf_evaluation_is_bound_to_the_identifier() {
+    let (server, _) = oprf_server().await;
+    let blinded_element = hex::encode(blind(b"password", &Scalar::from(7u64)));
+    let mut evaluations = Vec::new();
+    for identifier in [IDENTIFIER, &"02".repeat(32)] {
+        let evaluation = server
+            .post("/oprf")
+            .json(&json!({
+                "identifier": identifier,
+                "blinded_element": blinded_element,
+            }))
+            .expect_success()
+            .await
+            .json::<OprfEvaluation>();
+        evaluations.push(evaluation.evaluated_element);
+    }
+    assert_ne!(evaluations[0], evaluations[1]);
+
+    let input = b"argon2 output of the password";
+    let victim = client_output(&server, IDENTIFIER, input, &Scalar::from(7u64)).await;
+    let throwaway = client_output(&server, &"03".repeat(32), input, &Scalar::from(7u64)).await;
+    assert_ne!(victim, throwaway);
+}
+
+/// Evaluations spend the identifier's candidate budget: a retried request is
+/// a free replay, a new one takes a slot, and `/fetch` sees the same budget.
+/// Malformed requests cost nothing.
+#[tokio::test]
+async fn test_oprf_shares_the_fetch_budget() {
+    let (server, state) = oprf_server().await;
+    let request = |r: u64| {
+        json!({
+            "identifier": IDENTIFIER,
+            "blinded_element": hex::encode(blind(b"password", &Scalar::from(r))),
+        })
+    };
+    for invalid in ["00".repeat(32), "ff".repeat(32), "abcd".to_string()] {
+        let response = server
+            .post("/oprf")
+            .json(&json!({ "identifier": IDENTIFIER, "blinded_element": invalid }))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST, "{invalid}");
+    }
+    let id_hash = crate::utils::identifier_hash(IDENTIFIER).unwrap();
+    assert!(!state
+        .identifier_rate_limit
+        .lock()
+        .await
+        .contains_key(&id_hash));
+
+    let first = server
+        .post("/oprf")
+        .json(&request(1))
+        .expect_success()
+        .await;
+    let replay = server
+        .post("/oprf")
+        .json(&request(1))
+        .expect_success()
+        .await;
+    assert_eq!(
+        replay
+            .json::<OprfEvaluation>()
+            .attempt_status
+            .total_attempts,
+        1
+    );
+    assert_eq!(
+        first
+            .json::<OprfEvaluation>()
+            .attempt_status
+            .failed_attempts,
+        0
+    );
+    let candidate = oprf_candidate(
+        IDENTIFIER,
+        &hex::encode(blind(b"password", &Scalar::from(1u64))),
+    );
+    assert!(
+        state.identifier_rate_limit.lock().await[&id_hash].candidates[&candidate]
+            == crate::models::CandidateState::Committed
+    );
+
+    for r in 2..=u64::from(state.rate_limit_max_attempts) {
+        server
+            .post("/oprf")
+            .json(&request(r))
+            .expect_success()
+            .await;
+    }
+    let saturated = server.post("/oprf").json(&request(100)).await;
+    assert_eq!(saturated.status_code(), StatusCode::TOO_MANY_REQUESTS);
+    let fetch = server
+        .post("/fetch")
+        .json(&json!({ "identifier": IDENTIFIER, "authentication_key": IDENTIFIER }))
+        .await;
+    assert_eq!(fetch.status_code(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+/// Without OPRF_KEY_PATH the endpoint and the `/info` key are absent.
+#[tokio::test]
+async fn test_oprf_is_disabled_by_default() {
+    let (server, _) = crate::tests::test_server::new_test_server().await;
+    let response = server
+        .post("/oprf")
+        .json(&json!({
+            "identifier": IDENTIFIER,
+            "blinded_element": hex::encode(blind(b"password", &Scalar::from(1u64))),
+        }))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+    let body = server.get("/info").expect_success().await.text();
+    assert!(!body.contains("oprf_public_key"));
+}
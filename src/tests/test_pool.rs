@@ -0,0 +1,127 @@
+use crate::{
+    database::{establish_connection, new_pool, pooled_connection, read_secret_by_id},
+    models::{FetchSecret, StoreSecret},
+    secret_store::SqliteStore,
+    tests::{
+        distinct_candidate, test_server::new_test_server, BASE64_ENCRYPTED_SECRET, SHA256_111111,
+        SHA256_222222,
+    },
+    utils::identifier_hash,
+};
+use axum::http::StatusCode;
+use diesel::{sql_query, QueryableByName, RunQueryDsl};
+use std::sync::Arc;
+
+#[derive(QueryableByName)]
+struct BusyTimeout {
+    #[diesel(sql_type = diesel::sql_types::Integer)]
+    timeout: i32,
+}
+
+/// Requests reuse the pooled connections instead of opening one each, and
+/// every pooled connection carries the per-connection pragmas.
+#[tokio::test]
+async fn test_requests_reuse_pooled_connections() {
+    let mut state = crate::env::init();
+    let secret_store = Arc::new(SqliteStore::new(
+        &state.database_url,
+        state.database_semaphore.available_permits() as u32,
+        false,
+    ));
+    let pool = secret_store.pool().clone();
+    state.secret_store = secret_store;
+    crate::database::init_db(state.clone());
+    let server = axum_test::TestServer::new(crate::router::new(state.clone())).unwrap();
+
+    server
+        .post("/store")
+        .json(&StoreSecret {
+            identifier: SHA256_111111.to_string(),
+            authentication_key: SHA256_222222.to_string(),
+            encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
+        })
+        .expect_success()
+        .await;
+    for _ in 0..20 {
+        server
+            .post("/fetch")
+            .json(&FetchSecret {
+                identifier: SHA256_111111.to_string(),
+                authentication_key: SHA256_222222.to_string(),
+                pow: None,
+            })
+            .expect_success()
+            .await;
+    }
+
+    // sequential requests: one connection serves them all
+    assert_eq!(pool.state().connections, 1);
+    let mut connection = pooled_connection(&pool).unwrap();
+    let busy_timeout = sql_query("PRAGMA busy_timeout")
+        .get_result::<BusyTimeout>(&mut connection)
+        .unwrap();
+    assert_eq!(busy_timeout.timeout, 5000);
+}
+
+/// A pool that cannot open a connection is a database error: `500`, and the
+/// attempt is refunded.
+#[tokio::test]
+async fn test_unavailable_pool_returns_500_without_consuming_attempts() {
+    let mut state = crate::env::init();
+    crate::database::init_db(state.clone());
+    // a directory cannot be opened as a SQLite file
+    state.secret_store = Arc::new(SqliteStore::new(
+        &std::env::temp_dir().to_string_lossy(),
+        1,
+        false,
+    ));
+    let server = axum_test::TestServer::new(crate::router::new(state.clone())).unwrap();
+
+    let response = server
+        .post("/fetch")
+        .json(&FetchSecret {
+            identifier: SHA256_111111.to_string(),
+            authentication_key: SHA256_222222.to_string(),
+            pow: None,
+        })
+        .await;
+    assert_eq!(response.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+    let identifier_rate_limit = state.identifier_rate_limit.lock().await;
+    assert!(!identifier_rate_limit.contains_key(&identifier_hash(SHA256_111111).unwrap()));
+}
+
+/// Benchmark: the same lookups with a fresh connection each (the former
+/// behavior) and through the pool. Wall-clock timings are not reliable on a
+/// loaded runner, so it only runs on demand:
+/// `cargo test test_pooled_lookups -- --ignored`.
+#[tokio::test]
+#[ignore = "benchmark, timing-dependent"]
+async fn test_pooled_lookups_outperform_fresh_connections() {
+    const LOOKUPS: u32 = 500;
+    let (_, state) = new_test_server().await;
+    let pool = new_pool(&state.database_url, 1);
+
+    let started = std::time::Instant::now();
+    for index in 0..LOOKUPS {
+        let mut connection = establish_connection(state.database_url.clone());
+        read_secret_by_id(&mut connection, &distinct_candidate(index as usize)).unwrap();
+    }
+    let fresh = started.elapsed();
+
+    let started = std::time::Instant::now();
+    for index in 0..LOOKUPS {
+        let mut connection = pooled_connection(&pool).unwrap();
+        read_secret_by_id(&mut connection, &distinct_candidate(index as usize)).unwrap();
+    }
+    let pooled = started.elapsed();
+
+    assert!(
+        pooled < fresh,
+        "lookups per second: fresh connection {:.0}, pooled {:.0}",
+        f64::from(LOOKUPS) / fresh.as_secs_f64(),
+        f64::from(LOOKUPS) / pooled.as_secs_f64()
+    );
+}
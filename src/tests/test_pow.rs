@@ -0,0 +1,271 @@
+use crate::{
+    models::{Challenge, FetchSecret, Info, ProofOfWork, StoreSecret},
+    pow::{
+        difficulty, issue_challenge, leading_zero_bits, meets_difficulty, solution_digest, solve,
+        verify_challenge, MAX_POW_DIFFICULTY_BITS,
+    },
+    tests::test_server::{configured_test_server, fetch_secret},
+    tests::{
+        distinct_candidate, BASE64_ENCRYPTED_SECRET, NOT_PASSWORD_HASH, SHA256_111111,
+        SHA256_222222,
+    },
+    utils::{generate_secret_id, identifier_hash},
+};
+use axum::http::StatusCode;
+
+async fn pow_server(bits: u32, step_bits: u32) -> (axum_test::TestServer, crate::AppState) {
+    configured_test_server(|state| {
+        state.pow_difficulty_bits = bits;
+        state.pow_difficulty_step_bits = step_bits;
+    })
+    .await
+}
+
+async fn solved_fetch(
+    server: &axum_test::TestServer,
+    authentication_key: &str,
+    difficulty_bits: u32,
+) -> FetchSecret {
+    let challenge = server.get("/challenge").await.json::<Challenge>().challenge;
+    let nonce = solve(
+        &challenge,
+        &identifier_hash(SHA256_111111).unwrap(),
+        &generate_secret_id(SHA256_111111, authentication_key),
+        difficulty_bits,
+    );
+    FetchSecret {
+        identifier: SHA256_111111.to_string(),
+        authentication_key: authentication_key.to_string(),
+        pow: Some(ProofOfWork { challenge, nonce }),
+    }
+}
+
+#[test]
+fn test_leading_zero_bits() {
+    assert_eq!(leading_zero_bits(&[0xff]), 0);
+    assert_eq!(leading_zero_bits(&[0x0f, 0xff]), 4);
+    assert_eq!(leading_zero_bits(&[0x00, 0x01]), 15);
+    assert_eq!(leading_zero_bits(&[0x00, 0x00]), 16);
+}
+
+#[test]
+fn test_difficulty_escalates_per_candidate_and_is_capped() {
+    assert_eq!(difficulty(10, 2, 0), 10);
+    assert_eq!(difficulty(10, 2, 1), 12);
+    assert_eq!(difficulty(10, 2, 3), 16);
+    assert_eq!(difficulty(32, 8, u8::MAX), MAX_POW_DIFFICULTY_BITS);
+}
+
+#[test]
+fn test_challenge_is_authenticated_and_expires() {
+    let key = [7u8; 32];
+    let now = chrono::Utc::now();
+    let lifetime = chrono::Duration::seconds(300);
+    let proof = ProofOfWork {
+        challenge: issue_challenge(&key, now),
+        nonce: "0".to_string(),
+    };
+    assert!(verify_challenge(&key, &proof, lifetime, now));
+
+    // another server key (or a restart) invalidates the challenge
+    assert!(!verify_challenge(&[8u8; 32], &proof, lifetime, now));
+
+    // expired
+    assert!(!verify_challenge(
+        &key,
+        &proof,
+        lifetime,
+        now + chrono::Duration::seconds(301)
+    ));
+
+    // a tampered issue time does not match the MAC
+    let (_, mac) = proof.challenge.split_once('.').unwrap();
+    let forged = ProofOfWork {
+        challenge: format!("{}.{mac}", now.timestamp() + 1000),
+        nonce: "0".to_string(),
+    };
+    assert!(!verify_challenge(
+        &key,
+        &forged,
+        lifetime,
+        now + chrono::Duration::seconds(1000)
+    ));
+
+    // unbounded nonces are refused before hashing
+    let long_nonce = ProofOfWork {
+        challenge: proof.challenge.clone(),
+        nonce: "0".repeat(65),
+    };
+    assert!(!verify_challenge(&key, &long_nonce, lifetime, now));
+}
+
+/// A solution is bound to both the identifier bucket and the candidate: it
+/// cannot be replayed to pay for another guess.
+#[test]
+fn test_solution_is_bound_to_identifier_and_candidate() {
+    let challenge = issue_challenge(&[7u8; 32], chrono::Utc::now());
+    let id_hash = identifier_hash(SHA256_111111).unwrap();
+    let candidate = generate_secret_id(SHA256_111111, SHA256_222222);
+    let nonce = solve(&challenge, &id_hash, &candidate, 12);
+    let proof = ProofOfWork {
+        challenge: challenge.clone(),
+        nonce: nonce.clone(),
+    };
+    assert!(meets_difficulty(&proof, &id_hash, &candidate, 12));
+
+    let other_candidate = generate_secret_id(SHA256_111111, NOT_PASSWORD_HASH);
+    let other_id_hash = identifier_hash(SHA256_222222).unwrap();
+    // 12 bits: a coincidental pass has probability 1/4096 per check, and the
+    // inputs are fixed, so the outcome is deterministic for this vector
+    assert!(
+        leading_zero_bits(&solution_digest(
+            &challenge,
+            &id_hash,
+            &other_candidate,
+            &nonce
+        )) < 12
+            || leading_zero_bits(&solution_digest(
+                &challenge,
+                &other_id_hash,
+                &candidate,
+                &nonce
+            )) < 12
+    );
+}
+
+#[tokio::test]
+async fn test_challenge_and_info_advertise_difficulty() {
+    let (server, state) = pow_server(6, 3).await;
+
+    let challenge = server
+        .get("/challenge")
+        .expect_success()
+        .await
+        .json::<Challenge>();
+    assert_eq!(challenge.difficulty_bits, 6);
+    assert_eq!(challenge.difficulty_step_bits, 3);
+    assert!(challenge.expires_at > chrono::Utc::now());
+    assert!(challenge.expires_at <= chrono::Utc::now() + state.pow_challenge_lifetime);
+
+    let info = server.get("/info").expect_success().await.json::<Info>();
+    assert_eq!(info.pow_difficulty_bits, 6);
+    assert_eq!(info.pow_difficulty_step_bits, 3);
+    assert_eq!(
+        info.pow_challenge_lifetime_seconds,
+        state.pow_challenge_lifetime.num_seconds() as u64
+    );
+}
+
+/// Without a solution, a request is rejected before any candidate slot is
+/// reserved, and the response states the required difficulty.
+#[tokio::test]
+async fn test_missing_pow_is_rejected_without_consuming_budget() {
+    let (server, state) = pow_server(4, 2).await;
+
+    let response = server
+        .post("/fetch")
+        .json(&fetch_secret(NOT_PASSWORD_HASH))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        response.json::<serde_json::Value>()["pow_difficulty_bits"],
+        4
+    );
+
+    let identifier_rate_limit = state.identifier_rate_limit.lock().await;
+    let candidates = identifier_rate_limit
+        .get(&identifier_hash(SHA256_111111).unwrap())
+        .map(|info| info.candidate_count())
+        .unwrap_or(0);
+    assert_eq!(candidates, 0, "a rejected proof must not reserve a slot");
+}
+
+/// Every admitted distinct candidate raises the difficulty of the next one.
+#[tokio::test]
+async fn test_difficulty_escalates_with_candidate_count() {
+    let (server, _) = pow_server(4, 2).await;
+
+    let first = solved_fetch(&server, &distinct_candidate(0), 4).await;
+    let response = server.post("/fetch").json(&first).await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+
+    // a proof for exactly 4 bits (not 6) is no longer enough
+    let challenge = server.get("/challenge").await.json::<Challenge>().challenge;
+    let id_hash = identifier_hash(SHA256_111111).unwrap();
+    let candidate = generate_secret_id(SHA256_111111, &distinct_candidate(1));
+    let weak_nonce = (0u64..)
+        .map(|nonce| nonce.to_string())
+        .find(|nonce| {
+            let bits = leading_zero_bits(&solution_digest(&challenge, &id_hash, &candidate, nonce));
+            (4..6).contains(&bits)
+        })
+        .unwrap();
+    let response = server
+        .post("/fetch")
+        .json(&FetchSecret {
+            identifier: SHA256_111111.to_string(),
+            authentication_key: distinct_candidate(1),
+            pow: Some(ProofOfWork {
+                challenge,
+                nonce: weak_nonce,
+            }),
+        })
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    assert_eq!(
+        response.json::<serde_json::Value>()["pow_difficulty_bits"],
+        6
+    );
+
+    let second = solved_fetch(&server, &distinct_candidate(1), 6).await;
+    let response = server.post("/fetch").json(&second).await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+}
+
+/// Replays need a proof too: exempting known candidates would reveal, for
+/// free, whether a candidate was already tried in the window.
+#[tokio::test]
+async fn test_replay_of_known_candidate_requires_pow() {
+    let (server, _) = pow_server(4, 0).await;
+    server
+        .post("/store")
+        .json(&StoreSecret {
+            identifier: SHA256_111111.to_string(),
+            authentication_key: SHA256_222222.to_string(),
+            encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
+        })
+        .expect_success()
+        .await;
+
+    let fetch = solved_fetch(&server, SHA256_222222, 4).await;
+    server.post("/fetch").json(&fetch).expect_success().await;
+
+    let replay = server
+        .post("/fetch")
+        .json(&fetch_secret(SHA256_222222))
+        .await;
+    assert_eq!(replay.status_code(), StatusCode::BAD_REQUEST);
+
+    // the same solution stays valid for the same candidate within its lifetime
+    server.post("/fetch").json(&fetch).expect_success().await;
+}
+
+#[tokio::test]
+async fn test_forged_challenge_is_rejected() {
+    let (server, _) = pow_server(1, 0).await;
+    let response = server
+        .post("/trash")
+        .json(&FetchSecret {
+            identifier: SHA256_111111.to_string(),
+            authentication_key: SHA256_222222.to_string(),
+            pow: Some(ProofOfWork {
+                challenge: format!("{}.{}", chrono::Utc::now().timestamp(), "00".repeat(32)),
+                nonce: "0".to_string(),
+            }),
+        })
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+}
@@ -81,6 +81,7 @@ async fn test_fetch_expires_sub_threshold_entry_after_cooldown() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .expect_failure()
         .await;
@@ -102,6 +103,7 @@ async fn test_new_identifiers_fail_closed_when_rate_limit_map_is_full() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .await;
     assert_eq!(first.status_code(), StatusCode::UNAUTHORIZED);
@@ -111,6 +113,7 @@ async fn test_new_identifiers_fail_closed_when_rate_limit_map_is_full() {
         .json(&FetchSecret {
             identifier: SHA256_222222.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .await;
     assert_eq!(second.status_code(), StatusCode::SERVICE_UNAVAILABLE);
@@ -128,6 +131,7 @@ async fn test_database_concurrency_rejection_refunds_lookup_attempt() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: NOT_PASSWORD_HASH.to_string(),
+            pow: None,
         })
         .await;
     assert_eq!(response.status_code(), StatusCode::SERVICE_UNAVAILABLE);
@@ -159,6 +163,7 @@ async fn test_cancelled_request_does_not_consume_an_attempt() {
     let request = FetchSecret {
         identifier: SHA256_111111.to_string(),
         authentication_key: NOT_PASSWORD_HASH.to_string(),
+        pow: None,
     };
 
     // Call the handler directly (bypassing the HTTP server) so cancellation
@@ -209,6 +214,9 @@ async fn test_cancelled_trash_after_sqlite_start_keeps_attempt_reserved() {
         identifier: SHA256_111111.to_string(),
         authentication_key: SHA256_222222.to_string(),
         encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+        ttl_days: None,
+        share_index: None,
+        federation_id: None,
     };
     server.post("/store").json(&store).expect_success().await;
 
@@ -220,6 +228,7 @@ async fn test_cancelled_trash_after_sqlite_start_keeps_attempt_reserved() {
     let request = FetchSecret {
         identifier: SHA256_111111.to_string(),
         authentication_key: SHA256_222222.to_string(),
+        pow: None,
     };
     let outcome = tokio::time::timeout(
         std::time::Duration::from_millis(100),
@@ -283,6 +292,7 @@ async fn test_concurrent_cancellation_refunds_every_reservation() {
     let request = || FetchSecret {
         identifier: SHA256_111111.to_string(),
         authentication_key: NOT_PASSWORD_HASH.to_string(),
+        pow: None,
     };
 
     let mut handles = Vec::new();
@@ -358,6 +368,7 @@ async fn test_deferred_refund_runs_when_drop_finds_the_lock_contended() {
     let request = FetchSecret {
         identifier: SHA256_111111.to_string(),
         authentication_key: NOT_PASSWORD_HASH.to_string(),
+        pow: None,
     };
     let handler_state = state.clone();
     let handle = tokio::spawn(async move {
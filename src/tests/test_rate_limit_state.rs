@@ -0,0 +1,214 @@
+use std::sync::Arc;
+
+use crate::{
+    env::unique_test_database,
+    models::{CandidateState, RateLimitInfo, StoreSecret},
+    rate_limit_state::{establish_connection, flush, restore, save, StateFile},
+    tests::test_server::{configured_test_server, fetch_secret},
+    tests::{distinct_candidate, BASE64_ENCRYPTED_SECRET, SHA256_111111, SHA256_222222},
+    utils::{generate_secret_id, identifier_hash},
+};
+use axum::http::StatusCode;
+
+/// A server whose rate-limit state is restored from, and written to,
+/// `state_url`, as `env::init` does when RATE_LIMIT_STATE_URL is set.
+async fn persistent_server(state_url: &str) -> (axum_test::TestServer, crate::AppState) {
+    configured_test_server(|state| {
+        let loaded = restore(
+            state_url,
+            chrono::Utc::now(),
+            state.rate_limit_cooldown,
+            state.rate_limit_max_identifiers,
+        )
+        .unwrap();
+        state.identifier_rate_limit = Arc::new(tokio::sync::Mutex::new(loaded.windows));
+        state.attempts_collection_started_at = loaded.collection_started_at;
+        state.rate_limit_state = Some(Arc::new(StateFile::new(state_url.to_string())));
+    })
+    .await
+}
+
+/// A restart no longer refunds the guessed candidates: the budget spent
+/// before the restart still applies after it.
+#[tokio::test]
+async fn test_restart_keeps_spent_candidates() {
+    let (state_url, _guard) = unique_test_database();
+    let (server, state) = persistent_server(&state_url).await;
+    for index in 0..3 {
+        let response = server
+            .post("/fetch")
+            .json(&fetch_secret(&distinct_candidate(index)))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+    }
+    flush(&state).await;
+    drop(server);
+
+    let (server, state) = persistent_server(&state_url).await;
+    {
+        let identifier_rate_limit = state.identifier_rate_limit.lock().await;
+        let info = identifier_rate_limit
+            .get(&identifier_hash(SHA256_111111).unwrap())
+            .expect("the window must be restored");
+        assert_eq!(info.candidate_count(), 3);
+        assert_eq!(info.failed_candidates, 3);
+        assert_eq!(info.total_requests, 3);
+        assert!(info
+            .candidates
+            .values()
+            .all(|candidate| *candidate == CandidateState::Committed));
+    }
+    let response = server
+        .post("/fetch")
+        .json(&fetch_secret(&distinct_candidate(3)))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+/// A candidate tried before the restart is still known after it: its
+/// replay is the free `Committed` replay, not a new guess.
+#[tokio::test]
+async fn test_replay_after_restart_is_not_charged_again() {
+    let (state_url, _guard) = unique_test_database();
+    let (server, state) = persistent_server(&state_url).await;
+    for index in 0..2 {
+        server
+            .post("/fetch")
+            .json(&fetch_secret(&distinct_candidate(index)))
+            .expect_failure()
+            .await;
+    }
+    flush(&state).await;
+    drop(server);
+
+    let (server, state) = persistent_server(&state_url).await;
+    let response = server
+        .post("/fetch")
+        .json(&fetch_secret(&distinct_candidate(0)))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+    let identifier_rate_limit = state.identifier_rate_limit.lock().await;
+    let info = &identifier_rate_limit[&identifier_hash(SHA256_111111).unwrap()];
+    assert_eq!(info.candidate_count(), 2);
+    assert_eq!(info.failed_candidates, 2);
+}
+
+/// An admitted candidate is on disk before its lookup runs: a crash before
+/// the next periodic flush does not refund it.
+#[tokio::test]
+async fn test_new_candidate_is_written_through_before_the_lookup() {
+    let (state_url, _guard) = unique_test_database();
+    let (server, state) = persistent_server(&state_url).await;
+    server
+        .post("/store")
+        .json(&StoreSecret {
+            identifier: SHA256_111111.to_string(),
+            authentication_key: SHA256_222222.to_string(),
+            encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
+        })
+        .expect_success()
+        .await;
+    server
+        .post("/fetch")
+        .json(&fetch_secret(SHA256_222222))
+        .expect_success()
+        .await;
+
+    // no flush: only the write-through reached the file
+    let loaded = restore(
+        &state_url,
+        chrono::Utc::now(),
+        state.rate_limit_cooldown,
+        state.rate_limit_max_identifiers,
+    )
+    .unwrap();
+    let info = loaded
+        .windows
+        .get(&identifier_hash(SHA256_111111).unwrap())
+        .expect("the window must be written through");
+    assert!(info
+        .candidates
+        .contains_key(&generate_secret_id(SHA256_111111, SHA256_222222)));
+}
+
+/// Restored state follows the same expiry as the in-memory map, and the
+/// collection start survives the restart with the counters it describes.
+#[tokio::test]
+async fn test_expired_windows_are_dropped_and_collection_start_is_kept() {
+    let (state_url, _guard) = unique_test_database();
+    let now = chrono::Utc::now();
+    let cooldown = chrono::Duration::minutes(60);
+    let collection_started_at = now - chrono::Duration::days(3);
+    let mut expired = RateLimitInfo::new(now - chrono::Duration::minutes(61));
+    expired
+        .candidates
+        .insert(distinct_candidate(0), CandidateState::Committed);
+    let mut live = RateLimitInfo::new(now - chrono::Duration::minutes(59));
+    live.candidates
+        .insert(distinct_candidate(1), CandidateState::Pending);
+    let windows = [("expired".to_string(), expired), ("live".to_string(), live)]
+        .into_iter()
+        .collect();
+    restore(&state_url, now, cooldown, 10).unwrap();
+    save(
+        &mut establish_connection(&state_url).unwrap(),
+        &windows,
+        collection_started_at,
+    )
+    .unwrap();
+
+    let loaded = restore(&state_url, now, cooldown, 10).unwrap();
+    assert!(!loaded.windows.contains_key("expired"));
+    assert!(
+        loaded.windows["live"]
+            .candidates
+            .get(&distinct_candidate(1))
+            == Some(&CandidateState::Committed),
+        "an interrupted admission keeps consuming budget"
+    );
+    assert_eq!(loaded.collection_started_at, collection_started_at);
+}
+
+/// The file holds `id_hash` buckets and derived CandidateTags only.
+#[tokio::test]
+async fn test_state_file_never_holds_raw_identifiers_or_keys() {
+    let (state_url, _guard) = unique_test_database();
+    let (server, state) = persistent_server(&state_url).await;
+    server
+        .post("/fetch")
+        .json(&fetch_secret(SHA256_222222))
+        .expect_failure()
+        .await;
+    flush(&state).await;
+
+    let mut contents = std::fs::read(&state_url).unwrap();
+    contents.extend(std::fs::read(format!("{state_url}-wal")).unwrap_or_default());
+    let contents = String::from_utf8_lossy(&contents);
+    assert!(contents.contains(&identifier_hash(SHA256_111111).unwrap()));
+    assert!(!contents.contains(SHA256_111111));
+    assert!(!contents.contains(SHA256_222222));
+}
+
+/// A reservation that cannot reach the state file is not looked up: the
+/// request fails without consuming the attempt.
+#[tokio::test]
+async fn test_failed_write_through_skips_the_lookup() {
+    let mut state = crate::env::init();
+    // a directory cannot be opened as a SQLite file
+    state.rate_limit_state = Some(Arc::new(StateFile::new(
+        std::env::temp_dir().to_string_lossy().into_owned(),
+    )));
+    crate::database::init_db(state.clone());
+    let server = axum_test::TestServer::new(crate::router::new(state.clone())).unwrap();
+
+    let response = server
+        .post("/fetch")
+        .json(&fetch_secret(SHA256_222222))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+    let identifier_rate_limit = state.identifier_rate_limit.lock().await;
+    assert!(!identifier_rate_limit.contains_key(&identifier_hash(SHA256_111111).unwrap()));
+}
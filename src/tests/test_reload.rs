@@ -0,0 +1,173 @@
+use std::sync::{atomic::Ordering, Arc};
+
+use crate::{
+    config::ConfigSources,
+    models::RateLimitInfo,
+    tests::{SHA256_111111, SHA256_222222},
+    utils::identifier_hash,
+};
+
+/// A temporary `.env` or CONFIG_FILE, removed on drop.
+struct TempFile(std::path::PathBuf);
+
+impl TempFile {
+    fn new(name: &str, contents: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "keychain-test-{}-{name}",
+            crate::env::unique_test_suffix().replace('_', "-")
+        ));
+        std::fs::write(&path, contents).unwrap();
+        Self(path)
+    }
+
+    fn write(&self, contents: &str) {
+        std::fs::write(&self.0, contents).unwrap();
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// The restart-only values keep coming from the test environment, as at
+/// `env::init`; everything else is left to the files.
+fn sources(config_file: Option<&TempFile>, dotenv: Option<&TempFile>) -> Arc<ConfigSources> {
+    Arc::new(ConfigSources {
+        process_variables: [
+            "SERVER_ADDRESS",
+            "RATE_LIMIT_COOLDOWN",
+            "RATE_LIMIT_MAX_ATTEMPTS",
+            "RATE_LIMIT_MAX_IDENTIFIERS",
+            "DATABASE_URL",
+            "DATABASE_MAX_CONCURRENCY",
+            "CANARY",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect(),
+        dotenv_path: dotenv.map(|file| file.0.clone()),
+        config_file: config_file.map(|file| file.0.clone()),
+    })
+}
+
+#[tokio::test]
+async fn test_reload_swaps_limits_and_keeps_rate_limit_windows() {
+    let (server, mut state) = crate::tests::test_server::new_test_server().await;
+    let id_hash = identifier_hash(SHA256_111111).unwrap();
+    let mut window = RateLimitInfo::new(chrono::Utc::now());
+    window.failed_candidates = 2;
+    window.total_requests = 2;
+    state
+        .identifier_rate_limit
+        .lock()
+        .await
+        .insert(id_hash.clone(), window);
+
+    let config_file = TempFile::new(
+        "config.toml",
+        "[server]\nsecret_max_length = 64\n\n\
+         [buckets.store]\nburst = 2.0\nrefill_per_second = 0.0\n\n\
+         [buckets.lookup]\nburst = 10000.0\nrefill_per_second = 10000.0\n\n\
+         [telemetry]\nsnapshot_ttl_seconds = 5\n",
+    );
+    state.config_sources = sources(Some(&config_file), None);
+
+    let mut changed = crate::reload::reload(&state).await.unwrap();
+    changed.sort_unstable();
+    assert_eq!(
+        changed,
+        [
+            "buckets.store",
+            "server.secret_max_length",
+            "telemetry.snapshot_ttl_seconds"
+        ]
+    );
+    assert_eq!(state.secret_max_length.load(Ordering::Relaxed), 64);
+    assert_eq!(
+        state.attempts_snapshot_ttl_seconds.load(Ordering::Relaxed),
+        5
+    );
+
+    // The store bucket shrank from 10000 to 2 tokens, without refill; the
+    // length check runs first and consumes none.
+    let store = |length: usize| {
+        server.post("/store").json(&crate::models::StoreSecret {
+            identifier: SHA256_111111.to_string(),
+            authentication_key: SHA256_222222.to_string(),
+            encrypted_secret: "A".repeat(length),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
+        })
+    };
+    assert_eq!(store(68).await.status_code(), 400);
+    assert_eq!(store(64).await.status_code(), 201);
+    assert_eq!(store(64).await.status_code(), 201);
+    assert_eq!(store(64).await.status_code(), 503);
+
+    let identifier_rate_limit = state.identifier_rate_limit.lock().await;
+    let window = &identifier_rate_limit[&id_hash];
+    assert_eq!(
+        (window.failed_candidates, window.total_requests),
+        (2, 2),
+        "a reload must not touch in-flight windows"
+    );
+}
+
+#[tokio::test]
+async fn test_reload_refuses_restart_only_changes_as_a_whole() {
+    let (_, mut state) = crate::tests::test_server::new_test_server().await;
+    let config_file = TempFile::new(
+        "config.toml",
+        "[server]\nsecret_max_length = 64\n\n[telemetry]\nsnapshot_ttl_seconds = 5\n",
+    );
+    let mut restart_only = sources(Some(&config_file), None);
+    Arc::get_mut(&mut restart_only)
+        .unwrap()
+        .process_variables
+        .retain(|name| name != "RATE_LIMIT_MAX_ATTEMPTS" && name != "RATE_LIMIT_COOLDOWN");
+    config_file.write(
+        "[server]\nsecret_max_length = 64\n\n\
+         [rate_limit]\nmax_attempts = 10\ncooldown_minutes = 2\n",
+    );
+    state.config_sources = restart_only;
+
+    let reason = crate::reload::reload(&state).await.unwrap_err();
+    assert_eq!(
+        reason,
+        "rate_limit.cooldown_minutes, rate_limit.max_attempts cannot change without a restart"
+    );
+    assert_eq!(state.secret_max_length.load(Ordering::Relaxed), 128);
+
+    // Invalid values are refused like at startup.
+    config_file.write("[server]\nsecret_max_length = 64\n\n[buckets.lookup]\nburst = 0.0\n");
+    state.config_sources = sources(Some(&config_file), None);
+    let reason = crate::reload::reload(&state).await.unwrap_err();
+    assert!(reason.contains("LOOKUP_RATE_LIMIT_BURST"), "{reason}");
+    assert_eq!(
+        state.attempts_snapshot_ttl_seconds.load(Ordering::Relaxed),
+        60
+    );
+}
+
+/// `.env` is read again: the running process environment cannot change, but
+/// the file can.
+#[tokio::test]
+async fn test_reload_reads_the_dotenv_file_again() {
+    let (_, mut state) = crate::tests::test_server::new_test_server().await;
+    let dotenv = TempFile::new(".env", "SECRET_MAX_LENGTH=128\n");
+    state.config_sources = sources(None, Some(&dotenv));
+    crate::reload::reload(&state).await.unwrap();
+    assert_eq!(state.secret_max_length.load(Ordering::Relaxed), 128);
+
+    dotenv.write("SECRET_MAX_LENGTH=256\nATTEMPTS_SNAPSHOT_TTL_SECONDS=10\n");
+    let mut changed = crate::reload::reload(&state).await.unwrap();
+    changed.sort_unstable();
+    assert_eq!(
+        changed,
+        ["server.secret_max_length", "telemetry.snapshot_ttl_seconds"]
+    );
+    assert_eq!(state.secret_max_length.load(Ordering::Relaxed), 256);
+}
@@ -0,0 +1,416 @@
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use diesel::{QueryDsl, RunQueryDsl};
+
+use crate::models::{FetchSecret, RotateSecret, StoreSecret};
+use crate::replication::{ship, Replication, SIGNATURE_HEADER};
+use crate::tests::test_server::{spawn_listener, SpawnedServer};
+use crate::tests::{distinct_candidate, BASE64_ENCRYPTED_SECRET, SHA256_222222};
+
+const KEY: [u8; 32] = [0x5a; 32];
+
+struct Instance {
+    state: crate::AppState,
+    /// The public router.
+    server: axum_test::TestServer,
+    /// The replication router, also served on `listener` for the peer.
+    replication: axum_test::TestServer,
+    listener: SpawnedServer,
+}
+
+/// A replicating server, its replication router on a loopback listener. Its
+/// own peer URL is a placeholder: each side must be listening before the
+/// other can name it, so `ship_to` sets the peer when shipping.
+async fn instance(trash_grace_hours: i64) -> Instance {
+    let mut state = crate::env::init();
+    let max_connections = state.database_semaphore.available_permits() as u32;
+    state.secret_store =
+        crate::secret_store::new_secret_store(&state.database_url, max_connections, true).unwrap();
+    state.replication = Some(Arc::new(
+        Replication::new("http://127.0.0.1:9", KEY).unwrap(),
+    ));
+    state.trash_grace_period = chrono::Duration::hours(trash_grace_hours);
+    crate::database::init_db(state.clone());
+    let replication_app = crate::router::replication(state.clone());
+    let listener = spawn_listener(replication_app.clone(), "127.0.0.1:0").await;
+    let server = axum_test::TestServer::new(crate::router::new(state.clone())).unwrap();
+    let replication = axum_test::TestServer::new(replication_app).unwrap();
+    Instance {
+        state,
+        server,
+        replication,
+        listener,
+    }
+}
+
+async fn ship_to(from: &Instance, to: &Instance) -> usize {
+    let mut state = from.state.clone();
+    let peer_url = format!("http://{}", to.listener.address);
+    state.replication = Some(Arc::new(Replication::new(&peer_url, KEY).unwrap()));
+    ship(&state).await.unwrap()
+}
+
+fn store(index: usize) -> StoreSecret {
+    StoreSecret {
+        identifier: distinct_candidate(index),
+        authentication_key: SHA256_222222.to_string(),
+        encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+        ttl_days: None,
+        share_index: None,
+        federation_id: None,
+    }
+}
+
+fn fetch(index: usize) -> FetchSecret {
+    FetchSecret {
+        identifier: distinct_candidate(index),
+        authentication_key: SHA256_222222.to_string(),
+        pow: None,
+    }
+}
+
+/// A batch body sent at `sent_at`, with `entries` as JSON.
+fn signed_body(sent_at: chrono::DateTime<chrono::Utc>, entries: &str) -> String {
+    format!(
+        r#"{{"sent_at":"{}","entries":{entries}}}"#,
+        crate::database::replication_timestamp(sent_at)
+    )
+}
+
+/// Every column of every record, ordered by id.
+fn rows(state: &crate::AppState) -> Vec<(String, String, String, Option<String>)> {
+    use crate::schema::secret::dsl::*;
+    let mut connection = crate::database::establish_connection(state.database_url.clone());
+    secret
+        .select((id, created_at, encrypted_secret, trash_after))
+        .order(id)
+        .load(&mut connection)
+        .unwrap()
+}
+
+/// Two loopback instances written to concurrently converge: stores, a
+/// rotation and trashes on either side reach the other, and a record
+/// stored on both then trashed on one is not brought back by the other's
+/// copy still in its log.
+#[tokio::test]
+async fn test_two_instances_converge() {
+    let a = instance(0).await;
+    let b = instance(0).await;
+
+    a.server
+        .post("/store")
+        .json(&store(1))
+        .expect_success()
+        .await;
+    b.server
+        .post("/store")
+        .json(&store(2))
+        .expect_success()
+        .await;
+    for instance in [&a, &b] {
+        instance
+            .server
+            .post("/store")
+            .json(&store(3))
+            .expect_success()
+            .await;
+    }
+    a.server
+        .post("/store")
+        .json(&store(4))
+        .expect_success()
+        .await;
+    a.server
+        .post("/rotate")
+        .json(&RotateSecret {
+            identifier: distinct_candidate(4),
+            authentication_key: SHA256_222222.to_string(),
+            pow: None,
+            new_secret: store(5),
+        })
+        .expect_success()
+        .await;
+    b.server
+        .post("/trash")
+        .json(&fetch(3))
+        .expect_success()
+        .await;
+
+    // B's tombstone reaches A first; A's write of record 3 comes after.
+    assert_eq!(ship_to(&b, &a).await, 3);
+    assert_eq!(ship_to(&a, &b).await, 5);
+    // What a peer applied is not shipped back.
+    assert_eq!(ship_to(&b, &a).await, 0);
+    assert_eq!(ship_to(&a, &b).await, 0);
+
+    let converged = rows(&a.state);
+    assert_eq!(converged, rows(&b.state));
+    let ids: Vec<String> = converged.into_iter().map(|row| row.0).collect();
+    let mut expected: Vec<String> = [1, 2, 5]
+        .into_iter()
+        .map(|index| crate::utils::generate_secret_id(&distinct_candidate(index), SHA256_222222))
+        .collect();
+    expected.sort();
+    assert_eq!(ids, expected);
+    for instance in [&a, &b] {
+        let response = instance.server.post("/fetch").json(&fetch(3)).await;
+        assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+    }
+}
+
+/// A delayed `/trash` replicates when the purge deletes the record, not
+/// when it is marked: a `/restore` in between leaves the peer untouched.
+#[tokio::test]
+async fn test_delayed_trash_replicates_on_purge() {
+    let a = instance(24).await;
+    let b = instance(24).await;
+    a.server
+        .post("/store")
+        .json(&store(1))
+        .expect_success()
+        .await;
+    a.server
+        .post("/trash")
+        .json(&fetch(1))
+        .expect_success()
+        .await;
+    assert_eq!(ship_to(&a, &b).await, 1);
+    assert_eq!(rows(&b.state).len(), 1);
+    assert!(rows(&b.state)[0].3.is_none());
+
+    let mut connection = crate::database::establish_connection(a.state.database_url.clone());
+    diesel::sql_query("UPDATE secret SET trash_after = '2000-01-01T00:00:00Z'")
+        .execute(&mut connection)
+        .unwrap();
+    crate::retention::purge(&a.state).await;
+    assert_eq!(ship_to(&a, &b).await, 1);
+    assert!(rows(&a.state).is_empty());
+    assert!(rows(&b.state).is_empty());
+}
+
+/// `keychain-admin purge` deletes through the same log as `/trash`: the
+/// peer purges the same records.
+#[tokio::test]
+async fn test_admin_purge_replicates() {
+    let a = instance(0).await;
+    let b = instance(0).await;
+    for index in [1, 2] {
+        a.server
+            .post("/store")
+            .json(&store(index))
+            .expect_success()
+            .await;
+    }
+    assert_eq!(ship_to(&a, &b).await, 2);
+
+    let mut connection = crate::database::establish_connection(a.state.database_url.clone());
+    let purged = rows(&a.state)[0].0.clone();
+    diesel::sql_query("UPDATE secret SET created_at = '2000-01-01T00:00:00+00:00' WHERE id = ?")
+        .bind::<diesel::sql_types::Text, _>(&purged)
+        .execute(&mut connection)
+        .unwrap();
+    let report = crate::admin::execute(
+        &mut connection,
+        &crate::admin::Command::Purge {
+            older_than_days: 30,
+        },
+        true,
+    )
+    .unwrap();
+    assert_eq!(report, "secrets purged: 1\n");
+    assert_eq!(ship_to(&a, &b).await, 1);
+    assert_eq!(rows(&a.state), rows(&b.state));
+    assert!(rows(&b.state).iter().all(|row| row.0 != purged));
+}
+
+/// A batch must carry the shared key's signature, and is received on the
+/// replication listener only: the public router does not serve
+/// `/replication`, nor does a server without a peer.
+#[tokio::test]
+async fn test_replication_requires_a_signed_batch() {
+    let a = instance(0).await;
+    let body = &signed_body(chrono::Utc::now(), "[]");
+    let response = a
+        .replication
+        .post("/replication")
+        .add_header(SIGNATURE_HEADER, "00".repeat(32))
+        .text(body)
+        .await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+    let response = a.replication.post("/replication").text(body).await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+
+    let other_key = Replication::new("http://127.0.0.1:9", [0x5b; 32]).unwrap();
+    let response = a
+        .replication
+        .post("/replication")
+        .add_header(SIGNATURE_HEADER, other_key.sign(body.as_bytes()))
+        .text(body)
+        .await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+
+    let key = Replication::new("http://127.0.0.1:9", KEY).unwrap();
+    let response = a
+        .replication
+        .post("/replication")
+        .add_header(SIGNATURE_HEADER, key.sign(body.as_bytes()))
+        .text(body)
+        .await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    let malformed = &signed_body(
+        chrono::Utc::now(),
+        r#"[{"tombstone":{"id":"x","deleted_at":"now"}}]"#,
+    );
+    let response = a
+        .replication
+        .post("/replication")
+        .add_header(SIGNATURE_HEADER, key.sign(malformed.as_bytes()))
+        .text(malformed)
+        .await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+
+    let response = a
+        .server
+        .post("/replication")
+        .add_header(SIGNATURE_HEADER, key.sign(body.as_bytes()))
+        .text(body)
+        .await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+    let (_, state) = crate::tests::test_server::new_test_server().await;
+    let response = axum_test::TestServer::new(crate::router::replication(state))
+        .unwrap()
+        .post("/replication")
+        .add_header(SIGNATURE_HEADER, key.sign(body.as_bytes()))
+        .text(body)
+        .await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+}
+
+/// A signed batch still gets the checks of `/store` on its records, length
+/// before base64, and at most `BATCH_ENTRIES` entries; the listener is
+/// token-bucketed.
+#[tokio::test]
+async fn test_replication_batch_is_bounded() {
+    let a = instance(0).await;
+    let key = Replication::new("http://127.0.0.1:9", KEY).unwrap();
+    let post = |body: String| {
+        a.replication
+            .post("/replication")
+            .add_header(SIGNATURE_HEADER, key.sign(body.as_bytes()))
+            .text(&body)
+    };
+    let write = |encrypted_secret: &str| {
+        let record = crate::models::Secret {
+            id: crate::utils::generate_secret_id(&distinct_candidate(1), SHA256_222222),
+            created_at: crate::database::replication_timestamp(chrono::Utc::now()),
+            encrypted_secret: encrypted_secret.to_string(),
+            trash_after: None,
+            expires_at: None,
+            share_index: None,
+            federation_id: None,
+        };
+        format!(r#"{{"write":{}}}"#, serde_json::to_string(&record).unwrap())
+    };
+    let secret_max_length = a
+        .state
+        .secret_max_length
+        .load(std::sync::atomic::Ordering::Relaxed);
+
+    let oversized = format!("[{}]", write(&"A".repeat(secret_max_length + 4)));
+    let response = post(signed_body(chrono::Utc::now(), &oversized)).await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    let not_base64 = format!("[{}]", write("not base64!"));
+    let response = post(signed_body(chrono::Utc::now(), &not_base64)).await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    let tombstone = format!(
+        r#"{{"tombstone":{{"id":"{}","deleted_at":"{}"}}}}"#,
+        "a".repeat(64),
+        crate::database::replication_timestamp(chrono::Utc::now())
+    );
+    let too_many = format!("[{}]", vec![tombstone; 101].join(","));
+    let response = post(signed_body(chrono::Utc::now(), &too_many)).await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    assert!(rows(&a.state).is_empty());
+
+    let valid = format!("[{}]", write(BASE64_ENCRYPTED_SECRET));
+    let response = post(signed_body(chrono::Utc::now(), &valid)).await;
+    assert_eq!(response.status_code(), StatusCode::OK);
+    assert_eq!(rows(&a.state).len(), 1);
+
+    let mut refused = false;
+    for _ in 0..crate::replication::RECEIVE_BURST as usize {
+        let response = post(signed_body(chrono::Utc::now(), "[]")).await;
+        if response.status_code() == StatusCode::SERVICE_UNAVAILABLE {
+            refused = true;
+            break;
+        }
+    }
+    assert!(refused);
+}
+
+/// A captured batch cannot be replayed: not after a newer batch was
+/// applied, which would otherwise bring back a trashed record once its
+/// tombstone is pruned, and not past `BATCH_MAX_AGE` on a receiver that
+/// never applied one.
+#[tokio::test]
+async fn test_replayed_batch_does_not_resurrect_a_trashed_record() {
+    let a = instance(0).await;
+    let b = instance(0).await;
+    a.server
+        .post("/store")
+        .json(&store(1))
+        .expect_success()
+        .await;
+    let mut connection = crate::database::establish_connection(a.state.database_url.clone());
+    let record = crate::schema::secret::table
+        .first::<crate::models::Secret>(&mut connection)
+        .unwrap();
+    let key = Replication::new("http://127.0.0.1:9", KEY).unwrap();
+    let captured = signed_body(
+        chrono::Utc::now(),
+        &format!(
+            r#"[{{"write":{}}}]"#,
+            serde_json::to_string(&record).unwrap()
+        ),
+    );
+    let replay = || {
+        b.replication
+            .post("/replication")
+            .add_header(SIGNATURE_HEADER, key.sign(captured.as_bytes()))
+            .text(&captured)
+    };
+    assert_eq!(replay().await.status_code(), StatusCode::OK);
+    assert_eq!(replay().await.status_code(), StatusCode::CONFLICT);
+
+    a.server
+        .post("/trash")
+        .json(&fetch(1))
+        .expect_success()
+        .await;
+    assert_eq!(ship_to(&a, &b).await, 2);
+    assert!(rows(&b.state).is_empty());
+    b.state
+        .secret_store
+        .prune_tombstones(chrono::Utc::now() + chrono::Duration::days(1))
+        .unwrap();
+    assert_eq!(replay().await.status_code(), StatusCode::CONFLICT);
+    assert!(rows(&b.state).is_empty());
+
+    let c = instance(0).await;
+    let old = signed_body(
+        chrono::Utc::now() - crate::replication::BATCH_MAX_AGE - chrono::Duration::minutes(1),
+        &format!(
+            r#"[{{"write":{}}}]"#,
+            serde_json::to_string(&record).unwrap()
+        ),
+    );
+    let response = c
+        .replication
+        .post("/replication")
+        .add_header(SIGNATURE_HEADER, key.sign(old.as_bytes()))
+        .text(&old)
+        .await;
+    assert_eq!(response.status_code(), StatusCode::CONFLICT);
+    assert!(rows(&c.state).is_empty());
+}
@@ -0,0 +1,138 @@
+use crate::{
+    database::{establish_connection, purge_expired_secrets},
+    models::{Info, Secret, StoreSecret},
+    tests::test_server::{configured_test_server, fetch_secret},
+    tests::{distinct_candidate, BASE64_ENCRYPTED_SECRET, SHA256_111111, SHA256_222222},
+};
+use axum::http::StatusCode;
+use diesel::{QueryDsl, RunQueryDsl};
+
+async fn ttl_server(max_ttl_days: u32) -> (axum_test::TestServer, crate::AppState) {
+    configured_test_server(|state| state.secret_max_ttl_days = max_ttl_days).await
+}
+
+fn store(ttl_days: Option<u32>) -> StoreSecret {
+    StoreSecret {
+        identifier: SHA256_111111.to_string(),
+        authentication_key: SHA256_222222.to_string(),
+        encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+        ttl_days,
+        share_index: None,
+        federation_id: None,
+    }
+}
+
+/// Inserts a record directly, bypassing `/store` and its bucket.
+fn insert(state: &crate::AppState, id: &str, expires_at: Option<&str>) {
+    let mut connection = establish_connection(state.database_url.clone());
+    assert!(crate::database::write(
+        &mut connection,
+        &Secret {
+            id: id.to_string(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            trash_after: None,
+            expires_at: expires_at.map(str::to_string),
+            share_index: None,
+            federation_id: None,
+        }
+    ));
+}
+
+fn row_count(state: &crate::AppState) -> i64 {
+    let mut connection = establish_connection(state.database_url.clone());
+    crate::schema::secret::table
+        .count()
+        .get_result(&mut connection)
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_store_with_ttl_reports_expiry() {
+    let (server, _) = ttl_server(365).await;
+    server
+        .post("/store")
+        .json(&store(Some(30)))
+        .expect_success()
+        .await;
+
+    let fetched = server
+        .post("/fetch")
+        .json(&fetch_secret(SHA256_222222))
+        .expect_success()
+        .await;
+    let expires_at = fetched.json::<serde_json::Value>()["expires_at"]
+        .as_str()
+        .unwrap()
+        .parse::<chrono::DateTime<chrono::Utc>>()
+        .unwrap();
+    let expected = chrono::Utc::now() + chrono::Duration::days(30);
+    assert!((expected - expires_at).abs() < chrono::Duration::minutes(1));
+}
+
+#[tokio::test]
+async fn test_ttl_outside_the_advertised_bound_is_rejected() {
+    let (server, _) = ttl_server(365).await;
+    for ttl_days in [0, 366] {
+        let response = server.post("/store").json(&store(Some(ttl_days))).await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    // expiry disabled: any ttl_days is refused, records without one are fine
+    let (server, _) = ttl_server(0).await;
+    let info = server.get("/info").expect_success().await.json::<Info>();
+    assert_eq!(info.secret_max_ttl_days, 0);
+    let response = server.post("/store").json(&store(Some(1))).await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    server
+        .post("/store")
+        .json(&store(None))
+        .expect_success()
+        .await;
+}
+
+/// Expired records are gone for every read and then purged; records stored
+/// without a TTL (including every legacy record) are never touched.
+#[tokio::test]
+async fn test_expired_records_are_purged_and_legacy_records_kept() {
+    let (server, state) = ttl_server(365).await;
+    server
+        .post("/store")
+        .json(&store(Some(1)))
+        .expect_success()
+        .await;
+    let mut connection = establish_connection(state.database_url.clone());
+    diesel::sql_query("UPDATE secret SET expires_at = '2000-01-01T00:00:00Z'")
+        .execute(&mut connection)
+        .unwrap();
+    insert(&state, &distinct_candidate(0), None);
+    insert(&state, &distinct_candidate(1), Some("2999-01-01T00:00:00Z"));
+
+    let fetched = server
+        .post("/fetch")
+        .json(&fetch_secret(SHA256_222222))
+        .await;
+    assert_eq!(fetched.status_code(), StatusCode::UNAUTHORIZED);
+
+    crate::retention::purge(&state).await;
+    assert_eq!(row_count(&state), 2);
+}
+
+#[tokio::test]
+async fn test_purge_deletes_in_batches() {
+    let (_, state) = ttl_server(365).await;
+    for index in 0..7 {
+        insert(
+            &state,
+            &distinct_candidate(index),
+            Some("2000-01-01T00:00:00Z"),
+        );
+    }
+
+    let mut connection = establish_connection(state.database_url.clone());
+    let now = chrono::Utc::now();
+    assert_eq!(purge_expired_secrets(&mut connection, now, 3).unwrap(), 3);
+    assert_eq!(purge_expired_secrets(&mut connection, now, 3).unwrap(), 3);
+    assert_eq!(purge_expired_secrets(&mut connection, now, 3).unwrap(), 1);
+    assert_eq!(row_count(&state), 0);
+}
@@ -0,0 +1,184 @@
+use crate::{
+    models::{FetchSecret, ResponseFailedAttempt, RotateSecret, StoreSecret},
+    tests::{
+        distinct_candidate, test_server::new_test_server, BASE64_ENCRYPTED_SECRET,
+        NOT_PASSWORD_HASH, SHA256_111111, SHA256_222222,
+    },
+    utils::identifier_hash,
+};
+use axum::http::StatusCode;
+
+const ROTATED_ENCRYPTED_SECRET: &str = "cm90YXRlZCBzZWNyZXQ=";
+
+fn store(identifier: &str, authentication_key: &str, encrypted_secret: &str) -> StoreSecret {
+    StoreSecret {
+        identifier: identifier.to_string(),
+        authentication_key: authentication_key.to_string(),
+        encrypted_secret: encrypted_secret.to_string(),
+        ttl_days: None,
+        share_index: None,
+        federation_id: None,
+    }
+}
+
+fn fetch(identifier: &str, authentication_key: &str) -> FetchSecret {
+    FetchSecret {
+        identifier: identifier.to_string(),
+        authentication_key: authentication_key.to_string(),
+        pow: None,
+    }
+}
+
+/// Rotates the record stored under `SHA256_111111`/`authentication_key` to
+/// `SHA256_222222`/`NOT_PASSWORD_HASH`.
+fn rotate(authentication_key: &str) -> RotateSecret {
+    RotateSecret {
+        identifier: SHA256_111111.to_string(),
+        authentication_key: authentication_key.to_string(),
+        pow: None,
+        new_secret: store(SHA256_222222, NOT_PASSWORD_HASH, ROTATED_ENCRYPTED_SECRET),
+    }
+}
+
+#[tokio::test]
+async fn test_rotate_replaces_the_record() {
+    let (server, _) = new_test_server().await;
+    server
+        .post("/store")
+        .json(&store(
+            SHA256_111111,
+            SHA256_222222,
+            BASE64_ENCRYPTED_SECRET,
+        ))
+        .expect_success()
+        .await;
+
+    let response = server.post("/rotate").json(&rotate(SHA256_222222)).await;
+    assert_eq!(response.status_code(), StatusCode::CREATED);
+    let body = response.json::<serde_json::Value>();
+    assert_eq!(body["attempt_status"]["total_attempts"], 1);
+    assert!(
+        body.get("encrypted_secret").is_none(),
+        "the replaced record is not echoed back"
+    );
+
+    let old = server
+        .post("/fetch")
+        .json(&fetch(SHA256_111111, SHA256_222222))
+        .await;
+    assert_eq!(old.status_code(), StatusCode::UNAUTHORIZED);
+    let new = server
+        .post("/fetch")
+        .json(&fetch(SHA256_222222, NOT_PASSWORD_HASH))
+        .expect_success()
+        .await;
+    assert_eq!(
+        new.json::<serde_json::Value>()["encrypted_secret"],
+        ROTATED_ENCRYPTED_SECRET
+    );
+}
+
+/// Wrong current credentials store nothing and count as a failed candidate,
+/// exactly like a failed `/fetch`.
+#[tokio::test]
+async fn test_rotate_with_wrong_credentials_stores_nothing() {
+    let (server, _) = new_test_server().await;
+    let response = server.post("/rotate").json(&rotate(SHA256_222222)).await;
+    assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+    let failed = response.json::<ResponseFailedAttempt>();
+    assert_eq!(failed.attempts, 1);
+
+    let new = server
+        .post("/fetch")
+        .json(&fetch(SHA256_222222, NOT_PASSWORD_HASH))
+        .await;
+    assert_eq!(new.status_code(), StatusCode::UNAUTHORIZED);
+}
+
+/// `/rotate`, `/fetch` and `/trash` share one candidate budget per
+/// identifier: rotation is not a way around the limit.
+#[tokio::test]
+async fn test_rotate_shares_the_candidate_budget() {
+    let (server, state) = new_test_server().await;
+    server
+        .post("/store")
+        .json(&store(
+            SHA256_111111,
+            SHA256_222222,
+            BASE64_ENCRYPTED_SECRET,
+        ))
+        .expect_success()
+        .await;
+    for index in 0..usize::from(state.rate_limit_max_attempts) {
+        server
+            .post("/rotate")
+            .json(&rotate(&distinct_candidate(index)))
+            .expect_failure()
+            .await;
+    }
+
+    let locked = server.post("/rotate").json(&rotate(SHA256_222222)).await;
+    assert_eq!(locked.status_code(), StatusCode::TOO_MANY_REQUESTS);
+    let locked = server
+        .post("/fetch")
+        .json(&fetch(SHA256_111111, SHA256_222222))
+        .await;
+    assert_eq!(locked.status_code(), StatusCode::TOO_MANY_REQUESTS);
+
+    let identifier_rate_limit = state.identifier_rate_limit.lock().await;
+    let info = &identifier_rate_limit[&identifier_hash(SHA256_111111).unwrap()];
+    assert_eq!(info.candidate_count(), state.rate_limit_max_attempts);
+    assert!(
+        !identifier_rate_limit.contains_key(&identifier_hash(SHA256_222222).unwrap()),
+        "the new identifier is not charged"
+    );
+}
+
+/// F1: an existing record under the new secret_id is neither overwritten nor
+/// signalled; the response is the same as for a fresh one.
+#[tokio::test]
+async fn test_rotate_onto_existing_record_gives_no_existence_signal() {
+    let (server, _) = new_test_server().await;
+    server
+        .post("/store")
+        .json(&store(
+            SHA256_111111,
+            SHA256_222222,
+            BASE64_ENCRYPTED_SECRET,
+        ))
+        .expect_success()
+        .await;
+    let planted = "cGxhbnRlZA==";
+    server
+        .post("/store")
+        .json(&store(SHA256_222222, NOT_PASSWORD_HASH, planted))
+        .expect_success()
+        .await;
+
+    let response = server.post("/rotate").json(&rotate(SHA256_222222)).await;
+    assert_eq!(response.status_code(), StatusCode::CREATED);
+
+    let new = server
+        .post("/fetch")
+        .json(&fetch(SHA256_222222, NOT_PASSWORD_HASH))
+        .expect_success()
+        .await;
+    assert_eq!(new.json::<serde_json::Value>()["encrypted_secret"], planted);
+}
+
+#[tokio::test]
+async fn test_rotate_rejects_invalid_requests_without_consuming_budget() {
+    let (server, state) = new_test_server().await;
+
+    let mut same_credentials = rotate(SHA256_222222);
+    same_credentials.new_secret = store(SHA256_111111, SHA256_222222, ROTATED_ENCRYPTED_SECRET);
+    let response = server.post("/rotate").json(&same_credentials).await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+
+    let mut invalid_secret = rotate(SHA256_222222);
+    invalid_secret.new_secret.encrypted_secret = "not base64!".to_string();
+    let response = server.post("/rotate").json(&invalid_secret).await;
+    assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+
+    assert!(state.identifier_rate_limit.lock().await.is_empty());
+}
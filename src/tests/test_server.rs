@@ -3,18 +3,118 @@ use diesel::{RunQueryDsl, SqliteConnection};
 
 #[cfg(test)]
 pub async fn new_test_server() -> (TestServer, crate::AppState) {
-    let app_state = crate::env::init();
-
-    crate::database::init_db(app_state.clone());
-
-    let app = crate::router::new(app_state.clone());
+    let (server, app_state) = configured_test_server(|_| {}).await;
 
     let mut connection = crate::database::establish_connection(app_state.clone().database_url);
     clear_table_secret(&mut connection).await;
 
-    (TestServer::new(app).unwrap(), app_state)
+    (server, app_state)
+}
+
+/// A test server whose state `configure` adjusts before the database is
+/// initialized and the router built, as a setting read by `env::init` would.
+pub async fn configured_test_server(
+    configure: impl FnOnce(&mut crate::AppState),
+) -> (TestServer, crate::AppState) {
+    let mut app_state = crate::env::init();
+    configure(&mut app_state);
+    crate::database::init_db(app_state.clone());
+    let server = TestServer::new(crate::router::new(app_state.clone())).unwrap();
+    (server, app_state)
+}
+
+/// A `/fetch` or `/trash` body for `SHA256_111111`, without proof of work.
+pub fn fetch_secret(authentication_key: &str) -> crate::models::FetchSecret {
+    crate::models::FetchSecret {
+        identifier: crate::tests::SHA256_111111.to_string(),
+        authentication_key: authentication_key.to_string(),
+        pow: None,
+    }
 }
 
 pub async fn clear_table_secret(connection: &mut SqliteConnection) {
     let _ = diesel::delete(crate::schema::secret::table).execute(connection);
 }
+
+/// One fresh, uninitialized `AppState` per storage backend, for the tests
+/// that must hold on every backend. SQLite always; PostgreSQL when built
+/// with the `postgres` feature and TEST_POSTGRES_URL names a server the
+/// tests may create databases on (e.g. `postgres://postgres@localhost/postgres`).
+pub fn backend_states() -> Vec<(&'static str, crate::AppState)> {
+    #[allow(unused_mut)]
+    let mut states = vec![("sqlite", crate::env::init())];
+    #[cfg(feature = "postgres")]
+    if let Ok(server_url) = std::env::var("TEST_POSTGRES_URL") {
+        let mut state = crate::env::init();
+        let (database_url, guard) = crate::env::unique_test_postgres_database(&server_url);
+        let max_connections = state.database_semaphore.available_permits() as u32;
+        state.secret_store =
+            crate::secret_store::new_secret_store(&database_url, max_connections, false).unwrap();
+        state._test_database_guard = guard;
+        states.push(("postgres", state));
+    }
+    states
+}
+
+/// The public listeners the end-to-end tests must hold on: TCP, and a Unix
+/// socket (SERVER_ADDRESS=unix:/path) in the temporary directory.
+pub fn listener_addresses() -> Vec<(&'static str, String)> {
+    let socket = std::env::temp_dir().join(format!(
+        "keychain-test-{}.sock",
+        crate::env::unique_test_suffix().replace('_', "-")
+    ));
+    vec![
+        ("tcp", "127.0.0.1:0".to_string()),
+        ("unix", format!("unix:{}", socket.display())),
+    ]
+}
+
+/// A server running on a real listener. The socket file of a Unix listener
+/// is removed on drop: the server task is not shut down gracefully, so it
+/// would otherwise outlive the test.
+pub struct SpawnedServer {
+    pub address: String,
+}
+
+impl Drop for SpawnedServer {
+    fn drop(&mut self) {
+        if let Some(path) = crate::listener::unix_socket_path(&self.address) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Serves `app` on `server_address` (see `listener_addresses`) the way
+/// `run` does, so requests are truly concurrent.
+pub async fn spawn_listener(app: axum::Router, server_address: &str) -> SpawnedServer {
+    let listener =
+        crate::listener::Listener::bind(server_address, crate::env::DEFAULT_SERVER_SOCKET_MODE)
+            .await
+            .unwrap();
+    let address = listener.local_address().unwrap();
+    tokio::spawn(async move {
+        listener.serve(app, std::future::pending()).await.unwrap();
+    });
+    SpawnedServer { address }
+}
+
+/// Sends a raw HTTP/1.1 request to a spawned server and returns the whole
+/// response.
+pub async fn raw_request(address: &str, request: &str) -> String {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut buf = Vec::new();
+    match crate::listener::unix_socket_path(address) {
+        Some(path) => {
+            let mut stream = tokio::net::UnixStream::connect(path).await.unwrap();
+            stream.write_all(request.as_bytes()).await.unwrap();
+            stream.read_to_end(&mut buf).await.unwrap();
+        }
+        None => {
+            let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+            stream.write_all(request.as_bytes()).await.unwrap();
+            stream.read_to_end(&mut buf).await.unwrap();
+        }
+    }
+    String::from_utf8_lossy(&buf).into_owned()
+}
@@ -0,0 +1,180 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{Duration, Utc};
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+
+use crate::canary::{load_signed_canary, SignedCanarySource};
+use crate::models::Info;
+
+const BLOCK_HASH: &str = "00000000000000000001c4a1d7a6c2c4d3e8a9bd2f7f07e6a3c0c1a0b2f5e6d7";
+
+fn operator_key() -> SigningKey {
+    SigningKey::from_bytes(&[7u8; 32])
+}
+
+/// A document issued 30 days before it expires, `expires_in` from now.
+fn document(statement: &str, expires_in: Duration) -> String {
+    let expires_at = Utc::now() + expires_in;
+    format!(
+        r#"{{"statement":"{statement}","issued_at":"{}","expires_at":"{}","bitcoin_block_hash":"{BLOCK_HASH}"}}"#,
+        (expires_at - Duration::days(30)).to_rfc3339(),
+        expires_at.to_rfc3339()
+    )
+}
+
+fn envelope(key: &SigningKey, document: &str) -> String {
+    format!(
+        r#"{{"document":"{}","signature":"{}"}}"#,
+        STANDARD.encode(document),
+        STANDARD.encode(key.sign(document.as_bytes()).to_bytes())
+    )
+}
+
+fn signed_canary_path() -> std::path::PathBuf {
+    std::env::temp_dir().join(format!(
+        "keychain-test-signed-canary-{}.json",
+        crate::env::unique_test_suffix()
+    ))
+}
+
+async fn server_with_signed_canary(
+    path: &std::path::Path,
+) -> (axum_test::TestServer, crate::AppState) {
+    let mut state = crate::env::init();
+    let source =
+        SignedCanarySource::open(path.to_path_buf(), operator_key().verifying_key()).unwrap();
+    state.signed_canary = Some(std::sync::Arc::new(source));
+    crate::database::init_db(state.clone());
+    let server = axum_test::TestServer::new(crate::router::new(state.clone())).unwrap();
+    (server, state)
+}
+
+/// A client holding only the pinned fingerprint can authenticate the key
+/// served by `/info`, then the document with that key.
+#[tokio::test]
+async fn test_info_serves_the_verifiable_signed_canary() {
+    let path = signed_canary_path();
+    let document = document("No warrants as of this date.", Duration::days(30));
+    std::fs::write(&path, envelope(&operator_key(), &document)).unwrap();
+    let (server, _) = server_with_signed_canary(&path).await;
+
+    let info = server.get("/info").expect_success().await.json::<Info>();
+    let pinned = crate::utils::sha256_hex(operator_key().verifying_key().as_bytes());
+    assert_eq!(
+        info.canary_key_fingerprint.as_deref(),
+        Some(pinned.as_str())
+    );
+    assert_eq!(info.canary_expired, Some(false));
+
+    let public_key: [u8; 32] = hex::decode(info.canary_public_key.unwrap())
+        .unwrap()
+        .try_into()
+        .unwrap();
+    assert_eq!(crate::utils::sha256_hex(&public_key), pinned);
+    let signed = info.signed_canary.unwrap();
+    let served_document = STANDARD.decode(signed.document).unwrap();
+    let signature: [u8; 64] = STANDARD
+        .decode(signed.signature)
+        .unwrap()
+        .try_into()
+        .unwrap();
+    VerifyingKey::from_bytes(&public_key)
+        .unwrap()
+        .verify_strict(
+            &served_document,
+            &ed25519_dalek::Signature::from_bytes(&signature),
+        )
+        .expect("the served document verifies with the served key");
+    assert_eq!(served_document, document.as_bytes());
+
+    let _ = std::fs::remove_file(&path);
+}
+
+/// An expired document is still served, flagged: a canary that is not
+/// renewed is the signal, and hiding it would look like an ops error.
+#[tokio::test]
+async fn test_info_flags_an_expired_signed_canary() {
+    let path = signed_canary_path();
+    let document = document("No warrants as of this date.", -Duration::hours(1));
+    std::fs::write(&path, envelope(&operator_key(), &document)).unwrap();
+    let (server, _) = server_with_signed_canary(&path).await;
+
+    let info = server.get("/info").expect_success().await.json::<Info>();
+    assert_eq!(info.canary_expired, Some(true));
+    assert!(info.signed_canary.is_some());
+
+    let _ = std::fs::remove_file(&path);
+}
+
+/// The operator renews the document without a restart; a replacement that
+/// fails verification is an ops error and the previous document stays.
+#[tokio::test]
+async fn test_signed_canary_renewal_and_invalid_replacement() {
+    let path = signed_canary_path();
+    std::fs::write(
+        &path,
+        envelope(&operator_key(), &document("First.", Duration::days(30))),
+    )
+    .unwrap();
+    let (server, _) = server_with_signed_canary(&path).await;
+
+    let renewed = document("Renewed statement.", Duration::days(60));
+    std::fs::write(&path, envelope(&operator_key(), &renewed)).unwrap();
+    let served = server.get("/info").await.json::<Info>().signed_canary;
+    assert_eq!(
+        STANDARD.decode(served.unwrap().document).unwrap(),
+        renewed.as_bytes()
+    );
+
+    let forged = document("Signed with another key, longer.", Duration::days(90));
+    let other_key = SigningKey::from_bytes(&[8u8; 32]);
+    std::fs::write(&path, envelope(&other_key, &forged)).unwrap();
+    let served = server.get("/info").await.json::<Info>().signed_canary;
+    assert_eq!(
+        STANDARD.decode(served.unwrap().document).unwrap(),
+        renewed.as_bytes()
+    );
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_signed_canary_verification_failures() {
+    let path = signed_canary_path();
+    let key = operator_key().verifying_key();
+    let valid = document("No warrants as of this date.", Duration::days(30));
+
+    let tampered = envelope(&operator_key(), &valid).replace(
+        &STANDARD.encode(&valid),
+        &STANDARD.encode(valid.replace("No warrants", "A warrant")),
+    );
+    let cases = [
+        (tampered, "does not match"),
+        (
+            envelope(&operator_key(), &valid.replace(BLOCK_HASH, "00ff")),
+            "bitcoin_block_hash",
+        ),
+        (
+            envelope(&operator_key(), "{\"statement\":\"no dates\"}"),
+            "document is invalid",
+        ),
+        ("not json".to_string(), "is not a signed canary"),
+    ];
+    for (contents, expected) in cases {
+        std::fs::write(&path, contents).unwrap();
+        let error = load_signed_canary(&path, &key).err().unwrap();
+        assert!(error.contains(expected), "{error}");
+    }
+    let _ = std::fs::remove_file(&path);
+
+    // Startup refuses a missing document instead of serving no canary.
+    assert!(SignedCanarySource::open(path, key).is_err());
+}
+
+/// Without a signed canary, `/info` is unchanged for existing clients.
+#[tokio::test]
+async fn test_info_omits_signed_canary_fields_when_unconfigured() {
+    let (server, _) = crate::tests::test_server::new_test_server().await;
+    let body = server.get("/info").expect_success().await.text();
+    assert!(!body.contains("signed_canary"));
+    assert!(!body.contains("canary_expired"));
+}
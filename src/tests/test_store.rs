@@ -13,6 +13,9 @@ async fn test_success_created() {
         identifier: SHA256_111111.to_string(),
         authentication_key: SHA256_222222.to_string(),
         encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+        ttl_days: None,
+        share_index: None,
+        federation_id: None,
     };
 
     let response = server.post("/store").json(store).expect_success().await;
@@ -28,6 +31,9 @@ async fn test_duplicate_store_is_indistinguishable_and_does_not_overwrite() {
         identifier: SHA256_111111.to_string(),
         authentication_key: SHA256_222222.to_string(),
         encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+        ttl_days: None,
+        share_index: None,
+        federation_id: None,
     };
     let first = server.post("/store").json(store).await;
 
@@ -35,6 +41,9 @@ async fn test_duplicate_store_is_indistinguishable_and_does_not_overwrite() {
         identifier: SHA256_111111.to_string(),
         authentication_key: SHA256_222222.to_string(),
         encrypted_secret: "dGVzdA==".to_string(),
+        ttl_days: None,
+        share_index: None,
+        federation_id: None,
     };
     let second = server.post("/store").json(duplicate).await;
 
@@ -49,6 +58,7 @@ async fn test_duplicate_store_is_indistinguishable_and_does_not_overwrite() {
         .json(&FetchSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
+            pow: None,
         })
         .expect_success()
         .await
@@ -64,6 +74,9 @@ async fn test_failure_identifier_not_64_letters() {
         identifier: SHA256_111111[1..].to_string(),
         authentication_key: SHA256_222222.to_string(),
         encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+        ttl_days: None,
+        share_index: None,
+        federation_id: None,
     };
 
     let response = server.post("/store").json(store).expect_failure().await;
@@ -81,6 +94,9 @@ async fn test_failure_encrypted_empty_secret() {
         identifier: SHA256_111111.to_string(),
         authentication_key: SHA256_222222.to_string(),
         encrypted_secret: "".to_string(),
+        ttl_days: None,
+        share_index: None,
+        federation_id: None,
     };
 
     let response = server.post("/store").json(store).expect_failure().await;
@@ -96,6 +112,9 @@ async fn test_failure_encrypted_secret_invalid_base64() {
         identifier: SHA256_111111.to_string(),
         authentication_key: SHA256_222222.to_string(),
         encrypted_secret: "!@#$%^&*()".to_string(), // invalid_base64
+        ttl_days: None,
+        share_index: None,
+        federation_id: None,
     };
 
     let response = server.post("/store").json(store).expect_failure().await;
@@ -110,13 +129,25 @@ async fn test_failure_encrypted_secret_invalid_base64() {
 async fn test_store_checks_length_before_base64() {
     let (server, state) = crate::tests::test_server::new_test_server().await;
 
-    let oversized_invalid = "!".repeat(state.secret_max_length + 4 - (state.secret_max_length % 4));
+    let oversized_invalid = "!".repeat(
+        state
+            .secret_max_length
+            .load(std::sync::atomic::Ordering::Relaxed)
+            + 4
+            - (state
+                .secret_max_length
+                .load(std::sync::atomic::Ordering::Relaxed)
+                % 4),
+    );
     let response = server
         .post("/store")
         .json(&StoreSecret {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
             encrypted_secret: oversized_invalid,
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         })
         .expect_failure()
         .await;
@@ -150,6 +181,9 @@ async fn test_store_database_error_returns_coherent_body() {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
             encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         })
         .await;
 
@@ -172,6 +206,9 @@ async fn test_store_rejects_oversized_json_before_deserialization() {
             identifier: SHA256_111111.to_string(),
             authentication_key: SHA256_222222.to_string(),
             encrypted_secret: "A".repeat(2_000),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
         })
         .expect_failure()
         .await;
@@ -0,0 +1,510 @@
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use base64::Engine;
+use diesel::RunQueryDsl;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+use crate::models::{
+    ConsistencyProof, FetchSecret, InclusionProof, Info, StoreReceipt, StoreSecret, TreeHead,
+};
+use crate::tests::{distinct_candidate, BASE64_ENCRYPTED_SECRET, SHA256_222222};
+use crate::transparency::{
+    store_leaf, trash_leaf, tree_head_message, verify_consistency, verify_inclusion, Hash,
+    MerkleTree,
+};
+
+/// RFC 6962's reference leaves, hashed as `SHA256(0x00 || data)`.
+const REFERENCE_LEAVES: [&str; 8] = [
+    "",
+    "00",
+    "10",
+    "2021",
+    "3031",
+    "40414243",
+    "5051525354555657",
+    "606162636465666768696a6b6c6d6e6f",
+];
+
+/// Roots of the first 0 to 8 reference leaves.
+const REFERENCE_ROOTS: [&str; 9] = [
+    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+    "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+    "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+    "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+    "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+    "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+    "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+    "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+    "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+];
+
+fn hash(value: &str) -> Hash {
+    let mut hash = [0u8; 32];
+    hex::decode_to_slice(value, &mut hash).unwrap();
+    hash
+}
+
+fn hashes(values: &[&str]) -> Vec<Hash> {
+    values.iter().map(|value| hash(value)).collect()
+}
+
+fn reference_leaves() -> Vec<Hash> {
+    REFERENCE_LEAVES
+        .iter()
+        .map(|data| {
+            let mut hasher = Sha256::new();
+            hasher.update([0x00]);
+            hasher.update(hex::decode(data).unwrap());
+            hasher.finalize().into()
+        })
+        .collect()
+}
+
+/// The tree matches RFC 6962's test vectors, and every proof it builds
+/// verifies while a tampered one does not.
+#[test]
+fn test_reference_vectors() {
+    let leaves = reference_leaves();
+    let mut tree = MerkleTree::default();
+    leaves.iter().for_each(|leaf| tree.push(*leaf));
+    for (size, root) in REFERENCE_ROOTS.iter().enumerate() {
+        assert_eq!(hex::encode(tree.root(size as u64)), *root, "size {size}");
+    }
+
+    let inclusion: [(u64, u64, &[&str]); 3] = [
+        (
+            0,
+            8,
+            &[
+                "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+            ],
+        ),
+        (
+            5,
+            8,
+            &[
+                "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            ],
+        ),
+        (
+            2,
+            3,
+            &["fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125"],
+        ),
+    ];
+    for (index, size, path) in inclusion {
+        assert_eq!(tree.inclusion_proof(index, size), hashes(path));
+    }
+    let consistency: [(u64, u64, &[&str]); 3] = [
+        (
+            3,
+            7,
+            &[
+                "0298d122906dcfc10892cb53a73992fc5b9f493ea4c9badb27b791b4127a7fe7",
+                "07506a85fd9dd2f120eb694f86011e5bb4662e5c415a62917033d4a9624487e7",
+                "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+                "837dbb152e9b079010717e84e865da4ebc0fa198a806d59d31bf15accef22d0e",
+            ],
+        ),
+        (
+            4,
+            8,
+            &["6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4"],
+        ),
+        (
+            6,
+            8,
+            &[
+                "0ebc5d3437fbe2db158b9f126a1d118e308181031d0a949f8dededebc558ef6a",
+                "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            ],
+        ),
+    ];
+    for (first, second, proof) in consistency {
+        assert_eq!(tree.consistency_proof(first, second), hashes(proof));
+    }
+
+    for size in 1..=8u64 {
+        let root = tree.root(size);
+        for index in 0..size {
+            let leaf = leaves[index as usize];
+            let path = tree.inclusion_proof(index, size);
+            assert!(verify_inclusion(&leaf, index, size, &path, &root));
+            let other = leaves[((index + 1) % size) as usize];
+            assert_eq!(
+                verify_inclusion(&other, index, size, &path, &root),
+                size == 1
+            );
+            if let Some(node) = path.first() {
+                let mut tampered = path.clone();
+                tampered[0] = node.map(|byte| byte ^ 1);
+                assert!(!verify_inclusion(&leaf, index, size, &tampered, &root));
+            }
+        }
+        for first in 1..size {
+            let first_root = tree.root(first);
+            let proof = tree.consistency_proof(first, size);
+            assert!(verify_consistency(first, size, &first_root, &root, &proof));
+            assert!(!verify_consistency(first, size, &root, &first_root, &proof));
+            let mut tampered = proof.clone();
+            tampered[0] = proof[0].map(|byte| byte ^ 1);
+            assert!(!verify_consistency(
+                first,
+                size,
+                &first_root,
+                &root,
+                &tampered
+            ));
+        }
+    }
+}
+
+fn store(index: usize, ttl_days: Option<u32>) -> StoreSecret {
+    StoreSecret {
+        identifier: distinct_candidate(index),
+        authentication_key: SHA256_222222.to_string(),
+        encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+        ttl_days,
+        share_index: None,
+        federation_id: None,
+    }
+}
+
+fn fetch(index: usize) -> FetchSecret {
+    FetchSecret {
+        identifier: distinct_candidate(index),
+        authentication_key: SHA256_222222.to_string(),
+        pow: None,
+    }
+}
+
+async fn server(state: &crate::AppState) -> axum_test::TestServer {
+    crate::database::init_db(state.clone());
+    axum_test::TestServer::new(crate::router::new(state.clone())).unwrap()
+}
+
+/// The leaf of record `index`'s store, or trash, from what `/fetch` returns.
+async fn leaf(server: &axum_test::TestServer, index: usize, trash: bool) -> Hash {
+    let fetched = server
+        .post("/fetch")
+        .json(&fetch(index))
+        .expect_success()
+        .await
+        .json::<serde_json::Value>();
+    let id = crate::utils::generate_secret_id(&distinct_candidate(index), SHA256_222222);
+    let (encrypted_secret, created_at) = (
+        fetched["encrypted_secret"].as_str().unwrap(),
+        fetched["created_at"].as_str().unwrap(),
+    );
+    if trash {
+        trash_leaf(&id, encrypted_secret, created_at)
+    } else {
+        store_leaf(&id, encrypted_secret, created_at)
+    }
+}
+
+/// Checks a tree head's signature against `/info`, and returns its root.
+async fn verified_root(server: &axum_test::TestServer, head: &TreeHead) -> Hash {
+    let info = server.get("/info").await.json::<Info>();
+    let key: [u8; 32] = hex::decode(info.attempts_public_key)
+        .unwrap()
+        .try_into()
+        .unwrap();
+    let signature: [u8; 64] = base64::engine::general_purpose::STANDARD
+        .decode(&head.signature)
+        .unwrap()
+        .try_into()
+        .unwrap();
+    let root = hash(&head.root_hash);
+    VerifyingKey::from_bytes(&key)
+        .unwrap()
+        .verify(
+            &tree_head_message(head.tree_size, &root, head.timestamp),
+            &Signature::from_bytes(&signature),
+        )
+        .unwrap();
+    root
+}
+
+async fn assert_included(server: &axum_test::TestServer, leaf: &Hash, proof: &InclusionProof) {
+    assert_eq!(proof.tree_size, proof.tree_head.tree_size);
+    let root = verified_root(server, &proof.tree_head).await;
+    let path = hashes(
+        &proof
+            .audit_path
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>(),
+    );
+    assert!(verify_inclusion(
+        leaf,
+        proof.leaf_index,
+        proof.tree_size,
+        &path,
+        &root
+    ));
+}
+
+/// A `/store` receipt proves the store, as the client can recompute it,
+/// under a head signed by the `/info` key. A duplicate store is logged and
+/// receipted like a fresh one, and the later head extends the earlier.
+#[tokio::test]
+async fn test_store_receipt_proves_inclusion() {
+    for (backend, state) in crate::tests::test_server::backend_states() {
+        let server = server(&state).await;
+        let first = server
+            .post("/store")
+            .json(&store(1, None))
+            .expect_success()
+            .await;
+        assert_eq!(first.status_code(), StatusCode::CREATED, "{backend}");
+        let first = first.json::<StoreReceipt>().log;
+        assert_included(&server, &leaf(&server, 1, false).await, &first).await;
+
+        let duplicate = server
+            .post("/store")
+            .json(&store(1, None))
+            .expect_success()
+            .await
+            .json::<StoreReceipt>()
+            .log;
+        assert_eq!(duplicate.leaf_index, first.leaf_index + 1, "{backend}");
+        assert_eq!(duplicate.tree_size, first.tree_size + 1, "{backend}");
+
+        let proof = server
+            .get("/log/consistency")
+            .add_query_param("first", first.tree_size)
+            .add_query_param("second", duplicate.tree_size)
+            .expect_success()
+            .await
+            .json::<ConsistencyProof>();
+        let path = hashes(
+            &proof
+                .consistency_path
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>(),
+        );
+        assert!(
+            verify_consistency(
+                first.tree_size,
+                duplicate.tree_size,
+                &hash(&first.tree_head.root_hash),
+                &verified_root(&server, &proof.tree_head).await,
+                &path,
+            ),
+            "{backend}"
+        );
+    }
+}
+
+/// A `/trash` and the purge of an expired record are logged; the log
+/// routes refuse malformed queries and do not find unknown leaves.
+#[tokio::test]
+async fn test_trash_and_expiry_are_logged() {
+    let mut state = crate::env::init();
+    state.trash_grace_period = chrono::Duration::zero();
+    state.secret_max_ttl_days = 365;
+    let server = server(&state).await;
+    for (index, ttl_days) in [(1, None), (2, Some(1))] {
+        server
+            .post("/store")
+            .json(&store(index, ttl_days))
+            .expect_success()
+            .await;
+    }
+
+    let trashed = leaf(&server, 1, true).await;
+    server.post("/trash").json(&fetch(1)).expect_success().await;
+    let expired = leaf(&server, 2, true).await;
+    let mut connection = crate::database::establish_connection(state.database_url.clone());
+    diesel::sql_query("UPDATE secret SET expires_at = '2000-01-01T00:00:00Z'")
+        .execute(&mut connection)
+        .unwrap();
+    crate::retention::purge(&state).await;
+
+    let head = server
+        .get("/log/head")
+        .expect_success()
+        .await
+        .json::<TreeHead>();
+    assert_eq!(head.tree_size, 4);
+    for leaf in [trashed, expired] {
+        let proof = server
+            .get("/log/inclusion")
+            .add_query_param("leaf_hash", hex::encode(leaf))
+            .expect_success()
+            .await
+            .json::<InclusionProof>();
+        assert_included(&server, &leaf, &proof).await;
+    }
+
+    let response = server
+        .get("/log/inclusion")
+        .add_query_param("leaf_hash", hex::encode([0x5a; 32]))
+        .await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+    // The trash of record 1 is leaf 2: not in the tree of 2 leaves.
+    let response = server
+        .get("/log/inclusion")
+        .add_query_param("leaf_hash", hex::encode(trashed))
+        .add_query_param("tree_size", 2)
+        .await;
+    assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+
+    for (path, query) in [
+        ("/log/inclusion", "leaf_hash=zz"),
+        ("/log/inclusion", "leaf_hash=00"),
+        (
+            "/log/inclusion",
+            &*format!("leaf_hash={}&tree_size=5", hex::encode(trashed)),
+        ),
+        ("/log/consistency", "first=0"),
+        ("/log/consistency", "first=3&second=2"),
+        ("/log/consistency", "first=1&second=5"),
+    ] {
+        let response = server.get(&format!("{path}?{query}")).await;
+        assert_eq!(
+            response.status_code(),
+            StatusCode::BAD_REQUEST,
+            "{path}?{query}"
+        );
+    }
+}
+
+/// The inclusion proof of `leaf`, or the status of the refusal.
+async fn inclusion(
+    server: &axum_test::TestServer,
+    leaf: &Hash,
+) -> Result<InclusionProof, StatusCode> {
+    let response = server
+        .get("/log/inclusion")
+        .add_query_param("leaf_hash", hex::encode(leaf))
+        .await;
+    match response.status_code() {
+        StatusCode::OK => Ok(response.json::<InclusionProof>()),
+        status => Err(status),
+    }
+}
+
+/// A duplicate `/store` is logged with the content it was sent, but the
+/// record only changes through a trash: its own store leaf stays the one
+/// of its `created_at`, with no trash of it after. A client can thus tell a
+/// duplicate from a replacement (see SECURITY.md).
+#[tokio::test]
+async fn test_duplicate_store_does_not_look_like_a_replacement() {
+    for (backend, state) in crate::tests::test_server::backend_states() {
+        let server = server(&state).await;
+        let first = server
+            .post("/store")
+            .json(&store(1, None))
+            .expect_success()
+            .await
+            .json::<StoreReceipt>()
+            .log;
+        let mut other_content = store(1, None);
+        other_content.encrypted_secret = "b3RoZXI=".to_string();
+        let duplicate = server
+            .post("/store")
+            .json(&other_content)
+            .expect_success()
+            .await
+            .json::<StoreReceipt>()
+            .log;
+        assert_eq!(duplicate.leaf_index, first.leaf_index + 1, "{backend}");
+
+        let fetched = server
+            .post("/fetch")
+            .json(&fetch(1))
+            .expect_success()
+            .await
+            .json::<serde_json::Value>();
+        assert_eq!(fetched["encrypted_secret"], BASE64_ENCRYPTED_SECRET);
+        let record = leaf(&server, 1, false).await;
+        assert_included(&server, &record, &first).await;
+        let proof = inclusion(&server, &record).await.unwrap();
+        assert_eq!(proof.leaf_index, first.leaf_index, "{backend}");
+        assert_eq!(
+            inclusion(&server, &leaf(&server, 1, true).await)
+                .await
+                .err(),
+            Some(StatusCode::NOT_FOUND),
+            "{backend}"
+        );
+    }
+}
+
+/// `keychain-admin purge` logs its deletions, and an import logs the store
+/// of each record it inserts, with its original `created_at`: the log of
+/// the new server covers what it holds.
+#[tokio::test]
+async fn test_admin_purge_and_import_are_logged() {
+    let state = crate::env::init();
+    let target_state = crate::env::init();
+    let (server, target) = (server(&state).await, server(&target_state).await);
+    for index in [1, 2] {
+        server
+            .post("/store")
+            .json(&store(index, None))
+            .expect_success()
+            .await;
+    }
+    let mut connection = crate::database::establish_connection(state.database_url.clone());
+    let purged = crate::utils::generate_secret_id(&distinct_candidate(1), SHA256_222222);
+    diesel::sql_query("UPDATE secret SET created_at = '2000-01-01T00:00:00+00:00' WHERE id = ?")
+        .bind::<diesel::sql_types::Text, _>(&purged)
+        .execute(&mut connection)
+        .unwrap();
+    let trashed = leaf(&server, 1, true).await;
+    crate::admin::execute(
+        &mut connection,
+        &crate::admin::Command::Purge {
+            older_than_days: 30,
+        },
+        false,
+    )
+    .unwrap();
+    let proof = inclusion(&server, &trashed).await.unwrap();
+    assert_included(&server, &trashed, &proof).await;
+    assert_eq!(proof.leaf_index, 2);
+
+    let mut dump = Vec::new();
+    crate::export::export(&mut connection, &mut dump).unwrap();
+    let mut target_connection =
+        crate::database::establish_connection(target_state.database_url.clone());
+    crate::export::import(&mut target_connection, &mut dump.as_slice()).unwrap();
+    let imported = leaf(&target, 2, false).await;
+    assert_eq!(imported, leaf(&server, 2, false).await);
+    let proof = inclusion(&target, &imported).await.unwrap();
+    assert_included(&target, &imported, &proof).await;
+    assert_eq!(proof.tree_size, 1);
+}
+
+/// The tree is rebuilt from the database: a restarted server serves the
+/// same root.
+#[tokio::test]
+async fn test_log_survives_a_restart() {
+    let mut state = crate::env::init();
+    let server = server(&state).await;
+    for index in 1..=3 {
+        server
+            .post("/store")
+            .json(&store(index, None))
+            .expect_success()
+            .await;
+    }
+    let before = server.get("/log/head").await.json::<TreeHead>();
+
+    state.transparency_log = Arc::new(crate::transparency::TransparencyLog::default());
+    let restarted = axum_test::TestServer::new(crate::router::new(state)).unwrap();
+    let after = restarted.get("/log/head").await.json::<TreeHead>();
+    assert_eq!(after.tree_size, 3);
+    assert_eq!(after.root_hash, before.root_hash);
+}
@@ -12,6 +12,9 @@ async fn test_trash_success() {
         identifier: SHA256_111111.to_string(),
         authentication_key: SHA256_222222.to_string(),
         encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+        ttl_days: None,
+        share_index: None,
+        federation_id: None,
     };
 
     server.post("/store").json(store).expect_success().await;
@@ -19,6 +22,7 @@ async fn test_trash_success() {
     let fetch = &FetchSecret {
         identifier: SHA256_111111.to_string(),
         authentication_key: SHA256_222222.to_string(),
+        pow: None,
     };
 
     let response = server.post("/trash").json(fetch).expect_success().await;
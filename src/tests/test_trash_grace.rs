@@ -0,0 +1,204 @@
+use crate::{
+    models::{Info, RotateSecret, StoreSecret},
+    tests::test_server::{configured_test_server, fetch_secret},
+    tests::{
+        distinct_candidate, BASE64_ENCRYPTED_SECRET, NOT_PASSWORD_HASH, SHA256_111111,
+        SHA256_222222,
+    },
+};
+use axum::http::StatusCode;
+use diesel::RunQueryDsl;
+
+async fn grace_server(hours: i64) -> (axum_test::TestServer, crate::AppState) {
+    let (server, state) = configured_test_server(|state| {
+        state.trash_grace_period = chrono::Duration::hours(hours);
+    })
+    .await;
+    server
+        .post("/store")
+        .json(&StoreSecret {
+            identifier: SHA256_111111.to_string(),
+            authentication_key: SHA256_222222.to_string(),
+            encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+            ttl_days: None,
+            share_index: None,
+            federation_id: None,
+        })
+        .expect_success()
+        .await;
+    (server, state)
+}
+
+fn trash_after(response: &axum_test::TestResponse) -> Option<String> {
+    response.json::<serde_json::Value>()["trash_after"]
+        .as_str()
+        .map(str::to_string)
+}
+
+/// Moves every pending deletion into the past, as if the grace period had
+/// elapsed.
+fn expire_grace_period(state: &crate::AppState) {
+    let mut connection = crate::database::establish_connection(state.database_url.clone());
+    diesel::sql_query(
+        "UPDATE secret SET trash_after = '2000-01-01T00:00:00Z' WHERE trash_after IS NOT NULL",
+    )
+    .execute(&mut connection)
+    .unwrap();
+}
+
+fn row_count(state: &crate::AppState) -> i64 {
+    use diesel::QueryDsl;
+    let mut connection = crate::database::establish_connection(state.database_url.clone());
+    crate::schema::secret::table
+        .count()
+        .get_result(&mut connection)
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_trash_marks_and_restore_cancels() {
+    let (server, state) = grace_server(24).await;
+
+    let trashed = server
+        .post("/trash")
+        .json(&fetch_secret(SHA256_222222))
+        .await;
+    assert_eq!(trashed.status_code(), StatusCode::ACCEPTED);
+    let deadline = trash_after(&trashed).expect("the pending deletion is reported");
+    let deadline = deadline.parse::<chrono::DateTime<chrono::Utc>>().unwrap();
+    assert!(deadline > chrono::Utc::now() + chrono::Duration::hours(23));
+
+    // still fetchable, with the pending deletion reported
+    let fetched = server
+        .post("/fetch")
+        .json(&fetch_secret(SHA256_222222))
+        .expect_success()
+        .await;
+    assert!(trash_after(&fetched).is_some());
+
+    let restored = server
+        .post("/restore")
+        .json(&fetch_secret(SHA256_222222))
+        .await;
+    assert_eq!(restored.status_code(), StatusCode::OK);
+    assert!(restored.json::<serde_json::Value>()["attempt_status"].is_object());
+
+    let fetched = server
+        .post("/fetch")
+        .json(&fetch_secret(SHA256_222222))
+        .expect_success()
+        .await;
+    assert_eq!(trash_after(&fetched), None);
+
+    crate::retention::purge(&state).await;
+    assert_eq!(row_count(&state), 1);
+}
+
+/// Once the grace period has elapsed the record is gone for every read, and
+/// the purge task deletes it.
+#[tokio::test]
+async fn test_elapsed_grace_period_deletes_the_record() {
+    let (server, state) = grace_server(24).await;
+    server
+        .post("/trash")
+        .json(&fetch_secret(SHA256_222222))
+        .expect_success()
+        .await;
+    expire_grace_period(&state);
+
+    let fetched = server
+        .post("/fetch")
+        .json(&fetch_secret(SHA256_222222))
+        .await;
+    assert_eq!(fetched.status_code(), StatusCode::UNAUTHORIZED);
+    let restored = server
+        .post("/restore")
+        .json(&fetch_secret(SHA256_222222))
+        .await;
+    assert_eq!(restored.status_code(), StatusCode::UNAUTHORIZED);
+
+    assert_eq!(row_count(&state), 1);
+    crate::retention::purge(&state).await;
+    assert_eq!(row_count(&state), 0);
+}
+
+/// A repeated `/trash` cannot postpone the deadline.
+#[tokio::test]
+async fn test_repeated_trash_keeps_the_first_deadline() {
+    let (server, _) = grace_server(24).await;
+    let first = server
+        .post("/trash")
+        .json(&fetch_secret(SHA256_222222))
+        .await;
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    let second = server
+        .post("/trash")
+        .json(&fetch_secret(SHA256_222222))
+        .await;
+    assert_eq!(second.status_code(), StatusCode::ACCEPTED);
+    assert_eq!(trash_after(&first), trash_after(&second));
+}
+
+/// `/restore` is a guess like any other: it shares the candidate budget.
+#[tokio::test]
+async fn test_restore_shares_the_candidate_budget() {
+    let (server, state) = grace_server(24).await;
+    for index in 0..usize::from(state.rate_limit_max_attempts) {
+        let response = server
+            .post("/restore")
+            .json(&fetch_secret(&distinct_candidate(index)))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+    }
+    let locked = server
+        .post("/restore")
+        .json(&fetch_secret(SHA256_222222))
+        .await;
+    assert_eq!(locked.status_code(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+/// With a grace period, `/rotate` marks the replaced record instead of
+/// deleting it: rotation is not a way around the delayed deletion.
+#[tokio::test]
+async fn test_rotate_marks_the_replaced_record() {
+    let (server, _) = grace_server(24).await;
+    server
+        .post("/rotate")
+        .json(&RotateSecret {
+            identifier: SHA256_111111.to_string(),
+            authentication_key: SHA256_222222.to_string(),
+            pow: None,
+            new_secret: StoreSecret {
+                identifier: SHA256_222222.to_string(),
+                authentication_key: NOT_PASSWORD_HASH.to_string(),
+                encrypted_secret: BASE64_ENCRYPTED_SECRET.to_string(),
+                ttl_days: None,
+                share_index: None,
+                federation_id: None,
+            },
+        })
+        .expect_success()
+        .await;
+
+    let old = server
+        .post("/fetch")
+        .json(&fetch_secret(SHA256_222222))
+        .expect_success()
+        .await;
+    assert!(trash_after(&old).is_some());
+}
+
+#[tokio::test]
+async fn test_info_reports_the_grace_period_and_immediate_trash_is_unchanged() {
+    let (server, state) = grace_server(0).await;
+    let info = server.get("/info").expect_success().await.json::<Info>();
+    assert_eq!(info.trash_grace_period_hours, 0);
+
+    let trashed = server
+        .post("/trash")
+        .json(&fetch_secret(SHA256_222222))
+        .await;
+    assert_eq!(trashed.status_code(), StatusCode::ACCEPTED);
+    assert_eq!(trash_after(&trashed), None);
+    assert_eq!(row_count(&state), 0);
+}
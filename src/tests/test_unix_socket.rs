@@ -0,0 +1,112 @@
+//! SERVER_ADDRESS=unix:/path: the socket lifecycle. The request handling
+//! itself is exercised on both listeners by the concurrency tests.
+
+use std::os::unix::fs::PermissionsExt;
+
+use crate::listener::Listener;
+use crate::tests::test_server::{listener_addresses, raw_request, spawn_listener};
+
+fn unix_address() -> String {
+    listener_addresses()
+        .into_iter()
+        .find(|(listener, _)| *listener == "unix")
+        .unwrap()
+        .1
+}
+
+fn socket_path(address: &str) -> std::path::PathBuf {
+    crate::listener::unix_socket_path(address)
+        .unwrap()
+        .to_path_buf()
+}
+
+/// The socket is created with SERVER_SOCKET_MODE, not the umask default.
+#[tokio::test]
+async fn test_socket_mode_is_applied() {
+    let address = unix_address();
+    let listener = Listener::bind(&address, 0o600).await.unwrap();
+    let mode = std::fs::metadata(socket_path(&address))
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o600);
+    drop(listener);
+    std::fs::remove_file(socket_path(&address)).unwrap();
+}
+
+/// A socket left behind by a crashed server (nobody accepts on it) must not
+/// prevent the restart.
+#[tokio::test]
+async fn test_stale_socket_is_replaced() {
+    let address = unix_address();
+    drop(std::os::unix::net::UnixListener::bind(socket_path(&address)).unwrap());
+    assert!(socket_path(&address).exists());
+
+    let (_, state) = crate::tests::test_server::new_test_server().await;
+    let server = spawn_listener(crate::router::new(state), &address).await;
+    let response = raw_request(
+        &server.address,
+        "GET /info HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+}
+
+/// A socket a running server still accepts on is not stolen, and a path
+/// that is not a socket is never deleted.
+#[tokio::test]
+async fn test_bind_refuses_live_socket_and_other_files() {
+    let (_, state) = crate::tests::test_server::new_test_server().await;
+    let address = unix_address();
+    let server = spawn_listener(crate::router::new(state), &address).await;
+    let error = Listener::bind(&address, 0o660).await.err().unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::AddrInUse);
+    drop(server);
+
+    let address = unix_address();
+    std::fs::write(socket_path(&address), "not a socket").unwrap();
+    let error = Listener::bind(&address, 0o660).await.err().unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::AlreadyExists);
+    assert_eq!(
+        std::fs::read_to_string(socket_path(&address)).unwrap(),
+        "not a socket"
+    );
+    std::fs::remove_file(socket_path(&address)).unwrap();
+}
+
+/// Graceful shutdown lets the in-flight request finish, then removes the
+/// socket file.
+#[tokio::test]
+async fn test_graceful_shutdown_removes_socket() {
+    let address = unix_address();
+    let app = axum::Router::new().route(
+        "/slow",
+        axum::routing::get(|| async {
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            "done"
+        }),
+    );
+    let listener = Listener::bind(&address, 0o660).await.unwrap();
+    let (shutdown, signal) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(listener.serve(app, async {
+        let _ = signal.await;
+    }));
+
+    let request = {
+        let address = address.clone();
+        tokio::spawn(async move {
+            raw_request(
+                &address,
+                "GET /slow HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n",
+            )
+            .await
+        })
+    };
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    shutdown.send(()).unwrap();
+
+    let response = request.await.unwrap();
+    assert!(response.ends_with("done"), "{response}");
+    server.await.unwrap().unwrap();
+    assert!(!socket_path(&address).exists());
+}
@@ -0,0 +1,352 @@
+//! Tamper-evident log of the changes to the `secret` table: an append-only
+//! Merkle tree (RFC 9162 §2.1) with one leaf per event, so that a client can
+//! hold the server to what it answered. Events are stores (every accepted
+//! `/store`, duplicates included, the new record of a `/rotate`, and every
+//! record `keychain-admin import` inserts) and trashes (every deletion the
+//! server makes: `/trash`, `/rotate`, the purge of an elapsed grace period
+//! or of an expired record, and `keychain-admin purge`). Replicated changes
+//! are logged by the peer that applies them too.
+//!
+//! Leaf: `event || u16 length || secret_id || sha256(encrypted_secret) ||
+//! u16 length || created_at`, `event` 0 for a store and 1 for a trash, the
+//! strings as returned by `/fetch`, lengths big-endian. A trash names the
+//! record it deleted. A duplicate store is built from the request, not from
+//! the stored record, whose leaf would reveal that it exists: a record only
+//! changes through a trash of it, so a store leaf with no trash before it
+//! is a duplicate. The server keeps and serves leaf hashes only: a
+//! `secret_id` cannot be computed without the credentials, so nobody else
+//! can find, or test for, a record's leaves.
+//!
+//! Tree heads are signed with the attempts key (`attempts_public_key` in
+//! `/info`) over `TREE_HEAD_SIGNATURE_CONTEXT || tree_size || root_hash ||
+//! timestamp`, the size and the Unix timestamp as big-endian u64 and i64.
+//!
+//! The leaves live in the database (`log_leaf`), appended in the
+//! transaction of the change they record; the tree is rebuilt in memory
+//! from them and kept in sync on every read.
+
+use std::collections::HashMap;
+
+use sha2::{Digest, Sha256};
+
+pub type Hash = [u8; 32];
+
+/// Domain separation of the tree head signatures.
+pub const TREE_HEAD_SIGNATURE_CONTEXT: &[u8] = b"keychain tree head v1\n";
+
+const STORE_EVENT: u8 = 0;
+const TRASH_EVENT: u8 = 1;
+
+/// Leaves read from the database per query while syncing.
+const SYNC_BATCH: i64 = 10_000;
+
+fn leaf_hash(event: u8, secret_id: &str, encrypted_secret: &str, created_at: &str) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([0x00, event]);
+    hasher.update((secret_id.len() as u16).to_be_bytes());
+    hasher.update(secret_id.as_bytes());
+    hasher.update(Sha256::digest(encrypted_secret.as_bytes()));
+    hasher.update((created_at.len() as u16).to_be_bytes());
+    hasher.update(created_at.as_bytes());
+    hasher.finalize().into()
+}
+
+/// Leaf hash of the store of a record.
+pub fn store_leaf(secret_id: &str, encrypted_secret: &str, created_at: &str) -> Hash {
+    leaf_hash(STORE_EVENT, secret_id, encrypted_secret, created_at)
+}
+
+/// Leaf hash of the deletion of a record.
+pub fn trash_leaf(secret_id: &str, encrypted_secret: &str, created_at: &str) -> Hash {
+    leaf_hash(TRASH_EVENT, secret_id, encrypted_secret, created_at)
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([0x01]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Largest power of two below `n`, for `n >= 2`.
+fn split_point(n: u64) -> u64 {
+    1 << (63 - (n - 1).leading_zeros())
+}
+
+/// Signed message of a tree head.
+pub fn tree_head_message(tree_size: u64, root_hash: &Hash, timestamp: i64) -> Vec<u8> {
+    [
+        TREE_HEAD_SIGNATURE_CONTEXT,
+        &tree_size.to_be_bytes(),
+        root_hash,
+        &timestamp.to_be_bytes(),
+    ]
+    .concat()
+}
+
+/// The tree, with the hash of every complete aligned subtree: level `k`
+/// holds the hashes of leaves `[j·2^k, (j+1)·2^k)`. Roots and proofs then
+/// take O(log² n) hashes.
+#[derive(Default)]
+pub struct MerkleTree {
+    levels: Vec<Vec<Hash>>,
+    /// Index of each leaf hash, the first one if repeated.
+    positions: HashMap<Hash, u64>,
+}
+
+impl MerkleTree {
+    pub fn size(&self) -> u64 {
+        self.levels.first().map_or(0, |leaves| leaves.len() as u64)
+    }
+
+    pub fn push(&mut self, leaf: Hash) {
+        let index = self.size();
+        self.positions.entry(leaf).or_insert(index);
+        let mut level = 0;
+        let mut hash = leaf;
+        loop {
+            if self.levels.len() == level {
+                self.levels.push(Vec::new());
+            }
+            self.levels[level].push(hash);
+            let nodes = &self.levels[level];
+            if nodes.len() % 2 == 1 {
+                return;
+            }
+            hash = node_hash(&nodes[nodes.len() - 2], &nodes[nodes.len() - 1]);
+            level += 1;
+        }
+    }
+
+    pub fn position(&self, leaf: &Hash) -> Option<u64> {
+        self.positions.get(leaf).copied()
+    }
+
+    /// `MTH(D[start:end])`, for `start < end <= size`.
+    fn subtree(&self, start: u64, end: u64) -> Hash {
+        let n = end - start;
+        if n.is_power_of_two() && start.is_multiple_of(n) {
+            return self.levels[n.trailing_zeros() as usize][(start / n) as usize];
+        }
+        let k = split_point(n);
+        node_hash(
+            &self.subtree(start, start + k),
+            &self.subtree(start + k, end),
+        )
+    }
+
+    /// Root of the first `size` leaves, `size <= self.size()`.
+    pub fn root(&self, size: u64) -> Hash {
+        if size == 0 {
+            return Sha256::digest([]).into();
+        }
+        self.subtree(0, size)
+    }
+
+    /// `PATH(index, D[0:size])`, for `index < size <= self.size()`.
+    pub fn inclusion_proof(&self, index: u64, size: u64) -> Vec<Hash> {
+        let mut path = Vec::new();
+        self.path(index, 0, size, &mut path);
+        path
+    }
+
+    fn path(&self, index: u64, start: u64, end: u64, path: &mut Vec<Hash>) {
+        let n = end - start;
+        if n <= 1 {
+            return;
+        }
+        let k = split_point(n);
+        if index < start + k {
+            self.path(index, start, start + k, path);
+            path.push(self.subtree(start + k, end));
+        } else {
+            self.path(index, start + k, end, path);
+            path.push(self.subtree(start, start + k));
+        }
+    }
+
+    /// `PROOF(first, D[0:second])`, for `0 < first <= second <=
+    /// self.size()`; empty when both are equal.
+    pub fn consistency_proof(&self, first: u64, second: u64) -> Vec<Hash> {
+        let mut proof = Vec::new();
+        if first < second {
+            self.subproof(first, 0, second, true, &mut proof);
+        }
+        proof
+    }
+
+    fn subproof(&self, m: u64, start: u64, end: u64, complete: bool, proof: &mut Vec<Hash>) {
+        let n = end - start;
+        if m == n {
+            if !complete {
+                proof.push(self.subtree(start, end));
+            }
+            return;
+        }
+        let k = split_point(n);
+        if m <= k {
+            self.subproof(m, start, start + k, complete, proof);
+            proof.push(self.subtree(start + k, end));
+        } else {
+            self.subproof(m - k, start + k, end, false, proof);
+            proof.push(self.subtree(start, start + k));
+        }
+    }
+}
+
+/// RFC 9162 §2.1.3.2: whether `path` proves `leaf` at `index` in the tree
+/// of `size` leaves with root `root`.
+pub fn verify_inclusion(leaf: &Hash, index: u64, size: u64, path: &[Hash], root: &Hash) -> bool {
+    if index >= size {
+        return false;
+    }
+    let (mut fn_, mut sn) = (index, size - 1);
+    let mut r = *leaf;
+    for p in path {
+        if sn == 0 {
+            return false;
+        }
+        if fn_ & 1 == 1 || fn_ == sn {
+            r = node_hash(p, &r);
+            while fn_ & 1 == 0 && fn_ != 0 {
+                fn_ >>= 1;
+                sn >>= 1;
+            }
+        } else {
+            r = node_hash(&r, p);
+        }
+        fn_ >>= 1;
+        sn >>= 1;
+    }
+    sn == 0 && r == *root
+}
+
+/// RFC 9162 §2.1.4.2: whether `proof` shows the tree of `second` leaves
+/// with root `second_root` extends the one of `first` leaves with root
+/// `first_root`.
+pub fn verify_consistency(
+    first: u64,
+    second: u64,
+    first_root: &Hash,
+    second_root: &Hash,
+    proof: &[Hash],
+) -> bool {
+    if first == 0 || first > second {
+        return false;
+    }
+    if first == second {
+        return proof.is_empty() && first_root == second_root;
+    }
+    let mut path = proof.to_vec();
+    if first.is_power_of_two() {
+        path.insert(0, *first_root);
+    }
+    let Some((head, rest)) = path.split_first() else {
+        return false;
+    };
+    let (mut fn_, mut sn) = (first - 1, second - 1);
+    while fn_ & 1 == 1 {
+        fn_ >>= 1;
+        sn >>= 1;
+    }
+    let (mut fr, mut sr) = (*head, *head);
+    for c in rest {
+        if sn == 0 {
+            return false;
+        }
+        if fn_ & 1 == 1 || fn_ == sn {
+            fr = node_hash(c, &fr);
+            sr = node_hash(c, &sr);
+            while fn_ & 1 == 0 && fn_ != 0 {
+                fn_ >>= 1;
+                sn >>= 1;
+            }
+        } else {
+            sr = node_hash(&sr, c);
+        }
+        fn_ >>= 1;
+        sn >>= 1;
+    }
+    fr == *first_root && sr == *second_root && sn == 0
+}
+
+/// The server's copy of the tree, behind the leaves in the database.
+#[derive(Default)]
+pub(crate) struct TransparencyLog {
+    tree: std::sync::Mutex<MerkleTree>,
+}
+
+impl TransparencyLog {
+    /// Locks the tree after appending the leaves committed since the last
+    /// sync. Blocking: call it from a blocking task.
+    pub(crate) fn synced(
+        &self,
+        secret_store: &dyn crate::secret_store::SecretStore,
+    ) -> crate::secret_store::StoreResult<std::sync::MutexGuard<'_, MerkleTree>> {
+        let mut tree = self.tree.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            let leaves = secret_store.read_log_leaves(tree.size() as i64, SYNC_BATCH)?;
+            let count = leaves.len();
+            for leaf in leaves {
+                let mut hash = [0u8; 32];
+                if leaf.leaf_index as u64 != tree.size()
+                    || hex::decode_to_slice(&leaf.leaf_hash, &mut hash).is_err()
+                {
+                    return Err(diesel::result::Error::DeserializationError(
+                        format!(
+                            "log leaf {} is out of sequence or malformed",
+                            leaf.leaf_index
+                        )
+                        .into(),
+                    ));
+                }
+                tree.push(hash);
+            }
+            if (count as i64) < SYNC_BATCH {
+                return Ok(tree);
+            }
+        }
+    }
+}
+
+/// Signs the head of the first `tree_size` leaves, now.
+pub(crate) fn tree_head(
+    tree: &MerkleTree,
+    tree_size: u64,
+    signing_key: &ed25519_dalek::SigningKey,
+) -> crate::models::TreeHead {
+    use base64::Engine;
+    use ed25519_dalek::Signer;
+
+    let root_hash = tree.root(tree_size);
+    let timestamp = chrono::Utc::now().timestamp();
+    let signature = signing_key.sign(&tree_head_message(tree_size, &root_hash, timestamp));
+    crate::models::TreeHead {
+        tree_size,
+        root_hash: hex::encode(root_hash),
+        timestamp,
+        signature: base64::engine::general_purpose::STANDARD.encode(signature.to_bytes()),
+    }
+}
+
+/// Inclusion proof of `leaf` in the first `tree_size` leaves, with their
+/// signed head; `None` if the leaf is not among them.
+pub(crate) fn inclusion(
+    tree: &MerkleTree,
+    leaf: &Hash,
+    tree_size: u64,
+    signing_key: &ed25519_dalek::SigningKey,
+) -> Option<crate::models::InclusionProof> {
+    let leaf_index = tree.position(leaf).filter(|index| *index < tree_size)?;
+    Some(crate::models::InclusionProof {
+        leaf_index,
+        tree_size,
+        audit_path: tree
+            .inclusion_proof(leaf_index, tree_size)
+            .iter()
+            .map(hex::encode)
+            .collect(),
+        tree_head: tree_head(tree, tree_size, signing_key),
+    })
+}
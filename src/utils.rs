@@ -21,6 +21,10 @@ pub fn is_256bits_hex_hash(input: &str) -> bool {
     is_length(64, input) && is_hex(input)
 }
 
+pub fn is_hex_of_length(length: usize, input: &str) -> bool {
+    is_length(length, input) && is_hex(input)
+}
+
 pub fn sha256_hex(data: &[u8]) -> String {
     let mut hasher = Sha256::new();
     hasher.update(data);